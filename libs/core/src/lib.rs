@@ -36,11 +36,19 @@ pub mod block_processor {
 pub mod kafka_utils {
     use rdkafka::config::ClientConfig;
     use rdkafka::consumer::{Consumer, StreamConsumer};
+    use rdkafka::error::KafkaResult;
     use rdkafka::producer::{FutureProducer, FutureRecord};
     use rdkafka::message::Message;
+    use rdkafka::{Offset, TopicPartitionList};
+    use std::collections::HashMap;
     use std::time::Duration;
     use tracing::info;
 
+    // Next offset to read, per topic and partition
+    pub type TopicOffsets = HashMap<String, HashMap<i32, i64>>;
+
+    const METADATA_TIMEOUT: Duration = Duration::from_secs(10);
+
     pub fn create_kafka_consumer(brokers: &str, group_id: &str) -> StreamConsumer {
         info!("Creating Kafka consumer for brokers: {}, group: {}", brokers, group_id);
         ClientConfig::new()
@@ -51,6 +59,19 @@ pub mod kafka_utils {
             .expect("Failed to create Kafka consumer")
     }
 
+    // Reads its topics from the beginning on every start; offsets are never
+    // committed, so control state can be rebuilt from the full history
+    pub fn create_replay_consumer(brokers: &str, group_id: &str) -> StreamConsumer {
+        info!("Creating replay consumer for brokers: {}, group: {}", brokers, group_id);
+        ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("group.id", group_id)
+            .set("enable.auto.commit", "false")
+            .set("auto.offset.reset", "earliest")
+            .create()
+            .expect("Failed to create Kafka consumer")
+    }
+
     pub fn create_kafka_producer(brokers: &str) -> FutureProducer {
         info!("Creating Kafka producer for brokers: {}", brokers);
         ClientConfig::new()
@@ -78,6 +99,128 @@ pub mod kafka_utils {
         }
     }
 
+    #[derive(Debug, Clone)]
+    pub struct ConsumedMessage {
+        pub topic: String,
+        pub partition: i32,
+        pub offset: i64,
+        pub payload: String,
+        pub timestamp_ms: Option<i64>, // broker timestamp, so replayed events keep their original time
+    }
+
+    pub async fn consume_message_with_metadata(consumer: &StreamConsumer) -> Option<ConsumedMessage> {
+        match consumer.recv().await {
+            Err(e) => {
+                tracing::error!("Kafka consume error: {:?}", e);
+                None
+            }
+            Ok(m) => {
+                let payload = m.payload()?;
+                let message = String::from_utf8_lossy(payload).to_string();
+                tracing::debug!("Consumed message from {}[{}]@{}: {}", m.topic(), m.partition(), m.offset(), message);
+                Some(ConsumedMessage {
+                    topic: m.topic().to_string(),
+                    partition: m.partition(),
+                    offset: m.offset(),
+                    payload: message,
+                    timestamp_ms: m.timestamp().to_millis(),
+                })
+            }
+        }
+    }
+
+    fn partitions(consumer: &StreamConsumer, topic: &str) -> KafkaResult<Vec<i32>> {
+        let metadata = consumer.fetch_metadata(Some(topic), METADATA_TIMEOUT)?;
+        Ok(metadata
+            .topics()
+            .iter()
+            .flat_map(|t| t.partitions().iter().map(|p| p.id()))
+            .collect())
+    }
+
+    // Current end of every partition of the topics
+    pub fn high_watermarks(consumer: &StreamConsumer, topics: &[&str]) -> KafkaResult<TopicOffsets> {
+        let mut offsets = TopicOffsets::new();
+        for topic in topics {
+            for partition in partitions(consumer, topic)? {
+                let (_, high) = consumer.fetch_watermarks(topic, partition, METADATA_TIMEOUT)?;
+                offsets.entry(topic.to_string()).or_default().insert(partition, high);
+            }
+        }
+        Ok(offsets)
+    }
+
+    // First offsets at or after the timestamp; partitions with nothing that
+    // recent start at their end
+    pub fn offsets_for_timestamp(consumer: &StreamConsumer, topics: &[&str], timestamp_ms: i64) -> KafkaResult<TopicOffsets> {
+        let mut query = TopicPartitionList::new();
+        for topic in topics {
+            for partition in partitions(consumer, topic)? {
+                query.add_partition_offset(topic, partition, Offset::Offset(timestamp_ms))?;
+            }
+        }
+
+        let mut offsets = TopicOffsets::new();
+        for element in consumer.offsets_for_times(query, METADATA_TIMEOUT)?.elements() {
+            let offset = match element.offset() {
+                Offset::Offset(offset) => offset,
+                _ => consumer.fetch_watermarks(element.topic(), element.partition(), METADATA_TIMEOUT)?.1,
+            };
+            offsets.entry(element.topic().to_string()).or_default().insert(element.partition(), offset);
+        }
+        Ok(offsets)
+    }
+
+    // Assigns every partition of the topics at the given offsets, or at the
+    // beginning when there is none. Returns the offsets reading starts from.
+    pub fn assign_from_offsets(consumer: &StreamConsumer, topics: &[&str], offsets: &TopicOffsets) -> KafkaResult<TopicOffsets> {
+        let mut assignment = TopicPartitionList::new();
+        let mut start = TopicOffsets::new();
+        for topic in topics {
+            for partition in partitions(consumer, topic)? {
+                let offset = match offsets.get(*topic).and_then(|partitions| partitions.get(&partition)) {
+                    Some(offset) => *offset,
+                    None => consumer.fetch_watermarks(topic, partition, METADATA_TIMEOUT)?.0,
+                };
+                assignment.add_partition_offset(topic, partition, Offset::Offset(offset))?;
+                start.entry(topic.to_string()).or_default().insert(partition, offset);
+            }
+        }
+        consumer.assign(&assignment)?;
+        Ok(start)
+    }
+
+    // Assigns the topics at the given offsets and reads until every partition
+    // has reached the end it had when reading started. The consumer is left
+    // positioned right after the returned messages.
+    pub async fn read_to_end(consumer: &StreamConsumer, topics: &[&str], offsets: &TopicOffsets) -> KafkaResult<Vec<ConsumedMessage>> {
+        let mut position = assign_from_offsets(consumer, topics, offsets)?;
+        let end = high_watermarks(consumer, topics)?;
+        let caught_up = |position: &TopicOffsets| {
+            end.iter().all(|(topic, partitions)| {
+                partitions.iter().all(|(partition, high)| {
+                    position.get(topic).and_then(|p| p.get(partition)).is_none_or(|next| next >= high)
+                })
+            })
+        };
+
+        let mut messages = Vec::new();
+        while !caught_up(&position) {
+            let m = consumer.recv().await?;
+            position.entry(m.topic().to_string()).or_default().insert(m.partition(), m.offset() + 1);
+            if let Some(payload) = m.payload() {
+                messages.push(ConsumedMessage {
+                    topic: m.topic().to_string(),
+                    partition: m.partition(),
+                    offset: m.offset(),
+                    payload: String::from_utf8_lossy(payload).to_string(),
+                    timestamp_ms: m.timestamp().to_millis(),
+                });
+            }
+        }
+        Ok(messages)
+    }
+
     pub async fn produce_message(
         producer: &FutureProducer,
         topic: &str,
@@ -141,6 +284,13 @@ pub mod metrics {
         register_counter!("circuit_breaker_tripped_total", "Total number of circuit breaker trips").unwrap()
     });
 
+    pub static CIRCUIT_BREAKER_STATE: Lazy<prometheus::GaugeVec> = Lazy::new(|| {
+        prometheus::register_gauge_vec!("circuit_breaker_state", "Circuit breaker state (0 closed, 1 half-open, 2 open)", &["scope", "key"]).unwrap()
+    });
+    pub static SURVEILLANCE_ALERTS: Lazy<CounterVec> = Lazy::new(|| {
+        register_counter_vec!("surveillance_alerts_total", "Users throttled by order surveillance", &["metric"]).unwrap()
+    });
+
     pub static ORDERS_MATCHED: Lazy<Counter> = Lazy::new(|| {
         register_counter!("orders_matched_total", "Total number of orders matched").unwrap()
     });
@@ -152,14 +302,33 @@ pub mod metrics {
     pub static POSITION_EXPOSURE: Lazy<prometheus::GaugeVec> = Lazy::new(|| {
         prometheus::register_gauge_vec!("position_exposure_usd", "Current position exposure in USD", &["symbol"]).unwrap()
     });
+
+    pub static USER_VAR: Lazy<prometheus::GaugeVec> = Lazy::new(|| {
+        prometheus::register_gauge_vec!("user_var_usd", "1-day 99% Value-at-Risk per user in USD", &["user_id", "model"]).unwrap()
+    });
+
+    pub static ACCOUNT_MARGIN: Lazy<prometheus::GaugeVec> = Lazy::new(|| {
+        prometheus::register_gauge_vec!("account_margin_usd", "Margin account equity and requirements in USD", &["user_id", "kind"]).unwrap()
+    });
+
+    pub static RISK_SNAPSHOT_FAILURES: Lazy<Counter> = Lazy::new(|| {
+        register_counter!("risk_snapshot_failures_total", "Total number of failed risk snapshot writes").unwrap()
+    });
+
+    pub static POSITION_PNL: Lazy<prometheus::GaugeVec> = Lazy::new(|| {
+        prometheus::register_gauge_vec!("position_pnl_usd", "Marked-to-market position PnL in USD", &["user_id", "symbol", "pnl_type"]).unwrap()
+    });
 }
 
 pub mod exchange_connector {
     use serde::{Deserialize, Serialize};
     use std::collections::HashMap;
     use chrono::{DateTime, Utc};
-    use tokio::sync::RwLock;
-    use std::sync::Arc;
+    use tokio::sync::mpsc;
+    use std::sync::{Arc, Mutex};
+    use async_trait::async_trait;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
 
     #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
     pub enum ExchangeType {
@@ -182,6 +351,21 @@ pub mod exchange_connector {
         }
     }
 
+    impl std::str::FromStr for ExchangeType {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s.to_ascii_lowercase().as_str() {
+                "binance" => Ok(ExchangeType::Binance),
+                "coinbase" => Ok(ExchangeType::Coinbase),
+                "kraken" => Ok(ExchangeType::Kraken),
+                "solana" => Ok(ExchangeType::Solana),
+                "ethereum" => Ok(ExchangeType::Ethereum),
+                other => Err(format!("Unknown exchange: {}", other)),
+            }
+        }
+    }
+
     #[derive(Debug, Clone, Serialize, Deserialize)]
     pub struct MarketData {
         pub symbol: String,
@@ -207,288 +391,4912 @@ pub mod exchange_connector {
         pub timestamp: DateTime<Utc>,
     }
 
+    // Everything a market data stream can deliver, under the symbols it was opened with
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub enum MarketDataEvent {
+        Book(OrderBookSnapshot),
+        Trade(TradeEvent),
+    }
+
+    // Venue view of an order; quantity and price are cumulative fill and average price
     #[derive(Debug, Clone, Serialize, Deserialize)]
     pub struct OrderExecution {
+        pub order_id: String,
+        pub exchange_order_id: String,
         pub price: f64,
         pub quantity: f64,
         pub status: OrderStatus,
+        pub reason: Option<String>,
         pub timestamp: DateTime<Utc>,
     }
 
-    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
     pub enum OrderStatus {
+        New,
         Filled,
         Partial,
         Rejected,
         Cancelled,
     }
 
+    impl OrderStatus {
+        pub fn is_open(&self) -> bool {
+            matches!(self, OrderStatus::New | OrderStatus::Partial)
+        }
+    }
+
     #[derive(Debug, Clone, Serialize, Deserialize)]
     pub struct FillReport {
         pub fill_id: String,
         pub order_id: String,
+        pub symbol: String,
+        pub side: String,
         pub price: f64,
         pub quantity: f64,
         pub timestamp: DateTime<Utc>,
     }
 
-    pub struct ExchangeConnector {
-        exchange_type: ExchangeType,
-        api_key: String,
-        api_secret: String,
-        endpoint: String,
-        rate_limit: u64,
-        connections: Arc<RwLock<u32>>,
-    }
+    // Venue protocol behind the execution engine and market data handler.
+    // Order ids are ours; adapters map them to whatever the venue assigns.
+    #[async_trait]
+    pub trait ExchangeConnector: Send + Sync {
+        fn exchange_type(&self) -> ExchangeType;
 
-    impl ExchangeConnector {
-        pub fn new(
-            exchange_type: ExchangeType,
-            api_key: String,
-            api_secret: String,
-            endpoint: String,
-            rate_limit: u64,
-        ) -> Self {
-            Self {
-                exchange_type,
-                api_key,
-                api_secret,
-                endpoint,
-                rate_limit,
-                connections: Arc::new(RwLock::new(0)),
-            }
-        }
+        // Acknowledgement only: executions are delivered through stream_fills
+        async fn place_order(&self, order: &Order) -> Result<OrderExecution, String>;
 
-        pub async fn execute_order(&self, order: &crate::Order) -> Result<OrderExecution, String> {
-            // Simulate order execution
-            tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
-            
-            Ok(OrderExecution {
-                price: order.price,
-                quantity: order.quantity,
-                status: OrderStatus::Filled,
-                timestamp: Utc::now(),
-            })
-        }
+        async fn cancel_order(&self, symbol: &str, order_id: &str) -> Result<OrderExecution, String>;
 
-        pub fn active_connections(&self) -> u32 {
-            // This would be implemented with actual connection tracking
-            1
-        }
+        async fn amend_order(&self, symbol: &str, order_id: &str, price: Option<f64>, quantity: Option<f64>) -> Result<OrderExecution, String>;
 
-        pub async fn get_market_data(&self, symbol: &str) -> Result<MarketData, String> {
-            // Simulate market data retrieval
-            Ok(MarketData {
-                symbol: symbol.to_string(),
-                price: 50000.0,
-                volume: 1000.0,
-                timestamp: Utc::now(),
-            })
-        }
-    }
+        async fn query_order(&self, symbol: &str, order_id: &str) -> Result<OrderExecution, String>;
 
-    // Common order structure used across services
-    #[derive(Debug, Clone, Serialize, Deserialize)]
-    pub struct Order {
-        pub order_id: String,
-        pub client_order_id: String,
-        pub symbol: String,
-        pub price: f64,
-        pub quantity: f64,
-        pub side: String,
-        pub order_type: String,
-        pub time_in_force: String,
-        pub user_id: String,
-        pub timestamp: DateTime<Utc>,
+        async fn stream_fills(&self) -> Result<mpsc::Receiver<FillReport>, String>;
+
+        async fn stream_market_data(&self, symbols: &[String]) -> Result<mpsc::Receiver<MarketDataEvent>, String>;
+
+        fn active_connections(&self) -> u32;
     }
-}
 
-pub mod auth {
-    use serde::{Deserialize, Serialize};
-    use std::collections::HashMap;
+    #[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum AdapterKind {
+        // Fills every order in full at its limit price
+        #[default]
+        Paper,
+        // Matches against its own simulated books
+        Simulated,
+        // Talks to the venue itself
+        Live,
+    }
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
-    pub struct ApiKeyAuth {
-        pub key: String,
-        pub secret: String,
-        pub permissions: Vec<String>,
+    pub struct ConnectorConfig {
+        pub exchange_type: ExchangeType,
+        #[serde(default)]
+        pub adapter: AdapterKind,
+        #[serde(default)]
+        pub api_key: String,
+        #[serde(default)]
+        pub api_secret: String,
+        #[serde(default)]
+        pub endpoint: String,
+        #[serde(default)]
+        pub ws_endpoint: String,
+        #[serde(default)]
+        pub rate_limit: u64,
+        // Our symbol to the venue's, where the two differ
+        #[serde(default)]
+        pub symbol_map: HashMap<String, String>,
+        #[serde(default)]
+        pub simulator: SimulatorConfig,
+        #[serde(default)]
+        pub binance: binance::BinanceConfig,
+        #[serde(default)]
+        pub coinbase: coinbase::CoinbaseConfig,
     }
 
-    pub fn authenticate(api_key: &str, api_secret: &str) -> Result<String, String> {
-        // Simplified authentication - in production this would validate against a database
-        if api_key == "user1" && api_secret == "secret1" {
-            Ok("user1".to_string())
-        } else {
-            Err("Invalid credentials".to_string())
+    impl ConnectorConfig {
+        pub fn new(exchange_type: ExchangeType, adapter: AdapterKind) -> Self {
+            Self {
+                exchange_type,
+                adapter,
+                api_key: String::new(),
+                api_secret: String::new(),
+                endpoint: String::new(),
+                ws_endpoint: String::new(),
+                rate_limit: 0,
+                symbol_map: HashMap::new(),
+                simulator: SimulatorConfig::default(),
+                binance: binance::BinanceConfig::default(),
+                coinbase: coinbase::CoinbaseConfig::default(),
+            }
         }
     }
 
-    pub fn authorize(user_id: &str, permission: &str) -> bool {
-        // Simplified authorization - in production this would check user permissions
-        match user_id {
-            "user1" => permission == "trade",
-            _ => false,
+    // Picks the adapter for a venue from its config
+    pub fn connect(config: &ConnectorConfig) -> Result<Arc<dyn ExchangeConnector>, String> {
+        match config.adapter {
+            AdapterKind::Paper => Ok(Arc::new(PaperConnector::new(config.exchange_type.clone()))),
+            AdapterKind::Simulated => Ok(Arc::new(SimulatedConnector::new(config.exchange_type.clone(), config.simulator.clone())?)),
+            AdapterKind::Live => match config.exchange_type {
+                ExchangeType::Binance => Ok(Arc::new(binance::BinanceConnector::new(config)?)),
+                ExchangeType::Coinbase => Ok(Arc::new(coinbase::CoinbaseConnector::new(config)?)),
+                _ => Err(format!("No live adapter for {}", config.exchange_type)),
+            },
         }
     }
-}
 
-pub mod rate_limiter {
-    use std::time::{Duration, Instant};
-    use std::collections::VecDeque;
-
-    #[derive(Debug)]
-    pub struct RateLimitExceeded;
+    const STREAM_BUFFER: usize = 1024;
 
-    pub struct RateLimiter {
-        max_requests: u64,
-        window: Duration,
-        requests: VecDeque<Instant>,
+    // Fill and market data listeners shared by the in-process adapters
+    #[derive(Default)]
+    struct Subscribers {
+        fills: Mutex<Vec<mpsc::Sender<FillReport>>>,
+        market_data: Mutex<Vec<(Vec<String>, mpsc::Sender<MarketDataEvent>)>>,
     }
 
-    impl RateLimiter {
-        pub fn new(max_requests: u64) -> Self {
-            Self {
-                max_requests,
-                window: Duration::from_secs(60), // 1 minute window
-                requests: VecDeque::new(),
-            }
+    impl Subscribers {
+        fn subscribe_fills(&self) -> mpsc::Receiver<FillReport> {
+            let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+            self.fills.lock().unwrap().push(tx);
+            rx
         }
 
-        pub fn check(&mut self) -> Result<(), RateLimitExceeded> {
-            let now = Instant::now();
-            
-            // Remove old requests outside the window
-            while let Some(&front) = self.requests.front() {
-                if now.duration_since(front) > self.window {
-                    self.requests.pop_front();
-                } else {
-                    break;
+        fn subscribe_market_data(&self, symbols: &[String]) -> (mpsc::Sender<MarketDataEvent>, mpsc::Receiver<MarketDataEvent>) {
+            let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+            self.market_data.lock().unwrap().push((symbols.to_vec(), tx.clone()));
+            (tx, rx)
+        }
+
+        // Senders are cloned out so no lock is held across the sends
+        async fn publish_fills(&self, fills: &[FillReport]) {
+            if fills.is_empty() {
+                return;
+            }
+            let subscribers: Vec<_> = {
+                let mut subscribers = self.fills.lock().unwrap();
+                subscribers.retain(|tx| !tx.is_closed());
+                subscribers.clone()
+            };
+            for tx in subscribers {
+                for fill in fills {
+                    let _ = tx.send(fill.clone()).await;
                 }
             }
+        }
 
-            // Check if we're at the limit
-            if self.requests.len() >= self.max_requests as usize {
-                return Err(RateLimitExceeded);
+        async fn publish_market_data(&self, symbol: &str, event: MarketDataEvent) {
+            let subscribers: Vec<_> = {
+                let mut subscribers = self.market_data.lock().unwrap();
+                subscribers.retain(|(_, tx)| !tx.is_closed());
+                subscribers.iter()
+                    .filter(|(symbols, _)| symbols.iter().any(|s| s == symbol))
+                    .map(|(_, tx)| tx.clone())
+                    .collect()
+            };
+            for tx in subscribers {
+                let _ = tx.send(event.clone()).await;
             }
-
-            // Add current request
-            self.requests.push_back(now);
-            Ok(())
         }
 
-        pub fn get_current_qps(&self) -> f64 {
-            self.requests.len() as f64 / self.window.as_secs_f64()
+        fn active(&self) -> u32 {
+            let fills = self.fills.lock().unwrap().iter().filter(|tx| !tx.is_closed()).count();
+            let market_data = self.market_data.lock().unwrap().iter().filter(|(_, tx)| !tx.is_closed()).count();
+            (fills + market_data) as u32
         }
     }
-}
 
-pub mod order_validator {
-    use crate::exchange_connector::Order;
-
-    #[derive(Debug)]
-    pub enum OrderValidationError {
-        InvalidPrice,
-        InvalidQuantity,
-        InvalidSymbol,
-        InvalidSide,
-        InvalidOrderType,
+    pub struct PaperConnector {
+        exchange_type: ExchangeType,
+        orders: Mutex<HashMap<String, OrderExecution>>,
+        subscribers: Subscribers,
     }
 
-    impl std::fmt::Display for OrderValidationError {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            match self {
-                OrderValidationError::InvalidPrice => write!(f, "Invalid price"),
-                OrderValidationError::InvalidQuantity => write!(f, "Invalid quantity"),
-                OrderValidationError::InvalidSymbol => write!(f, "Invalid symbol"),
-                OrderValidationError::InvalidSide => write!(f, "Invalid side"),
-                OrderValidationError::InvalidOrderType => write!(f, "Invalid order type"),
+    impl PaperConnector {
+        pub fn new(exchange_type: ExchangeType) -> Self {
+            Self {
+                exchange_type,
+                orders: Mutex::new(HashMap::new()),
+                subscribers: Subscribers::default(),
+            }
+        }
+
+        fn not_open(&self, order_id: &str) -> String {
+            match self.orders.lock().unwrap().get(order_id) {
+                Some(execution) => format!("Order {} is {:?}", order_id, execution.status),
+                None => format!("Unknown order: {}", order_id),
             }
         }
     }
 
-    pub fn validate_order(order: &Order) -> Result<(), OrderValidationError> {
-        // Price validation
-        if order.price <= 0.0 {
-            return Err(OrderValidationError::InvalidPrice);
+    #[async_trait]
+    impl ExchangeConnector for PaperConnector {
+        fn exchange_type(&self) -> ExchangeType {
+            self.exchange_type.clone()
         }
 
-        // Quantity validation
-        if order.quantity <= 0.0 {
-            return Err(OrderValidationError::InvalidQuantity);
+        async fn place_order(&self, order: &Order) -> Result<OrderExecution, String> {
+            let now = Utc::now();
+            if order.price <= 0.0 || order.quantity <= 0.0 {
+                return Ok(OrderExecution {
+                    order_id: order.order_id.clone(),
+                    exchange_order_id: String::new(),
+                    price: 0.0,
+                    quantity: 0.0,
+                    status: OrderStatus::Rejected,
+                    reason: Some("paper fills need a positive price and quantity".to_string()),
+                    timestamp: now,
+                });
+            }
+
+            let exchange_order_id = uuid::Uuid::new_v4().to_string();
+            self.orders.lock().unwrap().insert(order.order_id.clone(), OrderExecution {
+                order_id: order.order_id.clone(),
+                exchange_order_id: exchange_order_id.clone(),
+                price: order.price,
+                quantity: order.quantity,
+                status: OrderStatus::Filled,
+                reason: None,
+                timestamp: now,
+            });
+
+            let fill = FillReport {
+                fill_id: uuid::Uuid::new_v4().to_string(),
+                order_id: order.order_id.clone(),
+                symbol: order.symbol.clone(),
+                side: order.side.clone(),
+                price: order.price,
+                quantity: order.quantity,
+                timestamp: now,
+            };
+            let trade = MarketDataEvent::Trade(TradeEvent {
+                symbol: order.symbol.clone(),
+                price: order.price,
+                quantity: order.quantity,
+                side: order.side.clone(),
+                timestamp: now,
+            });
+            self.subscribers.publish_fills(&[fill]).await;
+            self.subscribers.publish_market_data(&order.symbol, trade).await;
+
+            Ok(OrderExecution {
+                order_id: order.order_id.clone(),
+                exchange_order_id,
+                price: 0.0,
+                quantity: 0.0,
+                status: OrderStatus::New,
+                reason: None,
+                timestamp: now,
+            })
         }
 
-        // Symbol validation
-        if order.symbol.is_empty() {
-            return Err(OrderValidationError::InvalidSymbol);
+        async fn cancel_order(&self, _symbol: &str, order_id: &str) -> Result<OrderExecution, String> {
+            // Paper orders fill on arrival, so there is never anything left to cancel
+            Err(self.not_open(order_id))
         }
 
-        // Side validation
-        if !matches!(order.side.as_str(), "buy" | "sell") {
-            return Err(OrderValidationError::InvalidSide);
+        async fn amend_order(&self, _symbol: &str, order_id: &str, _price: Option<f64>, _quantity: Option<f64>) -> Result<OrderExecution, String> {
+            Err(self.not_open(order_id))
         }
 
-        // Order type validation
-        if !matches!(order.order_type.as_str(), "limit" | "market" | "stop" | "stop_limit") {
-            return Err(OrderValidationError::InvalidOrderType);
+        async fn query_order(&self, _symbol: &str, order_id: &str) -> Result<OrderExecution, String> {
+            self.orders.lock().unwrap()
+                .get(order_id)
+                .cloned()
+                .ok_or_else(|| format!("Unknown order: {}", order_id))
         }
 
-        Ok(())
-    }
-}
+        async fn stream_fills(&self) -> Result<mpsc::Receiver<FillReport>, String> {
+            Ok(self.subscribers.subscribe_fills())
+        }
 
-pub mod risk_engine {
-    use crate::exchange_connector::Order;
-    use serde::{Deserialize, Serialize};
-    use std::collections::HashMap;
+        async fn stream_market_data(&self, symbols: &[String]) -> Result<mpsc::Receiver<MarketDataEvent>, String> {
+            Ok(self.subscribers.subscribe_market_data(symbols).1)
+        }
 
-    #[derive(Debug, Clone, Serialize, Deserialize)]
-    pub struct PositionLimit {
-        pub max_long: f64,
-        pub max_short: f64,
-        pub max_exposure: f64,
-        pub max_daily_pnl: f64,
-        pub max_drawdown: f64,
+        fn active_connections(&self) -> u32 {
+            self.subscribers.active()
+        }
     }
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
-    pub struct RiskRule {
-        pub name: String,
-        pub description: String,
-        pub condition: String,
+    #[serde(default)]
+    pub struct SimulatorConfig {
+        // Mid prices the synthetic books start from, by symbol
+        pub reference_prices: HashMap<String, f64>,
+        // JSON lines of OrderBookSnapshot, replayed in a loop in place of the synthetic walk
+        pub recorded_books: Option<String>,
+        pub spread_bps: f64,
+        pub depth_levels: usize,
+        pub level_quantity: f64,
+        // Largest move of a synthetic mid per tick
+        pub volatility_bps: f64,
+        pub tick_interval_ms: u64,
+        pub latency_ms: u64,
+        pub latency_jitter_ms: u64,
+        pub reject_probability: f64,
+        // Chance a match only finds part of the liquidity on the book
+        pub partial_fill_probability: f64,
+        // Largest adverse move of a fill away from the book price
+        pub slippage_bps: f64,
+        pub seed: Option<u64>,
     }
 
-    #[derive(Debug, Clone, Serialize, Deserialize)]
-    pub struct ComplianceCheck {
-        pub name: String,
-        pub description: String,
-        pub validation: String,
-    }
+    impl Default for SimulatorConfig {
+        fn default() -> Self {
+            let mut reference_prices = HashMap::new();
+            reference_prices.insert("BTC/USD".to_string(), 50000.0);
+            reference_prices.insert("ETH/USD".to_string(), 3000.0);
+            Self {
+                reference_prices,
+                recorded_books: None,
+                spread_bps: 2.0,
+                depth_levels: 10,
+                level_quantity: 1.0,
+                volatility_bps: 5.0,
+                tick_interval_ms: 500,
+                latency_ms: 20,
+                latency_jitter_ms: 10,
+                reject_probability: 0.01,
+                partial_fill_probability: 0.2,
+                slippage_bps: 1.0,
+                seed: None,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct SimulatedBook {
+        mid: f64,
+        bids: Vec<(f64, f64)>,
+        asks: Vec<(f64, f64)>,
+    }
+
+    impl SimulatedBook {
+        fn synthetic(mid: f64, config: &SimulatorConfig) -> Self {
+            let half_spread = mid * config.spread_bps / 20_000.0;
+            let tick = (mid * 0.0001).max(f64::EPSILON);
+            let bids = (0..config.depth_levels)
+                .map(|i| (mid - half_spread - tick * i as f64, config.level_quantity))
+                .collect();
+            let asks = (0..config.depth_levels)
+                .map(|i| (mid + half_spread + tick * i as f64, config.level_quantity))
+                .collect();
+            Self { mid, bids, asks }
+        }
+
+        fn recorded(snapshot: &OrderBookSnapshot) -> Self {
+            let mut bids = snapshot.bids.clone();
+            let mut asks = snapshot.asks.clone();
+            bids.sort_by(|a, b| b.0.total_cmp(&a.0));
+            asks.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let mid = match (bids.first(), asks.first()) {
+                (Some(bid), Some(ask)) => (bid.0 + ask.0) / 2.0,
+                (Some(level), None) | (None, Some(level)) => level.0,
+                (None, None) => 0.0,
+            };
+            Self { mid, bids, asks }
+        }
+
+        fn snapshot(&self, symbol: &str) -> OrderBookSnapshot {
+            OrderBookSnapshot {
+                symbol: symbol.to_string(),
+                bids: self.bids.clone(),
+                asks: self.asks.clone(),
+                timestamp: Utc::now(),
+            }
+        }
+    }
+
+    struct SimulatedOrder {
+        order: Order,
+        execution: OrderExecution,
+        remaining: f64,
+    }
+
+    struct SimulatorState {
+        rng: StdRng,
+        books: HashMap<String, SimulatedBook>,
+        orders: HashMap<String, SimulatedOrder>,
+        recorded: Vec<OrderBookSnapshot>,
+        replay_index: usize,
+    }
+
+    impl SimulatorState {
+        // Liquidity an order could take right now without moving past its limit
+        fn fillable(&self, order: &Order) -> f64 {
+            let book = match self.books.get(&order.symbol) {
+                Some(book) => book,
+                None => return 0.0,
+            };
+            let is_buy = order.side.eq_ignore_ascii_case("buy");
+            let levels = if is_buy { &book.asks } else { &book.bids };
+            levels.iter()
+                .take_while(|(price, _)| crosses(order, is_buy, *price))
+                .map(|(_, quantity)| quantity)
+                .sum()
+        }
+
+        // Takes liquidity for an open order, returning the fills it got
+        fn match_order(&mut self, order_id: &str, config: &SimulatorConfig) -> Vec<FillReport> {
+            let SimulatorState { rng, books, orders, .. } = self;
+            let sim = match orders.get_mut(order_id) {
+                Some(sim) if sim.execution.status.is_open() => sim,
+                _ => return Vec::new(),
+            };
+            let book = match books.get_mut(&sim.order.symbol) {
+                Some(book) => book,
+                None => return Vec::new(),
+            };
+            let is_buy = sim.order.side.eq_ignore_ascii_case("buy");
+            let levels = if is_buy { &mut book.asks } else { &mut book.bids };
+
+            let mut wanted = sim.remaining;
+            if rng.gen_bool(config.partial_fill_probability.clamp(0.0, 1.0)) {
+                wanted *= rng.gen_range(0.1..1.0);
+            }
+
+            let mut fills = Vec::new();
+            let now = Utc::now();
+            for level in levels.iter_mut() {
+                if wanted <= f64::EPSILON || !crosses(&sim.order, is_buy, level.0) {
+                    break;
+                }
+                let quantity = level.1.min(wanted);
+                let slippage = rng.gen_range(0.0..=config.slippage_bps.max(0.0)) / 10_000.0;
+                let mut price = if is_buy { level.0 * (1.0 + slippage) } else { level.0 * (1.0 - slippage) };
+                // Slippage never takes a limit order through its price
+                if !is_market(&sim.order) {
+                    price = if is_buy { price.min(sim.order.price) } else { price.max(sim.order.price) };
+                }
+
+                level.1 -= quantity;
+                wanted -= quantity;
+                sim.remaining -= quantity;
+                let filled = sim.execution.quantity + quantity;
+                sim.execution.price = (sim.execution.price * sim.execution.quantity + price * quantity) / filled;
+                sim.execution.quantity = filled;
+                sim.execution.timestamp = now;
+                fills.push(FillReport {
+                    fill_id: uuid::Uuid::new_v4().to_string(),
+                    order_id: sim.order.order_id.clone(),
+                    symbol: sim.order.symbol.clone(),
+                    side: sim.order.side.clone(),
+                    price,
+                    quantity,
+                    timestamp: now,
+                });
+            }
+            levels.retain(|(_, quantity)| *quantity > f64::EPSILON);
+
+            sim.execution.status = if sim.remaining <= f64::EPSILON {
+                OrderStatus::Filled
+            } else if sim.execution.quantity > 0.0 {
+                OrderStatus::Partial
+            } else {
+                OrderStatus::New
+            };
+            fills
+        }
+
+        // Moves every book one step and lets resting orders trade against the result
+        fn tick(&mut self, config: &SimulatorConfig) -> (Vec<FillReport>, Vec<OrderBookSnapshot>) {
+            let changed: Vec<String> = if self.recorded.is_empty() {
+                let symbols: Vec<String> = self.books.keys().cloned().collect();
+                for symbol in &symbols {
+                    let step = config.volatility_bps.max(0.0) / 10_000.0;
+                    let shock = if step > 0.0 { self.rng.gen_range(-step..=step) } else { 0.0 };
+                    let mid = self.books[symbol].mid * (1.0 + shock);
+                    self.books.insert(symbol.clone(), SimulatedBook::synthetic(mid, config));
+                }
+                symbols
+            } else {
+                let snapshot = self.recorded[self.replay_index % self.recorded.len()].clone();
+                self.replay_index += 1;
+                self.books.insert(snapshot.symbol.clone(), SimulatedBook::recorded(&snapshot));
+                vec![snapshot.symbol]
+            };
+
+            let resting: Vec<String> = self.orders.iter()
+                .filter(|(_, sim)| sim.execution.status.is_open() && changed.contains(&sim.order.symbol))
+                .map(|(order_id, _)| order_id.clone())
+                .collect();
+            let mut fills = Vec::new();
+            for order_id in resting {
+                fills.extend(self.match_order(&order_id, config));
+            }
+
+            let books = changed.iter()
+                .filter_map(|symbol| self.books.get(symbol).map(|book| book.snapshot(symbol)))
+                .collect();
+            (fills, books)
+        }
+    }
+
+    fn is_market(order: &Order) -> bool {
+        order.order_type.eq_ignore_ascii_case("market")
+    }
+
+    fn crosses(order: &Order, is_buy: bool, level_price: f64) -> bool {
+        is_market(order) || if is_buy { level_price <= order.price } else { level_price >= order.price }
+    }
+
+    // Matches orders against books it keeps itself, so the pipeline runs with no venue
+    pub struct SimulatedConnector {
+        exchange_type: ExchangeType,
+        config: SimulatorConfig,
+        state: Arc<Mutex<SimulatorState>>,
+        subscribers: Arc<Subscribers>,
+    }
+
+    impl SimulatedConnector {
+        // Must be called from within a tokio runtime, which drives the books
+        pub fn new(exchange_type: ExchangeType, config: SimulatorConfig) -> Result<Self, String> {
+            let runtime = tokio::runtime::Handle::try_current()
+                .map_err(|_| "Simulated connector must be created inside a tokio runtime".to_string())?;
+            let mut books: HashMap<String, SimulatedBook> = config.reference_prices.iter()
+                .map(|(symbol, mid)| (symbol.clone(), SimulatedBook::synthetic(*mid, &config)))
+                .collect();
+
+            let mut recorded = Vec::new();
+            if let Some(path) = &config.recorded_books {
+                let contents = std::fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read recorded books {}: {}", path, e))?;
+                for (index, line) in contents.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+                    let snapshot: OrderBookSnapshot = serde_json::from_str(line)
+                        .map_err(|e| format!("Invalid book on line {} of {}: {}", index + 1, path, e))?;
+                    books.entry(snapshot.symbol.clone()).or_insert_with(|| SimulatedBook::recorded(&snapshot));
+                    recorded.push(snapshot);
+                }
+                if recorded.is_empty() {
+                    return Err(format!("No books recorded in {}", path));
+                }
+            }
+
+            let rng = match config.seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            };
+            let connector = Self {
+                exchange_type,
+                config,
+                state: Arc::new(Mutex::new(SimulatorState {
+                    rng,
+                    books,
+                    orders: HashMap::new(),
+                    recorded,
+                    replay_index: 0,
+                })),
+                subscribers: Arc::new(Subscribers::default()),
+            };
+            connector.start_market(&runtime);
+            Ok(connector)
+        }
+
+        // Drives the books until the connector is dropped
+        fn start_market(&self, runtime: &tokio::runtime::Handle) {
+            let state = Arc::downgrade(&self.state);
+            let subscribers = Arc::clone(&self.subscribers);
+            let config = self.config.clone();
+            runtime.spawn(async move {
+                let mut interval = tokio::time::interval(tokio::time::Duration::from_millis(config.tick_interval_ms.max(1)));
+                loop {
+                    interval.tick().await;
+                    let state = match state.upgrade() {
+                        Some(state) => state,
+                        None => break,
+                    };
+                    let (fills, books) = state.lock().unwrap().tick(&config);
+                    subscribers.publish_fills(&fills).await;
+                    for book in books {
+                        let symbol = book.symbol.clone();
+                        subscribers.publish_market_data(&symbol, MarketDataEvent::Book(book)).await;
+                    }
+                }
+            });
+        }
+
+        async fn simulate_latency(&self) {
+            let jitter = if self.config.latency_jitter_ms > 0 {
+                self.state.lock().unwrap().rng.gen_range(0..=self.config.latency_jitter_ms)
+            } else {
+                0
+            };
+            let delay = self.config.latency_ms + jitter;
+            if delay > 0 {
+                tokio::time::sleep(tokio::time::Duration::from_millis(delay)).await;
+            }
+        }
+
+        async fn publish(&self, symbol: &str, fills: Vec<FillReport>) {
+            if fills.is_empty() {
+                return;
+            }
+            let book = self.state.lock().unwrap().books.get(symbol).map(|book| book.snapshot(symbol));
+            self.subscribers.publish_fills(&fills).await;
+            for fill in &fills {
+                let trade = MarketDataEvent::Trade(TradeEvent {
+                    symbol: fill.symbol.clone(),
+                    price: fill.price,
+                    quantity: fill.quantity,
+                    side: fill.side.clone(),
+                    timestamp: fill.timestamp,
+                });
+                self.subscribers.publish_market_data(symbol, trade).await;
+            }
+            if let Some(book) = book {
+                self.subscribers.publish_market_data(symbol, MarketDataEvent::Book(book)).await;
+            }
+        }
+
+        fn open_order(state: &SimulatorState, order_id: &str) -> Result<(), String> {
+            match state.orders.get(order_id) {
+                Some(sim) if sim.execution.status.is_open() => Ok(()),
+                Some(sim) => Err(format!("Order {} is {:?}", order_id, sim.execution.status)),
+                None => Err(format!("Unknown order: {}", order_id)),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ExchangeConnector for SimulatedConnector {
+        fn exchange_type(&self) -> ExchangeType {
+            self.exchange_type.clone()
+        }
+
+        async fn place_order(&self, order: &Order) -> Result<OrderExecution, String> {
+            self.simulate_latency().await;
+
+            let (execution, fills) = {
+                let mut state = self.state.lock().unwrap();
+                let mut execution = OrderExecution {
+                    order_id: order.order_id.clone(),
+                    exchange_order_id: uuid::Uuid::new_v4().to_string(),
+                    price: 0.0,
+                    quantity: 0.0,
+                    status: OrderStatus::New,
+                    reason: None,
+                    timestamp: Utc::now(),
+                };
+
+                let reject = if state.orders.contains_key(&order.order_id) {
+                    Some("duplicate order id")
+                } else if !state.books.contains_key(&order.symbol) {
+                    Some("unknown symbol")
+                } else if order.quantity <= 0.0 || (!is_market(order) && order.price <= 0.0) {
+                    Some("invalid price or quantity")
+                } else if state.rng.gen_bool(self.config.reject_probability.clamp(0.0, 1.0)) {
+                    Some("simulated reject")
+                } else if order.time_in_force.eq_ignore_ascii_case("FOK") && state.fillable(order) < order.quantity {
+                    Some("fill or kill not fillable")
+                } else {
+                    None
+                };
+                if let Some(reason) = reject {
+                    execution.status = OrderStatus::Rejected;
+                    execution.reason = Some(reason.to_string());
+                    return Ok(execution);
+                }
+
+                state.orders.insert(order.order_id.clone(), SimulatedOrder {
+                    order: order.clone(),
+                    execution,
+                    remaining: order.quantity,
+                });
+                let fills = state.match_order(&order.order_id, &self.config);
+
+                // Whatever an IOC or market order could not take is gone
+                let sim = state.orders.get_mut(&order.order_id).unwrap();
+                let immediate = is_market(order) || order.time_in_force.eq_ignore_ascii_case("IOC")
+                    || order.time_in_force.eq_ignore_ascii_case("FOK");
+                if immediate && sim.execution.status.is_open() {
+                    sim.execution.status = OrderStatus::Cancelled;
+                    sim.remaining = 0.0;
+                }
+                (sim.execution.clone(), fills)
+            };
+
+            self.publish(&order.symbol, fills).await;
+            Ok(execution)
+        }
+
+        async fn cancel_order(&self, _symbol: &str, order_id: &str) -> Result<OrderExecution, String> {
+            self.simulate_latency().await;
+
+            let mut state = self.state.lock().unwrap();
+            Self::open_order(&state, order_id)?;
+            let sim = state.orders.get_mut(order_id).unwrap();
+            sim.execution.status = OrderStatus::Cancelled;
+            sim.execution.timestamp = Utc::now();
+            sim.remaining = 0.0;
+            Ok(sim.execution.clone())
+        }
+
+        async fn amend_order(&self, symbol: &str, order_id: &str, price: Option<f64>, quantity: Option<f64>) -> Result<OrderExecution, String> {
+            self.simulate_latency().await;
+
+            let (execution, fills) = {
+                let mut state = self.state.lock().unwrap();
+                Self::open_order(&state, order_id)?;
+                let sim = state.orders.get_mut(order_id).unwrap();
+                if let Some(quantity) = quantity {
+                    if quantity <= sim.execution.quantity {
+                        return Err(format!("Order {} already filled {}", order_id, sim.execution.quantity));
+                    }
+                    sim.remaining = quantity - sim.execution.quantity;
+                    sim.order.quantity = quantity;
+                }
+                if let Some(price) = price {
+                    if price <= 0.0 {
+                        return Err(format!("Invalid price: {}", price));
+                    }
+                    sim.order.price = price;
+                }
+
+                // A new price may now cross the book
+                let fills = state.match_order(order_id, &self.config);
+                (state.orders[order_id].execution.clone(), fills)
+            };
+
+            self.publish(symbol, fills).await;
+            Ok(execution)
+        }
+
+        async fn query_order(&self, _symbol: &str, order_id: &str) -> Result<OrderExecution, String> {
+            self.simulate_latency().await;
+
+            self.state.lock().unwrap().orders
+                .get(order_id)
+                .map(|sim| sim.execution.clone())
+                .ok_or_else(|| format!("Unknown order: {}", order_id))
+        }
+
+        async fn stream_fills(&self) -> Result<mpsc::Receiver<FillReport>, String> {
+            Ok(self.subscribers.subscribe_fills())
+        }
+
+        async fn stream_market_data(&self, symbols: &[String]) -> Result<mpsc::Receiver<MarketDataEvent>, String> {
+            let (tx, rx) = self.subscribers.subscribe_market_data(symbols);
+
+            // New subscribers start from the current books
+            let books: Vec<_> = {
+                let state = self.state.lock().unwrap();
+                symbols.iter()
+                    .filter_map(|symbol| state.books.get(symbol).map(|book| book.snapshot(symbol)))
+                    .collect()
+            };
+            for book in books {
+                let _ = tx.try_send(MarketDataEvent::Book(book));
+            }
+            Ok(rx)
+        }
+
+        fn active_connections(&self) -> u32 {
+            self.subscribers.active()
+        }
+    }
+
+    // Binance spot: signed REST for orders, the listen-key user data stream for
+    // executions and combined depth/trade streams for market data
+    pub mod binance {
+        use super::*;
+        use crate::rate_limiter::RateLimiter;
+        use futures_util::{SinkExt, StreamExt};
+        use hmac::{Hmac, Mac};
+        use reqwest::{Method, StatusCode, Url};
+        use serde::de::DeserializeOwned;
+        use std::sync::atomic::{AtomicU32, Ordering};
+        use tokio::time::Duration;
+        use tokio_tungstenite::tungstenite::Message;
+        use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+        const DEFAULT_ENDPOINT: &str = "https://api.binance.com";
+        const DEFAULT_WS_ENDPOINT: &str = "wss://stream.binance.com:9443";
+
+        type Socket = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+        #[derive(Debug, Clone, Serialize, Deserialize)]
+        #[serde(default)]
+        pub struct BinanceConfig {
+            // How long after its timestamp Binance still accepts a signed request
+            pub recv_window_ms: u64,
+            // Levels per partial book update: 5, 10 or 20
+            pub depth_levels: u32,
+            // Listen keys expire after an hour without a keepalive
+            pub keepalive_secs: u64,
+        }
+
+        impl Default for BinanceConfig {
+            fn default() -> Self {
+                Self {
+                    recv_window_ms: 5000,
+                    depth_levels: 20,
+                    keepalive_secs: 1800,
+                }
+            }
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct ApiError {
+            code: i64,
+            msg: String,
+        }
+
+        enum RestError {
+            // The request never got a definite answer
+            Transport(String),
+            // Binance looked at the request and refused it
+            Venue(ApiError),
+        }
+
+        impl std::fmt::Display for RestError {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    RestError::Transport(message) => write!(f, "{}", message),
+                    RestError::Venue(e) => write!(f, "{} ({})", e.msg, e.code),
+                }
+            }
+        }
+
+        #[derive(Debug, Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct OrderResponse {
+            order_id: u64,
+            #[serde(default)]
+            price: String,
+            #[serde(default)]
+            orig_qty: String,
+            executed_qty: String,
+            cummulative_quote_qty: String,
+            status: String,
+            #[serde(default)]
+            time_in_force: String,
+            #[serde(rename = "type", default)]
+            order_type: String,
+            #[serde(default)]
+            side: String,
+            #[serde(default)]
+            transact_time: Option<i64>,
+            #[serde(default)]
+            update_time: Option<i64>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct CancelReplaceResponse {
+            cancel_response: OrderResponse,
+            new_order_response: OrderResponse,
+        }
+
+        #[derive(Debug, Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct ListenKey {
+            listen_key: String,
+        }
+
+        // executionReport event on the user data stream
+        #[derive(Debug, Deserialize)]
+        struct ExecutionReport {
+            #[serde(rename = "s")]
+            symbol: String,
+            #[serde(rename = "c")]
+            client_order_id: String,
+            #[serde(rename = "S")]
+            side: String,
+            #[serde(rename = "x")]
+            execution_type: String,
+            #[serde(rename = "l")]
+            last_quantity: String,
+            #[serde(rename = "L")]
+            last_price: String,
+            #[serde(rename = "T")]
+            trade_time: i64,
+            #[serde(rename = "t")]
+            trade_id: i64,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct CombinedEvent {
+            stream: String,
+            data: serde_json::Value,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct PartialDepth {
+            bids: Vec<(String, String)>,
+            asks: Vec<(String, String)>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Trade {
+            #[serde(rename = "p")]
+            price: String,
+            #[serde(rename = "q")]
+            quantity: String,
+            #[serde(rename = "T")]
+            trade_time: i64,
+            #[serde(rename = "m")]
+            buyer_is_maker: bool,
+        }
+
+        struct RestClient {
+            http: reqwest::Client,
+            endpoint: String,
+            api_key: String,
+            api_secret: String,
+            recv_window_ms: u64,
+            limiter: Option<Mutex<RateLimiter>>,
+        }
+
+        impl RestClient {
+            fn sign(&self, payload: &str) -> String {
+                let mut mac = Hmac::<sha2::Sha256>::new_from_slice(self.api_secret.as_bytes())
+                    .expect("HMAC accepts keys of any length");
+                mac.update(payload.as_bytes());
+                hex::encode(mac.finalize().into_bytes())
+            }
+
+            fn url(&self, path: &str, params: &[(&str, String)]) -> Result<Url, RestError> {
+                let mut url = Url::parse(&format!("{}{}", self.endpoint, path))
+                    .map_err(|e| RestError::Transport(format!("Invalid endpoint {}: {}", self.endpoint, e)))?;
+                if !params.is_empty() {
+                    url.query_pairs_mut().extend_pairs(params.iter().map(|(k, v)| (*k, v.as_str())));
+                }
+                Ok(url)
+            }
+
+            // TRADE and USER_DATA endpoints: the signature covers the query exactly as sent
+            async fn signed<T: DeserializeOwned>(&self, method: Method, path: &str, params: &[(&str, String)]) -> Result<T, RestError> {
+                let mut params = params.to_vec();
+                params.push(("recvWindow", self.recv_window_ms.to_string()));
+                params.push(("timestamp", Utc::now().timestamp_millis().to_string()));
+                let mut url = self.url(path, &params)?;
+                let signature = self.sign(url.query().unwrap_or(""));
+                url.query_pairs_mut().append_pair("signature", &signature);
+                self.send(method, url).await
+            }
+
+            // USER_STREAM endpoints only need the API key
+            async fn keyed<T: DeserializeOwned>(&self, method: Method, path: &str, params: &[(&str, String)]) -> Result<T, RestError> {
+                let url = self.url(path, params)?;
+                self.send(method, url).await
+            }
+
+            async fn send<T: DeserializeOwned>(&self, method: Method, url: Url) -> Result<T, RestError> {
+                if let Some(limiter) = &self.limiter {
+                    limiter.lock().unwrap().check()
+                        .map_err(|_| RestError::Transport("Rate limit exceeded".to_string()))?;
+                }
+
+                let response = self.http.request(method, url)
+                    .header("X-MBX-APIKEY", &self.api_key)
+                    .send()
+                    .await
+                    .map_err(|e| RestError::Transport(format!("Request failed: {}", e)))?;
+                let status = response.status();
+                let body = response.text()
+                    .await
+                    .map_err(|e| RestError::Transport(format!("Failed to read response: {}", e)))?;
+
+                if status.is_success() {
+                    return serde_json::from_str(&body)
+                        .map_err(|e| RestError::Transport(format!("Unexpected response: {}", e)));
+                }
+                // Throttling and 5xx leave the outcome unknown, so they are not rejections
+                let throttled = status == StatusCode::TOO_MANY_REQUESTS || status.as_u16() == 418;
+                match serde_json::from_str::<ApiError>(&body) {
+                    Ok(e) if status.is_client_error() && !throttled => Err(RestError::Venue(e)),
+                    _ => Err(RestError::Transport(format!("HTTP {}: {}", status, body))),
+                }
+            }
+        }
+
+        pub struct BinanceConnector {
+            rest: Arc<RestClient>,
+            ws_endpoint: String,
+            config: BinanceConfig,
+            symbol_map: HashMap<String, String>,
+            // Fills and quote of the orders amend_order replaced, by our order id
+            carried: Mutex<HashMap<String, (f64, f64)>>,
+            connections: Arc<AtomicU32>,
+        }
+
+        impl BinanceConnector {
+            pub fn new(config: &ConnectorConfig) -> Result<Self, String> {
+                if ![5, 10, 20].contains(&config.binance.depth_levels) {
+                    return Err(format!("Binance depth must be 5, 10 or 20, not {}", config.binance.depth_levels));
+                }
+                let http = reqwest::Client::builder()
+                    .timeout(Duration::from_secs(10))
+                    .build()
+                    .map_err(|e| format!("Failed to build HTTP client: {}", e))?;
+                let endpoint = if config.endpoint.is_empty() { DEFAULT_ENDPOINT } else { &config.endpoint };
+                let ws_endpoint = if config.ws_endpoint.is_empty() { DEFAULT_WS_ENDPOINT } else { &config.ws_endpoint };
+
+                Ok(Self {
+                    rest: Arc::new(RestClient {
+                        http,
+                        endpoint: endpoint.trim_end_matches('/').to_string(),
+                        api_key: config.api_key.clone(),
+                        api_secret: config.api_secret.clone(),
+                        recv_window_ms: config.binance.recv_window_ms,
+                        limiter: (config.rate_limit > 0).then(|| Mutex::new(RateLimiter::new(config.rate_limit))),
+                    }),
+                    ws_endpoint: ws_endpoint.trim_end_matches('/').to_string(),
+                    config: config.binance.clone(),
+                    symbol_map: config.symbol_map.clone(),
+                    carried: Mutex::new(HashMap::new()),
+                    connections: Arc::new(AtomicU32::new(0)),
+                })
+            }
+
+            fn venue_symbol(&self, symbol: &str) -> String {
+                venue_symbol(&self.symbol_map, symbol)
+            }
+
+            fn to_execution(&self, order_id: &str, response: &OrderResponse) -> OrderExecution {
+                let (carried_quantity, carried_quote) = self.carried.lock().unwrap()
+                    .get(order_id)
+                    .copied()
+                    .unwrap_or((0.0, 0.0));
+                let quantity = number(&response.executed_qty) + carried_quantity;
+                let quote = number(&response.cummulative_quote_qty) + carried_quote;
+                OrderExecution {
+                    order_id: order_id.to_string(),
+                    exchange_order_id: response.order_id.to_string(),
+                    price: if quantity > 0.0 { quote / quantity } else { 0.0 },
+                    quantity,
+                    status: order_status(&response.status),
+                    reason: None,
+                    timestamp: timestamp(response.update_time.or(response.transact_time)),
+                }
+            }
+
+            fn spawn_stream<T, F>(&self, socket: Socket, tx: mpsc::Sender<T>, parse: F, keepalive: Option<String>)
+            where
+                T: Send + 'static,
+                F: Fn(&str) -> Vec<T> + Send + 'static,
+            {
+                let rest = Arc::clone(&self.rest);
+                let connections = Arc::clone(&self.connections);
+                let keepalive_every = Duration::from_secs(self.config.keepalive_secs.max(1));
+                connections.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let mut socket = socket;
+                    let mut keepalive_timer = tokio::time::interval_at(tokio::time::Instant::now() + keepalive_every, keepalive_every);
+                    loop {
+                        tokio::select! {
+                            message = socket.next() => {
+                                let text = match message {
+                                    Some(Ok(Message::Text(text))) => text,
+                                    Some(Ok(Message::Ping(payload))) => {
+                                        let _ = socket.send(Message::Pong(payload)).await;
+                                        continue;
+                                    }
+                                    Some(Ok(Message::Close(_))) | None => break,
+                                    Some(Ok(_)) => continue,
+                                    Some(Err(e)) => {
+                                        tracing::warn!("Binance stream error: {}", e);
+                                        break;
+                                    }
+                                };
+                                for item in parse(&text) {
+                                    if tx.send(item).await.is_err() {
+                                        break;
+                                    }
+                                }
+                            }
+                            _ = keepalive_timer.tick(), if keepalive.is_some() => {
+                                let params = [("listenKey", keepalive.clone().unwrap_or_default())];
+                                if let Err(e) = rest.keyed::<serde_json::Value>(Method::PUT, "/api/v3/userDataStream", &params).await {
+                                    tracing::warn!("Failed to keep Binance listen key alive: {}", e);
+                                }
+                            }
+                            _ = tx.closed() => break,
+                        }
+                    }
+                    connections.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        }
+
+        #[async_trait]
+        impl ExchangeConnector for BinanceConnector {
+            fn exchange_type(&self) -> ExchangeType {
+                ExchangeType::Binance
+            }
+
+            async fn place_order(&self, order: &Order) -> Result<OrderExecution, String> {
+                let order_type = order.order_type.to_uppercase();
+                let mut params = vec![
+                    ("symbol", self.venue_symbol(&order.symbol)),
+                    ("side", order.side.to_uppercase()),
+                    ("type", order_type.clone()),
+                    ("quantity", decimal(order.quantity)),
+                ];
+                match order_type.as_str() {
+                    "MARKET" => {}
+                    "LIMIT" => {
+                        let time_in_force = if order.time_in_force.is_empty() { "GTC".to_string() } else { order.time_in_force.to_uppercase() };
+                        params.push(("timeInForce", time_in_force));
+                        params.push(("price", decimal(order.price)));
+                    }
+                    "LIMIT_MAKER" => params.push(("price", decimal(order.price))),
+                    other => return Ok(rejected(order, format!("Unsupported order type: {}", other))),
+                }
+                params.push(("newClientOrderId", order.order_id.clone()));
+                params.push(("newOrderRespType", "RESULT".to_string()));
+
+                match self.rest.signed::<OrderResponse>(Method::POST, "/api/v3/order", &params).await {
+                    Ok(response) => Ok(self.to_execution(&order.order_id, &response)),
+                    Err(RestError::Venue(e)) => Ok(rejected(order, format!("{} ({})", e.msg, e.code))),
+                    Err(e) => Err(e.to_string()),
+                }
+            }
+
+            async fn cancel_order(&self, symbol: &str, order_id: &str) -> Result<OrderExecution, String> {
+                let params = [("symbol", self.venue_symbol(symbol)), ("origClientOrderId", order_id.to_string())];
+                let response: OrderResponse = self.rest.signed(Method::DELETE, "/api/v3/order", &params)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                Ok(self.to_execution(order_id, &response))
+            }
+
+            async fn amend_order(&self, symbol: &str, order_id: &str, price: Option<f64>, quantity: Option<f64>) -> Result<OrderExecution, String> {
+                let venue_symbol = self.venue_symbol(symbol);
+                let lookup = [("symbol", venue_symbol.clone()), ("origClientOrderId", order_id.to_string())];
+                let current: OrderResponse = self.rest.signed(Method::GET, "/api/v3/order", &lookup)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                if !order_status(&current.status).is_open() {
+                    return Err(format!("Order {} is {}", order_id, current.status));
+                }
+
+                // Spot has no in-place amend, so cancel and re-place under the same client id
+                let carried = self.carried.lock().unwrap().get(order_id).map(|(filled, _)| *filled).unwrap_or(0.0);
+                let filled = carried + number(&current.executed_qty);
+                let total = quantity.unwrap_or(carried + number(&current.orig_qty));
+                if total <= filled {
+                    return Err(format!("Order {} already filled {}", order_id, filled));
+                }
+                let mut params = vec![
+                    ("symbol", venue_symbol),
+                    ("side", current.side.clone()),
+                    ("type", current.order_type.clone()),
+                    ("cancelReplaceMode", "STOP_ON_FAILURE".to_string()),
+                    ("cancelOrigClientOrderId", order_id.to_string()),
+                    ("newClientOrderId", order_id.to_string()),
+                    ("quantity", decimal(total - filled)),
+                    ("newOrderRespType", "RESULT".to_string()),
+                ];
+                if current.order_type != "MARKET" {
+                    params.push(("price", decimal(price.unwrap_or_else(|| number(&current.price)))));
+                }
+                if current.order_type == "LIMIT" {
+                    params.push(("timeInForce", current.time_in_force.clone()));
+                }
+
+                let response: CancelReplaceResponse = self.rest.signed(Method::POST, "/api/v3/order/cancelReplace", &params)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                {
+                    // What the replaced order filled now counts towards the new one
+                    let mut carried = self.carried.lock().unwrap();
+                    let entry = carried.entry(order_id.to_string()).or_insert((0.0, 0.0));
+                    entry.0 += number(&response.cancel_response.executed_qty);
+                    entry.1 += number(&response.cancel_response.cummulative_quote_qty);
+                }
+                Ok(self.to_execution(order_id, &response.new_order_response))
+            }
+
+            async fn query_order(&self, symbol: &str, order_id: &str) -> Result<OrderExecution, String> {
+                let params = [("symbol", self.venue_symbol(symbol)), ("origClientOrderId", order_id.to_string())];
+                let response: OrderResponse = self.rest.signed(Method::GET, "/api/v3/order", &params)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                Ok(self.to_execution(order_id, &response))
+            }
+
+            async fn stream_fills(&self) -> Result<mpsc::Receiver<FillReport>, String> {
+                let key: ListenKey = self.rest.keyed(Method::POST, "/api/v3/userDataStream", &[])
+                    .await
+                    .map_err(|e| format!("Failed to open user data stream: {}", e))?;
+                let url = format!("{}/ws/{}", self.ws_endpoint, key.listen_key);
+                let (socket, _) = tokio_tungstenite::connect_async(url)
+                    .await
+                    .map_err(|e| format!("Failed to connect user data stream: {}", e))?;
+
+                let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+                let symbol_map = self.symbol_map.clone();
+                self.spawn_stream(socket, tx, move |text| parse_fill(&symbol_map, text).into_iter().collect(), Some(key.listen_key));
+                Ok(rx)
+            }
+
+            async fn stream_market_data(&self, symbols: &[String]) -> Result<mpsc::Receiver<MarketDataEvent>, String> {
+                // Stream names are lowercase venue symbols; events go back out under the caller's symbols
+                let by_stream: HashMap<String, String> = symbols.iter()
+                    .map(|symbol| (self.venue_symbol(symbol).to_lowercase(), symbol.clone()))
+                    .collect();
+                let streams: Vec<String> = by_stream.keys()
+                    .flat_map(|name| [format!("{}@depth{}@100ms", name, self.config.depth_levels), format!("{}@trade", name)])
+                    .collect();
+                let url = format!("{}/stream?streams={}", self.ws_endpoint, streams.join("/"));
+                let (socket, _) = tokio_tungstenite::connect_async(url)
+                    .await
+                    .map_err(|e| format!("Failed to connect market data stream: {}", e))?;
+
+                let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+                self.spawn_stream(socket, tx, move |text| parse_market_data(&by_stream, text).into_iter().collect(), None);
+                Ok(rx)
+            }
+
+            fn active_connections(&self) -> u32 {
+                self.connections.load(Ordering::SeqCst)
+            }
+        }
+
+        fn parse_fill(symbol_map: &HashMap<String, String>, text: &str) -> Option<FillReport> {
+            let value: serde_json::Value = serde_json::from_str(text).ok()?;
+            // Balance and account updates share the stream
+            if value.get("e").and_then(|e| e.as_str()) != Some("executionReport") {
+                return None;
+            }
+            let report: ExecutionReport = match serde_json::from_value(value) {
+                Ok(report) => report,
+                Err(e) => {
+                    tracing::warn!("Invalid Binance execution report: {}", e);
+                    return None;
+                }
+            };
+            if report.execution_type != "TRADE" {
+                return None;
+            }
+            Some(FillReport {
+                fill_id: format!("{}-{}", report.symbol, report.trade_id),
+                order_id: report.client_order_id,
+                symbol: our_symbol(symbol_map, &report.symbol),
+                side: report.side.to_lowercase(),
+                price: number(&report.last_price),
+                quantity: number(&report.last_quantity),
+                timestamp: timestamp(Some(report.trade_time)),
+            })
+        }
+
+        fn parse_market_data(by_stream: &HashMap<String, String>, text: &str) -> Option<MarketDataEvent> {
+            let event: CombinedEvent = serde_json::from_str(text).ok()?;
+            let (name, kind) = event.stream.split_once('@')?;
+            let symbol = by_stream.get(name)?.clone();
+            if kind.starts_with("depth") {
+                let depth: PartialDepth = serde_json::from_value(event.data).ok()?;
+                let levels = |side: Vec<(String, String)>| side.iter().map(|(price, quantity)| (number(price), number(quantity))).collect();
+                Some(MarketDataEvent::Book(OrderBookSnapshot {
+                    symbol,
+                    bids: levels(depth.bids),
+                    asks: levels(depth.asks),
+                    timestamp: Utc::now(),
+                }))
+            } else if kind == "trade" {
+                let trade: Trade = serde_json::from_value(event.data).ok()?;
+                Some(MarketDataEvent::Trade(TradeEvent {
+                    symbol,
+                    price: number(&trade.price),
+                    quantity: number(&trade.quantity),
+                    // The aggressor is whoever was not the maker
+                    side: if trade.buyer_is_maker { "sell" } else { "buy" }.to_string(),
+                    timestamp: timestamp(Some(trade.trade_time)),
+                }))
+            } else {
+                None
+            }
+        }
+
+        fn venue_symbol(symbol_map: &HashMap<String, String>, symbol: &str) -> String {
+            symbol_map.get(symbol)
+                .cloned()
+                .unwrap_or_else(|| symbol.replace(['/', '-'], "").to_uppercase())
+        }
+
+        fn our_symbol(symbol_map: &HashMap<String, String>, venue_symbol: &str) -> String {
+            symbol_map.iter()
+                .find(|(_, venue)| venue.as_str() == venue_symbol)
+                .map(|(ours, _)| ours.clone())
+                .unwrap_or_else(|| venue_symbol.to_string())
+        }
+
+        fn order_status(status: &str) -> OrderStatus {
+            match status {
+                "NEW" | "PENDING_NEW" => OrderStatus::New,
+                "PARTIALLY_FILLED" => OrderStatus::Partial,
+                "FILLED" => OrderStatus::Filled,
+                "REJECTED" => OrderStatus::Rejected,
+                // CANCELED, EXPIRED and EXPIRED_IN_MATCH all end the order unfilled
+                _ => OrderStatus::Cancelled,
+            }
+        }
+
+        fn rejected(order: &Order, reason: String) -> OrderExecution {
+            OrderExecution {
+                order_id: order.order_id.clone(),
+                exchange_order_id: String::new(),
+                price: 0.0,
+                quantity: 0.0,
+                status: OrderStatus::Rejected,
+                reason: Some(reason),
+                timestamp: Utc::now(),
+            }
+        }
+
+        // Binance sends every decimal as a string
+        fn number(value: &str) -> f64 {
+            value.parse().unwrap_or(0.0)
+        }
+
+        fn decimal(value: f64) -> String {
+            let formatted = format!("{:.8}", value);
+            formatted.trim_end_matches('0').trim_end_matches('.').to_string()
+        }
+
+        fn timestamp(millis: Option<i64>) -> DateTime<Utc> {
+            millis.and_then(DateTime::from_timestamp_millis).unwrap_or_else(Utc::now)
+        }
+    }
+
+    // Coinbase Advanced Trade: JWT or HMAC authenticated REST for orders, the
+    // user channel for executions and level2/market_trades for market data
+    pub mod coinbase {
+        use super::*;
+        use crate::rate_limiter::RateLimiter;
+        use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        use base64::Engine;
+        use futures_util::{SinkExt, StreamExt};
+        use hmac::{Hmac, Mac};
+        use p256::ecdsa::signature::Signer;
+        use p256::ecdsa::{Signature, SigningKey};
+        use p256::pkcs8::DecodePrivateKey;
+        use reqwest::{Method, RequestBuilder, StatusCode, Url};
+        use serde::de::DeserializeOwned;
+        use serde_json::json;
+        use std::collections::{BTreeMap, VecDeque};
+        use std::sync::atomic::{AtomicU32, Ordering};
+        use tokio::time::Duration;
+        use tokio_tungstenite::tungstenite::Message;
+        use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+        const DEFAULT_ENDPOINT: &str = "https://api.coinbase.com";
+        const DEFAULT_WS_ENDPOINT: &str = "wss://advanced-trade-ws.coinbase.com";
+        // Coinbase refuses JWTs that live longer than two minutes
+        const JWT_TTL_SECS: i64 = 120;
+        // Book levels are keyed in 1e-8 ticks so prices order exactly
+        const PRICE_SCALE: f64 = 1e8;
+        // Pages of a product's orders searched for one placed before a restart
+        const MAX_LOOKUP_PAGES: usize = 10;
+        // Finished orders remembered so late updates for them are not fills
+        const MAX_FINISHED_ORDERS: usize = 10_000;
+
+        type Socket = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+        #[derive(Debug, Clone, Serialize, Deserialize)]
+        #[serde(default)]
+        pub struct CoinbaseConfig {
+            // Levels per side of the local book sent with each update
+            pub depth_levels: usize,
+            // Pause before reopening a stream that dropped or skipped a message
+            pub reconnect_delay_ms: u64,
+        }
+
+        impl Default for CoinbaseConfig {
+            fn default() -> Self {
+                Self {
+                    depth_levels: 10,
+                    reconnect_delay_ms: 1000,
+                }
+            }
+        }
+
+        enum Credentials {
+            // CDP keys: a short-lived ES256 JWT per request, named by the key
+            Jwt { key_name: String, signing_key: SigningKey },
+            // Legacy keys: HMAC-SHA256 over timestamp, method, path and body
+            Hmac { api_key: String, api_secret: String },
+        }
+
+        impl Credentials {
+            fn new(api_key: &str, api_secret: &str) -> Result<Self, String> {
+                if !api_secret.contains("-----BEGIN") {
+                    return Ok(Credentials::Hmac {
+                        api_key: api_key.to_string(),
+                        api_secret: api_secret.to_string(),
+                    });
+                }
+                // The portal hands out SEC1 keys, often with the newlines escaped
+                let pem = api_secret.replace("\\n", "\n");
+                let secret = p256::SecretKey::from_sec1_pem(&pem)
+                    .or_else(|_| p256::SecretKey::from_pkcs8_pem(&pem))
+                    .map_err(|_| "Coinbase API secret is not a P-256 private key".to_string())?;
+                Ok(Credentials::Jwt {
+                    key_name: api_key.to_string(),
+                    signing_key: SigningKey::from(&secret),
+                })
+            }
+
+            // REST tokens are bound to "METHOD host/path"; WebSocket tokens carry no uri
+            fn jwt(key_name: &str, signing_key: &SigningKey, uri: Option<String>) -> String {
+                let now = Utc::now().timestamp();
+                let header = json!({
+                    "alg": "ES256",
+                    "typ": "JWT",
+                    "kid": key_name,
+                    "nonce": hex::encode(rand::random::<[u8; 16]>()),
+                });
+                let mut claims = json!({
+                    "sub": key_name,
+                    "iss": "cdp",
+                    "nbf": now,
+                    "exp": now + JWT_TTL_SECS,
+                });
+                if let Some(uri) = uri {
+                    claims["uri"] = json!(uri);
+                }
+                let message = format!("{}.{}", URL_SAFE_NO_PAD.encode(header.to_string()), URL_SAFE_NO_PAD.encode(claims.to_string()));
+                let signature: Signature = signing_key.sign(message.as_bytes());
+                format!("{}.{}", message, URL_SAFE_NO_PAD.encode(signature.to_bytes()))
+            }
+
+            fn authorize(&self, request: RequestBuilder, method: &Method, url: &Url, body: &str) -> RequestBuilder {
+                match self {
+                    Credentials::Jwt { key_name, signing_key } => {
+                        let host = match url.port() {
+                            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+                            None => url.host_str().unwrap_or_default().to_string(),
+                        };
+                        let uri = format!("{} {}{}", method, host, url.path());
+                        request.bearer_auth(Self::jwt(key_name, signing_key, Some(uri)))
+                    }
+                    Credentials::Hmac { api_key, api_secret } => {
+                        let timestamp = Utc::now().timestamp().to_string();
+                        let signature = sign(api_secret, &format!("{}{}{}{}", timestamp, method, url.path(), body));
+                        request
+                            .header("CB-ACCESS-KEY", api_key)
+                            .header("CB-ACCESS-SIGN", signature)
+                            .header("CB-ACCESS-TIMESTAMP", timestamp)
+                    }
+                }
+            }
+
+            fn subscribe(&self, channel: &str, product_ids: &[String]) -> String {
+                let mut message = json!({
+                    "type": "subscribe",
+                    "channel": channel,
+                    "product_ids": product_ids,
+                });
+                match self {
+                    Credentials::Jwt { key_name, signing_key } => {
+                        message["jwt"] = json!(Self::jwt(key_name, signing_key, None));
+                    }
+                    // Public channels need no key at all
+                    Credentials::Hmac { api_key, .. } if api_key.is_empty() => {}
+                    Credentials::Hmac { api_key, api_secret } => {
+                        let timestamp = Utc::now().timestamp().to_string();
+                        message["api_key"] = json!(api_key);
+                        message["signature"] = json!(sign(api_secret, &format!("{}{}{}", timestamp, channel, product_ids.join(","))));
+                        message["timestamp"] = json!(timestamp);
+                    }
+                }
+                message.to_string()
+            }
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct ApiError {
+            #[serde(default)]
+            error: String,
+            #[serde(default)]
+            message: String,
+        }
+
+        enum RestError {
+            // The request never got a definite answer
+            Transport(String),
+            // Coinbase looked at the request and refused it
+            Venue(ApiError),
+        }
+
+        impl std::fmt::Display for RestError {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    RestError::Transport(message) => write!(f, "{}", message),
+                    RestError::Venue(e) => write!(f, "{} ({})", e.message, e.error),
+                }
+            }
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct CreateOrderResponse {
+            success: bool,
+            #[serde(default)]
+            success_response: Option<CreatedOrder>,
+            #[serde(default)]
+            error_response: Option<CreateOrderError>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct CreatedOrder {
+            order_id: String,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct CreateOrderError {
+            #[serde(default)]
+            error: String,
+            #[serde(default)]
+            message: String,
+            #[serde(default)]
+            preview_failure_reason: String,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct CancelResponse {
+            results: Vec<CancelResult>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct CancelResult {
+            success: bool,
+            #[serde(default)]
+            failure_reason: String,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct EditResponse {
+            success: bool,
+            #[serde(default)]
+            errors: Vec<EditError>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct EditError {
+            #[serde(default)]
+            edit_failure_reason: String,
+            #[serde(default)]
+            preview_failure_reason: String,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct OrderEnvelope {
+            order: HistoricalOrder,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct OrderList {
+            orders: Vec<HistoricalOrder>,
+            #[serde(default)]
+            has_next: bool,
+            #[serde(default)]
+            cursor: String,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct HistoricalOrder {
+            order_id: String,
+            #[serde(default)]
+            client_order_id: String,
+            status: String,
+            #[serde(default)]
+            filled_size: String,
+            #[serde(default)]
+            average_filled_price: String,
+            #[serde(default)]
+            order_configuration: serde_json::Value,
+            #[serde(default)]
+            created_time: String,
+            #[serde(default)]
+            last_fill_time: Option<String>,
+        }
+
+        // Every message on an Advanced Trade socket, whatever the channel
+        #[derive(Debug, Deserialize)]
+        struct ChannelMessage {
+            channel: String,
+            sequence_num: u64,
+            #[serde(default)]
+            timestamp: String,
+            #[serde(default)]
+            events: Vec<serde_json::Value>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Level2Event {
+            #[serde(rename = "type")]
+            event_type: String,
+            product_id: String,
+            updates: Vec<Level2Update>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Level2Update {
+            side: String,
+            price_level: String,
+            new_quantity: String,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct MarketTradesEvent {
+            #[serde(rename = "type")]
+            event_type: String,
+            trades: Vec<MarketTrade>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct MarketTrade {
+            product_id: String,
+            price: String,
+            size: String,
+            side: String,
+            time: String,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct UserEvent {
+            #[serde(rename = "type")]
+            event_type: String,
+            orders: Vec<UserOrder>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct UserOrder {
+            order_id: String,
+            client_order_id: String,
+            cumulative_quantity: String,
+            avg_price: String,
+            status: String,
+            product_id: String,
+            order_side: String,
+        }
+
+        struct RestClient {
+            http: reqwest::Client,
+            endpoint: String,
+            credentials: Credentials,
+            limiter: Option<Mutex<RateLimiter>>,
+        }
+
+        impl RestClient {
+            async fn send<T: DeserializeOwned>(&self, method: Method, path: &str, body: Option<serde_json::Value>) -> Result<T, RestError> {
+                if let Some(limiter) = &self.limiter {
+                    limiter.lock().unwrap().check()
+                        .map_err(|_| RestError::Transport("Rate limit exceeded".to_string()))?;
+                }
+
+                let url = Url::parse(&format!("{}{}", self.endpoint, path))
+                    .map_err(|e| RestError::Transport(format!("Invalid endpoint {}: {}", self.endpoint, e)))?;
+                let body = body.map(|body| body.to_string()).unwrap_or_default();
+                let mut request = self.http.request(method.clone(), url.clone());
+                request = self.credentials.authorize(request, &method, &url, &body);
+                if !body.is_empty() {
+                    request = request.header("Content-Type", "application/json").body(body);
+                }
+
+                let response = request.send()
+                    .await
+                    .map_err(|e| RestError::Transport(format!("Request failed: {}", e)))?;
+                let status = response.status();
+                let text = response.text()
+                    .await
+                    .map_err(|e| RestError::Transport(format!("Failed to read response: {}", e)))?;
+
+                if status.is_success() {
+                    return serde_json::from_str(&text)
+                        .map_err(|e| RestError::Transport(format!("Unexpected response: {}", e)));
+                }
+                // Throttling and 5xx leave the outcome unknown, so they are not rejections
+                match serde_json::from_str::<ApiError>(&text) {
+                    Ok(e) if status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS => Err(RestError::Venue(e)),
+                    _ => Err(RestError::Transport(format!("HTTP {}: {}", status, text.trim()))),
+                }
+            }
+        }
+
+        pub struct CoinbaseConnector {
+            rest: Arc<RestClient>,
+            ws_endpoint: String,
+            config: CoinbaseConfig,
+            symbol_map: HashMap<String, String>,
+            // Coinbase's id for each of our orders; cancels and edits go by it
+            order_ids: Mutex<HashMap<String, String>>,
+            // Cumulative quantity and notional already sent as fills, by our order id
+            reported: Arc<Mutex<ReportedFills>>,
+            connections: Arc<AtomicU32>,
+        }
+
+        impl CoinbaseConnector {
+            pub fn new(config: &ConnectorConfig) -> Result<Self, String> {
+                let http = reqwest::Client::builder()
+                    .timeout(Duration::from_secs(10))
+                    .build()
+                    .map_err(|e| format!("Failed to build HTTP client: {}", e))?;
+                let endpoint = if config.endpoint.is_empty() { DEFAULT_ENDPOINT } else { &config.endpoint };
+                let ws_endpoint = if config.ws_endpoint.is_empty() { DEFAULT_WS_ENDPOINT } else { &config.ws_endpoint };
+
+                Ok(Self {
+                    rest: Arc::new(RestClient {
+                        http,
+                        endpoint: endpoint.trim_end_matches('/').to_string(),
+                        credentials: Credentials::new(&config.api_key, &config.api_secret)?,
+                        limiter: (config.rate_limit > 0).then(|| Mutex::new(RateLimiter::new(config.rate_limit))),
+                    }),
+                    ws_endpoint: ws_endpoint.to_string(),
+                    config: config.coinbase.clone(),
+                    symbol_map: config.symbol_map.clone(),
+                    order_ids: Mutex::new(HashMap::new()),
+                    reported: Arc::new(Mutex::new(ReportedFills::default())),
+                    connections: Arc::new(AtomicU32::new(0)),
+                })
+            }
+
+            fn product_id(&self, symbol: &str) -> String {
+                product_id(&self.symbol_map, symbol)
+            }
+
+            // Orders placed before a restart are not in order_ids, so they are
+            // found among the product's orders by the client_order_id we sent
+            async fn exchange_order_id(&self, symbol: &str, order_id: &str) -> Result<String, String> {
+                let known = self.order_ids.lock().unwrap().get(order_id).cloned();
+                if let Some(exchange_order_id) = known {
+                    return Ok(exchange_order_id);
+                }
+
+                let mut cursor = String::new();
+                for _ in 0..MAX_LOOKUP_PAGES {
+                    let mut path = format!("/api/v3/brokerage/orders/historical/batch?product_ids={}&limit=250", self.product_id(symbol));
+                    if !cursor.is_empty() {
+                        path.push_str(&format!("&cursor={}", cursor));
+                    }
+                    let page: OrderList = self.rest.send(Method::GET, &path, None)
+                        .await
+                        .map_err(|e| e.to_string())?;
+                    if let Some(found) = page.orders.iter().find(|order| order.client_order_id == order_id) {
+                        self.order_ids.lock().unwrap().insert(order_id.to_string(), found.order_id.clone());
+                        return Ok(found.order_id.clone());
+                    }
+                    if !page.has_next || page.cursor.is_empty() {
+                        break;
+                    }
+                    cursor = page.cursor;
+                }
+                Err(format!("Unknown Coinbase order {}", order_id))
+            }
+
+            async fn fetch(&self, order_id: &str, exchange_order_id: &str) -> Result<OrderExecution, String> {
+                let path = format!("/api/v3/brokerage/orders/historical/{}", exchange_order_id);
+                let response: OrderEnvelope = self.rest.send(Method::GET, &path, None)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                Ok(to_execution(order_id, &response.order))
+            }
+
+            fn spawn_stream<H: ChannelHandler>(&self, socket: Socket, channels: Vec<(&'static str, Vec<String>)>, tx: mpsc::Sender<H::Item>, mut handler: H) {
+                let rest = Arc::clone(&self.rest);
+                let url = self.ws_endpoint.clone();
+                let connections = Arc::clone(&self.connections);
+                let reconnect_delay = Duration::from_millis(self.config.reconnect_delay_ms);
+                tokio::spawn(async move {
+                    let mut socket = Some(socket);
+                    while !tx.is_closed() {
+                        let mut current = match socket.take() {
+                            Some(current) => current,
+                            None => {
+                                tokio::time::sleep(reconnect_delay).await;
+                                match open(&url, &rest.credentials, &channels).await {
+                                    Ok(current) => current,
+                                    Err(e) => {
+                                        tracing::warn!("{}", e);
+                                        continue;
+                                    }
+                                }
+                            }
+                        };
+                        // Subscribing again replays snapshots, so start from scratch
+                        handler.reset();
+                        connections.fetch_add(1, Ordering::SeqCst);
+                        pump(&mut current, &tx, &mut handler).await;
+                        connections.fetch_sub(1, Ordering::SeqCst);
+                    }
+                });
+            }
+        }
+
+        #[async_trait]
+        impl ExchangeConnector for CoinbaseConnector {
+            fn exchange_type(&self) -> ExchangeType {
+                ExchangeType::Coinbase
+            }
+
+            async fn place_order(&self, order: &Order) -> Result<OrderExecution, String> {
+                let order_type = order.order_type.to_uppercase();
+                let time_in_force = if order.time_in_force.is_empty() { "GTC".to_string() } else { order.time_in_force.to_uppercase() };
+                let base_size = decimal(order.quantity);
+                let limit_price = decimal(order.price);
+                let configuration = match (order_type.as_str(), time_in_force.as_str()) {
+                    ("MARKET", _) => json!({"market_market_ioc": {"base_size": base_size}}),
+                    ("LIMIT", "GTC") => json!({"limit_limit_gtc": {"base_size": base_size, "limit_price": limit_price, "post_only": false}}),
+                    ("LIMIT", "IOC") => json!({"sor_limit_ioc": {"base_size": base_size, "limit_price": limit_price}}),
+                    ("LIMIT", "FOK") => json!({"limit_limit_fok": {"base_size": base_size, "limit_price": limit_price}}),
+                    ("LIMIT_MAKER", _) => json!({"limit_limit_gtc": {"base_size": base_size, "limit_price": limit_price, "post_only": true}}),
+                    (order_type, time_in_force) => {
+                        return Ok(rejected(order, format!("Unsupported order type: {} {}", order_type, time_in_force)));
+                    }
+                };
+                let body = json!({
+                    "client_order_id": order.order_id,
+                    "product_id": self.product_id(&order.symbol),
+                    "side": order.side.to_uppercase(),
+                    "order_configuration": configuration,
+                });
+
+                let response: CreateOrderResponse = match self.rest.send(Method::POST, "/api/v3/brokerage/orders", Some(body)).await {
+                    Ok(response) => response,
+                    Err(RestError::Venue(e)) => return Ok(rejected(order, format!("{} ({})", e.message, e.error))),
+                    Err(e) => return Err(e.to_string()),
+                };
+                let created = match (response.success, response.success_response) {
+                    (true, Some(created)) => created,
+                    _ => {
+                        let reason = response.error_response
+                            .map(|e| format!("{} ({})", e.message, if e.error.is_empty() { e.preview_failure_reason } else { e.error }))
+                            .unwrap_or_else(|| "Order rejected".to_string());
+                        return Ok(rejected(order, reason));
+                    }
+                };
+                self.order_ids.lock().unwrap().insert(order.order_id.clone(), created.order_id.clone());
+                // The fill stream may already have reported fills for the order
+                self.reported.lock().unwrap().open(&order.order_id);
+
+                // Creates are acked before matching, so look immediate orders up for their outcome
+                if order_type == "MARKET" || time_in_force == "IOC" || time_in_force == "FOK" {
+                    match self.fetch(&order.order_id, &created.order_id).await {
+                        Ok(execution) => return Ok(execution),
+                        Err(e) => tracing::warn!("Failed to look up Coinbase order {}: {}", created.order_id, e),
+                    }
+                }
+                Ok(OrderExecution {
+                    order_id: order.order_id.clone(),
+                    exchange_order_id: created.order_id,
+                    price: 0.0,
+                    quantity: 0.0,
+                    status: OrderStatus::New,
+                    reason: None,
+                    timestamp: Utc::now(),
+                })
+            }
+
+            async fn cancel_order(&self, symbol: &str, order_id: &str) -> Result<OrderExecution, String> {
+                let exchange_order_id = self.exchange_order_id(symbol, order_id).await?;
+                let body = json!({"order_ids": [exchange_order_id]});
+                let response: CancelResponse = self.rest.send(Method::POST, "/api/v3/brokerage/orders/batch_cancel", Some(body))
+                    .await
+                    .map_err(|e| e.to_string())?;
+                match response.results.first() {
+                    Some(result) if result.success => {}
+                    Some(result) => return Err(format!("Failed to cancel {}: {}", order_id, result.failure_reason)),
+                    None => return Err(format!("Failed to cancel {}: no result", order_id)),
+                }
+                self.fetch(order_id, &exchange_order_id).await
+            }
+
+            async fn amend_order(&self, symbol: &str, order_id: &str, price: Option<f64>, quantity: Option<f64>) -> Result<OrderExecution, String> {
+                let exchange_order_id = self.exchange_order_id(symbol, order_id).await?;
+                let path = format!("/api/v3/brokerage/orders/historical/{}", exchange_order_id);
+                let current: OrderEnvelope = self.rest.send(Method::GET, &path, None)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                let current = current.order;
+                if !order_status(&current).is_open() {
+                    return Err(format!("Order {} is {}", order_id, current.status));
+                }
+
+                // Only resting GTC limits can be edited, and edits keep the order id
+                let terms = current.order_configuration.get("limit_limit_gtc")
+                    .ok_or_else(|| format!("Coinbase can only edit GTC limit orders, not {} on {}", order_id, symbol))?;
+                let size = quantity.unwrap_or_else(|| number(terms["base_size"].as_str().unwrap_or_default()));
+                let filled = number(&current.filled_size);
+                if size <= filled {
+                    return Err(format!("Order {} already filled {}", order_id, filled));
+                }
+                let body = json!({
+                    "order_id": exchange_order_id,
+                    "price": decimal(price.unwrap_or_else(|| number(terms["limit_price"].as_str().unwrap_or_default()))),
+                    "size": decimal(size),
+                });
+
+                let response: EditResponse = self.rest.send(Method::POST, "/api/v3/brokerage/orders/edit", Some(body))
+                    .await
+                    .map_err(|e| e.to_string())?;
+                if !response.success {
+                    let reasons: Vec<String> = response.errors.iter()
+                        .map(|e| if e.edit_failure_reason.is_empty() { e.preview_failure_reason.clone() } else { e.edit_failure_reason.clone() })
+                        .collect();
+                    return Err(format!("Failed to amend {}: {}", order_id, reasons.join(", ")));
+                }
+                self.fetch(order_id, &exchange_order_id).await
+            }
+
+            async fn query_order(&self, symbol: &str, order_id: &str) -> Result<OrderExecution, String> {
+                let exchange_order_id = self.exchange_order_id(symbol, order_id).await?;
+                self.fetch(order_id, &exchange_order_id).await
+            }
+
+            async fn stream_fills(&self) -> Result<mpsc::Receiver<FillReport>, String> {
+                let channels = vec![("user", Vec::new())];
+                let socket = open(&self.ws_endpoint, &self.rest.credentials, &channels).await?;
+                let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+                let handler = FillFeed {
+                    symbol_map: self.symbol_map.clone(),
+                    reported: Arc::clone(&self.reported),
+                };
+                self.spawn_stream(socket, channels, tx, handler);
+                Ok(rx)
+            }
+
+            async fn stream_market_data(&self, symbols: &[String]) -> Result<mpsc::Receiver<MarketDataEvent>, String> {
+                // Events go back out under the caller's symbols
+                let by_product: HashMap<String, String> = symbols.iter()
+                    .map(|symbol| (self.product_id(symbol), symbol.clone()))
+                    .collect();
+                let product_ids: Vec<String> = by_product.keys().cloned().collect();
+                // market_trades is Advanced Trade's name for the matches feed
+                let channels = vec![("level2", product_ids.clone()), ("market_trades", product_ids)];
+                let socket = open(&self.ws_endpoint, &self.rest.credentials, &channels).await?;
+                let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+                let handler = MarketDataFeed {
+                    by_product,
+                    depth_levels: self.config.depth_levels,
+                    books: HashMap::new(),
+                };
+                self.spawn_stream(socket, channels, tx, handler);
+                Ok(rx)
+            }
+
+            fn active_connections(&self) -> u32 {
+                self.connections.load(Ordering::SeqCst)
+            }
+        }
+
+        // Connects and subscribes; heartbeats keep quiet channels from being dropped
+        async fn open(url: &str, credentials: &Credentials, channels: &[(&'static str, Vec<String>)]) -> Result<Socket, String> {
+            let (mut socket, _) = tokio_tungstenite::connect_async(url)
+                .await
+                .map_err(|e| format!("Failed to connect to Coinbase stream: {}", e))?;
+            for (channel, product_ids) in channels.iter().chain(std::iter::once(&("heartbeats", Vec::new()))) {
+                socket.send(Message::Text(credentials.subscribe(channel, product_ids)))
+                    .await
+                    .map_err(|e| format!("Failed to subscribe to Coinbase {}: {}", channel, e))?;
+            }
+            Ok(socket)
+        }
+
+        // Reads one connection until it ends, the receiver goes away or a
+        // sequence number is skipped, after which the books can't be trusted
+        async fn pump<H: ChannelHandler>(socket: &mut Socket, tx: &mpsc::Sender<H::Item>, handler: &mut H) {
+            let mut expected: Option<u64> = None;
+            loop {
+                let text = tokio::select! {
+                    message = socket.next() => match message {
+                        Some(Ok(Message::Text(text))) => text,
+                        Some(Ok(Message::Ping(payload))) => {
+                            let _ = socket.send(Message::Pong(payload)).await;
+                            continue;
+                        }
+                        Some(Ok(Message::Close(_))) | None => return,
+                        Some(Ok(_)) => continue,
+                        Some(Err(e)) => {
+                            tracing::warn!("Coinbase stream error: {}", e);
+                            return;
+                        }
+                    },
+                    _ = tx.closed() => return,
+                };
+                let message: ChannelMessage = match serde_json::from_str(&text) {
+                    Ok(message) => message,
+                    Err(_) => {
+                        tracing::warn!("Unexpected Coinbase message: {}", text);
+                        continue;
+                    }
+                };
+                if let Some(expected) = expected {
+                    if message.sequence_num != expected {
+                        tracing::warn!("Coinbase stream jumped from {} to {}, resubscribing", expected, message.sequence_num);
+                        return;
+                    }
+                }
+                expected = Some(message.sequence_num + 1);
+
+                for item in handler.handle(&message) {
+                    if tx.send(item).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+
+        trait ChannelHandler: Send + 'static {
+            type Item: Send + 'static;
+
+            // Called before each connection's snapshots arrive
+            fn reset(&mut self);
+
+            fn handle(&mut self, message: &ChannelMessage) -> Vec<Self::Item>;
+        }
+
+        #[derive(Default)]
+        struct LocalBook {
+            bids: BTreeMap<i64, (f64, f64)>,
+            asks: BTreeMap<i64, (f64, f64)>,
+        }
+
+        struct MarketDataFeed {
+            by_product: HashMap<String, String>,
+            depth_levels: usize,
+            // Only products whose snapshot has arrived on this connection
+            books: HashMap<String, LocalBook>,
+        }
+
+        impl ChannelHandler for MarketDataFeed {
+            type Item = MarketDataEvent;
+
+            fn reset(&mut self) {
+                self.books.clear();
+            }
+
+            fn handle(&mut self, message: &ChannelMessage) -> Vec<MarketDataEvent> {
+                let mut events = Vec::new();
+                for event in &message.events {
+                    match message.channel.as_str() {
+                        "l2_data" => {
+                            let Ok(event) = serde_json::from_value::<Level2Event>(event.clone()) else { continue };
+                            let Some(symbol) = self.by_product.get(&event.product_id) else { continue };
+                            if event.event_type == "snapshot" {
+                                self.books.insert(event.product_id.clone(), LocalBook::default());
+                            }
+                            let Some(book) = self.books.get_mut(&event.product_id) else { continue };
+                            for update in &event.updates {
+                                let side = if update.side == "bid" { &mut book.bids } else { &mut book.asks };
+                                let price = number(&update.price_level);
+                                let quantity = number(&update.new_quantity);
+                                let key = (price * PRICE_SCALE).round() as i64;
+                                if quantity > 0.0 {
+                                    side.insert(key, (price, quantity));
+                                } else {
+                                    side.remove(&key);
+                                }
+                            }
+                            events.push(MarketDataEvent::Book(OrderBookSnapshot {
+                                symbol: symbol.clone(),
+                                bids: book.bids.values().rev().take(self.depth_levels).copied().collect(),
+                                asks: book.asks.values().take(self.depth_levels).copied().collect(),
+                                timestamp: timestamp(&message.timestamp),
+                            }));
+                        }
+                        "market_trades" => {
+                            let Ok(event) = serde_json::from_value::<MarketTradesEvent>(event.clone()) else { continue };
+                            // The snapshot repeats recent history; only updates are new trades
+                            if event.event_type == "snapshot" {
+                                continue;
+                            }
+                            for trade in event.trades {
+                                let Some(symbol) = self.by_product.get(&trade.product_id) else { continue };
+                                events.push(MarketDataEvent::Trade(TradeEvent {
+                                    symbol: symbol.clone(),
+                                    price: number(&trade.price),
+                                    quantity: number(&trade.size),
+                                    side: trade.side.to_lowercase(),
+                                    timestamp: timestamp(&trade.time),
+                                }));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                events
+            }
+        }
+
+        // Cumulative quantity and notional per order, and whether it finished.
+        // Finished orders stay for a while, oldest dropped first, in case
+        // Coinbase sends them again
+        #[derive(Default)]
+        struct ReportedFills {
+            orders: HashMap<String, (f64, f64, bool)>,
+            finished: VecDeque<String>,
+        }
+
+        impl ReportedFills {
+            fn get(&self, client_order_id: &str) -> Option<(f64, f64)> {
+                self.orders.get(client_order_id).map(|(quantity, notional, _)| (*quantity, *notional))
+            }
+
+            fn open(&mut self, client_order_id: &str) {
+                self.orders.entry(client_order_id.to_string()).or_insert((0.0, 0.0, false));
+            }
+
+            // An update behind what was already reported keeps the larger totals
+            fn update(&mut self, client_order_id: String, quantity: f64, notional: f64, done: bool) {
+                let entry = self.orders.entry(client_order_id.clone()).or_insert((0.0, 0.0, false));
+                if quantity >= entry.0 {
+                    entry.0 = quantity;
+                    entry.1 = notional;
+                }
+                if done && !entry.2 {
+                    entry.2 = true;
+                    self.finished.push_back(client_order_id);
+                }
+                while self.finished.len() > MAX_FINISHED_ORDERS {
+                    if let Some(oldest) = self.finished.pop_front() {
+                        self.orders.remove(&oldest);
+                    }
+                }
+            }
+        }
+
+        // The user channel reports cumulative quantity and average price per
+        // order; each increase becomes a fill
+        struct FillFeed {
+            symbol_map: HashMap<String, String>,
+            reported: Arc<Mutex<ReportedFills>>,
+        }
+
+        impl ChannelHandler for FillFeed {
+            type Item = FillReport;
+
+            // What was reported carries over, so fills missed while disconnected
+            // show up against the next snapshot
+            fn reset(&mut self) {}
+
+            fn handle(&mut self, message: &ChannelMessage) -> Vec<FillReport> {
+                if message.channel != "user" {
+                    return Vec::new();
+                }
+                let mut fills = Vec::new();
+                let mut reported = self.reported.lock().unwrap();
+                for event in &message.events {
+                    let event: UserEvent = match serde_json::from_value(event.clone()) {
+                        Ok(event) => event,
+                        Err(e) => {
+                            tracing::warn!("Invalid Coinbase user event: {}", e);
+                            continue;
+                        }
+                    };
+                    for order in event.orders {
+                        if order.client_order_id.is_empty() {
+                            continue;
+                        }
+                        let quantity = number(&order.cumulative_quantity);
+                        let notional = quantity * number(&order.avg_price);
+                        // Orders from before this process start out as already reported
+                        let (reported_quantity, reported_notional) = match reported.get(&order.client_order_id) {
+                            Some(previous) => previous,
+                            None if event.event_type == "snapshot" => (quantity, notional),
+                            None => (0.0, 0.0),
+                        };
+                        if quantity > reported_quantity + f64::EPSILON {
+                            let fill_quantity = quantity - reported_quantity;
+                            fills.push(FillReport {
+                                fill_id: format!("{}-{}", order.order_id, decimal(quantity)),
+                                order_id: order.client_order_id.clone(),
+                                symbol: our_symbol(&self.symbol_map, &order.product_id),
+                                side: order.order_side.to_lowercase(),
+                                price: (notional - reported_notional) / fill_quantity,
+                                quantity: fill_quantity,
+                                timestamp: timestamp(&message.timestamp),
+                            });
+                        }
+                        let done = matches!(order.status.as_str(), "FILLED" | "CANCELLED" | "EXPIRED" | "FAILED");
+                        reported.update(order.client_order_id, quantity, notional, done);
+                    }
+                }
+                fills
+            }
+        }
+
+        fn to_execution(order_id: &str, order: &HistoricalOrder) -> OrderExecution {
+            OrderExecution {
+                order_id: order_id.to_string(),
+                exchange_order_id: order.order_id.clone(),
+                price: number(&order.average_filled_price),
+                quantity: number(&order.filled_size),
+                status: order_status(order),
+                reason: None,
+                timestamp: timestamp(order.last_fill_time.as_deref().unwrap_or(&order.created_time)),
+            }
+        }
+
+        fn order_status(order: &HistoricalOrder) -> OrderStatus {
+            match order.status.as_str() {
+                "PENDING" | "QUEUED" | "OPEN" | "CANCEL_QUEUED" if number(&order.filled_size) > 0.0 => OrderStatus::Partial,
+                "PENDING" | "QUEUED" | "OPEN" | "CANCEL_QUEUED" => OrderStatus::New,
+                "FILLED" => OrderStatus::Filled,
+                "FAILED" => OrderStatus::Rejected,
+                // CANCELLED and EXPIRED both end the order with what it filled
+                _ => OrderStatus::Cancelled,
+            }
+        }
+
+        fn product_id(symbol_map: &HashMap<String, String>, symbol: &str) -> String {
+            symbol_map.get(symbol)
+                .cloned()
+                .unwrap_or_else(|| symbol.replace('/', "-").to_uppercase())
+        }
+
+        fn our_symbol(symbol_map: &HashMap<String, String>, product_id: &str) -> String {
+            symbol_map.iter()
+                .find(|(_, venue)| venue.as_str() == product_id)
+                .map(|(ours, _)| ours.clone())
+                .unwrap_or_else(|| product_id.to_string())
+        }
+
+        fn rejected(order: &Order, reason: String) -> OrderExecution {
+            OrderExecution {
+                order_id: order.order_id.clone(),
+                exchange_order_id: String::new(),
+                price: 0.0,
+                quantity: 0.0,
+                status: OrderStatus::Rejected,
+                reason: Some(reason),
+                timestamp: Utc::now(),
+            }
+        }
+
+        fn sign(secret: &str, payload: &str) -> String {
+            let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes())
+                .expect("HMAC accepts keys of any length");
+            mac.update(payload.as_bytes());
+            hex::encode(mac.finalize().into_bytes())
+        }
+
+        // Coinbase sends every decimal as a string
+        fn number(value: &str) -> f64 {
+            value.parse().unwrap_or(0.0)
+        }
+
+        fn decimal(value: f64) -> String {
+            let formatted = format!("{:.8}", value);
+            formatted.trim_end_matches('0').trim_end_matches('.').to_string()
+        }
+
+        fn timestamp(value: &str) -> DateTime<Utc> {
+            DateTime::parse_from_rfc3339(value)
+                .map(|t| t.with_timezone(&Utc))
+                .unwrap_or_else(|_| Utc::now())
+        }
+    }
+
+    // Common order structure used across services
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Order {
+        pub order_id: String,
+        pub client_order_id: String,
+        pub symbol: String,
+        pub price: f64,
+        pub quantity: f64,
+        pub side: String,
+        pub order_type: String,
+        pub time_in_force: String,
+        pub user_id: String,
+        pub timestamp: DateTime<Utc>,
+    }
+}
+
+pub mod auth {
+    use serde::{Deserialize, Serialize};
+    use std::collections::HashMap;
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ApiKeyAuth {
+        pub key: String,
+        pub secret: String,
+        pub permissions: Vec<String>,
+    }
+
+    pub fn authenticate(api_key: &str, api_secret: &str) -> Result<String, String> {
+        // Simplified authentication - in production this would validate against a database
+        if api_key == "user1" && api_secret == "secret1" {
+            Ok("user1".to_string())
+        } else {
+            Err("Invalid credentials".to_string())
+        }
+    }
+
+    pub fn authorize(user_id: &str, permission: &str) -> bool {
+        // Simplified authorization - in production this would check user permissions
+        match user_id {
+            "user1" => permission == "trade",
+            _ => false,
+        }
+    }
+}
+
+pub mod rate_limiter {
+    use std::time::{Duration, Instant};
+    use std::collections::VecDeque;
+
+    #[derive(Debug)]
+    pub struct RateLimitExceeded;
+
+    pub struct RateLimiter {
+        max_requests: u64,
+        window: Duration,
+        requests: VecDeque<Instant>,
+    }
+
+    impl RateLimiter {
+        pub fn new(max_requests: u64) -> Self {
+            Self {
+                max_requests,
+                window: Duration::from_secs(60), // 1 minute window
+                requests: VecDeque::new(),
+            }
+        }
+
+        pub fn check(&mut self) -> Result<(), RateLimitExceeded> {
+            let now = Instant::now();
+            
+            // Remove old requests outside the window
+            while let Some(&front) = self.requests.front() {
+                if now.duration_since(front) > self.window {
+                    self.requests.pop_front();
+                } else {
+                    break;
+                }
+            }
+
+            // Check if we're at the limit
+            if self.requests.len() >= self.max_requests as usize {
+                return Err(RateLimitExceeded);
+            }
+
+            // Add current request
+            self.requests.push_back(now);
+            Ok(())
+        }
+
+        pub fn get_current_qps(&self) -> f64 {
+            self.requests.len() as f64 / self.window.as_secs_f64()
+        }
+    }
+}
+
+pub mod order_validator {
+    use crate::exchange_connector::Order;
+
+    #[derive(Debug)]
+    pub enum OrderValidationError {
+        InvalidPrice,
+        InvalidQuantity,
+        InvalidSymbol,
+        InvalidSide,
+        InvalidOrderType,
+    }
+
+    impl std::fmt::Display for OrderValidationError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                OrderValidationError::InvalidPrice => write!(f, "Invalid price"),
+                OrderValidationError::InvalidQuantity => write!(f, "Invalid quantity"),
+                OrderValidationError::InvalidSymbol => write!(f, "Invalid symbol"),
+                OrderValidationError::InvalidSide => write!(f, "Invalid side"),
+                OrderValidationError::InvalidOrderType => write!(f, "Invalid order type"),
+            }
+        }
+    }
+
+    pub fn validate_order(order: &Order) -> Result<(), OrderValidationError> {
+        // Price validation
+        if order.price <= 0.0 {
+            return Err(OrderValidationError::InvalidPrice);
+        }
+
+        // Quantity validation
+        if order.quantity <= 0.0 {
+            return Err(OrderValidationError::InvalidQuantity);
+        }
+
+        // Symbol validation
+        if order.symbol.is_empty() {
+            return Err(OrderValidationError::InvalidSymbol);
+        }
+
+        // Side validation
+        if !matches!(order.side.as_str(), "buy" | "sell") {
+            return Err(OrderValidationError::InvalidSide);
+        }
+
+        // Order type validation
+        if !matches!(order.order_type.as_str(), "limit" | "market" | "stop" | "stop_limit") {
+            return Err(OrderValidationError::InvalidOrderType);
+        }
+
+        Ok(())
+    }
+}
+
+pub mod order_lifecycle {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+    pub enum OrderState {
+        PendingNew,
+        New,
+        PartiallyFilled,
+        Filled,
+        Cancelled,
+        Rejected,
+    }
+
+    impl std::fmt::Display for OrderState {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                OrderState::PendingNew => write!(f, "pending_new"),
+                OrderState::New => write!(f, "new"),
+                OrderState::PartiallyFilled => write!(f, "partially_filled"),
+                OrderState::Filled => write!(f, "filled"),
+                OrderState::Cancelled => write!(f, "cancelled"),
+                OrderState::Rejected => write!(f, "rejected"),
+            }
+        }
+    }
+
+    impl OrderState {
+        pub fn is_terminal(&self) -> bool {
+            matches!(self, OrderState::Filled | OrderState::Cancelled | OrderState::Rejected)
+        }
+
+        pub fn is_open(&self) -> bool {
+            !self.is_terminal()
+        }
+
+        // Events from different topics can arrive out of order, so a fill may
+        // be seen before the risk approval; the order is then implicitly New
+        pub fn can_transition_to(&self, next: OrderState) -> bool {
+            use OrderState::*;
+            matches!(
+                (self, next),
+                (PendingNew, New | PartiallyFilled | Filled | Cancelled | Rejected)
+                    | (New, PartiallyFilled | Filled | Cancelled)
+                    | (PartiallyFilled, PartiallyFilled | Filled | Cancelled)
+            )
+        }
+    }
+}
+
+pub mod expression {
+    // Small expression language for risk rule conditions, e.g.
+    // "order.quantity < market_depth.bid_size * 0.1"
+    use std::collections::HashMap;
+    use std::fmt;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum ValueType {
+        Number,
+        Bool,
+        Text,
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Value {
+        Number(f64),
+        Bool(bool),
+        Text(String),
+    }
+
+    impl Value {
+        pub fn value_type(&self) -> ValueType {
+            match self {
+                Value::Number(_) => ValueType::Number,
+                Value::Bool(_) => ValueType::Bool,
+                Value::Text(_) => ValueType::Text,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum UnaryOp {
+        Neg,
+        Not,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum BinaryOp {
+        Add,
+        Sub,
+        Mul,
+        Div,
+        Rem,
+        Lt,
+        Le,
+        Gt,
+        Ge,
+        Eq,
+        Ne,
+        And,
+        Or,
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Expr {
+        Literal(Value),
+        Variable(String),
+        Unary(UnaryOp, Box<Expr>),
+        Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum EvalError {
+        // A variable the rule needs has no value yet (e.g. no market data)
+        Unavailable(String),
+        Invalid(String),
+    }
+
+    impl fmt::Display for EvalError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                EvalError::Unavailable(name) => write!(f, "{} is not available", name),
+                EvalError::Invalid(message) => write!(f, "{}", message),
+            }
+        }
+    }
+
+    // Variable name -> type, used to check expressions before they run
+    pub type Schema = HashMap<String, ValueType>;
+
+    #[derive(Debug, Clone, Default)]
+    pub struct Context {
+        values: HashMap<String, Value>,
+    }
+
+    impl Context {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn set(&mut self, name: &str, value: Value) {
+            self.values.insert(name.to_string(), value);
+        }
+
+        pub fn set_number(&mut self, name: &str, value: f64) {
+            self.set(name, Value::Number(value));
+        }
+
+        pub fn set_text(&mut self, name: &str, value: &str) {
+            self.set(name, Value::Text(value.to_string()));
+        }
+
+        pub fn get(&self, name: &str) -> Option<&Value> {
+            self.values.get(name)
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Token {
+        Number(f64),
+        Text(String),
+        Ident(String),
+        Op(&'static str),
+        LParen,
+        RParen,
+    }
+
+    const OPERATORS: [&str; 15] = ["<=", ">=", "==", "!=", "&&", "||", "<", ">", "+", "-", "*", "/", "%", "!", "="];
+
+    fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+        let chars: Vec<char> = input.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+
+        while i < chars.len() {
+            let c = chars[i];
+            if c.is_whitespace() {
+                i += 1;
+            } else if c == '(' {
+                tokens.push(Token::LParen);
+                i += 1;
+            } else if c == ')' {
+                tokens.push(Token::RParen);
+                i += 1;
+            } else if c.is_ascii_digit() || (c == '.' && chars.get(i + 1).is_some_and(|n| n.is_ascii_digit())) {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                let literal: String = chars[start..i].iter().collect();
+                let number = literal.parse().map_err(|_| format!("Invalid number '{}' at position {}", literal, start))?;
+                tokens.push(Token::Number(number));
+            } else if c == '"' || c == '\'' {
+                let start = i;
+                i += 1;
+                while i < chars.len() && chars[i] != c {
+                    i += 1;
+                }
+                if i == chars.len() {
+                    return Err(format!("Unterminated string at position {}", start));
+                }
+                tokens.push(Token::Text(chars[start + 1..i].iter().collect()));
+                i += 1;
+            } else if c.is_alphabetic() || c == '_' {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.') {
+                    i += 1;
+                }
+                tokens.push(Token::Ident(chars[start..i].iter().collect()));
+            } else {
+                let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+                let op = OPERATORS
+                    .iter()
+                    .find(|op| rest.starts_with(**op))
+                    .ok_or_else(|| format!("Unexpected character '{}' at position {}", c, i))?;
+                if *op == "=" {
+                    return Err(format!("Unexpected '=' at position {}, use '==' for equality", i));
+                }
+                tokens.push(Token::Op(op));
+                i += op.len();
+            }
+        }
+
+        Ok(tokens)
+    }
+
+    struct Parser {
+        tokens: Vec<Token>,
+        pos: usize,
+    }
+
+    impl Parser {
+        fn peek(&self) -> Option<&Token> {
+            self.tokens.get(self.pos)
+        }
+
+        fn next(&mut self) -> Option<Token> {
+            let token = self.tokens.get(self.pos).cloned();
+            self.pos += 1;
+            token
+        }
+
+        // Consumes the next token if it is one of the given operators or
+        // keywords and returns the operator it stands for
+        fn accept(&mut self, ops: &[(&str, BinaryOp)]) -> Option<BinaryOp> {
+            let matched = match self.peek() {
+                Some(Token::Op(op)) => ops.iter().find(|(name, _)| name == op),
+                Some(Token::Ident(word)) => ops.iter().find(|(name, _)| name == word),
+                _ => None,
+            };
+            let op = matched.map(|(_, op)| *op)?;
+            self.pos += 1;
+            Some(op)
+        }
+
+        fn parse_or(&mut self) -> Result<Expr, String> {
+            let mut left = self.parse_and()?;
+            while let Some(op) = self.accept(&[("||", BinaryOp::Or), ("or", BinaryOp::Or)]) {
+                let right = self.parse_and()?;
+                left = Expr::Binary(op, Box::new(left), Box::new(right));
+            }
+            Ok(left)
+        }
+
+        fn parse_and(&mut self) -> Result<Expr, String> {
+            let mut left = self.parse_not()?;
+            while let Some(op) = self.accept(&[("&&", BinaryOp::And), ("and", BinaryOp::And)]) {
+                let right = self.parse_not()?;
+                left = Expr::Binary(op, Box::new(left), Box::new(right));
+            }
+            Ok(left)
+        }
+
+        fn parse_not(&mut self) -> Result<Expr, String> {
+            match self.peek() {
+                Some(Token::Op("!")) => {}
+                Some(Token::Ident(word)) if word == "not" => {}
+                _ => return self.parse_comparison(),
+            }
+            self.pos += 1;
+            Ok(Expr::Unary(UnaryOp::Not, Box::new(self.parse_not()?)))
+        }
+
+        // Comparisons don't chain: "a < b < c" is a syntax error
+        fn parse_comparison(&mut self) -> Result<Expr, String> {
+            let left = self.parse_additive()?;
+            let ops = [
+                ("<", BinaryOp::Lt),
+                ("<=", BinaryOp::Le),
+                (">", BinaryOp::Gt),
+                (">=", BinaryOp::Ge),
+                ("==", BinaryOp::Eq),
+                ("!=", BinaryOp::Ne),
+            ];
+            match self.accept(&ops) {
+                Some(op) => {
+                    let right = self.parse_additive()?;
+                    Ok(Expr::Binary(op, Box::new(left), Box::new(right)))
+                }
+                None => Ok(left),
+            }
+        }
+
+        fn parse_additive(&mut self) -> Result<Expr, String> {
+            let mut left = self.parse_multiplicative()?;
+            while let Some(op) = self.accept(&[("+", BinaryOp::Add), ("-", BinaryOp::Sub)]) {
+                let right = self.parse_multiplicative()?;
+                left = Expr::Binary(op, Box::new(left), Box::new(right));
+            }
+            Ok(left)
+        }
+
+        fn parse_multiplicative(&mut self) -> Result<Expr, String> {
+            let mut left = self.parse_unary()?;
+            while let Some(op) = self.accept(&[("*", BinaryOp::Mul), ("/", BinaryOp::Div), ("%", BinaryOp::Rem)]) {
+                let right = self.parse_unary()?;
+                left = Expr::Binary(op, Box::new(left), Box::new(right));
+            }
+            Ok(left)
+        }
+
+        fn parse_unary(&mut self) -> Result<Expr, String> {
+            if let Some(Token::Op("-")) = self.peek() {
+                self.pos += 1;
+                return Ok(Expr::Unary(UnaryOp::Neg, Box::new(self.parse_unary()?)));
+            }
+            self.parse_primary()
+        }
+
+        fn parse_primary(&mut self) -> Result<Expr, String> {
+            match self.next() {
+                Some(Token::Number(number)) => Ok(Expr::Literal(Value::Number(number))),
+                Some(Token::Text(text)) => Ok(Expr::Literal(Value::Text(text))),
+                Some(Token::Ident(word)) => match word.as_str() {
+                    "true" => Ok(Expr::Literal(Value::Bool(true))),
+                    "false" => Ok(Expr::Literal(Value::Bool(false))),
+                    "and" | "or" | "not" => Err(format!("Unexpected keyword '{}'", word)),
+                    _ => Ok(Expr::Variable(word)),
+                },
+                Some(Token::LParen) => {
+                    let inner = self.parse_or()?;
+                    match self.next() {
+                        Some(Token::RParen) => Ok(inner),
+                        _ => Err("Expected ')'".to_string()),
+                    }
+                }
+                Some(token) => Err(format!("Unexpected token {:?}", token)),
+                None => Err("Unexpected end of expression".to_string()),
+            }
+        }
+    }
+
+    pub fn parse(input: &str) -> Result<Expr, String> {
+        let mut parser = Parser { tokens: tokenize(input)?, pos: 0 };
+        let expr = parser.parse_or()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("Unexpected token {:?} after end of expression", token));
+        }
+        Ok(expr)
+    }
+
+    pub fn type_check(expr: &Expr, schema: &Schema) -> Result<ValueType, String> {
+        match expr {
+            Expr::Literal(value) => Ok(value.value_type()),
+            Expr::Variable(name) => schema.get(name).copied().ok_or_else(|| format!("Unknown variable '{}'", name)),
+            Expr::Unary(op, operand) => {
+                let operand_type = type_check(operand, schema)?;
+                let expected = match op {
+                    UnaryOp::Neg => ValueType::Number,
+                    UnaryOp::Not => ValueType::Bool,
+                };
+                if operand_type != expected {
+                    return Err(format!("{:?} expects {:?}, found {:?}", op, expected, operand_type));
+                }
+                Ok(expected)
+            }
+            Expr::Binary(op, left, right) => {
+                let left_type = type_check(left, schema)?;
+                let right_type = type_check(right, schema)?;
+                let (operands, result) = match op {
+                    BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => {
+                        (Some(ValueType::Number), ValueType::Number)
+                    }
+                    BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => (Some(ValueType::Number), ValueType::Bool),
+                    BinaryOp::And | BinaryOp::Or => (Some(ValueType::Bool), ValueType::Bool),
+                    // Equality works on any type as long as both sides match
+                    BinaryOp::Eq | BinaryOp::Ne => (None, ValueType::Bool),
+                };
+                if left_type != right_type || operands.is_some_and(|expected| expected != left_type) {
+                    return Err(format!("{:?} cannot be applied to {:?} and {:?}", op, left_type, right_type));
+                }
+                Ok(result)
+            }
+        }
+    }
+
+    // Parses and type checks a rule condition, which must be boolean
+    pub fn compile(input: &str, schema: &Schema) -> Result<Expr, String> {
+        let expr = parse(input)?;
+        match type_check(&expr, schema)? {
+            ValueType::Bool => Ok(expr),
+            other => Err(format!("Condition must be boolean, found {:?}", other)),
+        }
+    }
+
+    // Prices are binary floats, so "price % tick_size == 0" needs a tolerance
+    fn approx_eq(a: f64, b: f64) -> bool {
+        (a - b).abs() <= 1e-9 * a.abs().max(b.abs()).max(1.0)
+    }
+
+    fn number(value: Value) -> Result<f64, EvalError> {
+        match value {
+            Value::Number(number) => Ok(number),
+            other => Err(EvalError::Invalid(format!("Expected number, found {:?}", other.value_type()))),
+        }
+    }
+
+    fn boolean(value: Value) -> Result<bool, EvalError> {
+        match value {
+            Value::Bool(flag) => Ok(flag),
+            other => Err(EvalError::Invalid(format!("Expected bool, found {:?}", other.value_type()))),
+        }
+    }
+
+    pub fn evaluate(expr: &Expr, context: &Context) -> Result<Value, EvalError> {
+        match expr {
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Variable(name) => context.get(name).cloned().ok_or_else(|| EvalError::Unavailable(name.clone())),
+            Expr::Unary(UnaryOp::Neg, operand) => Ok(Value::Number(-number(evaluate(operand, context)?)?)),
+            Expr::Unary(UnaryOp::Not, operand) => Ok(Value::Bool(!boolean(evaluate(operand, context)?)?)),
+            Expr::Binary(BinaryOp::And, left, right) => {
+                if !boolean(evaluate(left, context)?)? {
+                    return Ok(Value::Bool(false));
+                }
+                Ok(Value::Bool(boolean(evaluate(right, context)?)?))
+            }
+            Expr::Binary(BinaryOp::Or, left, right) => {
+                if boolean(evaluate(left, context)?)? {
+                    return Ok(Value::Bool(true));
+                }
+                Ok(Value::Bool(boolean(evaluate(right, context)?)?))
+            }
+            Expr::Binary(op, left, right) => {
+                let left = evaluate(left, context)?;
+                let right = evaluate(right, context)?;
+
+                if let BinaryOp::Eq | BinaryOp::Ne = op {
+                    let equal = match (&left, &right) {
+                        (Value::Number(a), Value::Number(b)) => approx_eq(*a, *b),
+                        _ => left == right,
+                    };
+                    return Ok(Value::Bool(equal == (*op == BinaryOp::Eq)));
+                }
+
+                let (a, b) = (number(left)?, number(right)?);
+                let value = match op {
+                    BinaryOp::Add => Value::Number(a + b),
+                    BinaryOp::Sub => Value::Number(a - b),
+                    BinaryOp::Mul => Value::Number(a * b),
+                    BinaryOp::Div | BinaryOp::Rem if b == 0.0 => {
+                        return Err(EvalError::Invalid("Division by zero".to_string()));
+                    }
+                    BinaryOp::Div => Value::Number(a / b),
+                    BinaryOp::Rem => {
+                        let remainder = a % b;
+                        if approx_eq(remainder.abs(), b.abs()) {
+                            Value::Number(0.0)
+                        } else {
+                            Value::Number(remainder)
+                        }
+                    }
+                    BinaryOp::Lt => Value::Bool(a < b),
+                    BinaryOp::Le => Value::Bool(a <= b),
+                    BinaryOp::Gt => Value::Bool(a > b),
+                    BinaryOp::Ge => Value::Bool(a >= b),
+                    BinaryOp::Eq | BinaryOp::Ne | BinaryOp::And | BinaryOp::Or => unreachable!(),
+                };
+                Ok(value)
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn schema() -> Schema {
+            Schema::from([
+                ("order.quantity".to_string(), ValueType::Number),
+                ("order.price".to_string(), ValueType::Number),
+                ("order.side".to_string(), ValueType::Text),
+                ("market.halted".to_string(), ValueType::Bool),
+            ])
+        }
+
+        fn context() -> Context {
+            let mut context = Context::new();
+            context.set_number("order.quantity", 10.0);
+            context.set_number("order.price", 0.3);
+            context.set_text("order.side", "buy");
+            context.set("market.halted", Value::Bool(false));
+            context
+        }
+
+        fn eval(input: &str) -> Result<Value, EvalError> {
+            evaluate(&compile(input, &schema()).unwrap(), &context())
+        }
+
+        fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+            Expr::Binary(op, Box::new(left), Box::new(right))
+        }
+
+        fn num(value: f64) -> Expr {
+            Expr::Literal(Value::Number(value))
+        }
+
+        fn var(name: &str) -> Expr {
+            Expr::Variable(name.to_string())
+        }
+
+        #[test]
+        fn multiplication_binds_tighter_than_addition() {
+            assert_eq!(
+                parse("1 + 2 * 3").unwrap(),
+                binary(BinaryOp::Add, num(1.0), binary(BinaryOp::Mul, num(2.0), num(3.0)))
+            );
+            assert_eq!(eval("1 + 2 * 3 == 7"), Ok(Value::Bool(true)));
+            assert_eq!(eval("(1 + 2) * 3 == 9"), Ok(Value::Bool(true)));
+        }
+
+        #[test]
+        fn arithmetic_is_left_associative() {
+            assert_eq!(eval("10 - 4 - 3 == 3"), Ok(Value::Bool(true)));
+            assert_eq!(eval("12 / 3 / 2 == 2"), Ok(Value::Bool(true)));
+        }
+
+        #[test]
+        fn unary_minus_binds_tighter_than_multiplication() {
+            assert_eq!(
+                parse("-order.quantity * 2").unwrap(),
+                binary(BinaryOp::Mul, Expr::Unary(UnaryOp::Neg, Box::new(var("order.quantity"))), num(2.0))
+            );
+        }
+
+        #[test]
+        fn and_binds_tighter_than_or() {
+            assert_eq!(
+                parse("true || false && false").unwrap(),
+                binary(
+                    BinaryOp::Or,
+                    Expr::Literal(Value::Bool(true)),
+                    binary(BinaryOp::And, Expr::Literal(Value::Bool(false)), Expr::Literal(Value::Bool(false)))
+                )
+            );
+            assert_eq!(eval("true or false and false"), Ok(Value::Bool(true)));
+            assert_eq!(eval("(true or false) and false"), Ok(Value::Bool(false)));
+        }
+
+        #[test]
+        fn not_applies_to_the_whole_comparison() {
+            assert_eq!(eval("not order.quantity > 100 && !market.halted"), Ok(Value::Bool(true)));
+        }
+
+        #[test]
+        fn comparisons_do_not_chain() {
+            assert!(parse("1 < order.quantity < 20").is_err());
+        }
+
+        #[test]
+        fn rejects_malformed_input() {
+            assert!(parse("order.quantity = 10").unwrap_err().contains("use '=='"));
+            assert!(parse("(order.quantity > 1").unwrap_err().contains("Expected ')'"));
+            assert!(parse("order.side == 'buy").unwrap_err().contains("Unterminated string"));
+            assert!(parse("order.quantity >").unwrap_err().contains("Unexpected end"));
+            assert!(parse("order.quantity > 1 2").is_err());
+            assert!(parse("order.quantity # 2").unwrap_err().contains("Unexpected character '#'"));
+        }
+
+        #[test]
+        fn unknown_fields_are_rejected_when_compiling() {
+            let error = compile("order.notional > 1000", &schema()).unwrap_err();
+            assert_eq!(error, "Unknown variable 'order.notional'");
+        }
+
+        #[test]
+        fn mismatched_types_are_rejected_when_compiling() {
+            assert!(compile("order.quantity + order.side > 1", &schema()).is_err());
+            assert!(compile("order.side > 1", &schema()).is_err());
+            assert!(compile("order.side == 1", &schema()).is_err());
+            assert!(compile("market.halted and order.quantity", &schema()).is_err());
+            assert!(compile("-market.halted", &schema()).is_err());
+            assert!(compile("not order.quantity", &schema()).is_err());
+        }
+
+        #[test]
+        fn conditions_must_be_boolean() {
+            let error = compile("order.quantity * 2", &schema()).unwrap_err();
+            assert_eq!(error, "Condition must be boolean, found Number");
+        }
+
+        #[test]
+        fn equality_works_on_any_matching_type() {
+            assert_eq!(eval("order.side == 'buy'"), Ok(Value::Bool(true)));
+            assert_eq!(eval("order.side != \"sell\""), Ok(Value::Bool(true)));
+            assert_eq!(eval("market.halted == false"), Ok(Value::Bool(true)));
+        }
+
+        #[test]
+        fn division_by_zero_is_an_error() {
+            assert_eq!(eval("order.quantity / 0 > 1"), Err(EvalError::Invalid("Division by zero".to_string())));
+            assert_eq!(eval("order.quantity % (1 - 1) == 0"), Err(EvalError::Invalid("Division by zero".to_string())));
+        }
+
+        #[test]
+        fn short_circuit_skips_division_by_zero() {
+            assert_eq!(eval("market.halted && order.quantity / 0 > 1"), Ok(Value::Bool(false)));
+            assert_eq!(eval("!market.halted || order.quantity / 0 > 1"), Ok(Value::Bool(true)));
+        }
+
+        #[test]
+        fn remainder_tolerates_float_error() {
+            assert_eq!(eval("order.price % 0.1 == 0"), Ok(Value::Bool(true)));
+            assert_eq!(eval("order.price % 0.25 == 0"), Ok(Value::Bool(false)));
+        }
+
+        #[test]
+        fn missing_values_are_unavailable() {
+            let expr = compile("order.quantity > 1", &schema()).unwrap();
+            assert_eq!(evaluate(&expr, &Context::new()), Err(EvalError::Unavailable("order.quantity".to_string())));
+        }
+    }
+}
+
+pub mod kill_switch {
+    use chrono::{DateTime, Duration, Utc};
+    use serde::{Deserialize, Serialize};
+    use std::collections::HashMap;
+    use std::fmt;
+
+    // A re-enable request must be confirmed within this window
+    pub const REENABLE_CONFIRMATION_WINDOW_SECS: i64 = 300;
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+    #[serde(tag = "scope", content = "value", rename_all = "snake_case")]
+    pub enum KillSwitchScope {
+        Global,
+        User(String),
+        Symbol(String),
+    }
+
+    impl KillSwitchScope {
+        pub fn matches(&self, user_id: &str, symbol: &str) -> bool {
+            match self {
+                KillSwitchScope::Global => true,
+                KillSwitchScope::User(user) => user == user_id,
+                KillSwitchScope::Symbol(sym) => sym == symbol,
+            }
+        }
+    }
+
+    impl fmt::Display for KillSwitchScope {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                KillSwitchScope::Global => write!(f, "global"),
+                KillSwitchScope::User(user) => write!(f, "user:{}", user),
+                KillSwitchScope::Symbol(symbol) => write!(f, "symbol:{}", symbol),
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum KillSwitchAction {
+        Activate,
+        RequestReenable,
+        ConfirmReenable,
+    }
+
+    // Published on the kill switch control topic
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct KillSwitchCommand {
+        pub command_id: String,
+        pub action: KillSwitchAction,
+        pub scope: KillSwitchScope,
+        pub issued_by: String,
+        pub reason: String,
+        #[serde(default)]
+        pub confirms: Option<String>, // command_id of the re-enable request being confirmed
+        pub timestamp: DateTime<Utc>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct KillSwitchEntry {
+        pub scope: KillSwitchScope,
+        pub activated_by: String,
+        pub reason: String,
+        pub activated_at: DateTime<Utc>,
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum KillSwitchOutcome {
+        Activated,
+        ReenableRequested,
+        Reenabled,
+        Ignored(String),
+    }
+
+    // Every service replays the same command stream, so all of them end up
+    // with the same view of what is switched off. Command timestamps are used
+    // instead of the local clock to keep replays deterministic.
+    #[derive(Debug, Default)]
+    pub struct KillSwitch {
+        active: HashMap<KillSwitchScope, KillSwitchEntry>,
+        pending_reenables: HashMap<String, KillSwitchCommand>, // request command_id -> request
+    }
+
+    impl KillSwitch {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn apply(&mut self, command: &KillSwitchCommand) -> KillSwitchOutcome {
+            match command.action {
+                KillSwitchAction::Activate => {
+                    self.pending_reenables.retain(|_, request| request.scope != command.scope);
+                    self.active.insert(command.scope.clone(), KillSwitchEntry {
+                        scope: command.scope.clone(),
+                        activated_by: command.issued_by.clone(),
+                        reason: command.reason.clone(),
+                        activated_at: command.timestamp,
+                    });
+                    KillSwitchOutcome::Activated
+                }
+                KillSwitchAction::RequestReenable => {
+                    if !self.active.contains_key(&command.scope) {
+                        return KillSwitchOutcome::Ignored(format!("Kill switch for {} is not active", command.scope));
+                    }
+                    self.pending_reenables.insert(command.command_id.clone(), command.clone());
+                    KillSwitchOutcome::ReenableRequested
+                }
+                KillSwitchAction::ConfirmReenable => {
+                    let request = match command.confirms.as_ref().and_then(|id| self.pending_reenables.remove(id)) {
+                        Some(request) => request,
+                        None => return KillSwitchOutcome::Ignored("No pending re-enable request to confirm".to_string()),
+                    };
+                    // Re-enabling takes two people; the request stays open for someone else
+                    if request.issued_by == command.issued_by {
+                        let reason = format!("Re-enable request {} must be confirmed by someone other than {}", request.command_id, request.issued_by);
+                        self.pending_reenables.insert(request.command_id.clone(), request);
+                        return KillSwitchOutcome::Ignored(reason);
+                    }
+                    if request.scope != command.scope {
+                        return KillSwitchOutcome::Ignored(format!("Confirmation scope {} does not match request scope {}", command.scope, request.scope));
+                    }
+                    if command.timestamp - request.timestamp > Duration::seconds(REENABLE_CONFIRMATION_WINDOW_SECS) {
+                        return KillSwitchOutcome::Ignored(format!("Re-enable request {} expired", request.command_id));
+                    }
+                    self.active.remove(&command.scope);
+                    KillSwitchOutcome::Reenabled
+                }
+            }
+        }
+
+        // The active kill switch that stops trading for this user and symbol, if any
+        pub fn blocking_scope(&self, user_id: &str, symbol: &str) -> Option<&KillSwitchScope> {
+            self.active.keys().find(|scope| scope.matches(user_id, symbol))
+        }
+
+        pub fn active(&self) -> Vec<KillSwitchEntry> {
+            self.active.values().cloned().collect()
+        }
+
+        pub fn pending_reenables(&self) -> Vec<KillSwitchCommand> {
+            self.pending_reenables.values().cloned().collect()
+        }
+    }
+}
+
+pub mod circuit_breaker {
+    use chrono::{DateTime, Utc};
+    use serde::{Deserialize, Serialize};
+    use std::collections::{HashMap, VecDeque};
+    use std::fmt;
+    use std::time::{Duration, Instant};
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+    #[serde(tag = "scope", content = "value", rename_all = "snake_case")]
+    pub enum BreakerKey {
+        User(String),
+        Symbol(String),
+    }
+
+    impl BreakerKey {
+        pub fn scope(&self) -> &'static str {
+            match self {
+                BreakerKey::User(_) => "user",
+                BreakerKey::Symbol(_) => "symbol",
+            }
+        }
+
+        pub fn value(&self) -> &str {
+            match self {
+                BreakerKey::User(value) | BreakerKey::Symbol(value) => value,
+            }
+        }
+    }
+
+    impl fmt::Display for BreakerKey {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{}:{}", self.scope(), self.value())
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct CircuitBreakerConfig {
+        pub window_secs: u64,
+        pub min_orders: usize, // orders needed in the window before the rejection rate counts
+        pub max_rejection_rate: f64,
+        pub max_violations: usize, // violations in the window
+        pub cooldown_secs: u64,
+        pub max_cooldown_secs: u64, // cooldown doubles on every failed probe up to this
+        pub probe_orders: usize, // clean orders needed while half-open to close again
+    }
+
+    impl Default for CircuitBreakerConfig {
+        fn default() -> Self {
+            CircuitBreakerConfig {
+                window_secs: 60,
+                min_orders: 20,
+                max_rejection_rate: 0.5,
+                max_violations: 100,
+                cooldown_secs: 30,
+                max_cooldown_secs: 600,
+                probe_orders: 3,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum BreakerState {
+        Closed,
+        HalfOpen,
+        Open,
+    }
+
+    impl BreakerState {
+        // Value exported on the state gauge
+        pub fn as_gauge(&self) -> f64 {
+            match self {
+                BreakerState::Closed => 0.0,
+                BreakerState::HalfOpen => 1.0,
+                BreakerState::Open => 2.0,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize)]
+    pub struct BreakerStatus {
+        pub key: BreakerKey,
+        pub state: BreakerState,
+        pub orders_in_window: usize,
+        pub rejections_in_window: usize,
+        pub violations_in_window: usize,
+        pub cooldown_remaining_secs: Option<u64>,
+        pub trips: u64,
+        pub last_trip_reason: Option<String>,
+        pub last_tripped_at: Option<DateTime<Utc>>,
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct BreakerTrip {
+        pub key: BreakerKey,
+        pub reason: String,
+        pub cooldown: Duration,
+    }
+
+    // Times are stored relative to when the snapshot was taken
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct BreakerSnapshot {
+        pub key: BreakerKey,
+        pub state: BreakerState,
+        pub outcomes: Vec<(u64, usize)>, // (age in ms, violations)
+        pub cooldown_remaining_ms: Option<u64>,
+        pub cooldown_ms: u64,
+        pub probe_successes: usize,
+        pub trips: u64,
+        pub last_trip_reason: Option<String>,
+        pub last_tripped_at: Option<DateTime<Utc>>,
+    }
+
+    #[derive(Debug)]
+    struct Outcome {
+        at: Instant,
+        violations: usize,
+    }
+
+    #[derive(Debug)]
+    struct Breaker {
+        state: BreakerState,
+        outcomes: VecDeque<Outcome>,
+        open_until: Option<Instant>,
+        cooldown: Duration,
+        probe_successes: usize,
+        trips: u64,
+        last_trip_reason: Option<String>,
+        last_tripped_at: Option<DateTime<Utc>>,
+    }
+
+    impl Breaker {
+        fn new(config: &CircuitBreakerConfig) -> Self {
+            Breaker {
+                state: BreakerState::Closed,
+                outcomes: VecDeque::new(),
+                open_until: None,
+                cooldown: Duration::from_secs(config.cooldown_secs),
+                probe_successes: 0,
+                trips: 0,
+                last_trip_reason: None,
+                last_tripped_at: None,
+            }
+        }
+
+        fn prune(&mut self, window: Duration, now: Instant) {
+            while let Some(front) = self.outcomes.front() {
+                if now.duration_since(front.at) > window {
+                    self.outcomes.pop_front();
+                } else {
+                    break;
+                }
+            }
+        }
+
+        // An open breaker lets probes through once its cooldown has passed
+        fn refresh(&mut self, now: Instant) {
+            if self.state == BreakerState::Open && self.open_until.is_none_or(|until| now >= until) {
+                self.state = BreakerState::HalfOpen;
+                self.open_until = None;
+                self.probe_successes = 0;
+            }
+        }
+
+        fn trip(&mut self, reason: String, now: Instant) {
+            self.state = BreakerState::Open;
+            self.open_until = Some(now + self.cooldown);
+            self.probe_successes = 0;
+            self.trips += 1;
+            self.last_trip_reason = Some(reason);
+            self.last_tripped_at = Some(Utc::now());
+        }
+
+        fn close(&mut self, config: &CircuitBreakerConfig) {
+            self.state = BreakerState::Closed;
+            self.open_until = None;
+            self.probe_successes = 0;
+            self.outcomes.clear();
+            self.cooldown = Duration::from_secs(config.cooldown_secs);
+        }
+
+        fn rejections(&self) -> usize {
+            self.outcomes.iter().filter(|o| o.violations > 0).count()
+        }
+
+        fn violations(&self) -> usize {
+            self.outcomes.iter().map(|o| o.violations).sum()
+        }
+    }
+
+    // Breakers are kept per user and per symbol. Each one watches the orders
+    // it saw over a rolling window and opens when too many of them were
+    // rejected or they piled up too many violations. After the cooldown it
+    // goes half-open and closes again once enough probe orders pass cleanly;
+    // a rejected probe reopens it with a doubled cooldown.
+    #[derive(Debug)]
+    pub struct CircuitBreaker {
+        config: CircuitBreakerConfig,
+        breakers: HashMap<BreakerKey, Breaker>,
+    }
+
+    impl CircuitBreaker {
+        pub fn new(config: CircuitBreakerConfig) -> Self {
+            CircuitBreaker {
+                config,
+                breakers: HashMap::new(),
+            }
+        }
+
+        pub fn config(&self) -> &CircuitBreakerConfig {
+            &self.config
+        }
+
+        fn keys(user_id: &str, symbol: &str) -> [BreakerKey; 2] {
+            [BreakerKey::User(user_id.to_string()), BreakerKey::Symbol(symbol.to_string())]
+        }
+
+        // The open breaker that stops this order, if any
+        pub fn blocking(&mut self, user_id: &str, symbol: &str, now: Instant) -> Option<BreakerKey> {
+            Self::keys(user_id, symbol).into_iter().find(|key| {
+                match self.breakers.get_mut(key) {
+                    Some(breaker) => {
+                        breaker.refresh(now);
+                        breaker.state == BreakerState::Open
+                    }
+                    None => false,
+                }
+            })
+        }
+
+        // Records a risk check result and returns the breakers it tripped
+        pub fn record(&mut self, user_id: &str, symbol: &str, violations: usize, now: Instant) -> Vec<BreakerTrip> {
+            let window = Duration::from_secs(self.config.window_secs);
+            let max_cooldown = Duration::from_secs(self.config.max_cooldown_secs);
+            let mut trips = Vec::new();
+            
+            for key in Self::keys(user_id, symbol) {
+                let config = &self.config;
+                let breaker = self.breakers.entry(key.clone()).or_insert_with(|| Breaker::new(config));
+                breaker.refresh(now);
+                breaker.prune(window, now);
+                breaker.outcomes.push_back(Outcome { at: now, violations });
+                
+                let reason = match breaker.state {
+                    BreakerState::Open => None,
+                    BreakerState::HalfOpen => {
+                        if violations > 0 {
+                            breaker.cooldown = (breaker.cooldown * 2).min(max_cooldown);
+                            Some("probe order rejected".to_string())
+                        } else {
+                            breaker.probe_successes += 1;
+                            if breaker.probe_successes >= config.probe_orders {
+                                breaker.close(config);
+                            }
+                            None
+                        }
+                    }
+                    BreakerState::Closed => {
+                        let orders = breaker.outcomes.len();
+                        let rejections = breaker.rejections();
+                        let total_violations = breaker.violations();
+                        let rejection_rate = rejections as f64 / orders as f64;
+                        
+                        if total_violations >= config.max_violations {
+                            Some(format!("{} violations in {}s", total_violations, config.window_secs))
+                        } else if orders >= config.min_orders && rejection_rate >= config.max_rejection_rate {
+                            Some(format!("{}/{} orders rejected in {}s", rejections, orders, config.window_secs))
+                        } else {
+                            None
+                        }
+                    }
+                };
+                
+                if let Some(reason) = reason {
+                    breaker.trip(reason.clone(), now);
+                    trips.push(BreakerTrip {
+                        key,
+                        reason,
+                        cooldown: breaker.cooldown,
+                    });
+                }
+            }
+            
+            trips
+        }
+
+        // Manual reset closes the breaker and forgets its window
+        pub fn reset(&mut self, key: &BreakerKey) -> bool {
+            let config = &self.config;
+            match self.breakers.get_mut(key) {
+                Some(breaker) => {
+                    breaker.close(config);
+                    true
+                }
+                None => false,
+            }
+        }
+
+        pub fn export_state(&self, now: Instant) -> Vec<BreakerSnapshot> {
+            self.breakers
+                .iter()
+                .map(|(key, breaker)| BreakerSnapshot {
+                    key: key.clone(),
+                    state: breaker.state,
+                    outcomes: breaker.outcomes
+                        .iter()
+                        .map(|outcome| (now.saturating_duration_since(outcome.at).as_millis() as u64, outcome.violations))
+                        .collect(),
+                    cooldown_remaining_ms: breaker.open_until.map(|until| until.saturating_duration_since(now).as_millis() as u64),
+                    cooldown_ms: breaker.cooldown.as_millis() as u64,
+                    probe_successes: breaker.probe_successes,
+                    trips: breaker.trips,
+                    last_trip_reason: breaker.last_trip_reason.clone(),
+                    last_tripped_at: breaker.last_tripped_at,
+                })
+                .collect()
+        }
+
+        // Downtime does not count towards cooldowns; an open breaker stays
+        // open for whatever was left of it
+        pub fn restore_state(&mut self, snapshots: Vec<BreakerSnapshot>, now: Instant) {
+            self.breakers = snapshots
+                .into_iter()
+                .map(|snapshot| {
+                    let breaker = Breaker {
+                        state: snapshot.state,
+                        outcomes: snapshot.outcomes
+                            .iter()
+                            .filter_map(|(age_ms, violations)| {
+                                let at = now.checked_sub(Duration::from_millis(*age_ms))?;
+                                Some(Outcome { at, violations: *violations })
+                            })
+                            .collect(),
+                        open_until: snapshot.cooldown_remaining_ms.map(|remaining| now + Duration::from_millis(remaining)),
+                        cooldown: Duration::from_millis(snapshot.cooldown_ms),
+                        probe_successes: snapshot.probe_successes,
+                        trips: snapshot.trips,
+                        last_trip_reason: snapshot.last_trip_reason,
+                        last_tripped_at: snapshot.last_tripped_at,
+                    };
+                    (snapshot.key, breaker)
+                })
+                .collect();
+        }
+
+        pub fn statuses(&mut self, now: Instant) -> Vec<BreakerStatus> {
+            let window = Duration::from_secs(self.config.window_secs);
+            self.breakers
+                .iter_mut()
+                .map(|(key, breaker)| {
+                    breaker.refresh(now);
+                    breaker.prune(window, now);
+                    BreakerStatus {
+                        key: key.clone(),
+                        state: breaker.state,
+                        orders_in_window: breaker.outcomes.len(),
+                        rejections_in_window: breaker.rejections(),
+                        violations_in_window: breaker.violations(),
+                        cooldown_remaining_secs: breaker.open_until.map(|until| until.saturating_duration_since(now).as_secs()),
+                        trips: breaker.trips,
+                        last_trip_reason: breaker.last_trip_reason.clone(),
+                        last_tripped_at: breaker.last_tripped_at,
+                    }
+                })
+                .collect()
+        }
+    }
+}
+
+pub mod surveillance {
+    use chrono::{DateTime, Utc};
+    use serde::{Deserialize, Serialize};
+    use std::collections::{HashMap, VecDeque};
+    use std::time::{Duration, Instant};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct SurveillanceLimits {
+        pub window_secs: u64,
+        pub max_orders: Option<usize>, // new orders per window
+        pub max_order_to_trade: Option<f64>,
+        pub max_cancel_to_fill: Option<f64>,
+        pub min_orders: usize, // ratios only apply past this many orders in the window
+        pub throttle_secs: u64,
+    }
+
+    impl Default for SurveillanceLimits {
+        fn default() -> Self {
+            SurveillanceLimits {
+                window_secs: 60,
+                max_orders: Some(600),
+                max_order_to_trade: Some(50.0),
+                max_cancel_to_fill: Some(20.0),
+                min_orders: 50,
+                throttle_secs: 60,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Activity {
+        Order,
+        Cancel,
+        Trade,
+    }
+
+    // Raised when a user crosses a limit and gets throttled
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct SurveillanceAlert {
+        pub user_id: String,
+        pub metric: String,
+        pub value: f64,
+        pub limit: f64,
+        pub orders: usize,
+        pub cancels: usize,
+        pub trades: usize,
+        pub window_secs: u64,
+        pub throttle_secs: u64,
+        pub timestamp: DateTime<Utc>,
+    }
+
+    #[derive(Debug, Clone, Serialize)]
+    pub struct ActivityStats {
+        pub user_id: String,
+        pub orders: usize,
+        pub cancels: usize,
+        pub trades: usize,
+        pub order_to_trade: f64,
+        pub cancel_to_fill: f64,
+        pub throttled_for_secs: Option<u64>,
+    }
+
+    #[derive(Debug, Default)]
+    struct UserActivity {
+        events: VecDeque<(Instant, Activity)>,
+        throttled_until: Option<Instant>,
+    }
+
+    impl UserActivity {
+        fn prune(&mut self, window: Duration, now: Instant) {
+            while let Some((at, _)) = self.events.front() {
+                if now.duration_since(*at) > window {
+                    self.events.pop_front();
+                } else {
+                    break;
+                }
+            }
+        }
+
+        fn count(&self, activity: Activity) -> usize {
+            self.events.iter().filter(|(_, kind)| *kind == activity).count()
+        }
+    }
+
+    // Ratios are taken against at least one trade so users who never trade
+    // still have a finite ratio
+    fn ratio(count: usize, trades: usize) -> f64 {
+        count as f64 / trades.max(1) as f64
+    }
+
+    // Rolling per-user counts of new orders, cancels and trades
+    #[derive(Debug)]
+    pub struct OrderSurveillance {
+        limits: SurveillanceLimits,
+        users: HashMap<String, UserActivity>,
+    }
+
+    impl OrderSurveillance {
+        pub fn new(limits: SurveillanceLimits) -> Self {
+            OrderSurveillance {
+                limits,
+                users: HashMap::new(),
+            }
+        }
+
+        // Time left on the user's throttle, if any
+        pub fn throttled(&self, user_id: &str, now: Instant) -> Option<Duration> {
+            let until = self.users.get(user_id)?.throttled_until?;
+            if now < until { Some(until - now) } else { None }
+        }
+
+        // Records activity and throttles the user if it takes them over a limit
+        pub fn record(&mut self, user_id: &str, activity: Activity, now: Instant) -> Option<SurveillanceAlert> {
+            let limits = &self.limits;
+            let user = self.users.entry(user_id.to_string()).or_default();
+            user.prune(Duration::from_secs(limits.window_secs), now);
+            user.events.push_back((now, activity));
+
+            if activity == Activity::Trade || user.throttled_until.is_some_and(|until| now < until) {
+                return None;
+            }
+
+            let (orders, cancels, trades) = (user.count(Activity::Order), user.count(Activity::Cancel), user.count(Activity::Trade));
+            let mut breaches = Vec::new();
+            if let Some(max_orders) = limits.max_orders {
+                breaches.push(("order_rate", orders as f64, max_orders as f64));
+            }
+            if orders >= limits.min_orders {
+                if let Some(max) = limits.max_order_to_trade {
+                    breaches.push(("order_to_trade", ratio(orders, trades), max));
+                }
+                if let Some(max) = limits.max_cancel_to_fill {
+                    breaches.push(("cancel_to_fill", ratio(cancels, trades), max));
+                }
+            }
+            let (metric, value, limit) = breaches.into_iter().find(|(_, value, limit)| value > limit)?;
+
+            user.throttled_until = Some(now + Duration::from_secs(limits.throttle_secs));
+            Some(SurveillanceAlert {
+                user_id: user_id.to_string(),
+                metric: metric.to_string(),
+                value,
+                limit,
+                orders,
+                cancels,
+                trades,
+                window_secs: limits.window_secs,
+                throttle_secs: limits.throttle_secs,
+                timestamp: Utc::now(),
+            })
+        }
+
+        pub fn stats(&mut self, now: Instant) -> Vec<ActivityStats> {
+            let window = Duration::from_secs(self.limits.window_secs);
+            self.users
+                .iter_mut()
+                .map(|(user_id, user)| {
+                    user.prune(window, now);
+                    let (orders, cancels, trades) = (user.count(Activity::Order), user.count(Activity::Cancel), user.count(Activity::Trade));
+                    ActivityStats {
+                        user_id: user_id.clone(),
+                        orders,
+                        cancels,
+                        trades,
+                        order_to_trade: ratio(orders, trades),
+                        cancel_to_fill: ratio(cancels, trades),
+                        throttled_for_secs: user.throttled_until
+                            .filter(|until| now < *until)
+                            .map(|until| (until - now).as_secs()),
+                    }
+                })
+                .collect()
+        }
+    }
+}
+
+pub mod value_at_risk {
+    // 1-day 99% Value-at-Risk from sampled market returns
+    use chrono::{DateTime, Utc};
+    use serde::{Deserialize, Serialize};
+    use std::collections::{HashMap, VecDeque};
+    use std::time::Duration;
+
+    pub const CONFIDENCE: f64 = 0.99;
+    pub const Z_99: f64 = 2.326_347_874;
+    // Fewer scenarios than this give a meaningless tail estimate
+    pub const MIN_SCENARIOS: usize = 20;
+
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum VarModel {
+        #[default]
+        Historical,
+        Parametric,
+    }
+
+    impl std::str::FromStr for VarModel {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s.to_lowercase().as_str() {
+                "historical" => Ok(VarModel::Historical),
+                "parametric" => Ok(VarModel::Parametric),
+                other => Err(format!("Unknown VaR model: {}", other)),
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct VarEstimate {
+        pub user_id: String,
+        pub historical: Option<f64>,
+        pub parametric: Option<f64>,
+        pub scenarios: usize,
+        pub computed_at: DateTime<Utc>,
+    }
+
+    // Price returns between evenly spaced samples, one scenario per interval
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ReturnHistory {
+        scenarios: VecDeque<HashMap<String, f64>>, // symbol -> return over one interval
+        last_prices: HashMap<String, f64>,
+        max_scenarios: usize,
+        horizon_scale: f64, // sqrt-of-time factor from the sample interval to one day
+    }
+
+    impl ReturnHistory {
+        pub fn new(max_scenarios: usize, sample_interval: Duration) -> Self {
+            let interval_secs = sample_interval.as_secs_f64().max(1.0);
+            Self {
+                scenarios: VecDeque::new(),
+                last_prices: HashMap::new(),
+                max_scenarios,
+                horizon_scale: (86_400.0 / interval_secs).sqrt(),
+            }
+        }
+
+        // Rebuilds a history from recorded prices in timestamp order, sampling
+        // the last price of each symbol in every interval
+        pub fn from_prices<I>(max_scenarios: usize, sample_interval: Duration, prices: I) -> Self
+        where
+            I: IntoIterator<Item = (DateTime<Utc>, String, f64)>,
+        {
+            let mut history = Self::new(max_scenarios, sample_interval);
+            let interval_ms = sample_interval.as_millis().max(1000) as i64;
+            let mut bucket = None;
+            let mut sample: HashMap<String, f64> = HashMap::new();
+
+            for (timestamp, symbol, price) in prices {
+                let current = timestamp.timestamp_millis().div_euclid(interval_ms);
+                if bucket.is_some_and(|bucket| bucket != current) && !sample.is_empty() {
+                    history.record_prices(&sample);
+                    sample.clear();
+                }
+                bucket = Some(current);
+                sample.insert(symbol, price);
+            }
+            if !sample.is_empty() {
+                history.record_prices(&sample);
+            }
+            history
+        }
+
+        pub fn record_prices(&mut self, prices: &HashMap<String, f64>) {
+            let scenario: HashMap<String, f64> = prices
+                .iter()
+                .filter_map(|(symbol, price)| {
+                    let previous = self.last_prices.get(symbol).filter(|previous| **previous > 0.0)?;
+                    Some((symbol.clone(), price / previous - 1.0))
+                })
+                .collect();
+
+            self.last_prices.extend(prices.iter().map(|(symbol, price)| (symbol.clone(), *price)));
+            if scenario.is_empty() {
+                return;
+            }
+
+            self.scenarios.push_back(scenario);
+            while self.scenarios.len() > self.max_scenarios {
+                self.scenarios.pop_front();
+            }
+        }
+
+        pub fn len(&self) -> usize {
+            self.scenarios.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.scenarios.is_empty()
+        }
+
+        // Symbols missing from a scenario are treated as unchanged
+        fn scenario_return(scenario: &HashMap<String, f64>, symbol: &str) -> f64 {
+            scenario.get(symbol).copied().unwrap_or(0.0)
+        }
+
+        // Replays every stored scenario against today's exposures (signed
+        // notional per symbol) and reads the loss at the 1% tail
+        pub fn historical_var(&self, exposures: &HashMap<String, f64>) -> Option<f64> {
+            if self.scenarios.len() < MIN_SCENARIOS {
+                return None;
+            }
+
+            let mut pnl: Vec<f64> = self.scenarios
+                .iter()
+                .map(|scenario| {
+                    exposures
+                        .iter()
+                        .map(|(symbol, exposure)| exposure * Self::scenario_return(scenario, symbol) * self.horizon_scale)
+                        .sum()
+                })
+                .collect();
+            pnl.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+            let index = ((1.0 - CONFIDENCE) * pnl.len() as f64).floor() as usize;
+            Some((-pnl[index.min(pnl.len() - 1)]).max(0.0))
+        }
+
+        // Normal approximation: z * sqrt(w' * cov * w) scaled to one day
+        pub fn parametric_var(&self, exposures: &HashMap<String, f64>) -> Option<f64> {
+            let n = self.scenarios.len();
+            if n < MIN_SCENARIOS {
+                return None;
+            }
+
+            let symbols: Vec<&String> = exposures.keys().collect();
+            let series: Vec<Vec<f64>> = symbols
+                .iter()
+                .map(|symbol| self.scenarios.iter().map(|scenario| Self::scenario_return(scenario, symbol)).collect())
+                .collect();
+            let means: Vec<f64> = series.iter().map(|returns| returns.iter().sum::<f64>() / n as f64).collect();
+
+            let mut variance = 0.0;
+            for (i, a) in symbols.iter().enumerate() {
+                for (j, b) in symbols.iter().enumerate() {
+                    let covariance = series[i]
+                        .iter()
+                        .zip(&series[j])
+                        .map(|(ra, rb)| (ra - means[i]) * (rb - means[j]))
+                        .sum::<f64>()
+                        / (n - 1) as f64;
+                    variance += exposures[*a] * exposures[*b] * covariance;
+                }
+            }
+
+            Some(Z_99 * (variance.max(0.0)).sqrt() * self.horizon_scale)
+        }
+
+        pub fn var(&self, model: VarModel, exposures: &HashMap<String, f64>) -> Option<f64> {
+            match model {
+                VarModel::Historical => self.historical_var(exposures),
+                VarModel::Parametric => self.parametric_var(exposures),
+            }
+        }
+    }
+}
+
+pub mod risk_engine {
+    use crate::exchange_connector::Order;
+    use crate::expression::{self, Context, EvalError, Expr, Schema, Value, ValueType};
+    use crate::value_at_risk::{ReturnHistory, VarEstimate, VarModel, MIN_SCENARIOS};
+    use chrono::{DateTime, Utc};
+    use serde::{Deserialize, Serialize};
+    use std::collections::{HashMap, HashSet, VecDeque};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct PositionLimit {
+        pub max_long: f64,
+        pub max_short: f64,
+        pub max_exposure: f64,
+        pub max_daily_pnl: f64,
+        pub max_drawdown: f64,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct RiskRule {
+        pub name: String,
+        pub description: String,
+        pub condition: String,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ComplianceCheck {
+        pub name: String,
+        pub description: String,
+        pub validation: String,
+    }
+
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum PnlMethod {
+        #[default]
+        AverageCost,
+        Fifo,
+    }
+
+    impl std::str::FromStr for PnlMethod {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s.to_lowercase().as_str() {
+                "average_cost" | "avg" => Ok(PnlMethod::AverageCost),
+                "fifo" => Ok(PnlMethod::Fifo),
+                other => Err(format!("Unknown PnL method: {}", other)),
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Lot {
+        pub quantity: f64, // signed like the position
+        pub price: f64,
+    }
+
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+    pub struct Position {
+        pub quantity: f64, // positive long, negative short
+        pub avg_entry_price: f64,
+        pub realized_pnl: f64,
+        #[serde(default)]
+        pub lots: VecDeque<Lot>,
+    }
+
+    impl Position {
+        pub fn apply_fill(&mut self, side: &str, quantity: f64, price: f64, method: PnlMethod) {
+            let signed_quantity = match side {
+                "buy" => quantity,
+                "sell" => -quantity,
+                _ => return,
+            };
+
+            match method {
+                PnlMethod::AverageCost => self.apply_average_cost(signed_quantity, price),
+                PnlMethod::Fifo => self.apply_fifo(signed_quantity, price),
+            }
+        }
+
+        // Adding to a position moves the entry price, reducing it realizes
+        // PnL against the entry price
+        fn apply_average_cost(&mut self, signed_quantity: f64, price: f64) {
+            let quantity = signed_quantity.abs();
+            if self.quantity == 0.0 || self.quantity.signum() == signed_quantity.signum() {
+                let new_quantity = self.quantity + signed_quantity;
+                self.avg_entry_price = (self.avg_entry_price * self.quantity.abs() + price * quantity) / new_quantity.abs();
+                self.quantity = new_quantity;
+            } else {
+                let closed_quantity = quantity.min(self.quantity.abs());
+                self.realized_pnl += closed_quantity * (price - self.avg_entry_price) * self.quantity.signum();
+
+                let new_quantity = self.quantity + signed_quantity;
+                if new_quantity == 0.0 {
+                    self.avg_entry_price = 0.0;
+                } else if new_quantity.signum() != self.quantity.signum() {
+                    // Flipped through zero; the remainder opens at the fill price
+                    self.avg_entry_price = price;
+                }
+                self.quantity = new_quantity;
+            }
+
+            // Keep a single lot so switching to FIFO later stays consistent
+            self.lots.clear();
+            if self.quantity != 0.0 {
+                self.lots.push_back(Lot { quantity: self.quantity, price: self.avg_entry_price });
+            }
+        }
+
+        // Reductions close the oldest lots first
+        fn apply_fifo(&mut self, signed_quantity: f64, price: f64) {
+            let mut remaining = signed_quantity;
+
+            while remaining != 0.0 {
+                let Some(lot) = self.lots.front_mut() else { break };
+                if lot.quantity.signum() == remaining.signum() {
+                    break;
+                }
+
+                let closed_quantity = remaining.abs().min(lot.quantity.abs());
+                let direction = lot.quantity.signum();
+                self.realized_pnl += closed_quantity * (price - lot.price) * direction;
+                lot.quantity -= closed_quantity * direction;
+                remaining += closed_quantity * direction;
+
+                if lot.quantity == 0.0 {
+                    self.lots.pop_front();
+                }
+            }
+
+            if remaining != 0.0 {
+                self.lots.push_back(Lot { quantity: remaining, price });
+            }
+
+            self.quantity = self.lots.iter().map(|lot| lot.quantity).sum();
+            self.avg_entry_price = if self.quantity == 0.0 {
+                0.0
+            } else {
+                self.lots.iter().map(|lot| lot.quantity * lot.price).sum::<f64>() / self.quantity
+            };
+        }
+
+        pub fn unrealized_pnl(&self, mark_price: f64) -> f64 {
+            self.lots.iter().map(|lot| lot.quantity * (mark_price - lot.price)).sum()
+        }
+    }
+
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+    pub struct MarketPrice {
+        pub last: f64,
+        pub bid: Option<f64>,
+        pub ask: Option<f64>,
+        pub bid_size: Option<f64>,
+        pub ask_size: Option<f64>,
+    }
+
+    impl MarketPrice {
+        pub fn mid(&self) -> Option<f64> {
+            match (self.bid, self.ask) {
+                (Some(bid), Some(ask)) if bid > 0.0 && ask > 0.0 => Some((bid + ask) / 2.0),
+                _ => None,
+            }
+        }
+
+        // Mid when both sides are quoted, otherwise the last trade
+        pub fn reference(&self) -> Option<f64> {
+            self.mid().or(Some(self.last).filter(|last| *last > 0.0))
+        }
+    }
+
+    // PnL since the start of the trading session; breaches stay latched
+    // until the next session reset
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+    pub struct SessionPnl {
+        pub start_pnl: f64, // total PnL when the session started
+        pub peak_pnl: f64, // highest session PnL seen so far
+        pub daily_loss_breached: bool,
+        pub drawdown_breached: bool,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct LossLimitBreach {
+        pub user_id: String,
+        pub symbol: String,
+        pub limit_type: String, // "max_daily_pnl" or "max_drawdown"
+        pub limit: f64,
+        pub session_pnl: f64,
+        pub drawdown: f64,
+        pub timestamp: DateTime<Utc>,
+    }
+
+    // Compliance check name that enables the trade-through check
+    pub const TRADE_THROUGH_RULE: &str = "SEC Rule 611";
+
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum TradeThroughAction {
+        #[default]
+        Reject,
+        Flag, // let the order through but report it
+    }
+
+    impl std::str::FromStr for TradeThroughAction {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s.to_lowercase().as_str() {
+                "reject" => Ok(TradeThroughAction::Reject),
+                "flag" => Ok(TradeThroughAction::Flag),
+                other => Err(format!("Unknown trade-through action: {}", other)),
+            }
+        }
+    }
+
+    // Top of book on a single exchange
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ExchangeQuote {
+        pub bid: Option<f64>,
+        pub ask: Option<f64>,
+        pub bid_size: Option<f64>,
+        pub ask_size: Option<f64>,
+        pub timestamp: DateTime<Utc>,
+    }
+
+    // Best bid and offer across all exchanges with a fresh quote
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+    pub struct BboSnapshot {
+        pub symbol: String,
+        pub best_bid: Option<f64>,
+        pub best_bid_size: Option<f64>,
+        pub best_bid_exchange: Option<String>,
+        pub best_ask: Option<f64>,
+        pub best_ask_size: Option<f64>,
+        pub best_ask_exchange: Option<String>,
+        pub timestamp: Option<DateTime<Utc>>,
+    }
+
+    #[derive(Debug, Clone, Default)]
+    pub struct ComplianceOutcome {
+        pub violations: Vec<String>, // block the order
+        pub flags: Vec<String>, // reported, order still allowed
+        pub bbo: Option<BboSnapshot>,
+    }
+
+    // Pre-trade thresholds; unset fields are not checked
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+    pub struct FatFingerLimits {
+        #[serde(default)]
+        pub max_order_quantity: Option<f64>,
+        #[serde(default)]
+        pub max_order_notional: Option<f64>,
+        #[serde(default)]
+        pub max_price_deviation: Option<f64>, // fraction of the reference price, e.g. 0.05
+    }
+
+    // Cash in the quote currency; reserved funds back open orders
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+    pub struct AccountBalance {
+        pub cash: f64,
+        pub reserved: f64,
+    }
+
+    impl AccountBalance {
+        pub fn buying_power(&self) -> f64 {
+            self.cash - self.reserved
+        }
+    }
+
+    // Funds held for an open order, released as it fills or goes away
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Reservation {
+        pub user_id: String,
+        pub symbol: String,
+        pub side: String,
+        pub remaining: f64,
+        pub per_unit: f64, // reserved amount per unit of order quantity
+    }
+
+    // Static reference data for a tradable symbol
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct InstrumentSpec {
+        pub tick_size: f64,
+        pub lot_size: f64,
+        pub min_notional: f64,
+        #[serde(default)]
+        pub margin: Option<MarginRequirement>, // set for margined derivatives such as perpetuals
+        #[serde(default)]
+        pub base_currency: Option<String>,
+        #[serde(default)]
+        pub quote_currency: Option<String>,
+        #[serde(default)]
+        pub asset_class: Option<String>, // groups instruments on the same underlying; defaults to the base currency
+    }
+
+    // Portfolio-wide notional limits per user; unset fields are not checked
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+    pub struct PortfolioLimits {
+        #[serde(default)]
+        pub max_gross_notional: Option<f64>,
+        #[serde(default)]
+        pub max_net_notional: Option<f64>,
+        #[serde(default)]
+        pub max_asset_class_exposure: HashMap<String, f64>, // asset class -> max absolute net notional
+        #[serde(default)]
+        pub max_currency_exposure: HashMap<String, f64>, // currency -> max absolute net exposure
+    }
+
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+    pub struct PortfolioExposure {
+        pub user_id: String,
+        pub gross_notional: f64,
+        pub net_notional: f64,
+        pub asset_classes: HashMap<String, f64>, // signed net notional
+        pub currencies: HashMap<String, f64>, // long base, short quote for a long position
+    }
+
+    // Price shocks as fractions, e.g. -0.2, keyed by symbol or asset class.
+    // A symbol's own shock takes precedence over its asset class.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct StressScenario {
+        pub name: String,
+        pub shocks: HashMap<String, f64>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct StressResult {
+        pub scenario: String,
+        pub pnl: f64,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct StressReport {
+        pub user_id: String,
+        pub results: Vec<StressResult>,
+        pub worst_loss: f64,
+        pub loss_limit: Option<f64>,
+        pub computed_at: DateTime<Utc>,
+    }
+
+    fn tighter_limit(a: Option<f64>, b: Option<f64>) -> Option<f64> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    // Fractions of position notional
+    #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+    pub struct MarginRequirement {
+        pub initial: f64,
+        pub maintenance: f64,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum MarginStatus {
+        #[default]
+        Healthy,
+        MarginCall, // equity below initial margin
+        Liquidation, // equity below maintenance margin
+    }
+
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+    pub struct MarginUsage {
+        pub user_id: String,
+        pub equity: f64, // cash plus PnL on margined positions
+        pub position_notional: f64,
+        pub initial_margin: f64,
+        pub maintenance_margin: f64,
+        pub reserved: f64,
+        pub available: f64,
+        pub leverage: Option<f64>, // None once equity is gone
+        pub status: MarginStatus,
+    }
+
+    // Published whenever an account's margin status changes
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct MarginEvent {
+        pub user_id: String,
+        pub previous_status: MarginStatus,
+        pub status: MarginStatus,
+        pub equity: f64,
+        pub initial_margin: f64,
+        pub maintenance_margin: f64,
+        pub timestamp: DateTime<Utc>,
+    }
+
+    // Variables available to risk rule conditions
+    pub fn rule_schema() -> Schema {
+        let mut schema = Schema::new();
+        for name in ["order.side", "order.symbol", "order.order_type", "order.time_in_force", "order.user_id"] {
+            schema.insert(name.to_string(), ValueType::Text);
+        }
+        for name in [
+            "order.quantity",
+            "order.price",
+            "order.notional",
+            "position.quantity",
+            "position.projected_quantity",
+            "position.avg_entry_price",
+            "position.exposure",
+            "pnl.realized",
+            "pnl.unrealized",
+            "pnl.total",
+            "market_depth.bid_price",
+            "market_depth.bid_size",
+            "market_depth.ask_price",
+            "market_depth.ask_size",
+            "market_depth.mid_price",
+            "market_depth.last_price",
+            "instrument.tick_size",
+            "instrument.lot_size",
+            "instrument.min_notional",
+            "tick_size",
+            "lot_size",
+        ] {
+            schema.insert(name.to_string(), ValueType::Number);
+        }
+        schema
+    }
+
+    pub fn compile_risk_rules(rules: &[RiskRule]) -> Result<Vec<Expr>, String> {
+        let schema = rule_schema();
+        rules
+            .iter()
+            .map(|rule| expression::compile(&rule.condition, &schema).map_err(|e| format!("Invalid risk rule '{}': {}", rule.name, e)))
+            .collect()
+    }
+
+    // Everything the engine learns at runtime, as opposed to configuration
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+    pub struct RiskEngineState {
+        pub positions: HashMap<String, HashMap<String, Position>>,
+        pub last_prices: HashMap<String, f64>,
+        pub sessions: HashMap<String, HashMap<String, SessionPnl>>,
+        pub balances: HashMap<String, AccountBalance>,
+        pub reservations: HashMap<String, Reservation>,
+        pub margin_status: HashMap<String, MarginStatus>,
+        pub liquidations: HashMap<String, HashSet<String>>,
+        pub return_history: Option<ReturnHistory>,
+    }
+
+    pub struct RiskEngine {
+        position_limits: HashMap<String, PositionLimit>,
+        risk_rules: Vec<(RiskRule, Expr)>,
+        compliance_rules: Vec<ComplianceCheck>,
+        instruments: HashMap<String, InstrumentSpec>,
+        instrument_fat_finger: HashMap<String, FatFingerLimits>, // symbol -> limits
+        user_fat_finger: HashMap<String, FatFingerLimits>, // user_id -> limits
+        portfolio_limits: PortfolioLimits, // applies to every user
+        stress_scenarios: Vec<StressScenario>,
+        default_stress_loss_limit: Option<f64>,
+        stress_loss_limits: HashMap<String, f64>, // user_id -> max stressed loss
+        user_portfolio_limits: HashMap<String, PortfolioLimits>, // user_id -> limits
+        positions: HashMap<String, HashMap<String, Position>>, // user_id -> symbol -> position
+        last_prices: HashMap<String, f64>, // last fill price per symbol
+        market_prices: HashMap<String, MarketPrice>,
+        sessions: HashMap<String, HashMap<String, SessionPnl>>, // user_id -> symbol -> session
+        quotes: HashMap<String, HashMap<String, ExchangeQuote>>, // symbol -> exchange -> quote
+        balances: HashMap<String, AccountBalance>, // user_id -> balance
+        reservations: HashMap<String, Reservation>, // order_id -> reservation
+        leverage_limits: HashMap<String, f64>, // user_id -> max leverage
+        margin_status: HashMap<String, MarginStatus>, // user_id -> last reported status
+        liquidations: HashMap<String, HashSet<String>>, // user_id -> liquidation order ids in flight
+        return_history: ReturnHistory,
+        var_model: VarModel,
+        max_incremental_var: Option<f64>,
+        quote_max_age: chrono::Duration,
+        trade_through_action: TradeThroughAction,
+        pnl_method: PnlMethod,
+    }
+
+    impl RiskEngine {
+        pub fn new() -> Self {
+            Self {
+                position_limits: HashMap::new(),
+                risk_rules: Vec::new(),
+                compliance_rules: Vec::new(),
+                instruments: HashMap::new(),
+                instrument_fat_finger: HashMap::new(),
+                user_fat_finger: HashMap::new(),
+                portfolio_limits: PortfolioLimits::default(),
+                stress_scenarios: Vec::new(),
+                default_stress_loss_limit: None,
+                stress_loss_limits: HashMap::new(),
+                user_portfolio_limits: HashMap::new(),
+                positions: HashMap::new(),
+                last_prices: HashMap::new(),
+                market_prices: HashMap::new(),
+                sessions: HashMap::new(),
+                quotes: HashMap::new(),
+                balances: HashMap::new(),
+                reservations: HashMap::new(),
+                leverage_limits: HashMap::new(),
+                margin_status: HashMap::new(),
+                liquidations: HashMap::new(),
+                return_history: ReturnHistory::new(250, std::time::Duration::from_secs(3600)),
+                var_model: VarModel::default(),
+                max_incremental_var: None,
+                quote_max_age: chrono::Duration::seconds(5),
+                trade_through_action: TradeThroughAction::default(),
+                pnl_method: PnlMethod::default(),
+            }
+        }
+
+        pub fn set_pnl_method(&mut self, method: PnlMethod) {
+            self.pnl_method = method;
+        }
+
+        pub fn export_state(&self) -> RiskEngineState {
+            RiskEngineState {
+                positions: self.positions.clone(),
+                last_prices: self.last_prices.clone(),
+                sessions: self.sessions.clone(),
+                balances: self.balances.clone(),
+                reservations: self.reservations.clone(),
+                margin_status: self.margin_status.clone(),
+                liquidations: self.liquidations.clone(),
+                return_history: Some(self.return_history.clone()),
+            }
+        }
+
+        // Replaces runtime state; configuration set since construction is kept
+        pub fn restore_state(&mut self, state: RiskEngineState) {
+            self.positions = state.positions;
+            self.last_prices = state.last_prices;
+            self.sessions = state.sessions;
+            self.balances = state.balances;
+            self.reservations = state.reservations;
+            self.margin_status = state.margin_status;
+            self.liquidations = state.liquidations;
+            if let Some(return_history) = state.return_history {
+                self.return_history = return_history;
+            }
+        }
+
+        pub fn apply_fill(&mut self, user_id: &str, symbol: &str, side: &str, quantity: f64, price: f64) {
+            self.positions
+                .entry(user_id.to_string())
+                .or_default()
+                .entry(symbol.to_string())
+                .or_default()
+                .apply_fill(side, quantity, price, self.pnl_method);
+            self.last_prices.insert(symbol.to_string(), price);
+        }
+
+        pub fn update_market_price(&mut self, symbol: &str, price: MarketPrice) {
+            self.market_prices.insert(symbol.to_string(), price);
+        }
+
+        pub fn update_quote(&mut self, symbol: &str, exchange_id: &str, quote: ExchangeQuote) {
+            self.quotes.entry(symbol.to_string()).or_default().insert(exchange_id.to_string(), quote);
+        }
+
+        // Quotes older than this are no longer protected
+        pub fn set_quote_max_age(&mut self, max_age: chrono::Duration) {
+            self.quote_max_age = max_age;
+        }
+
+        pub fn set_trade_through_action(&mut self, action: TradeThroughAction) {
+            self.trade_through_action = action;
+        }
+
+        pub fn consolidated_bbo(&self, symbol: &str) -> Option<BboSnapshot> {
+            let cutoff = Utc::now() - self.quote_max_age;
+            let mut bbo = BboSnapshot { symbol: symbol.to_string(), ..BboSnapshot::default() };
+
+            for (exchange_id, quote) in self.quotes.get(symbol)?.iter().filter(|(_, quote)| quote.timestamp >= cutoff) {
+                if let Some(bid) = quote.bid.filter(|bid| bbo.best_bid.is_none_or(|best| *bid > best)) {
+                    bbo.best_bid = Some(bid);
+                    bbo.best_bid_size = quote.bid_size;
+                    bbo.best_bid_exchange = Some(exchange_id.clone());
+                }
+                if let Some(ask) = quote.ask.filter(|ask| bbo.best_ask.is_none_or(|best| *ask < best)) {
+                    bbo.best_ask = Some(ask);
+                    bbo.best_ask_size = quote.ask_size;
+                    bbo.best_ask_exchange = Some(exchange_id.clone());
+                }
+                bbo.timestamp = bbo.timestamp.max(Some(quote.timestamp));
+            }
 
-    pub struct RiskEngine {
-        position_limits: HashMap<String, PositionLimit>,
-        risk_rules: Vec<RiskRule>,
-        compliance_rules: Vec<ComplianceCheck>,
-        positions: HashMap<String, f64>,
-        pnl: HashMap<String, f64>,
-    }
+            if bbo.best_bid.is_none() && bbo.best_ask.is_none() { None } else { Some(bbo) }
+        }
 
-    impl RiskEngine {
-        pub fn new() -> Self {
-            Self {
-                position_limits: HashMap::new(),
-                risk_rules: Vec::new(),
-                compliance_rules: Vec::new(),
-                positions: HashMap::new(),
-                pnl: HashMap::new(),
+        pub fn get_market_price(&self, symbol: &str) -> Option<&MarketPrice> {
+            self.market_prices.get(symbol)
+        }
+
+        pub fn get_position(&self, user_id: &str, symbol: &str) -> Position {
+            self.positions
+                .get(user_id)
+                .and_then(|symbols| symbols.get(symbol))
+                .cloned()
+                .unwrap_or_default()
+        }
+
+        // Live mid, then the last traded price from market data
+        pub fn reference_price(&self, symbol: &str) -> Option<f64> {
+            self.market_prices.get(symbol).and_then(MarketPrice::reference)
+        }
+
+        // Reference price, falling back to our own last fill
+        pub fn mark_price(&self, symbol: &str) -> Option<f64> {
+            self.reference_price(symbol).or_else(|| self.last_prices.get(symbol).copied())
+        }
+
+        pub fn get_user_exposure(&self, user_id: &str, symbol: &str) -> f64 {
+            let position = self.get_position(user_id, symbol);
+            position.quantity.abs() * self.mark_price(symbol).unwrap_or(position.avg_entry_price)
+        }
+
+        pub fn get_user_realized_pnl(&self, user_id: &str, symbol: &str) -> f64 {
+            self.get_position(user_id, symbol).realized_pnl
+        }
+
+        pub fn get_user_unrealized_pnl(&self, user_id: &str, symbol: &str) -> f64 {
+            let position = self.get_position(user_id, symbol);
+            self.mark_price(symbol).map(|mark| position.unrealized_pnl(mark)).unwrap_or(0.0)
+        }
+
+        pub fn get_user_pnl(&self, user_id: &str, symbol: &str) -> f64 {
+            self.get_user_realized_pnl(user_id, symbol) + self.get_user_unrealized_pnl(user_id, symbol)
+        }
+
+        // (user_id, symbol) pairs with a tracked position
+        pub fn position_keys(&self) -> Vec<(String, String)> {
+            self.positions
+                .iter()
+                .flat_map(|(user_id, symbols)| symbols.keys().map(move |symbol| (user_id.clone(), symbol.clone())))
+                .collect()
+        }
+
+        // Returns the session PnL and the drawdown from the session peak
+        pub fn get_session_pnl(&self, user_id: &str, symbol: &str) -> (f64, f64) {
+            let session = self.sessions
+                .get(user_id)
+                .and_then(|symbols| symbols.get(symbol))
+                .cloned()
+                .unwrap_or_default();
+            let session_pnl = self.get_user_pnl(user_id, symbol) - session.start_pnl;
+            let peak_pnl = session.peak_pnl.max(session_pnl);
+            (session_pnl, peak_pnl - session_pnl)
+        }
+
+        // Updates the session peak and reports limits breached since the last call
+        pub fn refresh_loss_limits(&mut self, user_id: &str, symbol: &str) -> Vec<LossLimitBreach> {
+            let limit = match self.position_limits.get(symbol) {
+                Some(limit) => limit.clone(),
+                None => return Vec::new(),
+            };
+            let (session_pnl, drawdown) = self.get_session_pnl(user_id, symbol);
+            let session = self.sessions
+                .entry(user_id.to_string())
+                .or_default()
+                .entry(symbol.to_string())
+                .or_default();
+            session.peak_pnl = session.peak_pnl.max(session_pnl);
+
+            let mut breaches = Vec::new();
+            if !session.daily_loss_breached && limit.max_daily_pnl > 0.0 && session_pnl <= -limit.max_daily_pnl {
+                session.daily_loss_breached = true;
+                breaches.push(("max_daily_pnl", limit.max_daily_pnl));
+            }
+            if !session.drawdown_breached && limit.max_drawdown > 0.0 && drawdown >= limit.max_drawdown {
+                session.drawdown_breached = true;
+                breaches.push(("max_drawdown", limit.max_drawdown));
+            }
+
+            breaches
+                .into_iter()
+                .map(|(limit_type, limit)| LossLimitBreach {
+                    user_id: user_id.to_string(),
+                    symbol: symbol.to_string(),
+                    limit_type: limit_type.to_string(),
+                    limit,
+                    session_pnl,
+                    drawdown,
+                    timestamp: Utc::now(),
+                })
+                .collect()
+        }
+
+        // Re-marks every user holding the symbol, e.g. after a price update
+        pub fn refresh_symbol_loss_limits(&mut self, symbol: &str) -> Vec<LossLimitBreach> {
+            let users: Vec<String> = self.positions
+                .iter()
+                .filter(|(_, symbols)| symbols.contains_key(symbol))
+                .map(|(user_id, _)| user_id.clone())
+                .collect();
+            users.iter().flat_map(|user_id| self.refresh_loss_limits(user_id, symbol)).collect()
+        }
+
+        // Starts a new session from the current PnL and clears breaches
+        pub fn reset_sessions(&mut self) {
+            let mut sessions: HashMap<String, HashMap<String, SessionPnl>> = HashMap::new();
+            for (user_id, symbol) in self.position_keys() {
+                let start_pnl = self.get_user_pnl(&user_id, &symbol);
+                sessions.entry(user_id).or_default().insert(symbol, SessionPnl { start_pnl, ..SessionPnl::default() });
             }
+            self.sessions = sessions;
         }
 
         pub fn update_position_limits(&mut self, limits: HashMap<String, PositionLimit>) {
             self.position_limits = limits;
         }
 
-        pub fn add_risk_rules(&mut self, rules: Vec<RiskRule>) {
-            self.risk_rules = rules;
+        // Rules are compiled up front; on error the current rules stay in place
+        pub fn add_risk_rules(&mut self, rules: Vec<RiskRule>) -> Result<(), String> {
+            let compiled = compile_risk_rules(&rules)?;
+            self.risk_rules = rules.into_iter().zip(compiled).collect();
+            Ok(())
+        }
+
+        pub fn update_instruments(&mut self, instruments: HashMap<String, InstrumentSpec>) {
+            self.instruments = instruments;
+        }
+
+        pub fn update_fat_finger_limits(&mut self, instrument: HashMap<String, FatFingerLimits>, user: HashMap<String, FatFingerLimits>) {
+            self.instrument_fat_finger = instrument;
+            self.user_fat_finger = user;
+        }
+
+        // Instrument and user thresholds both apply, so the tighter one wins
+        fn effective_fat_finger_limits(&self, order: &Order) -> FatFingerLimits {
+            let instrument = self.instrument_fat_finger.get(&order.symbol).cloned().unwrap_or_default();
+            let user = self.user_fat_finger.get(&order.user_id).cloned().unwrap_or_default();
+            FatFingerLimits {
+                max_order_quantity: tighter_limit(instrument.max_order_quantity, user.max_order_quantity),
+                max_order_notional: tighter_limit(instrument.max_order_notional, user.max_order_notional),
+                max_price_deviation: tighter_limit(instrument.max_price_deviation, user.max_price_deviation),
+            }
+        }
+
+        pub fn check_fat_finger(&self, order: &Order) -> Option<Vec<String>> {
+            let limits = self.effective_fat_finger_limits(order);
+            let reference_price = self.reference_price(&order.symbol);
+            let is_market = order.order_type == "market";
+            let mut violations = Vec::new();
+
+            if let Some(max_quantity) = limits.max_order_quantity {
+                if order.quantity > max_quantity {
+                    violations.push(format!("max_order_quantity_exceeded: Order quantity {} > {}", order.quantity, max_quantity));
+                }
+            }
+
+            // Market orders carry no meaningful price, so value them at the reference
+            let notional_price = if is_market { reference_price } else { Some(order.price) };
+            if let (Some(max_notional), Some(price)) = (limits.max_order_notional, notional_price) {
+                let notional = order.quantity * price;
+                if notional > max_notional {
+                    violations.push(format!("max_order_notional_exceeded: Order notional {:.2} > {}", notional, max_notional));
+                }
+            }
+
+            if let (Some(max_deviation), Some(reference), false) = (limits.max_price_deviation, reference_price, is_market) {
+                let deviation = (order.price - reference).abs() / reference;
+                if deviation > max_deviation {
+                    violations.push(format!(
+                        "price_collar_breached: Order price {} deviates {:.2}% from reference {:.2}, limit {:.2}%",
+                        order.price, deviation * 100.0, reference, max_deviation * 100.0
+                    ));
+                }
+            }
+
+            if violations.is_empty() { None } else { Some(violations) }
+        }
+
+        pub fn update_portfolio_limits(&mut self, default: PortfolioLimits, user: HashMap<String, PortfolioLimits>) {
+            self.portfolio_limits = default;
+            self.user_portfolio_limits = user;
+        }
+
+        // Default and user limits both apply, so the tighter one wins
+        fn effective_portfolio_limits(&self, user_id: &str) -> PortfolioLimits {
+            let user = match self.user_portfolio_limits.get(user_id) {
+                Some(user) => user,
+                None => return self.portfolio_limits.clone(),
+            };
+            let merge = |a: &HashMap<String, f64>, b: &HashMap<String, f64>| {
+                let mut merged = a.clone();
+                for (key, limit) in b {
+                    merged.entry(key.clone()).and_modify(|current| *current = current.min(*limit)).or_insert(*limit);
+                }
+                merged
+            };
+            PortfolioLimits {
+                max_gross_notional: tighter_limit(self.portfolio_limits.max_gross_notional, user.max_gross_notional),
+                max_net_notional: tighter_limit(self.portfolio_limits.max_net_notional, user.max_net_notional),
+                max_asset_class_exposure: merge(&self.portfolio_limits.max_asset_class_exposure, &user.max_asset_class_exposure),
+                max_currency_exposure: merge(&self.portfolio_limits.max_currency_exposure, &user.max_currency_exposure),
+            }
+        }
+
+        // Reference data first, then a BASE/QUOTE symbol
+        fn instrument_currencies(&self, symbol: &str) -> (Option<String>, Option<String>) {
+            let spec = self.instruments.get(symbol);
+            let mut parts = symbol.splitn(2, '/');
+            let (parsed_base, parsed_quote) = match (parts.next(), parts.next()) {
+                (Some(base), Some(quote)) => (Some(base.to_string()), Some(quote.to_string())),
+                _ => (None, None),
+            };
+            (
+                spec.and_then(|spec| spec.base_currency.clone()).or(parsed_base),
+                spec.and_then(|spec| spec.quote_currency.clone()).or(parsed_quote),
+            )
+        }
+
+        fn asset_class(&self, symbol: &str) -> String {
+            self.instruments
+                .get(symbol)
+                .and_then(|spec| spec.asset_class.clone())
+                .or_else(|| self.instrument_currencies(symbol).0)
+                .unwrap_or_else(|| symbol.to_string())
+        }
+
+        // Aggregates signed notional per symbol; quote currencies are taken to
+        // be worth one unit of notional each
+        fn aggregate_exposure(&self, user_id: &str, exposures: &HashMap<String, f64>) -> PortfolioExposure {
+            let mut portfolio = PortfolioExposure { user_id: user_id.to_string(), ..PortfolioExposure::default() };
+            for (symbol, notional) in exposures {
+                portfolio.gross_notional += notional.abs();
+                portfolio.net_notional += notional;
+                *portfolio.asset_classes.entry(self.asset_class(symbol)).or_insert(0.0) += notional;
+
+                let (base, quote) = self.instrument_currencies(symbol);
+                if let Some(base) = base {
+                    *portfolio.currencies.entry(base).or_insert(0.0) += notional;
+                }
+                if let Some(quote) = quote {
+                    *portfolio.currencies.entry(quote).or_insert(0.0) -= notional;
+                }
+            }
+            portfolio
+        }
+
+        pub fn get_portfolio_exposure(&self, user_id: &str) -> PortfolioExposure {
+            self.aggregate_exposure(user_id, &self.get_user_exposures(user_id))
+        }
+
+        // Blocks orders that take a portfolio measure over its limit, or further
+        // over it; orders that reduce an existing excess still pass
+        pub fn check_portfolio_limits(&self, order: &Order) -> Option<Vec<String>> {
+            let signed_quantity = match order.side.as_str() {
+                "buy" => order.quantity,
+                "sell" => -order.quantity,
+                _ => return None,
+            };
+            let limits = self.effective_portfolio_limits(&order.user_id);
+            let exposures = self.get_user_exposures(&order.user_id);
+            let mut projected_exposures = exposures.clone();
+            *projected_exposures.entry(order.symbol.clone()).or_insert(0.0) += signed_quantity * self.order_price(order);
+
+            let current = self.aggregate_exposure(&order.user_id, &exposures);
+            let projected = self.aggregate_exposure(&order.user_id, &projected_exposures);
+            let breaches = |current: f64, projected: f64, limit: f64| projected.abs() > limit && projected.abs() > current.abs();
+            let mut violations = Vec::new();
+
+            if let Some(limit) = limits.max_gross_notional {
+                if breaches(current.gross_notional, projected.gross_notional, limit) {
+                    violations.push(format!("gross_notional_limit_exceeded: Gross notional would be {:.2} > {}", projected.gross_notional, limit));
+                }
+            }
+            if let Some(limit) = limits.max_net_notional {
+                if breaches(current.net_notional, projected.net_notional, limit) {
+                    violations.push(format!("net_notional_limit_exceeded: Net notional would be {:.2}, limit {}", projected.net_notional, limit));
+                }
+            }
+            for (asset_class, limit) in &limits.max_asset_class_exposure {
+                let before = current.asset_classes.get(asset_class).copied().unwrap_or(0.0);
+                let after = projected.asset_classes.get(asset_class).copied().unwrap_or(0.0);
+                if breaches(before, after, *limit) {
+                    violations.push(format!("asset_class_limit_exceeded: {} exposure would be {:.2}, limit {}", asset_class, after, limit));
+                }
+            }
+            for (currency, limit) in &limits.max_currency_exposure {
+                let before = current.currencies.get(currency).copied().unwrap_or(0.0);
+                let after = projected.currencies.get(currency).copied().unwrap_or(0.0);
+                if breaches(before, after, *limit) {
+                    violations.push(format!("currency_exposure_limit_exceeded: {} exposure would be {:.2}, limit {}", currency, after, limit));
+                }
+            }
+
+            if violations.is_empty() { None } else { Some(violations) }
+        }
+
+        pub fn update_stress_scenarios(&mut self, scenarios: Vec<StressScenario>) -> Result<(), String> {
+            for scenario in &scenarios {
+                if scenario.name.trim().is_empty() {
+                    return Err("Stress scenarios must have a name".to_string());
+                }
+                if let Some((key, shock)) = scenario.shocks.iter().find(|(_, shock)| !shock.is_finite() || **shock < -1.0) {
+                    return Err(format!("Stress scenario '{}' has an invalid shock {} for {}", scenario.name, shock, key));
+                }
+            }
+            self.stress_scenarios = scenarios;
+            Ok(())
+        }
+
+        // Accounts without their own limit use the default; with neither,
+        // orders are never rejected for stressed losses
+        pub fn set_stress_loss_limits(&mut self, default: Option<f64>, per_account: HashMap<String, f64>) {
+            self.default_stress_loss_limit = default;
+            self.stress_loss_limits = per_account;
+        }
+
+        pub fn stress_loss_limit(&self, user_id: &str) -> Option<f64> {
+            self.stress_loss_limits.get(user_id).copied().or(self.default_stress_loss_limit)
+        }
+
+        fn stressed_pnl(&self, scenario: &StressScenario, exposures: &HashMap<String, f64>) -> f64 {
+            exposures
+                .iter()
+                .map(|(symbol, notional)| {
+                    let shock = scenario.shocks
+                        .get(symbol)
+                        .or_else(|| scenario.shocks.get(&self.asset_class(symbol)))
+                        .copied()
+                        .unwrap_or(0.0);
+                    notional * shock
+                })
+                .sum()
+        }
+
+        // Largest loss across all scenarios, or zero if every scenario gains
+        fn worst_stress_loss(&self, exposures: &HashMap<String, f64>) -> (f64, Option<&str>) {
+            self.stress_scenarios
+                .iter()
+                .map(|scenario| (-self.stressed_pnl(scenario, exposures), Some(scenario.name.as_str())))
+                .fold((0.0, None), |worst, current| if current.0 > worst.0 { current } else { worst })
+        }
+
+        pub fn stress_report(&self, user_id: &str) -> StressReport {
+            let exposures = self.get_user_exposures(user_id);
+            StressReport {
+                user_id: user_id.to_string(),
+                results: self.stress_scenarios
+                    .iter()
+                    .map(|scenario| StressResult {
+                        scenario: scenario.name.clone(),
+                        pnl: self.stressed_pnl(scenario, &exposures),
+                    })
+                    .collect(),
+                worst_loss: self.worst_stress_loss(&exposures).0,
+                loss_limit: self.stress_loss_limit(user_id),
+                computed_at: Utc::now(),
+            }
+        }
+
+        pub fn stress_scenarios(&self) -> &[StressScenario] {
+            &self.stress_scenarios
+        }
+
+        // Applies every scenario to the positions plus the order; orders that
+        // lower the worst stressed loss still pass
+        pub fn check_stress_limits(&self, order: &Order) -> Option<Vec<String>> {
+            let limit = self.stress_loss_limit(&order.user_id)?;
+            let signed_quantity = match order.side.as_str() {
+                "buy" => order.quantity,
+                "sell" => -order.quantity,
+                _ => return None,
+            };
+            let exposures = self.get_user_exposures(&order.user_id);
+            let mut projected = exposures.clone();
+            *projected.entry(order.symbol.clone()).or_insert(0.0) += signed_quantity * self.order_price(order);
+
+            let (current_loss, _) = self.worst_stress_loss(&exposures);
+            let (projected_loss, scenario) = self.worst_stress_loss(&projected);
+            if projected_loss > limit && projected_loss > current_loss {
+                return Some(vec![format!(
+                    "stress_loss_limit_exceeded: Scenario '{}' loss would be {:.2} > {}",
+                    scenario.unwrap_or("unknown"), projected_loss, limit
+                )]);
+            }
+            None
         }
 
         pub fn add_compliance_rules(&mut self, rules: Vec<ComplianceCheck>) {
@@ -499,11 +5307,11 @@ pub mod risk_engine {
             let mut violations = Vec::new();
             
             if let Some(limit) = self.position_limits.get(&order.symbol) {
-                let current_position = self.positions.get(&order.symbol).unwrap_or(&0.0);
+                let current_position = self.get_position(&order.user_id, &order.symbol).quantity;
                 let new_position = match order.side.as_str() {
                     "buy" => current_position + order.quantity,
                     "sell" => current_position - order.quantity,
-                    _ => *current_position,
+                    _ => current_position,
                 };
 
                 if new_position > limit.max_long {
@@ -523,24 +5331,543 @@ pub mod risk_engine {
             if violations.is_empty() { None } else { Some(violations) }
         }
 
-        pub fn check_risk_rules(&self, _order: &Order) -> Option<Vec<String>> {
-            // Simplified risk rule checking
-            // In production, this would evaluate the rule conditions
+        // Once a loss limit is hit only orders that shrink the position pass
+        pub fn check_loss_limits(&self, order: &Order) -> Option<Vec<String>> {
+            let limit = self.position_limits.get(&order.symbol)?;
+            let current_position = self.get_position(&order.user_id, &order.symbol).quantity;
+            let new_position = match order.side.as_str() {
+                "buy" => current_position + order.quantity,
+                "sell" => current_position - order.quantity,
+                _ => current_position,
+            };
+            if new_position.abs() <= current_position.abs() && new_position * current_position >= 0.0 {
+                return None;
+            }
+
+            let session = self.sessions
+                .get(&order.user_id)
+                .and_then(|symbols| symbols.get(&order.symbol))
+                .cloned()
+                .unwrap_or_default();
+            let (session_pnl, drawdown) = self.get_session_pnl(&order.user_id, &order.symbol);
+            let mut violations = Vec::new();
+
+            if session.daily_loss_breached || (limit.max_daily_pnl > 0.0 && session_pnl <= -limit.max_daily_pnl) {
+                violations.push(format!("Daily loss limit of {} breached, session PnL: {:.2}", limit.max_daily_pnl, session_pnl));
+            }
+
+            if session.drawdown_breached || (limit.max_drawdown > 0.0 && drawdown >= limit.max_drawdown) {
+                violations.push(format!("Drawdown limit of {} breached, current drawdown: {:.2}", limit.max_drawdown, drawdown));
+            }
+
+            if violations.is_empty() { None } else { Some(violations) }
+        }
+
+        pub fn check_risk_rules(&self, order: &Order) -> Option<Vec<String>> {
+            if self.risk_rules.is_empty() {
+                return None;
+            }
+
+            let context = self.build_rule_context(order);
+            let mut violations = Vec::new();
+
+            for (rule, expr) in &self.risk_rules {
+                match expression::evaluate(expr, &context) {
+                    Ok(Value::Bool(true)) => {}
+                    Ok(_) => violations.push(format!("Risk rule {} failed: {}", rule.name, rule.condition)),
+                    // Rules that depend on data we don't have yet are skipped
+                    Err(EvalError::Unavailable(name)) => {
+                        tracing::debug!("Skipping risk rule {} for {}: {} is not available", rule.name, order.symbol, name);
+                    }
+                    Err(e) => violations.push(format!("Risk rule {} could not be evaluated: {}", rule.name, e)),
+                }
+            }
+
+            if violations.is_empty() { None } else { Some(violations) }
+        }
+
+        fn build_rule_context(&self, order: &Order) -> Context {
+            let mut context = Context::new();
+            context.set_text("order.side", &order.side);
+            context.set_text("order.symbol", &order.symbol);
+            context.set_text("order.order_type", &order.order_type);
+            context.set_text("order.time_in_force", &order.time_in_force);
+            context.set_text("order.user_id", &order.user_id);
+            context.set_number("order.quantity", order.quantity);
+            context.set_number("order.price", order.price);
+            context.set_number("order.notional", order.quantity * order.price);
+
+            let position = self.get_position(&order.user_id, &order.symbol);
+            let projected_quantity = match order.side.as_str() {
+                "buy" => position.quantity + order.quantity,
+                "sell" => position.quantity - order.quantity,
+                _ => position.quantity,
+            };
+            context.set_number("position.quantity", position.quantity);
+            context.set_number("position.projected_quantity", projected_quantity);
+            context.set_number("position.avg_entry_price", position.avg_entry_price);
+            context.set_number("position.exposure", self.get_user_exposure(&order.user_id, &order.symbol));
+            context.set_number("pnl.realized", self.get_user_realized_pnl(&order.user_id, &order.symbol));
+            context.set_number("pnl.unrealized", self.get_user_unrealized_pnl(&order.user_id, &order.symbol));
+            context.set_number("pnl.total", self.get_user_pnl(&order.user_id, &order.symbol));
+
+            if let Some(market) = self.market_prices.get(&order.symbol) {
+                let fields = [
+                    ("market_depth.bid_price", market.bid),
+                    ("market_depth.bid_size", market.bid_size),
+                    ("market_depth.ask_price", market.ask),
+                    ("market_depth.ask_size", market.ask_size),
+                    ("market_depth.mid_price", market.mid()),
+                    ("market_depth.last_price", Some(market.last).filter(|last| *last > 0.0)),
+                ];
+                for (name, value) in fields {
+                    if let Some(value) = value {
+                        context.set_number(name, value);
+                    }
+                }
+            }
+
+            if let Some(instrument) = self.instruments.get(&order.symbol) {
+                context.set_number("instrument.tick_size", instrument.tick_size);
+                context.set_number("instrument.lot_size", instrument.lot_size);
+                context.set_number("instrument.min_notional", instrument.min_notional);
+                context.set_number("tick_size", instrument.tick_size);
+                context.set_number("lot_size", instrument.lot_size);
+            }
+
+            context
+        }
+
+        pub fn check_compliance(&self, order: &Order) -> ComplianceOutcome {
+            let mut outcome = ComplianceOutcome::default();
+
+            // Only the trade-through rule can be checked pre-trade; the rest
+            // are enforced by the compliance gateway
+            if self.compliance_rules.iter().any(|rule| rule.name == TRADE_THROUGH_RULE) {
+                if let Some((finding, bbo)) = self.check_trade_through(order) {
+                    match self.trade_through_action {
+                        TradeThroughAction::Reject => outcome.violations.push(finding),
+                        TradeThroughAction::Flag => outcome.flags.push(finding),
+                    }
+                    outcome.bbo = Some(bbo);
+                }
+            }
+
+            outcome
+        }
+
+        // A limit order trades through when it would execute at a worse price
+        // than the protected quote on another exchange
+        fn check_trade_through(&self, order: &Order) -> Option<(String, BboSnapshot)> {
+            if order.order_type == "market" {
+                return None;
+            }
+            let bbo = self.consolidated_bbo(&order.symbol)?;
+
+            let finding = match order.side.as_str() {
+                "buy" => {
+                    let best_ask = bbo.best_ask.filter(|ask| order.price > *ask)?;
+                    format!(
+                        "trade_through: Buy limit {} trades through protected offer {} on {}",
+                        order.price, best_ask, bbo.best_ask_exchange.as_deref().unwrap_or("unknown")
+                    )
+                }
+                "sell" => {
+                    let best_bid = bbo.best_bid.filter(|bid| order.price < *bid)?;
+                    format!(
+                        "trade_through: Sell limit {} trades through protected bid {} on {}",
+                        order.price, best_bid, bbo.best_bid_exchange.as_deref().unwrap_or("unknown")
+                    )
+                }
+                _ => return None,
+            };
+
+            Some((finding, bbo))
+        }
+
+        // Seeds cash balances; funds already reserved for open orders are kept
+        pub fn update_account_balances(&mut self, balances: HashMap<String, f64>) {
+            for (user_id, cash) in balances {
+                self.balances.entry(user_id).or_default().cash = cash;
+            }
+        }
+
+        pub fn get_account_balance(&self, user_id: &str) -> Option<AccountBalance> {
+            self.balances.get(user_id).cloned()
+        }
+
+        fn order_price(&self, order: &Order) -> f64 {
+            if order.order_type == "market" {
+                self.reference_price(&order.symbol).unwrap_or(order.price)
+            } else {
+                order.price
+            }
+        }
+
+        // The part of the order that opens or extends a position
+        fn opening_quantity(&self, order: &Order) -> f64 {
+            let position = self.get_position(&order.user_id, &order.symbol).quantity;
+            match order.side.as_str() {
+                "buy" => (order.quantity - (-position).max(0.0)).max(0.0),
+                "sell" => (order.quantity - position.max(0.0)).max(0.0),
+                _ => 0.0,
+            }
+        }
+
+        // Spot buys need the full notional and sells only the part that opens
+        // a short; margined instruments need initial margin on the opening part
+        fn required_funds(&self, order: &Order) -> f64 {
+            let price = self.order_price(order);
+            if let Some(margin) = self.margin_requirement(&order.symbol) {
+                return self.opening_quantity(order) * price * margin.initial;
+            }
+            let quantity = match order.side.as_str() {
+                "buy" => order.quantity,
+                "sell" => self.opening_quantity(order),
+                _ => 0.0,
+            };
+            quantity * price
+        }
+
+        // Only accounts with a configured balance are credit checked
+        pub fn check_buying_power(&self, order: &Order) -> Option<Vec<String>> {
+            let available = self.get_margin_usage(&order.user_id)?.available;
+            let required = self.required_funds(order);
+            if required > available {
+                return Some(vec![format!(
+                    "insufficient_buying_power: Order requires {:.2}, available {:.2}",
+                    required, available
+                )]);
+            }
             None
         }
 
-        pub fn check_compliance(&self, _order: &Order) -> Option<Vec<String>> {
-            // Simplified compliance checking
-            // In production, this would validate against compliance rules
+        pub fn reserve_funds(&mut self, order: &Order) {
+            let required = self.required_funds(order);
+            let balance = match self.balances.get_mut(&order.user_id) {
+                Some(balance) => balance,
+                None => return,
+            };
+            balance.reserved += required;
+            self.reservations.insert(order.order_id.clone(), Reservation {
+                user_id: order.user_id.clone(),
+                symbol: order.symbol.clone(),
+                side: order.side.clone(),
+                remaining: required,
+                per_unit: if order.quantity > 0.0 { required / order.quantity } else { 0.0 },
+            });
+        }
+
+        // Returns whatever is still reserved for a cancelled, rejected or
+        // completed order
+        pub fn release_reservation(&mut self, order_id: &str) {
+            if let Some(reservation) = self.reservations.remove(order_id) {
+                if let Some(balance) = self.balances.get_mut(&reservation.user_id) {
+                    balance.reserved = (balance.reserved - reservation.remaining).max(0.0);
+                }
+            }
+        }
+
+        // Moves cash for a fill and releases the matching part of the reservation.
+        // Margined fills move no cash; their PnL counts towards equity instead.
+        pub fn settle_fill(&mut self, order_id: &str, quantity: f64, price: f64) {
+            let reservation = match self.reservations.get_mut(order_id) {
+                Some(reservation) => reservation,
+                None => return,
+            };
+            let released = (reservation.per_unit * quantity).min(reservation.remaining);
+            reservation.remaining -= released;
+            let (user_id, symbol, side) = (reservation.user_id.clone(), reservation.symbol.clone(), reservation.side.clone());
+            let margined = self.margin_requirement(&symbol).is_some();
+
+            if let Some(balance) = self.balances.get_mut(&user_id) {
+                balance.reserved = (balance.reserved - released).max(0.0);
+                if margined {
+                    return;
+                }
+                match side.as_str() {
+                    "buy" => balance.cash -= quantity * price,
+                    "sell" => balance.cash += quantity * price,
+                    _ => {}
+                }
+            }
+        }
+
+        pub fn margin_requirement(&self, symbol: &str) -> Option<MarginRequirement> {
+            self.instruments.get(symbol).and_then(|spec| spec.margin)
+        }
+
+        pub fn update_leverage_limits(&mut self, limits: HashMap<String, f64>) {
+            self.leverage_limits = limits;
+        }
+
+        // Accounts with a configured balance
+        pub fn margin_accounts(&self) -> Vec<String> {
+            self.balances.keys().cloned().collect()
+        }
+
+        // Marks margined positions to market against the account's cash
+        pub fn get_margin_usage(&self, user_id: &str) -> Option<MarginUsage> {
+            let balance = self.balances.get(user_id)?;
+            let mut usage = MarginUsage {
+                user_id: user_id.to_string(),
+                equity: balance.cash,
+                reserved: balance.reserved,
+                ..MarginUsage::default()
+            };
+
+            for (symbol, position) in self.positions.get(user_id).into_iter().flatten() {
+                let margin = match self.margin_requirement(symbol) {
+                    Some(margin) => margin,
+                    None => continue,
+                };
+                let mark = self.mark_price(symbol).unwrap_or(position.avg_entry_price);
+                let notional = position.quantity.abs() * mark;
+                usage.equity += position.realized_pnl + position.unrealized_pnl(mark);
+                usage.position_notional += notional;
+                usage.initial_margin += notional * margin.initial;
+                usage.maintenance_margin += notional * margin.maintenance;
+            }
+
+            usage.available = usage.equity - usage.initial_margin - usage.reserved;
+            usage.leverage = if usage.equity > 0.0 { Some(usage.position_notional / usage.equity) } else { None };
+            usage.status = if usage.position_notional > 0.0 && usage.equity < usage.maintenance_margin {
+                MarginStatus::Liquidation
+            } else if usage.position_notional > 0.0 && usage.equity < usage.initial_margin {
+                MarginStatus::MarginCall
+            } else {
+                MarginStatus::Healthy
+            };
+            Some(usage)
+        }
+
+        // Caps leverage on margined orders and blocks new exposure while an
+        // account is on margin call
+        pub fn check_margin(&self, order: &Order) -> Option<Vec<String>> {
+            self.margin_requirement(&order.symbol)?;
+            let usage = self.get_margin_usage(&order.user_id)?;
+            let opening_quantity = self.opening_quantity(order);
+            if opening_quantity <= 0.0 {
+                return None;
+            }
+
+            let mut violations = Vec::new();
+            if usage.status != MarginStatus::Healthy {
+                violations.push(format!("margin_call_active: Account is in {:?}, only reducing orders are accepted", usage.status));
+            }
+            if let Some(max_leverage) = self.leverage_limits.get(&order.user_id) {
+                let projected_notional = usage.position_notional + opening_quantity * self.order_price(order);
+                if usage.equity <= 0.0 || projected_notional / usage.equity > *max_leverage {
+                    violations.push(format!(
+                        "leverage_limit_exceeded: Projected notional {:.2} on equity {:.2} exceeds {}x",
+                        projected_notional, usage.equity, max_leverage
+                    ));
+                }
+            }
+
+            if violations.is_empty() { None } else { Some(violations) }
+        }
+
+        // Reports a margin status change since the last call
+        pub fn refresh_margin(&mut self, user_id: &str) -> Option<MarginEvent> {
+            let usage = self.get_margin_usage(user_id)?;
+            let previous_status = self.margin_status.insert(user_id.to_string(), usage.status).unwrap_or_default();
+            if previous_status == usage.status {
+                return None;
+            }
+            Some(MarginEvent {
+                user_id: user_id.to_string(),
+                previous_status,
+                status: usage.status,
+                equity: usage.equity,
+                initial_margin: usage.initial_margin,
+                maintenance_margin: usage.maintenance_margin,
+                timestamp: Utc::now(),
+            })
+        }
+
+        // Market orders that shrink the largest margined positions until equity
+        // covers initial margin again, or close everything once equity is gone.
+        // Nothing new is generated while earlier liquidation orders are in flight.
+        pub fn liquidation_orders(&mut self, user_id: &str) -> Vec<Order> {
+            let usage = match self.get_margin_usage(user_id) {
+                Some(usage) if usage.status == MarginStatus::Liquidation => usage,
+                _ => return Vec::new(),
+            };
+            if self.liquidations.get(user_id).is_some_and(|orders| !orders.is_empty()) {
+                return Vec::new();
+            }
+
+            let mut candidates: Vec<(String, f64, f64, MarginRequirement)> = self.positions
+                .get(user_id)
+                .into_iter()
+                .flatten()
+                .filter(|(_, position)| position.quantity != 0.0)
+                .filter_map(|(symbol, position)| {
+                    let margin = self.margin_requirement(symbol)?;
+                    let mark = self.mark_price(symbol).unwrap_or(position.avg_entry_price);
+                    Some((symbol.clone(), position.quantity, mark, margin))
+                })
+                .collect();
+            candidates.sort_by(|a, b| (b.1.abs() * b.2).total_cmp(&(a.1.abs() * a.2)));
+
+            let mut shortfall = usage.initial_margin - usage.equity.max(0.0);
+            let mut orders = Vec::new();
+            for (symbol, position, mark, margin) in candidates {
+                if shortfall <= 0.0 {
+                    break;
+                }
+                let margin_per_unit = mark * margin.initial;
+                let mut quantity = if usage.equity <= 0.0 || margin_per_unit <= 0.0 {
+                    position.abs()
+                } else {
+                    (shortfall / margin_per_unit).min(position.abs())
+                };
+                if let Some(lot_size) = self.instruments.get(&symbol).map(|spec| spec.lot_size).filter(|lot| *lot > 0.0) {
+                    quantity = ((quantity / lot_size).ceil() * lot_size).min(position.abs());
+                }
+                shortfall -= quantity * margin_per_unit;
+
+                let order_id = format!("liq-{}", uuid::Uuid::new_v4());
+                orders.push(Order {
+                    order_id: order_id.clone(),
+                    client_order_id: order_id,
+                    symbol,
+                    price: mark,
+                    quantity,
+                    side: if position > 0.0 { "sell" } else { "buy" }.to_string(),
+                    order_type: "market".to_string(),
+                    time_in_force: "ioc".to_string(),
+                    user_id: user_id.to_string(),
+                    timestamp: Utc::now(),
+                });
+            }
+
+            self.liquidations
+                .entry(user_id.to_string())
+                .or_default()
+                .extend(orders.iter().map(|order| order.order_id.clone()));
+            orders
+        }
+
+        // Called once a liquidation order is filled or cancelled
+        pub fn close_liquidation(&mut self, order_id: &str) {
+            for orders in self.liquidations.values_mut() {
+                orders.remove(order_id);
+            }
+        }
+
+        // Starts a fresh return history with the given lookback and sample spacing
+        pub fn configure_var(&mut self, max_scenarios: usize, sample_interval: std::time::Duration) {
+            self.return_history = ReturnHistory::new(max_scenarios, sample_interval);
+        }
+
+        // With no limit set, orders are never rejected for VaR
+        pub fn set_var_limit(&mut self, model: VarModel, max_incremental_var: Option<f64>) {
+            self.var_model = model;
+            self.max_incremental_var = max_incremental_var;
+        }
+
+        // Replaces the return history with one rebuilt from recorded prices,
+        // unless the current one (restored from a snapshot) has more scenarios.
+        // Returns the number of scenarios now held.
+        pub fn seed_return_history<I>(&mut self, max_scenarios: usize, sample_interval: std::time::Duration, prices: I) -> usize
+        where
+            I: IntoIterator<Item = (DateTime<Utc>, String, f64)>,
+        {
+            let seeded = ReturnHistory::from_prices(max_scenarios, sample_interval, prices);
+            if seeded.len() >= self.return_history.len() {
+                self.return_history = seeded;
+            }
+            self.return_history.len()
+        }
+
+        // Adds one return scenario from the current reference prices
+        pub fn record_return_sample(&mut self) {
+            let prices: HashMap<String, f64> = self.market_prices
+                .keys()
+                .filter_map(|symbol| self.reference_price(symbol).map(|price| (symbol.clone(), price)))
+                .collect();
+            self.return_history.record_prices(&prices);
+        }
+
+        pub fn users(&self) -> Vec<String> {
+            self.positions.keys().cloned().collect()
+        }
+
+        // Signed notional per symbol at the mark price
+        pub fn get_user_exposures(&self, user_id: &str) -> HashMap<String, f64> {
+            self.positions
+                .get(user_id)
+                .map(|symbols| {
+                    symbols
+                        .iter()
+                        .filter(|(_, position)| position.quantity != 0.0)
+                        .map(|(symbol, position)| {
+                            let price = self.mark_price(symbol).unwrap_or(position.avg_entry_price);
+                            (symbol.clone(), position.quantity * price)
+                        })
+                        .collect()
+                })
+                .unwrap_or_default()
+        }
+
+        pub fn compute_var(&self, user_id: &str) -> VarEstimate {
+            let exposures = self.get_user_exposures(user_id);
+            VarEstimate {
+                user_id: user_id.to_string(),
+                historical: self.return_history.historical_var(&exposures),
+                parametric: self.return_history.parametric_var(&exposures),
+                scenarios: self.return_history.len(),
+                computed_at: Utc::now(),
+            }
+        }
+
+        // Rejects orders whose fill would add more than the limit to the user's VaR
+        pub fn check_var_limit(&self, order: &Order) -> Option<Vec<String>> {
+            let max_incremental_var = self.max_incremental_var?;
+            let signed_quantity = match order.side.as_str() {
+                "buy" => order.quantity,
+                "sell" => -order.quantity,
+                _ => return None,
+            };
+            let price = match self.reference_price(&order.symbol) {
+                Some(price) if order.order_type == "market" => price,
+                _ => order.price,
+            };
+
+            // A limit that cannot be evaluated yet fails closed
+            if self.return_history.len() < MIN_SCENARIOS {
+                return Some(vec![format!(
+                    "var_history_insufficient: {} of {} return scenarios recorded",
+                    self.return_history.len(), MIN_SCENARIOS
+                )]);
+            }
+
+            let current = self.get_user_exposures(&order.user_id);
+            let mut projected = current.clone();
+            *projected.entry(order.symbol.clone()).or_insert(0.0) += signed_quantity * price;
+
+            let current_var = self.return_history.var(self.var_model, &current)?;
+            let projected_var = self.return_history.var(self.var_model, &projected)?;
+            let incremental_var = projected_var - current_var;
+
+            if incremental_var > max_incremental_var {
+                return Some(vec![format!(
+                    "var_limit_exceeded: Incremental VaR {:.2} > {} ({:?})",
+                    incremental_var, max_incremental_var, self.var_model
+                )]);
+            }
             None
         }
 
+        // Aggregated across all users
         pub fn get_position_exposure(&self, symbol: &str) -> f64 {
-            self.positions.get(symbol).unwrap_or(&0.0).abs() * 50000.0 // Simplified
+            self.positions.keys().map(|user_id| self.get_user_exposure(user_id, symbol)).sum()
         }
 
         pub fn get_position_pnl(&self, symbol: &str) -> f64 {
-            self.pnl.get(symbol).unwrap_or(&0.0).clone()
+            self.positions.keys().map(|user_id| self.get_user_pnl(user_id, symbol)).sum()
         }
     }
 }
@@ -0,0 +1,308 @@
+// Binance adapter against a local stand-in for the REST API and streams that
+// replays payloads recorded from Binance spot.
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, RawQuery, State};
+use axum::http::{HeaderMap, Method, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{any, get, post};
+use axum::Router;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use polaris_core::exchange_connector::{connect, AdapterKind, ConnectorConfig, ExchangeConnector, ExchangeType, MarketDataEvent, OrderStatus};
+use polaris_core::Order;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const API_KEY: &str = "vmPUZE6mv9SD5VNHk4HlWFsOr6aKE2zvsw0MuIgwCIPy6utIco14y7Ju91duEh8A";
+const API_SECRET: &str = "NhqPtmdSJYdKjVHjA7PZj4Mge3R5YNiP1e3UZjInClVN65XAbvqqM6A7H5fATj0j";
+const LISTEN_KEY: &str = "pqia91ma19a5s61cv6a81va65sdf19v8a65a1a5s61cv6a81va65sdf19v8a65a1";
+
+#[derive(Clone, Default)]
+struct MockBinance {
+    // "METHOD path?query" of every request, in arrival order
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockBinance {
+    fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+fn json(status: StatusCode, body: &'static str) -> Response {
+    (status, [("content-type", "application/json")], body).into_response()
+}
+
+fn param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query.split('&').find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+}
+
+// Checks a signed request the way Binance does: API key header, recvWindow
+// around the timestamp and an HMAC-SHA256 of the query before the signature
+fn verify(headers: &HeaderMap, query: &str) -> Result<(), Box<Response>> {
+    if headers.get("X-MBX-APIKEY").and_then(|v| v.to_str().ok()) != Some(API_KEY) {
+        return Err(Box::new(json(StatusCode::UNAUTHORIZED, r#"{"code":-2015,"msg":"Invalid API-key, IP, or permissions for action."}"#)));
+    }
+    let (payload, signature) = query.split_once("&signature=")
+        .ok_or_else(|| Box::new(json(StatusCode::BAD_REQUEST, r#"{"code":-1102,"msg":"Mandatory parameter 'signature' was not sent."}"#)))?;
+    let timestamp: i64 = param(payload, "timestamp").and_then(|v| v.parse().ok()).unwrap_or(0);
+    let recv_window: i64 = param(payload, "recvWindow").and_then(|v| v.parse().ok()).unwrap_or(0);
+    if recv_window <= 0 || (Utc::now().timestamp_millis() - timestamp).abs() > recv_window {
+        return Err(Box::new(json(StatusCode::BAD_REQUEST, r#"{"code":-1021,"msg":"Timestamp for this request is outside of the recvWindow."}"#)));
+    }
+
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(API_SECRET.as_bytes()).unwrap();
+    mac.update(payload.as_bytes());
+    if hex::encode(mac.finalize().into_bytes()) != signature {
+        return Err(Box::new(json(StatusCode::BAD_REQUEST, include_str!("fixtures/binance/invalid_signature.json"))));
+    }
+    Ok(())
+}
+
+async fn order_endpoint(State(mock): State<MockBinance>, method: Method, headers: HeaderMap, RawQuery(query): RawQuery) -> Response {
+    let query = query.unwrap_or_default();
+    mock.requests.lock().unwrap().push(format!("{} /api/v3/order?{}", method, query));
+    if let Err(response) = verify(&headers, &query) {
+        return *response;
+    }
+
+    match method {
+        Method::POST if param(&query, "quantity").and_then(|q| q.parse::<f64>().ok()).unwrap_or(0.0) > 100.0 => {
+            json(StatusCode::BAD_REQUEST, include_str!("fixtures/binance/insufficient_balance.json"))
+        }
+        Method::POST if param(&query, "timeInForce") == Some("IOC") => {
+            json(StatusCode::OK, include_str!("fixtures/binance/order_ioc_expired.json"))
+        }
+        Method::POST => json(StatusCode::OK, include_str!("fixtures/binance/order_new.json")),
+        Method::GET => json(StatusCode::OK, include_str!("fixtures/binance/order_partially_filled.json")),
+        Method::DELETE => json(StatusCode::OK, include_str!("fixtures/binance/order_canceled.json")),
+        _ => StatusCode::METHOD_NOT_ALLOWED.into_response(),
+    }
+}
+
+async fn cancel_replace(State(mock): State<MockBinance>, headers: HeaderMap, RawQuery(query): RawQuery) -> Response {
+    let query = query.unwrap_or_default();
+    mock.requests.lock().unwrap().push(format!("POST /api/v3/order/cancelReplace?{}", query));
+    if let Err(response) = verify(&headers, &query) {
+        return *response;
+    }
+    json(StatusCode::OK, include_str!("fixtures/binance/cancel_replace.json"))
+}
+
+async fn user_data_stream(State(mock): State<MockBinance>, method: Method, headers: HeaderMap, RawQuery(query): RawQuery) -> Response {
+    mock.requests.lock().unwrap().push(format!("{} /api/v3/userDataStream?{}", method, query.unwrap_or_default()));
+    if headers.get("X-MBX-APIKEY").and_then(|v| v.to_str().ok()) != Some(API_KEY) {
+        return json(StatusCode::UNAUTHORIZED, r#"{"code":-2015,"msg":"Invalid API-key, IP, or permissions for action."}"#);
+    }
+    match method {
+        Method::POST => json(StatusCode::OK, include_str!("fixtures/binance/listen_key.json")),
+        _ => json(StatusCode::OK, "{}"),
+    }
+}
+
+// Plays the recording, then holds the connection open like the real stream
+async fn replay(mut socket: WebSocket, recording: &'static str) {
+    for line in recording.lines().filter(|line| !line.is_empty()) {
+        if socket.send(Message::Text(line.to_string())).await.is_err() {
+            return;
+        }
+    }
+    while let Some(Ok(_)) = socket.recv().await {}
+}
+
+async fn user_data_socket(State(mock): State<MockBinance>, Path(listen_key): Path<String>, upgrade: WebSocketUpgrade) -> Response {
+    mock.requests.lock().unwrap().push(format!("GET /ws/{}", listen_key));
+    if listen_key != LISTEN_KEY {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    upgrade.on_upgrade(|socket| replay(socket, include_str!("fixtures/binance/user_data.jsonl")))
+}
+
+async fn market_data_socket(State(mock): State<MockBinance>, RawQuery(query): RawQuery, upgrade: WebSocketUpgrade) -> Response {
+    mock.requests.lock().unwrap().push(format!("GET /stream?{}", query.unwrap_or_default()));
+    upgrade.on_upgrade(|socket| replay(socket, include_str!("fixtures/binance/market_data.jsonl")))
+}
+
+async fn start_mock() -> (MockBinance, String) {
+    let mock = MockBinance::default();
+    let app = Router::new()
+        .route("/api/v3/order", any(order_endpoint))
+        .route("/api/v3/order/cancelReplace", post(cancel_replace))
+        .route("/api/v3/userDataStream", any(user_data_stream))
+        .route("/ws/:listen_key", get(user_data_socket))
+        .route("/stream", get(market_data_socket))
+        .with_state(mock.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    (mock, address.to_string())
+}
+
+fn connector(address: &str, api_secret: &str) -> Arc<dyn ExchangeConnector> {
+    let mut config = ConnectorConfig::new(ExchangeType::Binance, AdapterKind::Live);
+    config.api_key = API_KEY.to_string();
+    config.api_secret = api_secret.to_string();
+    config.endpoint = format!("http://{}", address);
+    config.ws_endpoint = format!("ws://{}", address);
+    config.symbol_map.insert("BTC/USD".to_string(), "BTCUSDT".to_string());
+    config.binance.depth_levels = 5;
+    connect(&config).unwrap()
+}
+
+fn order(order_id: &str, quantity: f64, time_in_force: &str) -> Order {
+    Order {
+        order_id: order_id.to_string(),
+        client_order_id: format!("client-{}", order_id),
+        symbol: "BTC/USD".to_string(),
+        price: 50000.0,
+        quantity,
+        side: "buy".to_string(),
+        order_type: "limit".to_string(),
+        time_in_force: time_in_force.to_string(),
+        user_id: "user1".to_string(),
+        timestamp: Utc::now(),
+    }
+}
+
+#[tokio::test]
+async fn place_order_sends_signed_request_and_maps_ack() {
+    let (mock, address) = start_mock().await;
+    let binance = connector(&address, API_SECRET);
+
+    let ack = binance.place_order(&order("ord-1", 0.5, "GTC")).await.unwrap();
+    assert_eq!(ack.status, OrderStatus::New);
+    assert_eq!(ack.order_id, "ord-1");
+    assert_eq!(ack.exchange_order_id, "28457");
+    assert_eq!(ack.quantity, 0.0);
+
+    let request = &mock.requests()[0];
+    assert!(request.starts_with("POST /api/v3/order?symbol=BTCUSDT&side=BUY&type=LIMIT&quantity=0.5&timeInForce=GTC&price=50000&newClientOrderId=ord-1"), "{}", request);
+    assert!(request.contains("&recvWindow=5000&timestamp="), "{}", request);
+}
+
+#[tokio::test]
+async fn venue_reject_comes_back_as_rejected_execution() {
+    let (_, address) = start_mock().await;
+    let binance = connector(&address, API_SECRET);
+
+    let ack = binance.place_order(&order("ord-big", 250.0, "GTC")).await.unwrap();
+    assert_eq!(ack.status, OrderStatus::Rejected);
+    assert_eq!(ack.reason.as_deref(), Some("Account has insufficient balance for requested action. (-2010)"));
+}
+
+#[tokio::test]
+async fn wrong_secret_fails_signature_check() {
+    let (_, address) = start_mock().await;
+    let binance = connector(&address, "not-the-secret");
+
+    let error = binance.query_order("BTC/USD", "ord-1").await.unwrap_err();
+    assert!(error.contains("-1022"), "{}", error);
+}
+
+#[tokio::test]
+async fn expired_ioc_reports_what_it_filled() {
+    let (_, address) = start_mock().await;
+    let binance = connector(&address, API_SECRET);
+
+    let ack = binance.place_order(&order("ord-ioc", 1.0, "IOC")).await.unwrap();
+    assert_eq!(ack.status, OrderStatus::Cancelled);
+    assert!((ack.quantity - 0.3).abs() < 1e-12);
+    assert!((ack.price - 50008.0).abs() < 1e-6);
+}
+
+#[tokio::test]
+async fn query_and_cancel_map_cumulative_fills() {
+    let (mock, address) = start_mock().await;
+    let binance = connector(&address, API_SECRET);
+
+    let open = binance.query_order("BTC/USD", "ord-1").await.unwrap();
+    assert_eq!(open.status, OrderStatus::Partial);
+    assert!((open.quantity - 0.2).abs() < 1e-12);
+    assert!((open.price - 49999.0).abs() < 1e-6);
+    assert_eq!(open.timestamp.timestamp_millis(), 1718000005001);
+
+    let cancelled = binance.cancel_order("BTC/USD", "ord-1").await.unwrap();
+    assert_eq!(cancelled.status, OrderStatus::Cancelled);
+    assert!((cancelled.quantity - 0.2).abs() < 1e-12);
+
+    let requests = mock.requests();
+    assert!(requests[1].starts_with("DELETE /api/v3/order?symbol=BTCUSDT&origClientOrderId=ord-1&recvWindow=5000"), "{}", requests[1]);
+}
+
+#[tokio::test]
+async fn amend_replaces_order_and_carries_fills() {
+    let (mock, address) = start_mock().await;
+    let binance = connector(&address, API_SECRET);
+
+    let amended = binance.amend_order("BTC/USD", "ord-1", Some(49900.0), Some(0.8)).await.unwrap();
+    assert_eq!(amended.status, OrderStatus::Partial);
+    // 0.2 filled before the replace plus 0.1 on the new order
+    assert!((amended.quantity - 0.3).abs() < 1e-12);
+    assert!((amended.price - (9999.8 + 4990.0) / 0.3).abs() < 1e-6);
+
+    let replace = &mock.requests()[1];
+    assert!(replace.starts_with("POST /api/v3/order/cancelReplace?symbol=BTCUSDT&side=BUY&type=LIMIT&cancelReplaceMode=STOP_ON_FAILURE&cancelOrigClientOrderId=ord-1&newClientOrderId=ord-1&quantity=0.6"), "{}", replace);
+    assert!(replace.contains("&price=49900&timeInForce=GTC&"), "{}", replace);
+}
+
+#[tokio::test]
+async fn user_data_stream_yields_trades_as_fills() {
+    let (mock, address) = start_mock().await;
+    let binance = connector(&address, API_SECRET);
+
+    let mut fills = binance.stream_fills().await.unwrap();
+    let first = tokio::time::timeout(Duration::from_secs(5), fills.recv()).await.unwrap().unwrap();
+    let second = tokio::time::timeout(Duration::from_secs(5), fills.recv()).await.unwrap().unwrap();
+
+    assert_eq!(first.fill_id, "BTCUSDT-3012871");
+    assert_eq!(first.order_id, "ord-1");
+    assert_eq!(first.symbol, "BTC/USD");
+    assert_eq!(first.side, "buy");
+    assert!((first.quantity - 0.12).abs() < 1e-12);
+    assert!((first.price - 49999.0).abs() < 1e-9);
+    assert_eq!(first.timestamp.timestamp_millis(), 1718000003010);
+    assert_eq!(second.fill_id, "BTCUSDT-3012902");
+    assert!((second.quantity - 0.08).abs() < 1e-12);
+    assert_eq!(binance.active_connections(), 1);
+
+    let requests = mock.requests();
+    assert_eq!(requests[0], "POST /api/v3/userDataStream?");
+    assert_eq!(requests[1], format!("GET /ws/{}", LISTEN_KEY));
+}
+
+#[tokio::test]
+async fn market_data_stream_maps_depth_and_trades() {
+    let (mock, address) = start_mock().await;
+    let binance = connector(&address, API_SECRET);
+
+    let mut events = binance.stream_market_data(&["BTC/USD".to_string()]).await.unwrap();
+    let mut received = Vec::new();
+    for _ in 0..3 {
+        received.push(tokio::time::timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap());
+    }
+
+    match &received[0] {
+        MarketDataEvent::Book(book) => {
+            assert_eq!(book.symbol, "BTC/USD");
+            assert_eq!(book.bids.len(), 5);
+            assert_eq!(book.bids[0], (64210.01, 1.204));
+            assert_eq!(book.asks[0], (64210.02, 3.119));
+        }
+        other => panic!("expected a book, got {:?}", other),
+    }
+    match (&received[1], &received[2]) {
+        (MarketDataEvent::Trade(taker_buy), MarketDataEvent::Trade(taker_sell)) => {
+            assert_eq!(taker_buy.side, "buy");
+            assert_eq!(taker_buy.quantity, 0.0021);
+            assert_eq!(taker_sell.side, "sell");
+            assert_eq!(taker_sell.price, 64210.01);
+        }
+        other => panic!("expected two trades, got {:?}", other),
+    }
+
+    let stream = &mock.requests()[0];
+    assert!(stream.contains("btcusdt@depth5@100ms") && stream.contains("btcusdt@trade"), "{}", stream);
+}
@@ -0,0 +1,480 @@
+// Coinbase adapter against a local stand-in for the Advanced Trade REST API
+// and WebSocket feed that replays recorded payloads.
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, Method, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::Router;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use p256::ecdsa::signature::Verifier;
+use p256::ecdsa::{Signature, VerifyingKey};
+use polaris_core::exchange_connector::{connect, AdapterKind, ConnectorConfig, ExchangeConnector, ExchangeType, MarketDataEvent, OrderStatus};
+use polaris_core::Order;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const KEY_NAME: &str = "organizations/5d2c9a7e-81f4-4b3e-a6d0-7e9c1b2f4a30/apiKeys/1f8b3c6d-2a4e-4d9f-b5c7-0e6a9d3b8f21";
+const CDP_KEY: &str = include_str!("fixtures/coinbase/cdp_key.pem");
+const LEGACY_KEY: &str = "t8QmZ3vK1pXc7RbN";
+const LEGACY_SECRET: &str = "Yw2fL9sJd4HqE6uPa0TnVx3Mk8Rc5GbZ";
+const ORDER_ID: &str = "3f4c6a0e-5b1d-4e7a-9c2b-1d8e0f7a6b51";
+const IOC_ORDER_ID: &str = "7b2e9d14-0c6f-4a38-8e51-6f3a2c9d0e77";
+
+const ORDER_OPEN: &str = include_str!("fixtures/coinbase/order_open.json");
+const ORDER_EDITED: &str = include_str!("fixtures/coinbase/order_edited.json");
+const ORDER_CANCELLED: &str = include_str!("fixtures/coinbase/order_cancelled.json");
+
+#[derive(Clone)]
+struct MockCoinbase {
+    // Method and path, then body, of every REST request in arrival order
+    requests: Arc<Mutex<Vec<(String, String)>>>,
+    // Channels subscribed on each WebSocket connection
+    subscriptions: Arc<Mutex<Vec<Vec<String>>>>,
+    // What a lookup of ORDER_ID currently returns
+    order: Arc<Mutex<&'static str>>,
+    // Whether ORDER_ID has been created and shows up in order listings
+    created: Arc<Mutex<bool>>,
+}
+
+impl MockCoinbase {
+    fn record(&self, method: &Method, uri: &Uri, body: &str) {
+        self.requests.lock().unwrap().push((format!("{} {}", method, uri.path()), body.to_string()));
+    }
+
+    fn requests(&self) -> Vec<(String, String)> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn subscriptions(&self) -> Vec<Vec<String>> {
+        self.subscriptions.lock().unwrap().clone()
+    }
+}
+
+fn json_response(body: &'static str) -> Response {
+    (StatusCode::OK, [("content-type", "application/json")], body).into_response()
+}
+
+fn unauthorized() -> Response {
+    (StatusCode::UNAUTHORIZED, "Unauthorized\n").into_response()
+}
+
+// Checks an ES256 token the way Coinbase does: key name, issuer, lifetime,
+// the request it is bound to and the signature itself
+fn verify_jwt(token: &str, uri: Option<&str>) -> bool {
+    let parts: Vec<&str> = token.split('.').collect();
+    if parts.len() != 3 {
+        return false;
+    }
+    let decode = |part: &str| URL_SAFE_NO_PAD.decode(part).ok().and_then(|bytes| serde_json::from_slice::<Value>(&bytes).ok());
+    let (Some(header), Some(claims)) = (decode(parts[0]), decode(parts[1])) else {
+        return false;
+    };
+    let Some(signature) = URL_SAFE_NO_PAD.decode(parts[2]).ok().and_then(|bytes| Signature::from_slice(&bytes).ok()) else {
+        return false;
+    };
+    let public_key = p256::SecretKey::from_sec1_pem(CDP_KEY).unwrap().public_key();
+    let now = Utc::now().timestamp();
+    let nbf = claims["nbf"].as_i64().unwrap_or(i64::MAX);
+    let exp = claims["exp"].as_i64().unwrap_or(0);
+
+    header["alg"] == "ES256"
+        && header["kid"] == KEY_NAME
+        && header["nonce"].as_str().is_some_and(|nonce| nonce.len() >= 16)
+        && claims["sub"] == KEY_NAME
+        && claims["iss"] == "cdp"
+        && nbf <= now + 5
+        && exp > now
+        && exp - nbf <= 120
+        && claims.get("uri").and_then(|value| value.as_str()) == uri
+        && VerifyingKey::from(public_key).verify(format!("{}.{}", parts[0], parts[1]).as_bytes(), &signature).is_ok()
+}
+
+fn authorized(headers: &HeaderMap, method: &Method, path: &str, body: &str) -> bool {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    if let Some(token) = header("authorization").and_then(|value| value.strip_prefix("Bearer ")) {
+        return verify_jwt(token, Some(&format!("{} {}{}", method, header("host").unwrap_or_default(), path)));
+    }
+
+    match (header("CB-ACCESS-KEY"), header("CB-ACCESS-SIGN"), header("CB-ACCESS-TIMESTAMP")) {
+        (Some(LEGACY_KEY), Some(signature), Some(timestamp)) => {
+            let fresh = timestamp.parse::<i64>().is_ok_and(|t| (Utc::now().timestamp() - t).abs() <= 30);
+            let mut mac = Hmac::<sha2::Sha256>::new_from_slice(LEGACY_SECRET.as_bytes()).unwrap();
+            mac.update(format!("{}{}{}{}", timestamp, method, path, body).as_bytes());
+            fresh && hex::encode(mac.finalize().into_bytes()) == signature
+        }
+        _ => false,
+    }
+}
+
+async fn create_order(State(mock): State<MockCoinbase>, method: Method, uri: Uri, headers: HeaderMap, body: String) -> Response {
+    mock.record(&method, &uri, &body);
+    if !authorized(&headers, &method, uri.path(), &body) {
+        return unauthorized();
+    }
+
+    let request: Value = serde_json::from_str(&body).unwrap_or_default();
+    let configuration = &request["order_configuration"];
+    let base_size = configuration.as_object()
+        .and_then(|configuration| configuration.values().next())
+        .and_then(|terms| terms["base_size"].as_str())
+        .and_then(|size| size.parse::<f64>().ok())
+        .unwrap_or(0.0);
+    if base_size > 100.0 {
+        json_response(include_str!("fixtures/coinbase/create_order_insufficient_fund.json"))
+    } else if configuration.get("sor_limit_ioc").is_some() {
+        json_response(include_str!("fixtures/coinbase/create_order_ioc.json"))
+    } else {
+        *mock.created.lock().unwrap() = true;
+        json_response(include_str!("fixtures/coinbase/create_order.json"))
+    }
+}
+
+async fn list_orders(State(mock): State<MockCoinbase>, method: Method, uri: Uri, headers: HeaderMap) -> Response {
+    mock.record(&method, &uri, "");
+    if !authorized(&headers, &method, uri.path(), "") {
+        return unauthorized();
+    }
+    if uri.query() != Some("product_ids=BTC-USD&limit=250") {
+        return (StatusCode::BAD_REQUEST, r#"{"error":"INVALID_ARGUMENT","message":"unexpected query"}"#).into_response();
+    }
+    if *mock.created.lock().unwrap() {
+        json_response(include_str!("fixtures/coinbase/list_orders.json"))
+    } else {
+        json_response(include_str!("fixtures/coinbase/list_orders_empty.json"))
+    }
+}
+
+async fn get_order(State(mock): State<MockCoinbase>, method: Method, uri: Uri, headers: HeaderMap, Path(order_id): Path<String>) -> Response {
+    mock.record(&method, &uri, "");
+    if !authorized(&headers, &method, uri.path(), "") {
+        return unauthorized();
+    }
+    match order_id.as_str() {
+        ORDER_ID => json_response(*mock.order.lock().unwrap()),
+        IOC_ORDER_ID => json_response(include_str!("fixtures/coinbase/order_ioc_expired.json")),
+        _ => (StatusCode::NOT_FOUND, r#"{"error":"NOT_FOUND","error_details":"order with this orderID was not found","message":"order with this orderID was not found"}"#).into_response(),
+    }
+}
+
+async fn batch_cancel(State(mock): State<MockCoinbase>, method: Method, uri: Uri, headers: HeaderMap, body: String) -> Response {
+    mock.record(&method, &uri, &body);
+    if !authorized(&headers, &method, uri.path(), &body) {
+        return unauthorized();
+    }
+    *mock.order.lock().unwrap() = ORDER_CANCELLED;
+    json_response(include_str!("fixtures/coinbase/batch_cancel.json"))
+}
+
+async fn edit_order(State(mock): State<MockCoinbase>, method: Method, uri: Uri, headers: HeaderMap, body: String) -> Response {
+    mock.record(&method, &uri, &body);
+    if !authorized(&headers, &method, uri.path(), &body) {
+        return unauthorized();
+    }
+    *mock.order.lock().unwrap() = ORDER_EDITED;
+    json_response(include_str!("fixtures/coinbase/edit_order.json"))
+}
+
+async fn socket(State(mock): State<MockCoinbase>, upgrade: WebSocketUpgrade) -> Response {
+    upgrade.on_upgrade(move |socket| replay(mock, socket))
+}
+
+// Waits for the subscriptions, then plays the recording for them and holds
+// the connection open like the real feed. The first market data connection
+// gets the recording with a missing sequence number.
+async fn replay(mock: MockCoinbase, mut socket: WebSocket) {
+    let mut channels = Vec::new();
+    while let Some(Ok(Message::Text(text))) = socket.recv().await {
+        let subscribe: Value = serde_json::from_str(&text).unwrap_or_default();
+        if subscribe["type"] != "subscribe" || !subscribe["jwt"].as_str().is_some_and(|token| verify_jwt(token, None)) {
+            let _ = socket.send(Message::Text(r#"{"type":"error","message":"authentication failure"}"#.to_string())).await;
+            return;
+        }
+        let channel = subscribe["channel"].as_str().unwrap_or_default().to_string();
+        channels.push(channel.clone());
+        if channel == "heartbeats" {
+            break;
+        }
+    }
+
+    let market_connections = {
+        let mut subscriptions = mock.subscriptions.lock().unwrap();
+        subscriptions.push(channels.clone());
+        subscriptions.iter().filter(|channels| !channels.iter().any(|c| c == "user")).count()
+    };
+    let recording = if channels.iter().any(|c| c == "user") {
+        include_str!("fixtures/coinbase/user.jsonl")
+    } else if market_connections == 1 {
+        include_str!("fixtures/coinbase/market_data_gap.jsonl")
+    } else {
+        include_str!("fixtures/coinbase/market_data.jsonl")
+    };
+    for line in recording.lines().filter(|line| !line.is_empty()) {
+        if socket.send(Message::Text(line.to_string())).await.is_err() {
+            return;
+        }
+    }
+    while let Some(Ok(_)) = socket.recv().await {}
+}
+
+async fn start_mock() -> (MockCoinbase, String) {
+    let mock = MockCoinbase {
+        requests: Arc::default(),
+        subscriptions: Arc::default(),
+        order: Arc::new(Mutex::new(ORDER_OPEN)),
+        created: Arc::default(),
+    };
+    let app = Router::new()
+        .route("/", get(socket))
+        .route("/api/v3/brokerage/orders", post(create_order))
+        .route("/api/v3/brokerage/orders/historical/batch", get(list_orders))
+        .route("/api/v3/brokerage/orders/historical/:order_id", get(get_order))
+        .route("/api/v3/brokerage/orders/batch_cancel", post(batch_cancel))
+        .route("/api/v3/brokerage/orders/edit", post(edit_order))
+        .with_state(mock.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    (mock, address.to_string())
+}
+
+fn connector(address: &str, api_key: &str, api_secret: &str) -> Arc<dyn ExchangeConnector> {
+    let mut config = ConnectorConfig::new(ExchangeType::Coinbase, AdapterKind::Live);
+    config.api_key = api_key.to_string();
+    config.api_secret = api_secret.to_string();
+    config.endpoint = format!("http://{}", address);
+    config.ws_endpoint = format!("ws://{}", address);
+    config.symbol_map.insert("BTC/USD".to_string(), "BTC-USD".to_string());
+    config.coinbase.depth_levels = 5;
+    config.coinbase.reconnect_delay_ms = 10;
+    connect(&config).unwrap()
+}
+
+fn order(order_id: &str, quantity: f64, time_in_force: &str) -> Order {
+    Order {
+        order_id: order_id.to_string(),
+        client_order_id: format!("client-{}", order_id),
+        symbol: "BTC/USD".to_string(),
+        price: 50000.0,
+        quantity,
+        side: "buy".to_string(),
+        order_type: "limit".to_string(),
+        time_in_force: time_in_force.to_string(),
+        user_id: "user1".to_string(),
+        timestamp: Utc::now(),
+    }
+}
+
+fn body(request: &(String, String)) -> Value {
+    serde_json::from_str(&request.1).unwrap()
+}
+
+#[tokio::test]
+async fn place_order_sends_jwt_signed_order_and_acks() {
+    let (mock, address) = start_mock().await;
+    let coinbase = connector(&address, KEY_NAME, CDP_KEY);
+
+    let ack = coinbase.place_order(&order("ord-1", 0.5, "GTC")).await.unwrap();
+    assert_eq!(ack.status, OrderStatus::New);
+    assert_eq!(ack.order_id, "ord-1");
+    assert_eq!(ack.exchange_order_id, ORDER_ID);
+
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].0, "POST /api/v3/brokerage/orders");
+    assert_eq!(body(&requests[0]), json!({
+        "client_order_id": "ord-1",
+        "product_id": "BTC-USD",
+        "side": "BUY",
+        "order_configuration": {"limit_limit_gtc": {"base_size": "0.5", "limit_price": "50000", "post_only": false}},
+    }));
+}
+
+#[tokio::test]
+async fn legacy_keys_sign_requests_with_hmac() {
+    let (_, address) = start_mock().await;
+    let coinbase = connector(&address, LEGACY_KEY, LEGACY_SECRET);
+
+    coinbase.place_order(&order("ord-1", 0.5, "GTC")).await.unwrap();
+    let open = coinbase.query_order("BTC/USD", "ord-1").await.unwrap();
+    assert_eq!(open.status, OrderStatus::Partial);
+
+    let wrong_secret = connector(&address, LEGACY_KEY, "not-the-secret");
+    let error = wrong_secret.place_order(&order("ord-1", 0.5, "GTC")).await.unwrap_err();
+    assert!(error.contains("401"), "{}", error);
+}
+
+#[tokio::test]
+async fn venue_reject_comes_back_as_rejected_execution() {
+    let (_, address) = start_mock().await;
+    let coinbase = connector(&address, KEY_NAME, CDP_KEY);
+
+    let ack = coinbase.place_order(&order("ord-big", 250.0, "GTC")).await.unwrap();
+    assert_eq!(ack.status, OrderStatus::Rejected);
+    assert_eq!(ack.reason.as_deref(), Some("Insufficient balance in source account (INSUFFICIENT_FUND)"));
+}
+
+#[tokio::test]
+async fn immediate_order_ack_reports_its_outcome() {
+    let (mock, address) = start_mock().await;
+    let coinbase = connector(&address, KEY_NAME, CDP_KEY);
+
+    let mut ioc = order("ord-ioc", 1.0, "IOC");
+    ioc.price = 50010.0;
+    let ack = coinbase.place_order(&ioc).await.unwrap();
+    assert_eq!(ack.status, OrderStatus::Cancelled);
+    assert_eq!(ack.exchange_order_id, IOC_ORDER_ID);
+    assert!((ack.quantity - 0.3).abs() < 1e-12);
+    assert!((ack.price - 50008.0).abs() < 1e-9);
+
+    let requests = mock.requests();
+    assert_eq!(body(&requests[0])["order_configuration"], json!({"sor_limit_ioc": {"base_size": "1", "limit_price": "50010"}}));
+    assert_eq!(requests[1].0, format!("GET /api/v3/brokerage/orders/historical/{}", IOC_ORDER_ID));
+}
+
+#[tokio::test]
+async fn query_and_cancel_go_by_coinbase_order_id() {
+    let (mock, address) = start_mock().await;
+    let coinbase = connector(&address, KEY_NAME, CDP_KEY);
+
+    let error = coinbase.query_order("BTC/USD", "ord-1").await.unwrap_err();
+    assert_eq!(error, "Unknown Coinbase order ord-1");
+
+    coinbase.place_order(&order("ord-1", 0.5, "GTC")).await.unwrap();
+    let open = coinbase.query_order("BTC/USD", "ord-1").await.unwrap();
+    assert_eq!(open.status, OrderStatus::Partial);
+    assert!((open.quantity - 0.2).abs() < 1e-12);
+    assert!((open.price - 49999.0).abs() < 1e-9);
+    assert_eq!(open.timestamp.timestamp_millis(), 1718000005001);
+
+    let cancelled = coinbase.cancel_order("BTC/USD", "ord-1").await.unwrap();
+    assert_eq!(cancelled.status, OrderStatus::Cancelled);
+    assert!((cancelled.quantity - 0.2).abs() < 1e-12);
+
+    let requests = mock.requests();
+    assert_eq!(requests[0].0, "GET /api/v3/brokerage/orders/historical/batch");
+    assert_eq!(requests[3].0, "POST /api/v3/brokerage/orders/batch_cancel");
+    assert_eq!(body(&requests[3]), json!({"order_ids": [ORDER_ID]}));
+}
+
+#[tokio::test]
+async fn orders_from_before_a_restart_are_found_by_client_order_id() {
+    let (mock, address) = start_mock().await;
+    connector(&address, KEY_NAME, CDP_KEY).place_order(&order("ord-1", 0.5, "GTC")).await.unwrap();
+
+    let restarted = connector(&address, KEY_NAME, CDP_KEY);
+    let open = restarted.query_order("BTC/USD", "ord-1").await.unwrap();
+    assert_eq!(open.status, OrderStatus::Partial);
+    assert_eq!(open.exchange_order_id, ORDER_ID);
+    let cancelled = restarted.cancel_order("BTC/USD", "ord-1").await.unwrap();
+    assert_eq!(cancelled.status, OrderStatus::Cancelled);
+
+    // The listing is only needed once; the id is remembered after that
+    let requests: Vec<String> = mock.requests().into_iter().map(|(request, _)| request).collect();
+    assert_eq!(requests[1..], [
+        "GET /api/v3/brokerage/orders/historical/batch".to_string(),
+        format!("GET /api/v3/brokerage/orders/historical/{}", ORDER_ID),
+        "POST /api/v3/brokerage/orders/batch_cancel".to_string(),
+        format!("GET /api/v3/brokerage/orders/historical/{}", ORDER_ID),
+    ]);
+}
+
+#[tokio::test]
+async fn amend_edits_the_order_in_place() {
+    let (mock, address) = start_mock().await;
+    let coinbase = connector(&address, KEY_NAME, CDP_KEY);
+    coinbase.place_order(&order("ord-1", 0.5, "GTC")).await.unwrap();
+
+    let error = coinbase.amend_order("BTC/USD", "ord-1", None, Some(0.1)).await.unwrap_err();
+    assert_eq!(error, "Order ord-1 already filled 0.2");
+
+    let amended = coinbase.amend_order("BTC/USD", "ord-1", Some(49900.0), Some(0.8)).await.unwrap();
+    assert_eq!(amended.status, OrderStatus::Partial);
+    assert_eq!(amended.exchange_order_id, ORDER_ID);
+    assert!((amended.quantity - 0.2).abs() < 1e-12);
+
+    let edit = mock.requests().into_iter()
+        .find(|(request, _)| request == "POST /api/v3/brokerage/orders/edit")
+        .unwrap();
+    assert_eq!(body(&edit), json!({"order_id": ORDER_ID, "price": "49900", "size": "0.8"}));
+}
+
+#[tokio::test]
+async fn user_channel_updates_become_fills() {
+    let (mock, address) = start_mock().await;
+    let coinbase = connector(&address, KEY_NAME, CDP_KEY);
+    coinbase.place_order(&order("ord-1", 0.5, "GTC")).await.unwrap();
+
+    let mut fills = coinbase.stream_fills().await.unwrap();
+    let mut received = Vec::new();
+    for _ in 0..5 {
+        received.push(tokio::time::timeout(Duration::from_secs(5), fills.recv()).await.unwrap().unwrap());
+    }
+
+    assert_eq!(received[0].fill_id, format!("{}-0.12", ORDER_ID));
+    assert_eq!(received[0].order_id, "ord-1");
+    assert_eq!(received[0].symbol, "BTC/USD");
+    assert_eq!(received[0].side, "buy");
+    assert!((received[0].quantity - 0.12).abs() < 1e-12);
+    assert!((received[0].price - 49999.0).abs() < 1e-6);
+    assert_eq!(received[0].timestamp.timestamp_millis(), 1718000003010);
+    // Filled before the stream opened, so only the increase since the snapshot counts
+    assert_eq!(received[1].order_id, "ext-9");
+    assert_eq!(received[1].symbol, "ETH-USD");
+    assert_eq!(received[1].side, "sell");
+    assert!((received[1].quantity - 0.5).abs() < 1e-12);
+    assert!((received[1].price - 3003.0).abs() < 1e-6);
+    assert_eq!(received[2].fill_id, format!("{}-0.2", ORDER_ID));
+    assert!((received[2].quantity - 0.08).abs() < 1e-12);
+    assert!((received[2].price - 49999.9).abs() < 1e-6);
+    assert_eq!(received[3].order_id, "ext-9");
+    assert!((received[3].quantity - 0.5).abs() < 1e-12);
+    assert!((received[3].price - 3005.0).abs() < 1e-6);
+    // The repeated update for the filled order is not another fill
+    assert_eq!(received[4].fill_id, format!("{}-0.25", ORDER_ID));
+    assert!((received[4].quantity - 0.05).abs() < 1e-12);
+    assert!((received[4].price - 50000.0).abs() < 1e-6);
+
+    assert_eq!(mock.subscriptions(), vec![vec!["user".to_string(), "heartbeats".to_string()]]);
+    assert_eq!(coinbase.active_connections(), 1);
+}
+
+#[tokio::test]
+async fn sequence_gap_resubscribes_for_a_fresh_book() {
+    let (mock, address) = start_mock().await;
+    let coinbase = connector(&address, KEY_NAME, CDP_KEY);
+
+    let mut events = coinbase.stream_market_data(&["BTC/USD".to_string()]).await.unwrap();
+    let mut books = Vec::new();
+    let mut trades = Vec::new();
+    while books.len() < 3 || trades.len() < 2 {
+        match tokio::time::timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap() {
+            MarketDataEvent::Book(book) => books.push(book),
+            MarketDataEvent::Trade(trade) => trades.push(trade),
+        }
+    }
+
+    assert!(books.iter().all(|book| book.symbol == "BTC/USD"));
+    assert_eq!(books[0].bids, vec![(64210.01, 1.204), (64210.0, 0.5), (64209.5, 2.0)]);
+    assert_eq!(books[0].asks, vec![(64210.02, 3.119), (64210.5, 0.8)]);
+    assert_eq!(books[1].bids[0], (64210.0, 0.5));
+    assert_eq!(books[1].asks[0], (64210.02, 2.5));
+    // The update after the gap is dropped; the next book is the new snapshot
+    assert_eq!(books[2].bids, vec![(64212.0, 0.7), (64210.0, 0.5)]);
+    assert_eq!(books[2].asks, vec![(64212.5, 1.1)]);
+
+    // The trade snapshot is history, so only the update comes through
+    assert_eq!(trades.len(), 2);
+    assert_eq!((trades[0].side.as_str(), trades[0].price, trades[0].quantity), ("buy", 64212.5, 0.0021));
+    assert_eq!((trades[1].side.as_str(), trades[1].price, trades[1].quantity), ("sell", 64212.0, 0.05));
+
+    let channels = vec!["level2".to_string(), "market_trades".to_string(), "heartbeats".to_string()];
+    assert_eq!(mock.subscriptions(), vec![channels.clone(), channels]);
+}
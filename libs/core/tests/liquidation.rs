@@ -0,0 +1,87 @@
+// Liquidation orders from the risk engine, sent to the simulated venue the way
+// the execution engine does and filled back into the position. BTC/USD is a
+// margined perpetual quoting 46995/47005 once the price has fallen.
+
+use polaris_core::exchange_connector::{connect, AdapterKind, ConnectorConfig, ExchangeType, OrderStatus, SimulatorConfig};
+use polaris_core::risk_engine::{InstrumentSpec, MarginRequirement, MarginStatus, MarketPrice, RiskEngine};
+use polaris_core::Order;
+use std::collections::HashMap;
+
+fn venue() -> ConnectorConfig {
+    let mut config = ConnectorConfig::new(ExchangeType::Binance, AdapterKind::Simulated);
+    config.simulator = SimulatorConfig {
+        reference_prices: HashMap::from([("BTC/USD".to_string(), 47000.0)]),
+        recorded_books: None,
+        spread_bps: 2.0,
+        depth_levels: 3,
+        level_quantity: 1.0,
+        volatility_bps: 0.0,
+        tick_interval_ms: 3_600_000,
+        latency_ms: 0,
+        latency_jitter_ms: 0,
+        reject_probability: 0.0,
+        partial_fill_probability: 0.0,
+        slippage_bps: 0.0,
+        seed: Some(1),
+    };
+    config
+}
+
+// Long 2 BTC at 50000 on 10000 of cash, 10% initial and 5% maintenance margin
+fn margined_account() -> RiskEngine {
+    let mut engine = RiskEngine::new();
+    engine.update_instruments(HashMap::from([("BTC/USD".to_string(), InstrumentSpec {
+        tick_size: 0.01,
+        lot_size: 0.001,
+        min_notional: 10.0,
+        margin: Some(MarginRequirement { initial: 0.1, maintenance: 0.05 }),
+        base_currency: None,
+        quote_currency: None,
+        asset_class: None,
+    })]));
+    engine.update_account_balances(HashMap::from([("user1".to_string(), 10_000.0)]));
+    engine.apply_fill("user1", "BTC/USD", "buy", 2.0, 50000.0);
+    engine
+}
+
+fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() < 1e-9
+}
+
+#[tokio::test]
+async fn liquidation_orders_fill_at_the_venue_and_shrink_the_position() {
+    let mut engine = margined_account();
+    engine.update_market_price("BTC/USD", MarketPrice { last: 47000.0, ..MarketPrice::default() });
+    assert_eq!(engine.get_margin_usage("user1").unwrap().status, MarginStatus::Liquidation);
+
+    let orders = engine.liquidation_orders("user1");
+    assert_eq!(orders.len(), 1);
+    assert_eq!(orders[0].side, "sell");
+    assert!(close(orders[0].quantity, 1.149), "{}", orders[0].quantity);
+    // Nothing more is generated while these are in flight
+    assert!(engine.liquidation_orders("user1").is_empty());
+
+    let venue = connect(&venue()).unwrap();
+    tokio::task::yield_now().await;
+    let mut fills = venue.stream_fills().await.unwrap();
+    for order in &orders {
+        // Published to the liquidation topic and read back by the execution engine
+        let order: Order = serde_json::from_str(&serde_json::to_string(order).unwrap()).unwrap();
+        let ack = venue.place_order(&order).await.unwrap();
+        assert_eq!(ack.status, OrderStatus::Filled);
+        engine.close_liquidation(&order.order_id);
+    }
+
+    let mut sold = 0.0;
+    while let Ok(fill) = fills.try_recv() {
+        assert_eq!(fill.order_id, orders[0].order_id);
+        engine.apply_fill("user1", &fill.symbol, &fill.side, fill.quantity, fill.price);
+        sold += fill.quantity;
+    }
+    assert!(close(sold, 1.149), "{}", sold);
+    assert!(close(engine.get_position("user1", "BTC/USD").quantity, 0.851));
+
+    let usage = engine.get_margin_usage("user1").unwrap();
+    assert_ne!(usage.status, MarginStatus::Liquidation);
+    assert!(engine.liquidation_orders("user1").is_empty());
+}
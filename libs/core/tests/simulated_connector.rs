@@ -0,0 +1,201 @@
+// Simulated adapter with a fixed seed, no latency and books that only move
+// when orders take from them. BTC/USD quotes 49995/50005 with one unit on
+// each of three levels, 5 apart.
+
+use chrono::Utc;
+use polaris_core::exchange_connector::{connect, AdapterKind, ConnectorConfig, ExchangeConnector, ExchangeType, FillReport, OrderStatus, SimulatorConfig};
+use polaris_core::Order;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+fn simulator(seed: u64) -> SimulatorConfig {
+    SimulatorConfig {
+        reference_prices: HashMap::from([("BTC/USD".to_string(), 50000.0)]),
+        recorded_books: None,
+        spread_bps: 2.0,
+        depth_levels: 3,
+        level_quantity: 1.0,
+        volatility_bps: 0.0,
+        tick_interval_ms: 3_600_000,
+        latency_ms: 0,
+        latency_jitter_ms: 0,
+        reject_probability: 0.0,
+        partial_fill_probability: 0.0,
+        slippage_bps: 0.0,
+        seed: Some(seed),
+    }
+}
+
+fn connector_config(simulator: SimulatorConfig) -> ConnectorConfig {
+    let mut config = ConnectorConfig::new(ExchangeType::Binance, AdapterKind::Simulated);
+    config.simulator = simulator;
+    config
+}
+
+// The market task ticks once as soon as it runs; letting it do so here keeps
+// it from refilling the books in the middle of a test
+async fn start(simulator: SimulatorConfig) -> (Arc<dyn ExchangeConnector>, mpsc::Receiver<FillReport>) {
+    let connector = connect(&connector_config(simulator)).unwrap();
+    tokio::task::yield_now().await;
+    let fills = connector.stream_fills().await.unwrap();
+    (connector, fills)
+}
+
+fn order(order_id: &str, side: &str, order_type: &str, price: f64, quantity: f64, time_in_force: &str) -> Order {
+    Order {
+        order_id: order_id.to_string(),
+        client_order_id: format!("client-{}", order_id),
+        symbol: "BTC/USD".to_string(),
+        price,
+        quantity,
+        side: side.to_string(),
+        order_type: order_type.to_string(),
+        time_in_force: time_in_force.to_string(),
+        user_id: "user1".to_string(),
+        timestamp: Utc::now(),
+    }
+}
+
+fn received(fills: &mut mpsc::Receiver<FillReport>) -> Vec<FillReport> {
+    let mut received = Vec::new();
+    while let Ok(fill) = fills.try_recv() {
+        received.push(fill);
+    }
+    received
+}
+
+fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() < 1e-9
+}
+
+#[test]
+fn connect_outside_a_runtime_is_an_error() {
+    let error = connect(&connector_config(simulator(1))).err().expect("connect should fail without a runtime");
+    assert!(error.contains("tokio runtime"), "{}", error);
+}
+
+#[tokio::test]
+async fn fill_or_kill_is_rejected_unless_the_book_covers_it() {
+    let (simulated, mut fills) = start(simulator(1)).await;
+
+    let ack = simulated.place_order(&order("fok-1", "buy", "limit", 50015.0, 4.0, "FOK")).await.unwrap();
+    assert_eq!(ack.status, OrderStatus::Rejected);
+    assert_eq!(ack.reason.as_deref(), Some("fill or kill not fillable"));
+    assert!(received(&mut fills).is_empty());
+
+    // Only two levels are inside the limit
+    let ack = simulated.place_order(&order("fok-2", "buy", "limit", 50010.0, 3.0, "FOK")).await.unwrap();
+    assert_eq!(ack.status, OrderStatus::Rejected);
+
+    let ack = simulated.place_order(&order("fok-3", "buy", "limit", 50010.0, 2.0, "FOK")).await.unwrap();
+    assert_eq!(ack.status, OrderStatus::Filled);
+    assert!(close(ack.quantity, 2.0));
+    assert!(close(ack.price, 50007.5));
+    let prices: Vec<f64> = received(&mut fills).iter().map(|fill| fill.price).collect();
+    assert_eq!(prices, vec![50005.0, 50010.0]);
+}
+
+#[tokio::test]
+async fn immediate_or_cancel_cancels_what_it_cannot_take() {
+    let (simulated, mut fills) = start(simulator(1)).await;
+
+    let ack = simulated.place_order(&order("ioc-1", "buy", "limit", 50010.0, 5.0, "IOC")).await.unwrap();
+    assert_eq!(ack.status, OrderStatus::Cancelled);
+    assert!(close(ack.quantity, 2.0));
+    assert_eq!(received(&mut fills).len(), 2);
+
+    // Nothing is left resting to trade later
+    let query = simulated.query_order("BTC/USD", "ioc-1").await.unwrap();
+    assert_eq!(query.status, OrderStatus::Cancelled);
+    assert!(simulated.cancel_order("BTC/USD", "ioc-1").await.is_err());
+}
+
+#[tokio::test]
+async fn partial_fill_leaves_the_rest_open() {
+    let mut config = simulator(7);
+    config.partial_fill_probability = 1.0;
+    let (simulated, mut fills) = start(config).await;
+
+    let ack = simulated.place_order(&order("gtc-1", "buy", "limit", 50005.0, 1.0, "GTC")).await.unwrap();
+    assert_eq!(ack.status, OrderStatus::Partial);
+    assert!(ack.quantity >= 0.1 && ack.quantity < 1.0, "{}", ack.quantity);
+
+    let reported = received(&mut fills);
+    assert_eq!(reported.len(), 1);
+    assert!(close(reported[0].quantity, ack.quantity));
+    assert_eq!(reported[0].order_id, "gtc-1");
+
+    let cancelled = simulated.cancel_order("BTC/USD", "gtc-1").await.unwrap();
+    assert_eq!(cancelled.status, OrderStatus::Cancelled);
+    assert!(close(cancelled.quantity, ack.quantity));
+}
+
+#[tokio::test]
+async fn slippage_stays_within_its_bound_and_inside_the_limit() {
+    let mut config = simulator(3);
+    config.slippage_bps = 20.0;
+    let (simulated, mut fills) = start(config).await;
+
+    let ack = simulated.place_order(&order("mkt-1", "buy", "market", 0.0, 3.0, "GTC")).await.unwrap();
+    assert_eq!(ack.status, OrderStatus::Filled);
+    let bought = received(&mut fills);
+    assert_eq!(bought.len(), 3);
+    for (fill, level) in bought.iter().zip([50005.0, 50010.0, 50015.0]) {
+        assert!(fill.price >= level && fill.price <= level * 1.002, "{} against {}", fill.price, level);
+    }
+
+    // A limit order never fills worse than its price
+    let ack = simulated.place_order(&order("lmt-1", "sell", "limit", 49990.0, 2.0, "GTC")).await.unwrap();
+    assert_eq!(ack.status, OrderStatus::Filled);
+    let sold = received(&mut fills);
+    assert_eq!(sold.len(), 2);
+    for (fill, level) in sold.iter().zip([49995.0, 49990.0]) {
+        assert!(fill.price <= level && fill.price >= 49990.0, "{} against {}", fill.price, level);
+    }
+}
+
+#[tokio::test]
+async fn amend_can_cross_the_book() {
+    let (simulated, mut fills) = start(simulator(1)).await;
+
+    let ack = simulated.place_order(&order("amd-1", "buy", "limit", 49000.0, 2.0, "GTC")).await.unwrap();
+    assert_eq!(ack.status, OrderStatus::New);
+    assert!(received(&mut fills).is_empty());
+
+    let amended = simulated.amend_order("BTC/USD", "amd-1", Some(50005.0), None).await.unwrap();
+    assert_eq!(amended.status, OrderStatus::Partial);
+    assert!(close(amended.quantity, 1.0));
+
+    let error = simulated.amend_order("BTC/USD", "amd-1", None, Some(0.5)).await.unwrap_err();
+    assert!(error.contains("already filled"), "{}", error);
+    assert!(simulated.amend_order("BTC/USD", "amd-1", Some(-1.0), None).await.is_err());
+
+    let amended = simulated.amend_order("BTC/USD", "amd-1", Some(50015.0), Some(3.0)).await.unwrap();
+    assert_eq!(amended.status, OrderStatus::Filled);
+    assert!(close(amended.quantity, 3.0));
+    assert!(close(amended.price, 50010.0));
+    let prices: Vec<f64> = received(&mut fills).iter().map(|fill| fill.price).collect();
+    assert_eq!(prices, vec![50005.0, 50010.0, 50015.0]);
+
+    assert!(simulated.amend_order("BTC/USD", "amd-1", Some(50020.0), None).await.is_err());
+    assert!(simulated.amend_order("BTC/USD", "missing", Some(50020.0), None).await.is_err());
+}
+
+#[tokio::test]
+async fn same_seed_gives_the_same_fills() {
+    async fn run(seed: u64) -> Vec<(f64, f64)> {
+        let mut config = simulator(seed);
+        config.partial_fill_probability = 0.5;
+        config.slippage_bps = 10.0;
+        let (simulated, mut fills) = start(config).await;
+        for i in 0..4 {
+            simulated.place_order(&order(&format!("seed-{}", i), "buy", "market", 0.0, 0.5, "IOC")).await.unwrap();
+        }
+        received(&mut fills).iter().map(|fill| (fill.price, fill.quantity)).collect()
+    }
+
+    let first = run(42).await;
+    assert!(!first.is_empty());
+    assert_eq!(first, run(42).await);
+}
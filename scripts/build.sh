@@ -218,6 +218,8 @@ main() {
         ["risk-manager"]="services/risk-manager"
         ["order-matching-engine"]="services/order-matching-engine"
         ["execution-engine"]="services/execution-engine"
+        ["order-state-store"]="services/order-state-store"
+        ["drop-copy"]="services/drop-copy"
         ["compliance-gateway"]="services/compliance-gateway"
     )
     
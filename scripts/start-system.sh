@@ -60,6 +60,8 @@ build_rust_services() {
         "risk-manager"
         "order-matching-engine"
         "execution-engine"
+        "order-state-store"
+        "drop-copy"
         "compliance-gateway"
     )
     
@@ -0,0 +1,658 @@
+use polaris_core::kafka_utils::{create_kafka_consumer, create_kafka_producer, create_replay_consumer, consume_message_with_metadata, produce_message,
+    assign_from_offsets, read_to_end, ConsumedMessage, TopicOffsets};
+use polaris_core::auth::ApiKeyAuth;
+use polaris_core::metrics::{KAFKA_MESSAGES_CONSUMED, KAFKA_MESSAGES_PRODUCED};
+use chrono::{Utc, DateTime};
+use tracing::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use rdkafka::producer::FutureProducer;
+use rdkafka::consumer::StreamConsumer;
+use tokio::sync::{broadcast, Mutex};
+use std::sync::Arc;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use axum::{
+    extract::ws::{Message, WebSocket, WebSocketUpgrade},
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+    routing::get,
+    Router,
+};
+
+// One entry in an account's drop-copy stream
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct DropCopyEvent {
+    account: String,
+    sequence: u64,
+    event_type: String, // "order", "gateway_response", "risk_approved", "risk_rejected", "trade", "execution_report", "cancel"
+    order_id: String,
+    source_topic: String,
+    source_partition: i32,
+    source_offset: i64,
+    payload: serde_json::Value,
+    timestamp: DateTime<Utc>,
+}
+
+// Raised when offsets on a source partition skip ahead, meaning events may be
+// missing from the drop copy
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SourceGap {
+    topic: String,
+    partition: i32,
+    expected_offset: i64,
+    received_offset: i64,
+    timestamp: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct DropCopyConfig {
+    kafka_brokers: String,
+    orders_topic: String,
+    response_topic: String,
+    approved_topic: String,
+    rejected_topic: String,
+    trades_topic: String,
+    fills_topic: String,
+    cancelled_topic: String,
+    output_topic: String,
+    gap_topic: String,
+    journal_size: usize, // events retained per account for resuming subscribers
+    max_unattributed: usize,
+    http_port: u16,
+    heartbeat_interval: u64,
+    api_keys: HashMap<String, ApiKeyAuth>, // a key reads its own account; "drop_copy_all" reads every account
+}
+
+impl Default for DropCopyConfig {
+    fn default() -> Self {
+        DropCopyConfig {
+            kafka_brokers: "redpanda:9092".to_string(),
+            orders_topic: "orders.incoming".to_string(),
+            response_topic: "orders.responses".to_string(),
+            approved_topic: "orders.validated".to_string(),
+            rejected_topic: "orders.rejected".to_string(),
+            trades_topic: "trades.executed".to_string(),
+            fills_topic: "fills".to_string(),
+            cancelled_topic: "orders.cancelled".to_string(),
+            output_topic: "dropcopy.events".to_string(),
+            gap_topic: "dropcopy.gaps".to_string(),
+            journal_size: 100_000,
+            max_unattributed: 10_000,
+            http_port: 8080,
+            heartbeat_interval: 30_000,
+            api_keys: HashMap::new(),
+        }
+    }
+}
+
+struct AccountStream {
+    next_sequence: u64,
+    journal: VecDeque<DropCopyEvent>,
+    live: broadcast::Sender<DropCopyEvent>,
+}
+
+impl AccountStream {
+    fn new() -> Self {
+        let (live, _) = broadcast::channel(1024);
+        Self {
+            next_sequence: 1,
+            journal: VecDeque::new(),
+            live,
+        }
+    }
+
+    fn first_retained_sequence(&self) -> u64 {
+        self.journal.front().map(|event| event.sequence).unwrap_or(self.next_sequence)
+    }
+}
+
+// An event that arrived before the order it refers to. Trades name their
+// orders by user and client_order_id rather than by order_id.
+enum UnattributedEvent {
+    Event { order_id: String, event_type: String, message: ConsumedMessage },
+    Trade { user_id: String, client_order_id: String, message: ConsumedMessage },
+}
+
+impl UnattributedEvent {
+    fn belongs_to(&self, order_id: &str, user_id: &str, client_order_id: &str) -> bool {
+        match self {
+            UnattributedEvent::Event { order_id: event_order_id, .. } => event_order_id == order_id,
+            UnattributedEvent::Trade { user_id: trade_user_id, client_order_id: trade_client_order_id, .. } => {
+                trade_user_id == user_id && trade_client_order_id == client_order_id
+            }
+        }
+    }
+}
+
+// An event attributed to an account that has not been given a sequence yet
+struct PendingEvent {
+    account: String,
+    event_type: String,
+    order_id: String,
+    message: ConsumedMessage,
+    payload: serde_json::Value,
+}
+
+struct DropCopyState {
+    accounts: HashMap<String, AccountStream>,
+    order_accounts: HashMap<String, String>, // order_id -> account
+    client_order_ids: HashMap<(String, String), String>, // (account, client_order_id) -> order_id
+    unattributed: VecDeque<UnattributedEvent>,
+    recovered: HashSet<(String, i32, i64, String)>, // source events already in the output topic, per account
+    source_offsets: HashMap<(String, i32), i64>,
+}
+
+struct AppState {
+    kafka_producer: FutureProducer,
+    event_consumer: StreamConsumer,
+    config: DropCopyConfig,
+    drop_copy: Mutex<DropCopyState>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ResumeParams {
+    from_sequence: Option<u64>,
+    limit: Option<usize>,
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+    
+    let config = DropCopyConfig {
+        kafka_brokers: env::var("KAFKA_BROKERS").unwrap_or_else(|_| "redpanda:9092".to_string()),
+        orders_topic: env::var("ORDERS_TOPIC").unwrap_or_else(|_| "orders.incoming".to_string()),
+        response_topic: env::var("RESPONSE_TOPIC").unwrap_or_else(|_| "orders.responses".to_string()),
+        approved_topic: env::var("APPROVED_TOPIC").unwrap_or_else(|_| "orders.validated".to_string()),
+        rejected_topic: env::var("REJECTED_TOPIC").unwrap_or_else(|_| "orders.rejected".to_string()),
+        trades_topic: env::var("TRADES_TOPIC").unwrap_or_else(|_| "trades.executed".to_string()),
+        fills_topic: env::var("FILLS_TOPIC").unwrap_or_else(|_| "fills".to_string()),
+        cancelled_topic: env::var("CANCELLED_TOPIC").unwrap_or_else(|_| "orders.cancelled".to_string()),
+        output_topic: env::var("OUTPUT_TOPIC").unwrap_or_else(|_| "dropcopy.events".to_string()),
+        gap_topic: env::var("GAP_TOPIC").unwrap_or_else(|_| "dropcopy.gaps".to_string()),
+        journal_size: env::var("JOURNAL_SIZE")
+            .map(|v| v.parse().unwrap_or(100_000))
+            .unwrap_or(100_000),
+        http_port: env::var("HTTP_PORT")
+            .map(|v| v.parse().unwrap_or(8080))
+            .unwrap_or(8080),
+        heartbeat_interval: env::var("HEARTBEAT_INTERVAL")
+            .map(|v| v.parse().unwrap_or(30_000))
+            .unwrap_or(30_000),
+        api_keys: env::var("API_KEYS")
+            .map(|v| serde_json::from_str(&v).expect("Invalid API_KEYS"))
+            .unwrap_or_default(),
+        ..DropCopyConfig::default()
+    };
+    
+    let producer = create_kafka_producer(&config.kafka_brokers);
+    
+    // A single consumer keeps events from all sources in arrival order. It is
+    // assigned once the journal has been recovered.
+    let event_consumer = create_kafka_consumer(&config.kafka_brokers, "drop-copy-group");
+    
+    info!("Drop copy service started");
+    
+    let http_port = config.http_port;
+    let app_state = Arc::new(AppState {
+        kafka_producer: producer,
+        event_consumer,
+        config,
+        drop_copy: Mutex::new(DropCopyState {
+            accounts: HashMap::new(),
+            order_accounts: HashMap::new(),
+            client_order_ids: HashMap::new(),
+            unattributed: VecDeque::new(),
+            recovered: HashSet::new(),
+            source_offsets: HashMap::new(),
+        }),
+    });
+    
+    // Sequences must carry on from what subscribers have already seen, so the
+    // journal is rebuilt from the output topic before anything is served
+    while let Err(e) = recover_journal(&app_state).await {
+        tracing::error!("Failed to recover drop copy journal, retrying: {}", e);
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+    
+    // The sources are read from the start to rebuild order attribution; events
+    // already in the output topic are skipped rather than published again
+    let config = &app_state.config;
+    let source_topics = [
+        config.orders_topic.as_str(),
+        config.response_topic.as_str(),
+        config.approved_topic.as_str(),
+        config.rejected_topic.as_str(),
+        config.trades_topic.as_str(),
+        config.fills_topic.as_str(),
+        config.cancelled_topic.as_str(),
+    ];
+    while let Err(e) = assign_from_offsets(&app_state.event_consumer, &source_topics, &TopicOffsets::new()) {
+        tracing::error!("Failed to assign order event topics, retrying: {}", e);
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+    
+    // Start background tasks
+    let state_for_processing = Arc::clone(&app_state);
+    tokio::spawn(async move {
+        process_events(state_for_processing).await;
+    });
+    
+    let state_for_heartbeat = Arc::clone(&app_state);
+    tokio::spawn(async move {
+        send_heartbeat(state_for_heartbeat).await;
+    });
+    
+    // Start subscriber API server
+    let app = Router::new()
+        .route("/health", get(health_check))
+        .route("/accounts/:account/events", get(get_events))
+        .route("/accounts/:account/stream", get(stream_events))
+        .with_state(Arc::clone(&app_state));
+    
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", http_port))
+        .await
+        .expect("Failed to bind HTTP port");
+    
+    info!("Drop copy API listening on port {}", http_port);
+    
+    axum::serve(listener, app)
+        .await
+        .expect("Failed to start server");
+}
+
+async fn recover_journal(state: &AppState) -> Result<(), String> {
+    let consumer = create_replay_consumer(&state.config.kafka_brokers, "drop-copy-recovery-group");
+    let messages = read_to_end(&consumer, &[&state.config.output_topic], &TopicOffsets::new())
+        .await
+        .map_err(|e| format!("Failed to read drop copy events: {}", e))?;
+    
+    let mut drop_copy = state.drop_copy.lock().await;
+    for message in messages {
+        let Ok(event) = serde_json::from_str::<DropCopyEvent>(&message.payload) else {
+            continue;
+        };
+        drop_copy.recovered.insert((event.source_topic.clone(), event.source_partition, event.source_offset, event.account.clone()));
+        
+        // Events are keyed by account, so each account's stream comes back in sequence order
+        let stream = drop_copy.accounts.entry(event.account.clone()).or_insert_with(AccountStream::new);
+        stream.next_sequence = stream.next_sequence.max(event.sequence + 1);
+        stream.journal.push_back(event);
+        while stream.journal.len() > state.config.journal_size {
+            stream.journal.pop_front();
+        }
+    }
+    
+    info!("Recovered drop copy journal for {} accounts", drop_copy.accounts.len());
+    Ok(())
+}
+
+async fn process_events(state: Arc<AppState>) {
+    loop {
+        if let Some(message) = consume_message_with_metadata(&state.event_consumer).await {
+            KAFKA_MESSAGES_CONSUMED.with_label_values(&[&message.topic]).inc();
+            
+            check_source_gap(&state, &message).await;
+            
+            if let Err(e) = handle_event(&state, message.clone()).await {
+                warn!("Failed to handle event from {}: {}", message.topic, e);
+            }
+        }
+    }
+}
+
+async fn check_source_gap(state: &AppState, message: &ConsumedMessage) {
+    let gap = {
+        let mut drop_copy = state.drop_copy.lock().await;
+        let key = (message.topic.clone(), message.partition);
+        let gap = match drop_copy.source_offsets.get(&key) {
+            Some(last) if message.offset > last + 1 => Some(SourceGap {
+                topic: message.topic.clone(),
+                partition: message.partition,
+                expected_offset: last + 1,
+                received_offset: message.offset,
+                timestamp: Utc::now(),
+            }),
+            _ => None,
+        };
+        drop_copy.source_offsets.insert(key, message.offset);
+        gap
+    };
+    
+    // Offsets can also skip over transaction markers or compacted records,
+    // so a gap is reported for reconciliation rather than treated as fatal
+    if let Some(gap) = gap {
+        warn!("Gap on {}[{}]: expected offset {}, received {}", gap.topic, gap.partition, gap.expected_offset, gap.received_offset);
+        
+        let gap_json = serde_json::to_string(&gap).unwrap();
+        produce_message(&state.kafka_producer, &state.config.gap_topic, &gap.topic, &gap_json)
+            .await
+            .ok();
+        KAFKA_MESSAGES_PRODUCED.with_label_values(&[&state.config.gap_topic]).inc();
+    }
+}
+
+async fn handle_event(state: &AppState, message: ConsumedMessage) -> Result<(), serde_json::Error> {
+    let config = &state.config;
+    let payload: serde_json::Value = serde_json::from_str(&message.payload)?;
+    let field = |name: &str| payload.get(name).and_then(|v| v.as_str()).unwrap_or_default().to_string();
+    
+    let mut attributed = Vec::new();
+    {
+        let mut drop_copy = state.drop_copy.lock().await;
+        
+        if message.topic == config.orders_topic {
+            // New orders are the only events that carry the account, so they
+            // also release anything that arrived for the order ahead of them
+            let order_id = field("order_id");
+            let account = field("user_id");
+            let client_order_id = field("client_order_id");
+            drop_copy.order_accounts.insert(order_id.clone(), account.clone());
+            drop_copy.client_order_ids.insert((account.clone(), client_order_id.clone()), order_id.clone());
+            attributed.extend(attribute_event(&mut drop_copy, &account, "order", &order_id, &message, payload.clone()));
+            
+            let (ready, waiting): (VecDeque<_>, VecDeque<_>) = drop_copy.unattributed
+                .drain(..)
+                .partition(|event| event.belongs_to(&order_id, &account, &client_order_id));
+            drop_copy.unattributed = waiting;
+            for event in ready {
+                let (event_type, message) = match event {
+                    UnattributedEvent::Event { event_type, message, .. } => (event_type, message),
+                    UnattributedEvent::Trade { message, .. } => ("trade".to_string(), message),
+                };
+                let payload = serde_json::from_str(&message.payload)?;
+                attributed.extend(attribute_event(&mut drop_copy, &account, &event_type, &order_id, &message, payload));
+            }
+        } else if message.topic == config.trades_topic {
+            // Each side of a trade goes to its own account, identified by the
+            // user and client_order_id the matching engine reports
+            for (user_id, client_order_id) in [(field("buyer_user_id"), field("buyer_id")), (field("seller_user_id"), field("seller_id"))] {
+                match drop_copy.client_order_ids.get(&(user_id.clone(), client_order_id.clone())).cloned() {
+                    Some(order_id) => route_event(&mut drop_copy, &mut attributed, "trade", &order_id, &message, &payload),
+                    None => hold_unattributed(&mut drop_copy, config, UnattributedEvent::Trade {
+                        user_id,
+                        client_order_id,
+                        message: message.clone(),
+                    }),
+                }
+            }
+        } else {
+            let event_type = if message.topic == config.response_topic {
+                "gateway_response"
+            } else if message.topic == config.approved_topic {
+                "risk_approved"
+            } else if message.topic == config.rejected_topic {
+                "risk_rejected"
+            } else if message.topic == config.fills_topic {
+                "execution_report"
+            } else {
+                "cancel"
+            };
+            
+            let order_id = field("order_id");
+            if order_id.is_empty() {
+                // Rejections before an order id was assigned and mass cancel
+                // summaries have no order to attribute to
+                return Ok(());
+            }
+            if drop_copy.order_accounts.contains_key(&order_id) {
+                route_event(&mut drop_copy, &mut attributed, event_type, &order_id, &message, &payload);
+            } else {
+                hold_unattributed(&mut drop_copy, config, UnattributedEvent::Event {
+                    order_id,
+                    event_type: event_type.to_string(),
+                    message: message.clone(),
+                });
+            }
+        }
+    }
+    
+    // A sequence is only taken once its event is in the output topic. Subscribers
+    // never see one that a restart could then give to a different event.
+    for pending in attributed {
+        let event = {
+            let mut drop_copy = state.drop_copy.lock().await;
+            let stream = drop_copy.accounts.entry(pending.account.clone()).or_insert_with(AccountStream::new);
+            DropCopyEvent {
+                account: pending.account,
+                sequence: stream.next_sequence,
+                event_type: pending.event_type,
+                order_id: pending.order_id,
+                source_topic: pending.message.topic.clone(),
+                source_partition: pending.message.partition,
+                source_offset: pending.message.offset,
+                payload: pending.payload,
+                timestamp: Utc::now(),
+            }
+        };
+        
+        let event_json = serde_json::to_string(&event)?;
+        produce_message(&state.kafka_producer, &config.output_topic, &event.account, &event_json)
+            .await
+            .expect("Failed to produce drop copy event");
+        KAFKA_MESSAGES_PRODUCED.with_label_values(&[&config.output_topic]).inc();
+        
+        let mut drop_copy = state.drop_copy.lock().await;
+        let stream = drop_copy.accounts.entry(event.account.clone()).or_insert_with(AccountStream::new);
+        stream.next_sequence = event.sequence + 1;
+        stream.journal.push_back(event.clone());
+        while stream.journal.len() > config.journal_size {
+            stream.journal.pop_front();
+        }
+        
+        // No live subscribers is not an error
+        let _ = stream.live.send(event);
+    }
+    
+    Ok(())
+}
+
+fn route_event(
+    drop_copy: &mut DropCopyState,
+    attributed: &mut Vec<PendingEvent>,
+    event_type: &str,
+    order_id: &str,
+    message: &ConsumedMessage,
+    payload: &serde_json::Value,
+) {
+    if let Some(account) = drop_copy.order_accounts.get(order_id).cloned() {
+        attributed.extend(attribute_event(drop_copy, &account, event_type, order_id, message, payload.clone()));
+    }
+}
+
+fn hold_unattributed(drop_copy: &mut DropCopyState, config: &DropCopyConfig, event: UnattributedEvent) {
+    if drop_copy.unattributed.len() >= config.max_unattributed {
+        if let Some(UnattributedEvent::Event { message, .. } | UnattributedEvent::Trade { message, .. }) = drop_copy.unattributed.pop_front() {
+            warn!("Dropping unattributed {} event at offset {}", message.topic, message.offset);
+        }
+    }
+    drop_copy.unattributed.push_back(event);
+}
+
+fn attribute_event(
+    drop_copy: &mut DropCopyState,
+    account: &str,
+    event_type: &str,
+    order_id: &str,
+    message: &ConsumedMessage,
+    payload: serde_json::Value,
+) -> Option<PendingEvent> {
+    // Already published before a restart and recovered into the journal
+    let source = (message.topic.clone(), message.partition, message.offset, account.to_string());
+    if drop_copy.recovered.remove(&source) {
+        return None;
+    }
+    
+    Some(PendingEvent {
+        account: account.to_string(),
+        event_type: event_type.to_string(),
+        order_id: order_id.to_string(),
+        message: message.clone(),
+        payload,
+    })
+}
+
+async fn send_heartbeat(state: Arc<AppState>) {
+    loop {
+        tokio::time::sleep(Duration::from_millis(state.config.heartbeat_interval)).await;
+        
+        let heartbeat = serde_json::json!({
+            "timestamp": Utc::now(),
+            "status": "alive",
+            "component": "drop-copy"
+        });
+        
+        produce_message(&state.kafka_producer, "system.heartbeats", "drop-copy", &heartbeat.to_string())
+            .await
+            .expect("Failed to send heartbeat");
+    }
+}
+
+// REST API handlers
+async fn health_check() -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "status": "healthy",
+        "timestamp": Utc::now(),
+        "service": "drop-copy"
+    }))
+}
+
+type ApiError = (StatusCode, Json<serde_json::Value>);
+
+fn api_error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(serde_json::json!({ "error": message })))
+}
+
+// Checks the X-API-Key / X-API-Secret headers; a key may only read its own account
+fn authenticate_account(config: &DropCopyConfig, headers: &HeaderMap, account: &str) -> Result<(), ApiError> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let (key, secret) = match (header("x-api-key"), header("x-api-secret")) {
+        (Some(key), Some(secret)) => (key, secret),
+        _ => return Err(api_error(StatusCode::UNAUTHORIZED, "Missing API credentials")),
+    };
+    
+    let auth = match config.api_keys.get(key) {
+        Some(auth) if auth.secret == secret => auth,
+        _ => return Err(api_error(StatusCode::UNAUTHORIZED, "Invalid API credentials")),
+    };
+    
+    if auth.key != account && !auth.permissions.iter().any(|p| p == "drop_copy_all") {
+        return Err(api_error(StatusCode::FORBIDDEN, "Not authorized for this account"));
+    }
+    
+    Ok(())
+}
+
+async fn get_events(
+    State(state): State<Arc<AppState>>,
+    Path(account): Path<String>,
+    Query(params): Query<ResumeParams>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    authenticate_account(&state.config, &headers, &account)?;
+    
+    let drop_copy = state.drop_copy.lock().await;
+    let from_sequence = params.from_sequence.unwrap_or(1);
+    let limit = params.limit.unwrap_or(1000);
+    
+    let (first_available, next_sequence, events): (u64, u64, Vec<DropCopyEvent>) = match drop_copy.accounts.get(&account) {
+        Some(stream) => (
+            stream.first_retained_sequence(),
+            stream.next_sequence,
+            stream.journal.iter()
+                .filter(|event| event.sequence >= from_sequence)
+                .take(limit)
+                .cloned()
+                .collect(),
+        ),
+        None => (1, 1, Vec::new()),
+    };
+    
+    Ok(Json(serde_json::json!({
+        "account": account,
+        "first_available_sequence": first_available,
+        "next_sequence": next_sequence,
+        "gap": from_sequence < first_available,
+        "events": events,
+    })))
+}
+
+async fn stream_events(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+    Path(account): Path<String>,
+    Query(params): Query<ResumeParams>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(e) = authenticate_account(&state.config, &headers, &account) {
+        return e.into_response();
+    }
+    ws.on_upgrade(move |socket| serve_subscriber(socket, state, account, params.from_sequence.unwrap_or(1)))
+}
+
+// Replays the journal from the requested sequence and then follows the live
+// stream, so a reconnecting subscriber sees every event exactly once
+async fn serve_subscriber(mut socket: WebSocket, state: Arc<AppState>, account: String, from_sequence: u64) {
+    let (backlog, mut live, first_available) = {
+        let mut drop_copy = state.drop_copy.lock().await;
+        let stream = drop_copy.accounts.entry(account.clone()).or_insert_with(AccountStream::new);
+        
+        // Subscribe while holding the lock so nothing is published between
+        // the replay snapshot and the live feed
+        let live = stream.live.subscribe();
+        let backlog: Vec<DropCopyEvent> = stream.journal.iter()
+            .filter(|event| event.sequence >= from_sequence)
+            .cloned()
+            .collect();
+        (backlog, live, stream.first_retained_sequence())
+    };
+    
+    if from_sequence < first_available {
+        let notice = serde_json::json!({
+            "type": "gap",
+            "account": account,
+            "requested_sequence": from_sequence,
+            "first_available_sequence": first_available,
+        });
+        if socket.send(Message::Text(notice.to_string())).await.is_err() {
+            return;
+        }
+    }
+    
+    let mut last_sent = from_sequence.saturating_sub(1);
+    for event in backlog {
+        last_sent = event.sequence;
+        if socket.send(Message::Text(serde_json::to_string(&event).unwrap())).await.is_err() {
+            return;
+        }
+    }
+    
+    loop {
+        match live.recv().await {
+            Ok(event) => {
+                if event.sequence <= last_sent {
+                    continue;
+                }
+                last_sent = event.sequence;
+                if socket.send(Message::Text(serde_json::to_string(&event).unwrap())).await.is_err() {
+                    return;
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                // The subscriber fell behind the live buffer; it can resume
+                // from its last sequence over a new connection
+                warn!("Drop copy subscriber for {} lagged by {} events", account, skipped);
+                let notice = serde_json::json!({
+                    "type": "lagged",
+                    "account": account,
+                    "resume_from_sequence": last_sent + 1,
+                });
+                let _ = socket.send(Message::Text(notice.to_string())).await;
+                return;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
@@ -1,45 +1,27 @@
-use polaris_core::kafka_utils::{create_kafka_consumer, create_kafka_producer, consume_messages, produce_message};
+use polaris_core::kafka_utils::{create_kafka_consumer, create_kafka_producer, consume_message_with_metadata, produce_message};
 use polaris_core::metrics::{KAFKA_MESSAGES_CONSUMED, KAFKA_MESSAGES_PRODUCED, EXECUTION_LATENCY, CONNECTION_FAILURES, CIRCUIT_BREAKER_TRIPPED};
-use polaris_core::exchange_connector::{ExchangeConnector, ExchangeType, OrderExecution, FillReport, OrderStatus};
+use polaris_core::exchange_connector::{connect, AdapterKind, ConnectorConfig, ExchangeConnector, ExchangeType, FillReport, OrderStatus, SimulatorConfig};
+use polaris_core::exchange_connector::binance::BinanceConfig;
+use polaris_core::exchange_connector::coinbase::CoinbaseConfig;
+use polaris_core::Order;
 use polaris_core::order_validator::{validate_order, OrderValidationError};
 use chrono::{Utc, DateTime};
-use tracing::info;
+use tracing::{info, warn};
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use rdkafka::producer::{FutureProducer, FutureRecord};
-use rdkafka::consumer::{StreamConsumer, Consumer};
+use rdkafka::consumer::Consumer;
 use tokio::sync::Mutex;
 use std::sync::Arc;
 use std::collections::{HashMap, HashSet};
-use uuid::Uuid;
 use tokio::time::Instant;
 use std::env;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct Order {
+#[derive(Deserialize, Debug, Clone)]
+struct CancelRequest {
     order_id: String,
-    client_order_id: String,
     symbol: String,
-    price: f64,
-    quantity: f64,
-    side: String, // "buy" or "sell"
-    order_type: String, // "limit", "market", etc.
-    time_in_force: String, // "GTC", "IOC", "FOK"
-    user_id: String,
-    timestamp: DateTime<Utc>,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct Fill {
-    fill_id: String,
-    order_id: String,
-    client_order_id: String,
-    symbol: String,
-    price: f64,
-    quantity: f64,
-    side: String,
-    exchange_id: String,
-    timestamp: DateTime<Utc>,
+    reason: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -51,6 +33,7 @@ struct ExecutionReport {
     filled_quantity: f64,
     remaining_quantity: f64,
     avg_price: f64,
+    reason: String,
     timestamp: DateTime<Utc>,
 }
 
@@ -59,6 +42,10 @@ struct ExecutionEngineConfig {
     kafka_brokers: String,
     input_topic: String,
     output_topic: String,
+    cancel_topic: String,
+    // Orders the risk manager sends to close out accounts below maintenance
+    // margin; they have already passed risk and go straight to the venue
+    liquidation_topic: String,
     heartbeat_interval: u64,
     circuit_breaker_threshold: u64,
     exchanges: Vec<ExchangeSubscription>,
@@ -69,11 +56,42 @@ struct ExecutionEngineConfig {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct ExchangeSubscription {
     exchange_type: ExchangeType,
+    #[serde(default)]
+    adapter: AdapterKind,
     symbols: Vec<String>,
     api_key: String,
     api_secret: String,
     endpoint: String,
+    #[serde(default)]
+    ws_endpoint: String,
     rate_limit: u64,
+    #[serde(default)]
+    simulator: SimulatorConfig,
+    // Our symbol -> venue symbol, for symbols the adapter can't derive
+    #[serde(default)]
+    symbol_map: HashMap<String, String>,
+    #[serde(default)]
+    binance: BinanceConfig,
+    #[serde(default)]
+    coinbase: CoinbaseConfig,
+}
+
+impl ExchangeSubscription {
+    fn connector_config(&self) -> ConnectorConfig {
+        ConnectorConfig {
+            exchange_type: self.exchange_type.clone(),
+            adapter: self.adapter.clone(),
+            api_key: self.api_key.clone(),
+            api_secret: self.api_secret.clone(),
+            endpoint: self.endpoint.clone(),
+            ws_endpoint: self.ws_endpoint.clone(),
+            rate_limit: self.rate_limit,
+            symbol_map: self.symbol_map.clone(),
+            simulator: self.simulator.clone(),
+            binance: self.binance.clone(),
+            coinbase: self.coinbase.clone(),
+        }
+    }
 }
 
 impl Default for ExecutionEngineConfig {
@@ -82,6 +100,8 @@ impl Default for ExecutionEngineConfig {
             kafka_brokers: "redpanda:9092".to_string(),
             input_topic: "orders.executable".to_string(),
             output_topic: "fills".to_string(),
+            cancel_topic: "orders.cancel".to_string(),
+            liquidation_topic: "orders.liquidation".to_string(),
             heartbeat_interval: 30_000,
             circuit_breaker_threshold: 5000,
             execution_timeout: 5000,
@@ -89,30 +109,72 @@ impl Default for ExecutionEngineConfig {
             exchanges: vec![
                 ExchangeSubscription {
                     exchange_type: ExchangeType::Binance,
+                    adapter: AdapterKind::Simulated,
                     symbols: vec!["BTC/USD".to_string(), "ETH/USD".to_string()],
                     api_key: "binance_key".to_string(),
                     api_secret: "binance_secret".to_string(),
-                    endpoint: "wss://binance.com/ws".to_string(),
+                    endpoint: "https://api.binance.com".to_string(),
+                    ws_endpoint: "wss://stream.binance.com:9443".to_string(),
                     rate_limit: 100,
+                    simulator: SimulatorConfig::default(),
+                    symbol_map: HashMap::new(),
+                    binance: BinanceConfig::default(),
+                    coinbase: CoinbaseConfig::default(),
                 },
                 ExchangeSubscription {
                     exchange_type: ExchangeType::Coinbase,
+                    adapter: AdapterKind::Simulated,
                     symbols: vec!["BTC/USD".to_string(), "ETH/USD".to_string()],
                     api_key: "coinbase_key".to_string(),
                     api_secret: "coinbase_secret".to_string(),
-                    endpoint: "wss://coinbase.com/ws".to_string(),
+                    endpoint: "https://api.coinbase.com".to_string(),
+                    ws_endpoint: "wss://advanced-trade-ws.coinbase.com".to_string(),
                     rate_limit: 50,
+                    simulator: SimulatorConfig::default(),
+                    symbol_map: HashMap::new(),
+                    binance: BinanceConfig::default(),
+                    coinbase: CoinbaseConfig::default(),
                 }
             ],
         }
     }
 }
 
+// An order resting at a venue; fills accumulate until it is done
+#[derive(Debug, Clone)]
+struct WorkingOrder {
+    order: Order,
+    exchange_type: ExchangeType,
+    filled_quantity: f64,
+    filled_notional: f64,
+    // Set once the venue has ended the order early, to the quantity it filled
+    closed_at: Option<f64>,
+}
+
+impl WorkingOrder {
+    fn remaining(&self) -> f64 {
+        (self.order.quantity - self.filled_quantity).max(0.0)
+    }
+    
+    // The venue closed the order and every fill it reported has arrived
+    fn venue_done(&self) -> bool {
+        self.closed_at.is_some_and(|filled| self.filled_quantity >= filled - 1e-9)
+    }
+    
+    fn avg_price(&self) -> f64 {
+        if self.filled_quantity > 0.0 {
+            self.filled_notional / self.filled_quantity
+        } else {
+            0.0
+        }
+    }
+}
+
 struct AppState {
     kafka_producer: FutureProducer,
-    order_consumer: StreamConsumer,
     config: ExecutionEngineConfig,
-    exchange_connectors: HashMap<ExchangeType, ExchangeConnector>,
+    exchange_connectors: HashMap<ExchangeType, Arc<dyn ExchangeConnector>>,
+    working_orders: HashMap<String, WorkingOrder>,
     circuit_breaker_state: CircuitBreakerState,
     last_error_time: Option<Instant>,
     error_count: u64,
@@ -132,6 +194,8 @@ async fn main() {
         kafka_brokers: env::var("KAFKA_BROKERS").unwrap_or_else(|_| "redpanda:9092".to_string()),
         input_topic: env::var("INPUT_TOPIC").unwrap_or_else(|_| "orders.executable".to_string()),
         output_topic: env::var("OUTPUT_TOPIC").unwrap_or_else(|_| "fills".to_string()),
+        cancel_topic: env::var("CANCEL_TOPIC").unwrap_or_else(|_| "orders.cancel".to_string()),
+        liquidation_topic: env::var("LIQUIDATION_TOPIC").unwrap_or_else(|_| "orders.liquidation".to_string()),
         heartbeat_interval: env::var("HEARTBEAT_INTERVAL")
             .map(|v| v.parse().unwrap_or(30_000))
             .unwrap_or(30_000),
@@ -146,7 +210,7 @@ async fn main() {
             .unwrap_or(3),
         exchanges: env::var("EXCHANGES")
             .map(|v| serde_json::from_str(&v).unwrap_or_default())
-            .unwrap_or_default(),
+            .unwrap_or_else(|_| ExecutionEngineConfig::default().exchanges),
         ..ExecutionEngineConfig::default()
     };
     
@@ -154,28 +218,26 @@ async fn main() {
     
     // Create order consumer
     let order_consumer = create_kafka_consumer(&config.kafka_brokers, "execution-engine-group");
-    order_consumer.subscribe(&[&config.input_topic]).expect("Failed to subscribe to executable orders topic");
+    order_consumer.subscribe(&[&config.input_topic, &config.liquidation_topic, &config.cancel_topic]).expect("Failed to subscribe to executable orders topic");
     
     info!("Execution engine started");
     
-    // Initialize exchange connectors
-    let mut exchange_connectors = HashMap::new();
+    // Initialize exchange connectors; the configured adapter decides how each venue is reached
+    let mut exchange_connectors: HashMap<ExchangeType, Arc<dyn ExchangeConnector>> = HashMap::new();
     for exchange_sub in &config.exchanges {
-        let connector = ExchangeConnector::new(
-            exchange_sub.exchange_type.clone(),
-            exchange_sub.api_key.clone(),
-            exchange_sub.api_secret.clone(),
-            exchange_sub.endpoint.clone(),
-            exchange_sub.rate_limit,
-        );
+        let connector = connect(&exchange_sub.connector_config())
+            .unwrap_or_else(|e| panic!("Failed to create {} connector: {}", exchange_sub.exchange_type, e));
+        info!("Using {:?} adapter for {}", exchange_sub.adapter, exchange_sub.exchange_type);
         exchange_connectors.insert(exchange_sub.exchange_type.clone(), connector);
     }
     
+    let fill_streams: Vec<_> = exchange_connectors.values().cloned().collect();
+    let order_topics = [config.input_topic.clone(), config.liquidation_topic.clone()];
     let app_state = Arc::new(Mutex::new(AppState {
         kafka_producer: producer,
-        order_consumer: order_consumer,
         config: config,
         exchange_connectors: exchange_connectors,
+        working_orders: HashMap::new(),
         circuit_breaker_state: CircuitBreakerState {
             error_count: 0,
             cooldown_until: None,
@@ -200,30 +262,43 @@ async fn main() {
         monitor_circuit_breaker(state_for_circuit_breaker).await;
     });
     
+    for connector in fill_streams {
+        let state_for_fills = Arc::clone(&app_state);
+        tokio::spawn(async move {
+            process_fills(state_for_fills, connector).await;
+        });
+    }
+    
     // Main loop for processing orders
     loop {
         let start = Instant::now();
         
-        // Process order messages
-        {
-            let mut state = app_state.lock().await;
+        // Wait without holding the state lock so venue fills keep flowing
+        if let Some(message) = consume_message_with_metadata(&order_consumer).await {
+            KAFKA_MESSAGES_CONSUMED.with_label_values(&[&message.topic]).inc();
             
-            if let Some(message) = consume_messages(&state.order_consumer, &state.config.input_topic, Duration::from_millis(100)).await {
-                KAFKA_MESSAGES_CONSUMED.with_label_values(&[&state.config.input_topic]).inc();
-                
-                if let Ok(order) = serde_json::from_str::<Order>(&message) {
-                    let result = handle_execution(&mut state, order).await;
-                    
-                    // Send execution report
-                    let output_topic = &state.config.output_topic;
-                    
-                    let result_json = serde_json::to_string(&result).unwrap();
-                    produce_message(&state.kafka_producer, output_topic, &result.order_id, &result_json)
-                        .await
-                        .expect("Failed to produce execution report");
-                    
-                    KAFKA_MESSAGES_PRODUCED.with_label_values(&[output_topic]).inc();
+            // Handlers take the lock themselves and release it around venue calls
+            let result = if order_topics.contains(&message.topic) {
+                match serde_json::from_str::<Order>(&message.payload) {
+                    Ok(order) => handle_execution(&app_state, order).await,
+                    Err(e) => {
+                        warn!("Failed to parse order: {}", e);
+                        None
+                    }
                 }
+            } else {
+                match serde_json::from_str::<CancelRequest>(&message.payload) {
+                    Ok(cancel) => handle_cancel(&app_state, cancel).await,
+                    Err(e) => {
+                        warn!("Failed to parse cancel request: {}", e);
+                        None
+                    }
+                }
+            };
+            
+            // Send execution report
+            if let Some(result) = result {
+                publish_report(&*app_state.lock().await, &result).await;
             }
         }
         
@@ -287,65 +362,86 @@ async fn monitor_circuit_breaker(state: Arc<Mutex<AppState>>) {
     }
 }
 
-async fn handle_execution(state: &mut AppState, order: Order) -> ExecutionReport {
+async fn handle_execution(app_state: &Arc<Mutex<AppState>>, order: Order) -> Option<ExecutionReport> {
     let start = Instant::now();
     
-    // Check circuit breaker
-    if let Some(cooldown) = state.circuit_breaker_state.cooldown_until {
-        if Instant::now() < cooldown {
-            return create_execution_report(
-                &order,
-                "rejected",
-                0.0,
-                order.quantity,
-                0.0,
-                Utc::now(),
-                "circuit_breaker_active"
-            );
-        }
-    }
-    
-    // Find appropriate exchange for the symbol
-    let exchange_type = match find_exchange_for_symbol(state, &order.symbol) {
-        Some(exchange) => exchange,
-        None => {
-            return create_execution_report(
-                &order,
-                "rejected",
-                0.0,
-                order.quantity,
-                0.0,
-                Utc::now(),
-                "no_exchange_for_symbol"
-            );
-        }
-    };
-    
-    // Get exchange connector
-    let connector = match state.exchange_connectors.get_mut(&exchange_type) {
-        Some(connector) => connector,
-        None => {
-            return create_execution_report(
-                &order,
-                "rejected",
-                0.0,
-                order.quantity,
-                0.0,
-                Utc::now(),
-                "exchange_connector_not_found"
-            );
+    let (connector, exchange_type, max_retries) = {
+        let mut state = app_state.lock().await;
+        
+        // Check circuit breaker
+        if let Some(cooldown) = state.circuit_breaker_state.cooldown_until {
+            if Instant::now() < cooldown {
+                return Some(create_execution_report(
+                    &order,
+                    "rejected",
+                    0.0,
+                    order.quantity,
+                    0.0,
+                    Utc::now(),
+                    "circuit_breaker_active"
+                ));
+            }
         }
+        
+        // Find appropriate exchange for the symbol
+        let exchange_type = match find_exchange_for_symbol(&state, &order.symbol) {
+            Some(exchange) => exchange,
+            None => {
+                return Some(create_execution_report(
+                    &order,
+                    "rejected",
+                    0.0,
+                    order.quantity,
+                    0.0,
+                    Utc::now(),
+                    "no_exchange_for_symbol"
+                ));
+            }
+        };
+        
+        // Get exchange connector
+        let connector = match state.exchange_connectors.get(&exchange_type) {
+            Some(connector) => Arc::clone(connector),
+            None => {
+                return Some(create_execution_report(
+                    &order,
+                    "rejected",
+                    0.0,
+                    order.quantity,
+                    0.0,
+                    Utc::now(),
+                    "exchange_connector_not_found"
+                ));
+            }
+        };
+        
+        // Track the order before it reaches the venue so early fills find it
+        state.working_orders.insert(order.order_id.clone(), WorkingOrder {
+            order: order.clone(),
+            exchange_type: exchange_type.clone(),
+            filled_quantity: 0.0,
+            filled_notional: 0.0,
+            closed_at: None,
+        });
+        (connector, exchange_type, state.config.max_retries)
     };
     
-    // Execute order with retries
+    // Place order with retries; fills arrive separately on the connector's fill stream.
+    // A transport error leaves the outcome unknown, so the venue is asked about the
+    // order before it is sent again.
     let mut retries = 0;
-    let execution_result = loop {
-        match connector.execute_order(&order).await {
-            Ok(execution) => break execution,
+    let ack = loop {
+        match connector.place_order(&order).await {
+            Ok(ack) => break ack,
             Err(e) => {
+                if let Ok(execution) = connector.query_order(&order.symbol, &order.order_id).await {
+                    warn!("Order {} reached {} despite error: {}", order.order_id, exchange_type, e);
+                    break execution;
+                }
                 retries += 1;
-                if retries > state.config.max_retries {
-                    return create_execution_report(
+                if retries > max_retries {
+                    app_state.lock().await.working_orders.remove(&order.order_id);
+                    return Some(create_execution_report(
                         &order,
                         "rejected",
                         0.0,
@@ -353,43 +449,170 @@ async fn handle_execution(state: &mut AppState, order: Order) -> ExecutionReport
                         0.0,
                         Utc::now(),
                         &format!("execution_failed: {}", e)
-                    );
+                    ));
                 }
                 tokio::time::sleep(Duration::from_millis(100)).await;
             }
         }
     };
     
-    // Create fill report
-    let fill = Fill {
-        fill_id: Uuid::new_v4().to_string(),
-        order_id: order.order_id.clone(),
-        client_order_id: order.client_order_id.clone(),
-        symbol: order.symbol.clone(),
-        price: execution_result.price,
-        quantity: execution_result.quantity,
-        side: order.side.clone(),
-        exchange_id: exchange_type.to_string(),
-        timestamp: Utc::now(),
-    };
-    
     // Update latency metrics
     let latency = (Utc::now().timestamp_nanos_opt().unwrap() - order.timestamp.timestamp_nanos_opt().unwrap()) as u64;
     EXECUTION_LATENCY.with_label_values(&[&order.symbol]).observe(latency as f64);
     
-    create_execution_report(
+    let mut state = app_state.lock().await;
+    if ack.status == OrderStatus::Rejected {
+        state.working_orders.remove(&order.order_id);
+        return Some(create_execution_report(
+            &order,
+            "rejected",
+            0.0,
+            order.quantity,
+            0.0,
+            Utc::now(),
+            ack.reason.as_deref().unwrap_or("exchange_rejected")
+        ));
+    }
+    
+    info!("Order {} accepted by {} as {}", order.order_id, exchange_type, ack.exchange_order_id);
+    
+    // IOC and market remainders are dropped by the venue straight away
+    if ack.status == OrderStatus::Cancelled {
+        if let Some(report) = close_working_order(&mut state, &order.order_id, ack.quantity, "unfilled_remainder_cancelled") {
+            return Some(report);
+        }
+    }
+    
+    // Fills that arrived while the order was being placed have already been
+    // reported, and an ack after them would set the cumulative fill back
+    match state.working_orders.get(&order.order_id) {
+        Some(working) if working.filled_quantity <= 0.0 => {}
+        _ => return None,
+    }
+    
+    Some(create_execution_report(
         &order,
-        match execution_result.status {
-            OrderStatus::Filled => "filled",
-            OrderStatus::Partial => "partial",
-            _ => "rejected",
-        },
-        execution_result.quantity,
-        order.quantity - execution_result.quantity,
-        execution_result.price,
+        "new",
+        0.0,
+        order.quantity,
+        0.0,
         Utc::now(),
+        "order_accepted"
+    ))
+}
+
+async fn handle_cancel(app_state: &Arc<Mutex<AppState>>, cancel: CancelRequest) -> Option<ExecutionReport> {
+    // Orders resting in the internal book are cancelled by the matching engine
+    let (working, connector) = {
+        let state = app_state.lock().await;
+        let working = state.working_orders.get(&cancel.order_id)?.clone();
+        let connector = Arc::clone(state.exchange_connectors.get(&working.exchange_type)?);
+        (working, connector)
+    };
+    
+    match connector.cancel_order(&cancel.symbol, &cancel.order_id).await {
+        Ok(execution) if execution.status == OrderStatus::Cancelled => {
+            close_working_order(&mut *app_state.lock().await, &cancel.order_id, execution.quantity, &cancel.reason)
+        }
+        Ok(execution) => {
+            warn!("Cancel of {} left order {:?}", cancel.order_id, execution.status);
+            None
+        }
+        Err(e) => {
+            warn!("Failed to cancel {} on {}: {}", cancel.order_id, working.exchange_type, e);
+            None
+        }
+    }
+}
+
+async fn process_fills(state: Arc<Mutex<AppState>>, connector: Arc<dyn ExchangeConnector>) {
+    let exchange_type = connector.exchange_type();
+    loop {
+        let mut fills = match connector.stream_fills().await {
+            Ok(fills) => fills,
+            Err(e) => {
+                warn!("Failed to open {} fill stream: {}", exchange_type, e);
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+        
+        while let Some(fill) = fills.recv().await {
+            let mut state = state.lock().await;
+            if let Some(report) = apply_fill(&mut state, &fill) {
+                publish_report(&state, &report).await;
+            }
+        }
+        
+        warn!("{} fill stream closed, reconnecting", exchange_type);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+fn apply_fill(state: &mut AppState, fill: &FillReport) -> Option<ExecutionReport> {
+    let working = match state.working_orders.get_mut(&fill.order_id) {
+        Some(working) => working,
+        None => {
+            warn!("Fill {} for unknown order {}", fill.fill_id, fill.order_id);
+            return None;
+        }
+    };
+    working.filled_quantity += fill.quantity;
+    working.filled_notional += fill.price * fill.quantity;
+    
+    let remaining = working.remaining();
+    let status = if remaining <= f64::EPSILON {
+        "filled"
+    } else if working.venue_done() {
+        "cancelled"
+    } else {
+        "partial"
+    };
+    let report = create_execution_report(
+        &working.order,
+        status,
+        working.filled_quantity,
+        remaining,
+        working.avg_price(),
+        fill.timestamp,
         "execution_complete"
-    )
+    );
+    
+    if status != "partial" {
+        state.working_orders.remove(&fill.order_id);
+    }
+    Some(report)
+}
+
+// Reports a venue-side close now, or once the fills it counted have all arrived
+fn close_working_order(state: &mut AppState, order_id: &str, venue_filled: f64, reason: &str) -> Option<ExecutionReport> {
+    let working = state.working_orders.get_mut(order_id)?;
+    working.closed_at = Some(venue_filled);
+    if !working.venue_done() {
+        return None;
+    }
+    
+    let working = state.working_orders.remove(order_id)?;
+    Some(create_execution_report(
+        &working.order,
+        "cancelled",
+        working.filled_quantity,
+        working.remaining(),
+        working.avg_price(),
+        Utc::now(),
+        reason
+    ))
+}
+
+async fn publish_report(state: &AppState, report: &ExecutionReport) {
+    let output_topic = &state.config.output_topic;
+    
+    let report_json = serde_json::to_string(report).unwrap();
+    produce_message(&state.kafka_producer, output_topic, &report.order_id, &report_json)
+        .await
+        .expect("Failed to produce execution report");
+    
+    KAFKA_MESSAGES_PRODUCED.with_label_values(&[output_topic]).inc();
 }
 
 fn create_execution_report(order: &Order, status: &str, filled_quantity: f64, remaining_quantity: f64, avg_price: f64, timestamp: DateTime<Utc>, reason: &str) -> ExecutionReport {
@@ -401,6 +624,7 @@ fn create_execution_report(order: &Order, status: &str, filled_quantity: f64, re
         filled_quantity: filled_quantity,
         remaining_quantity: remaining_quantity,
         avg_price: avg_price,
+        reason: reason.to_string(),
         timestamp: timestamp,
     }
 }
@@ -1,12 +1,14 @@
 use polaris_core::kafka_utils::{create_kafka_consumer, create_kafka_producer, consume_messages, produce_message};
 use polaris_core::metrics::{KAFKA_MESSAGES_CONSUMED, KAFKA_MESSAGES_PRODUCED, MARKET_DATA_LATENCY, CONNECTION_FAILURES, CIRCUIT_BREAKER_TRIPPED};
-use polaris_core::exchange_connector::{ExchangeConnector, ExchangeType, MarketData, OrderBookSnapshot, TradeEvent};
+use polaris_core::exchange_connector::{connect, AdapterKind, ConnectorConfig, ExchangeConnector, ExchangeType, MarketDataEvent, SimulatorConfig};
+use polaris_core::exchange_connector::binance::BinanceConfig;
+use polaris_core::exchange_connector::coinbase::CoinbaseConfig;
 use chrono::{Utc, DateTime};
-use tracing::info;
+use tracing::{info, warn};
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use rdkafka::producer::{FutureProducer, FutureRecord};
-use rdkafka::consumer::{StreamConsumer, Consumer};
+use rdkafka::consumer::Consumer;
 use tokio::sync::Mutex;
 use std::sync::Arc;
 use std::collections::{HashMap, HashSet};
@@ -22,6 +24,8 @@ struct MarketDataMessage {
     quantity: f64,
     bid: f64,
     ask: f64,
+    bid_size: f64,
+    ask_size: f64,
     timestamp: DateTime<Utc>,
 }
 
@@ -49,9 +53,39 @@ struct MarketDataHandlerConfig {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct ExchangeSubscription {
     exchange_type: ExchangeType,
+    #[serde(default)]
+    adapter: AdapterKind,
     symbols: Vec<String>,
     update_interval: u64, // milliseconds
     depth: u64, // order book depth
+    #[serde(default)]
+    endpoint: String,
+    #[serde(default)]
+    ws_endpoint: String,
+    #[serde(default)]
+    simulator: SimulatorConfig,
+    #[serde(default)]
+    symbol_map: HashMap<String, String>,
+    #[serde(default)]
+    binance: BinanceConfig,
+    #[serde(default)]
+    coinbase: CoinbaseConfig,
+}
+
+impl ExchangeSubscription {
+    // Market data only needs the public endpoints, so no credentials
+    fn connector_config(&self) -> ConnectorConfig {
+        let mut config = ConnectorConfig::new(self.exchange_type.clone(), self.adapter.clone());
+        config.endpoint = self.endpoint.clone();
+        // Left empty, the adapter falls back to the venue's public endpoint
+        config.ws_endpoint = self.ws_endpoint.clone();
+        config.rate_limit = 100; // Default rate limit
+        config.symbol_map = self.symbol_map.clone();
+        config.simulator = self.simulator.clone();
+        config.binance = self.binance.clone();
+        config.coinbase = self.coinbase.clone();
+        config
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -72,15 +106,29 @@ impl Default for MarketDataHandlerConfig {
             exchanges: vec![
                 ExchangeSubscription {
                     exchange_type: ExchangeType::Binance,
+                    adapter: AdapterKind::Paper,
                     symbols: vec!["BTCUSDT".to_string(), "ETHUSDT".to_string()],
                     update_interval: 100,
                     depth: 10,
+                    endpoint: String::new(),
+                    ws_endpoint: String::new(),
+                    simulator: SimulatorConfig::default(),
+                    symbol_map: HashMap::new(),
+                    binance: BinanceConfig::default(),
+                    coinbase: CoinbaseConfig::default(),
                 },
                 ExchangeSubscription {
                     exchange_type: ExchangeType::Coinbase,
+                    adapter: AdapterKind::Paper,
                     symbols: vec!["BTC-USD".to_string(), "ETH-USD".to_string()],
                     update_interval: 200,
                     depth: 5,
+                    endpoint: String::new(),
+                    ws_endpoint: String::new(),
+                    simulator: SimulatorConfig::default(),
+                    symbol_map: HashMap::new(),
+                    binance: BinanceConfig::default(),
+                    coinbase: CoinbaseConfig::default(),
                 }
             ],
             normalization_rules: {
@@ -113,9 +161,8 @@ impl Default for MarketDataHandlerConfig {
 
 struct AppState {
     kafka_producer: FutureProducer,
-    market_data_consumer: StreamConsumer,
     config: MarketDataHandlerConfig,
-    exchange_connectors: HashMap<ExchangeType, ExchangeConnector>,
+    exchange_connectors: HashMap<ExchangeType, Arc<dyn ExchangeConnector>>,
     circuit_breaker_state: CircuitBreakerState,
     last_error_time: Option<Instant>,
     error_count: u64,
@@ -146,9 +193,16 @@ async fn main() {
             .unwrap_or_else(|_| vec![
                 ExchangeSubscription {
                     exchange_type: ExchangeType::Binance,
+                    adapter: AdapterKind::Paper,
                     symbols: vec!["BTCUSDT".to_string(), "ETHUSDT".to_string()],
                     update_interval: 100,
                     depth: 10,
+                    endpoint: String::new(),
+                    ws_endpoint: String::new(),
+                    simulator: SimulatorConfig::default(),
+                    symbol_map: HashMap::new(),
+                    binance: BinanceConfig::default(),
+                    coinbase: CoinbaseConfig::default(),
                 }
             ]),
         normalization_rules: env::var("NORMALIZATION_RULES")
@@ -165,28 +219,20 @@ async fn main() {
     
     info!("Market data handler started");
     
-    // Initialize exchange connectors
-    let mut exchange_connectors = HashMap::new();
+    // Initialize exchange connectors; the configured adapter decides how each venue is reached
+    let mut exchange_connectors: HashMap<ExchangeType, Arc<dyn ExchangeConnector>> = HashMap::new();
+    let mut market_data_streams = Vec::new();
     for exchange_sub in &config.exchanges {
-        let connector = ExchangeConnector::new(
-            exchange_sub.exchange_type.clone(),
-            "".to_string(), // API key not needed for market data
-            "".to_string(), // API secret not needed for market data
-            match exchange_sub.exchange_type {
-                ExchangeType::Binance => "wss://binance.com/ws".to_string(),
-                ExchangeType::Coinbase => "wss://coinbase.com/ws".to_string(),
-                ExchangeType::Kraken => "wss://kraken.com/ws".to_string(),
-                ExchangeType::Solana => "wss://solana.com/ws".to_string(),
-                ExchangeType::Ethereum => "wss://ethereum.com/ws".to_string(),
-            },
-            100, // Default rate limit
-        );
+        let connector = connect(&exchange_sub.connector_config())
+            .unwrap_or_else(|e| panic!("Failed to create {} connector: {}", exchange_sub.exchange_type, e));
+        info!("Using {:?} adapter for {}", exchange_sub.adapter, exchange_sub.exchange_type);
+        market_data_streams.push((Arc::clone(&connector), exchange_sub.symbols.clone()));
         exchange_connectors.insert(exchange_sub.exchange_type.clone(), connector);
     }
     
+    let input_topic = config.input_topic.clone();
     let app_state = Arc::new(Mutex::new(AppState {
         kafka_producer: producer,
-        market_data_consumer: market_data_consumer,
         config: config,
         exchange_connectors: exchange_connectors,
         circuit_breaker_state: CircuitBreakerState {
@@ -218,31 +264,25 @@ async fn main() {
     tokio::spawn(async move {
         start_health_server(health_state).await;
     });
+    
+    for (connector, symbols) in market_data_streams {
+        let state_for_stream = Arc::clone(&app_state);
+        tokio::spawn(async move {
+            stream_market_data(state_for_stream, connector, symbols).await;
+        });
+    }
 
     // Main loop for processing market data
     loop {
         let start = Instant::now();
         
-        // Process raw market data messages
-        {
-            let mut state = app_state.lock().await;
+        // Wait without holding the state lock so venue streams keep publishing
+        if let Some(message) = consume_messages(&market_data_consumer, &input_topic, Duration::from_millis(100)).await {
+            KAFKA_MESSAGES_CONSUMED.with_label_values(&[&input_topic]).inc();
             
-            if let Some(message) = consume_messages(&state.market_data_consumer, &state.config.input_topic, Duration::from_millis(100)).await {
-                KAFKA_MESSAGES_CONSUMED.with_label_values(&[&state.config.input_topic]).inc();
-                
-                if let Ok(raw_data) = serde_json::from_str::<serde_json::Value>(&message) {
-                    let result = process_market_data(&mut state, raw_data).await;
-                    
-                    // Send normalized market data
-                    if let Ok(normalized_data) = result {
-                        let data_json = serde_json::to_string(&normalized_data).unwrap();
-                        produce_message(&state.kafka_producer, &state.config.output_topic, &normalized_data.symbol, &data_json)
-                            .await
-                            .expect("Failed to produce normalized market data");
-                        
-                        KAFKA_MESSAGES_PRODUCED.with_label_values(&[&state.config.output_topic]).inc();
-                    }
-                }
+            if let Ok(raw_data) = serde_json::from_str::<serde_json::Value>(&message) {
+                let mut state = app_state.lock().await;
+                publish_normalized(&mut state, raw_data).await;
             }
         }
         
@@ -305,6 +345,75 @@ async fn monitor_circuit_breaker(state: Arc<Mutex<AppState>>) {
     }
 }
 
+async fn stream_market_data(state: Arc<Mutex<AppState>>, connector: Arc<dyn ExchangeConnector>, symbols: Vec<String>) {
+    let exchange_type = connector.exchange_type();
+    loop {
+        let mut events = match connector.stream_market_data(&symbols).await {
+            Ok(events) => events,
+            Err(e) => {
+                warn!("Failed to open {} market data stream: {}", exchange_type, e);
+                CONNECTION_FAILURES.inc();
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+        
+        while let Some(event) = events.recv().await {
+            if let Some(raw_data) = raw_market_data(&exchange_type, event) {
+                let mut state = state.lock().await;
+                publish_normalized(&mut state, raw_data).await;
+            }
+        }
+        
+        warn!("{} market data stream closed, reconnecting", exchange_type);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+// Venue events take the same normalization path as messages on the raw topic
+fn raw_market_data(exchange_type: &ExchangeType, event: MarketDataEvent) -> Option<serde_json::Value> {
+    match event {
+        MarketDataEvent::Trade(trade) => Some(serde_json::json!({
+            "exchange": exchange_type.to_string(),
+            "symbol": trade.symbol,
+            "price": trade.price,
+            "quantity": trade.quantity,
+        })),
+        MarketDataEvent::Book(book) => {
+            let (bid, bid_size) = *book.bids.first()?;
+            let (ask, ask_size) = *book.asks.first()?;
+            Some(serde_json::json!({
+                "exchange": exchange_type.to_string(),
+                "symbol": book.symbol,
+                "price": (bid + ask) / 2.0,
+                "quantity": 0.0,
+                "bid": bid,
+                "ask": ask,
+                "bid_size": bid_size,
+                "ask_size": ask_size,
+            }))
+        }
+    }
+}
+
+async fn publish_normalized(state: &mut AppState, raw_data: serde_json::Value) {
+    let normalized_data = match process_market_data(state, raw_data).await {
+        Ok(normalized_data) => normalized_data,
+        Err(e) => {
+            tracing::debug!("Dropped market data: {}", e);
+            return;
+        }
+    };
+    
+    // Send normalized market data
+    let data_json = serde_json::to_string(&normalized_data).unwrap();
+    produce_message(&state.kafka_producer, &state.config.output_topic, &normalized_data.symbol, &data_json)
+        .await
+        .expect("Failed to produce normalized market data");
+    
+    KAFKA_MESSAGES_PRODUCED.with_label_values(&[&state.config.output_topic]).inc();
+}
+
 async fn process_market_data(state: &mut AppState, raw_data: serde_json::Value) -> Result<MarketDataMessage, String> {
     let start = Instant::now();
     
@@ -318,9 +427,8 @@ async fn process_market_data(state: &mut AppState, raw_data: serde_json::Value)
     // Extract exchange type and symbol from raw data
     let exchange_type = match raw_data.get("exchange") {
         Some(exchange_val) => {
-            match exchange_val.as_str() {
-                Some("binance") => ExchangeType::Binance,
-                Some("coinbase") => ExchangeType::Coinbase,
+            match exchange_val.as_str().map(|s| s.parse::<ExchangeType>()) {
+                Some(Ok(exchange_type)) => exchange_type,
                 _ => return Err("Unknown exchange".to_string()),
             }
         },
@@ -388,6 +496,8 @@ async fn process_market_data(state: &mut AppState, raw_data: serde_json::Value)
             Some(ask_val) => ask_val.as_f64().unwrap_or(normalized_price),
             None => normalized_price,
         },
+        bid_size: raw_data.get("bid_size").and_then(|v| v.as_f64()).unwrap_or(0.0),
+        ask_size: raw_data.get("ask_size").and_then(|v| v.as_f64()).unwrap_or(0.0),
         timestamp: Utc::now(),
     };
     
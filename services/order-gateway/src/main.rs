@@ -1,8 +1,10 @@
-use polaris_core::kafka_utils::{create_kafka_consumer, create_kafka_producer, consume_messages, produce_message};
+use polaris_core::kafka_utils::{create_kafka_consumer, create_kafka_producer, create_replay_consumer, consume_messages, consume_message_with_metadata,
+    produce_message, read_to_end, TopicOffsets};
 use polaris_core::metrics::{KAFKA_MESSAGES_CONSUMED, KAFKA_MESSAGES_PRODUCED, ORDER_LATENCY, CONNECTION_FAILURES, CIRCUIT_BREAKER_TRIPPED};
 use polaris_core::auth::{authenticate, authorize, ApiKeyAuth};
 use polaris_core::rate_limiter::{RateLimiter, RateLimitExceeded};
 use polaris_core::order_validator::{validate_order, OrderValidationError};
+use polaris_core::kill_switch::{KillSwitch, KillSwitchCommand, KillSwitchOutcome};
 use chrono::{Utc, DateTime};
 use tracing::info;
 use serde::{Deserialize, Serialize};
@@ -11,7 +13,7 @@ use rdkafka::producer::{FutureProducer, FutureRecord};
 use rdkafka::consumer::{StreamConsumer, Consumer};
 use tokio::sync::Mutex;
 use std::sync::Arc;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use uuid::Uuid;
 use tokio::time::Instant;
 use std::env;
@@ -39,6 +41,77 @@ struct OrderRequest {
     side: String,
     order_type: String,
     time_in_force: String,
+    #[serde(default)]
+    session_id: Option<String>, // WebSocket/FIX session that submitted the order
+}
+
+// Cancels every open order of the requesting user matching the filters
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct MassCancelRequest {
+    mass_cancel_id: String,
+    #[serde(default)]
+    session_id: Option<String>, // session sending the request; its user is the requester
+    #[serde(default)]
+    user_id: Option<String>,
+    #[serde(default)]
+    symbol: Option<String>,
+    #[serde(default)]
+    side: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum ClientMessage {
+    MassCancel(MassCancelRequest),
+    NewOrder(OrderRequest),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct MassCancelResponse {
+    mass_cancel_id: String,
+    cancelled_order_ids: Vec<String>,
+    timestamp: DateTime<Utc>,
+}
+
+// Connection lifecycle events published by the WebSocket/FIX front ends
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SessionEvent {
+    session_id: String,
+    user_id: String,
+    event: String, // "connected", "heartbeat", "disconnected"
+    #[serde(default)]
+    cancel_on_disconnect: Option<bool>,
+}
+
+// Sent to the matching engine to pull a resting order from the book
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CancelRequest {
+    cancel_id: String,
+    order_id: String,
+    client_order_id: String,
+    symbol: String,
+    side: String,
+    user_id: String,
+    reason: String,
+    timestamp: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct TradeNotice {
+    quantity: f64,
+    buyer_id: String,
+    seller_id: String,
+    #[serde(default)]
+    buyer_user_id: String,
+    #[serde(default)]
+    seller_user_id: String,
+}
+
+// Status carried by cancel reports and execution reports
+#[derive(Deserialize, Debug, Clone)]
+struct OrderStatusNotice {
+    order_id: String,
+    status: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -55,11 +128,27 @@ struct OrderGatewayConfig {
     kafka_brokers: String,
     input_topic: String,
     output_topic: String,
+    response_topic: String,
+    approved_topic: String,
+    rejected_topic: String,
+    ack_timeout: u64, // milliseconds
+    cancel_topic: String,
+    trades_topic: String,
+    cancelled_topic: String,
+    fills_topic: String, // execution reports
+    session_topic: String,
+    kill_switch_topic: String,
+    session_timeout: u64, // milliseconds without a heartbeat before a session counts as dropped
+    cancel_on_disconnect: bool,
     heartbeat_interval: u64,
     circuit_breaker_threshold: u64,
     rate_limit: u64,
     auth_type: String,
     api_keys: HashMap<String, ApiKeyAuth>,
+    dedup_window: u64, // seconds
+    dedup_capacity: u64,
+    // Tells gateway replicas apart; each one needs every decision for its own orders
+    instance_id: String,
 }
 
 impl Default for OrderGatewayConfig {
@@ -68,6 +157,18 @@ impl Default for OrderGatewayConfig {
             kafka_brokers: "redpanda:9092".to_string(),
             input_topic: "orders.client".to_string(),
             output_topic: "orders.incoming".to_string(),
+            response_topic: "orders.responses".to_string(),
+            approved_topic: "orders.validated".to_string(),
+            rejected_topic: "orders.rejected".to_string(),
+            ack_timeout: 5000,
+            cancel_topic: "orders.cancel".to_string(),
+            trades_topic: "trades.executed".to_string(),
+            cancelled_topic: "orders.cancelled".to_string(),
+            fills_topic: "fills".to_string(),
+            session_topic: "sessions.events".to_string(),
+            kill_switch_topic: "control.kill_switch".to_string(),
+            session_timeout: 90_000,
+            cancel_on_disconnect: true,
             heartbeat_interval: 30_000,
             circuit_breaker_threshold: 5000,
             rate_limit: 1000,
@@ -81,18 +182,59 @@ impl Default for OrderGatewayConfig {
                 });
                 keys
             },
+            dedup_window: 300,
+            dedup_capacity: 100_000,
+            instance_id: Uuid::new_v4().to_string(),
         }
     }
 }
 
 struct AppState {
     kafka_producer: FutureProducer,
-    order_consumer: StreamConsumer,
     config: OrderGatewayConfig,
     rate_limiter: RateLimiter,
     circuit_breaker_state: CircuitBreakerState,
     last_error_time: Option<Instant>,
     error_count: u64,
+    client_order_ids: ClientOrderIdCache,
+    pending_orders: HashMap<String, PendingOrder>,
+    open_orders: HashMap<String, OpenOrder>,
+    sessions: HashMap<String, Session>,
+    kill_switch: KillSwitch,
+}
+
+// An accepted order that may still be resting in the matching engine
+#[derive(Debug, Clone)]
+struct OpenOrder {
+    order: Order,
+    session_id: Option<String>,
+    remaining_quantity: f64,
+}
+
+#[derive(Debug, Clone)]
+struct Session {
+    user_id: String,
+    last_seen: Instant,
+    cancel_on_disconnect: bool,
+    order_ids: HashSet<String>,
+}
+
+// An order forwarded to the risk manager that has not yet been approved or
+// rejected downstream
+#[derive(Debug, Clone)]
+struct PendingOrder {
+    order: Order,
+    forwarded_at: Instant,
+}
+
+// The subset of the risk manager's validation result the gateway needs to
+// acknowledge an order back to the client
+#[derive(Deserialize, Debug, Clone)]
+struct RiskDecision {
+    order_id: String,
+    status: String,
+    #[serde(default)]
+    violations: Vec<String>,
 }
 
 #[derive(Clone, Debug)]
@@ -101,6 +243,77 @@ struct CircuitBreakerState {
     cooldown_until: Option<Instant>,
 }
 
+// Remembers the acknowledgement sent for each (user_id, client_order_id) so
+// that retried submissions within the window get the original response back
+// instead of creating a second order.
+struct ClientOrderIdCache {
+    window: Duration,
+    capacity: usize,
+    responses: HashMap<(String, String), OrderResponse>,
+    insertion_order: VecDeque<(Instant, (String, String))>,
+}
+
+impl ClientOrderIdCache {
+    fn new(window: Duration, capacity: usize) -> Self {
+        Self {
+            window,
+            capacity,
+            responses: HashMap::new(),
+            insertion_order: VecDeque::new(),
+        }
+    }
+
+    fn get(&mut self, user_id: &str, client_order_id: &str) -> Option<OrderResponse> {
+        self.evict_expired();
+        self.responses
+            .get(&(user_id.to_string(), client_order_id.to_string()))
+            .cloned()
+    }
+
+    fn insert(&mut self, user_id: &str, response: OrderResponse) {
+        self.evict_expired();
+
+        // Drop the oldest entries once the cache is full
+        while self.insertion_order.len() >= self.capacity {
+            match self.insertion_order.pop_front() {
+                Some((_, key)) => {
+                    self.responses.remove(&key);
+                }
+                None => break,
+            }
+        }
+
+        let key = (user_id.to_string(), response.client_order_id.clone());
+        self.insertion_order.push_back((Instant::now(), key.clone()));
+        self.responses.insert(key, response);
+    }
+
+    // Replaces the remembered acknowledgement once the order's fate is known,
+    // keeping its place in the window
+    fn update(&mut self, user_id: &str, response: OrderResponse) {
+        let key = (user_id.to_string(), response.client_order_id.clone());
+        if let Some(cached) = self.responses.get_mut(&key) {
+            *cached = response;
+        }
+    }
+
+    fn evict_expired(&mut self) {
+        let now = Instant::now();
+        while let Some((inserted_at, _)) = self.insertion_order.front() {
+            if now.duration_since(*inserted_at) <= self.window {
+                break;
+            }
+            if let Some((_, key)) = self.insertion_order.pop_front() {
+                self.responses.remove(&key);
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.responses.len()
+    }
+}
+
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
@@ -109,6 +322,24 @@ async fn main() {
         kafka_brokers: env::var("KAFKA_BROKERS").unwrap_or_else(|_| "redpanda:9092".to_string()),
         input_topic: env::var("INPUT_TOPIC").unwrap_or_else(|_| "orders.client".to_string()),
         output_topic: env::var("OUTPUT_TOPIC").unwrap_or_else(|_| "orders.incoming".to_string()),
+        response_topic: env::var("RESPONSE_TOPIC").unwrap_or_else(|_| "orders.responses".to_string()),
+        approved_topic: env::var("APPROVED_TOPIC").unwrap_or_else(|_| "orders.validated".to_string()),
+        rejected_topic: env::var("REJECTED_TOPIC").unwrap_or_else(|_| "orders.rejected".to_string()),
+        ack_timeout: env::var("ACK_TIMEOUT")
+            .map(|v| v.parse().unwrap_or(5000))
+            .unwrap_or(5000),
+        cancel_topic: env::var("CANCEL_TOPIC").unwrap_or_else(|_| "orders.cancel".to_string()),
+        trades_topic: env::var("TRADES_TOPIC").unwrap_or_else(|_| "trades.executed".to_string()),
+        cancelled_topic: env::var("CANCELLED_TOPIC").unwrap_or_else(|_| "orders.cancelled".to_string()),
+        fills_topic: env::var("FILLS_TOPIC").unwrap_or_else(|_| "fills".to_string()),
+        session_topic: env::var("SESSION_TOPIC").unwrap_or_else(|_| "sessions.events".to_string()),
+        kill_switch_topic: env::var("KILL_SWITCH_TOPIC").unwrap_or_else(|_| "control.kill_switch".to_string()),
+        session_timeout: env::var("SESSION_TIMEOUT")
+            .map(|v| v.parse().unwrap_or(90_000))
+            .unwrap_or(90_000),
+        cancel_on_disconnect: env::var("CANCEL_ON_DISCONNECT")
+            .map(|v| v.parse().unwrap_or(true))
+            .unwrap_or(true),
         heartbeat_interval: env::var("HEARTBEAT_INTERVAL")
             .map(|v| v.parse().unwrap_or(30_000))
             .unwrap_or(30_000),
@@ -131,6 +362,15 @@ async fn main() {
                 });
                 keys
             }),
+        dedup_window: env::var("DEDUP_WINDOW")
+            .map(|v| v.parse().unwrap_or(300))
+            .unwrap_or(300),
+        dedup_capacity: env::var("DEDUP_CAPACITY")
+            .map(|v| v.parse().unwrap_or(100_000))
+            .unwrap_or(100_000),
+        instance_id: env::var("INSTANCE_ID")
+            .or_else(|_| env::var("HOSTNAME"))
+            .unwrap_or_else(|_| Uuid::new_v4().to_string()),
         ..OrderGatewayConfig::default()
     };
     
@@ -140,11 +380,35 @@ async fn main() {
     let order_consumer = create_kafka_consumer(&config.kafka_brokers, "order-gateway-group");
     order_consumer.subscribe(&[&config.input_topic]).expect("Failed to subscribe to client orders topic");
     
+    // Create consumer for downstream risk decisions. Replicas must not share a
+    // group, or each would only see the decisions for some partitions.
+    let ack_consumer = create_kafka_consumer(&config.kafka_brokers, &format!("order-gateway-ack-group-{}", config.instance_id));
+    ack_consumer.subscribe(&[&config.approved_topic, &config.rejected_topic])
+        .expect("Failed to subscribe to risk decision topics");
+    
+    // Create consumer for fills so fully filled orders stop counting as open.
+    // Like decisions, every replica needs all of them.
+    let trade_consumer = create_kafka_consumer(&config.kafka_brokers, &format!("order-gateway-trades-group-{}", config.instance_id));
+    trade_consumer.subscribe(&[&config.trades_topic]).expect("Failed to subscribe to trades topic");
+    
+    // Create consumer for cancels and execution reports, which end orders that
+    // never fill completely (IOC remainders, venue and kill switch cancels)
+    let order_event_consumer = create_kafka_consumer(&config.kafka_brokers, &format!("order-gateway-order-events-group-{}", config.instance_id));
+    order_event_consumer.subscribe(&[&config.cancelled_topic, &config.fills_topic])
+        .expect("Failed to subscribe to cancel and execution report topics");
+    
+    // Create consumer for client session lifecycle events
+    let session_consumer = create_kafka_consumer(&config.kafka_brokers, "order-gateway-session-group");
+    session_consumer.subscribe(&[&config.session_topic]).expect("Failed to subscribe to session events topic");
+    
+    // Kill switch commands are replayed from the start so a restart keeps them.
+    // Every replica reads all partitions itself rather than sharing a group.
+    let kill_switch_consumer = create_replay_consumer(&config.kafka_brokers, "order-gateway-kill-switch-group");
+    
     info!("Order gateway started");
     
     let app_state = Arc::new(Mutex::new(AppState {
         kafka_producer: producer,
-        order_consumer: order_consumer,
         config: config.clone(),
         rate_limiter: RateLimiter::new(config.rate_limit),
         circuit_breaker_state: CircuitBreakerState {
@@ -153,8 +417,22 @@ async fn main() {
         },
         last_error_time: None,
         error_count: 0,
+        client_order_ids: ClientOrderIdCache::new(
+            Duration::from_secs(config.dedup_window),
+            config.dedup_capacity as usize,
+        ),
+        pending_orders: HashMap::new(),
+        open_orders: HashMap::new(),
+        sessions: HashMap::new(),
+        kill_switch: KillSwitch::new(),
     }));
     
+    // No order is accepted until every kill switch command so far is applied
+    while let Err(e) = replay_kill_switch(&app_state, &kill_switch_consumer).await {
+        tracing::error!("Failed to replay kill switch commands, retrying: {}", e);
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+    
     // Start background tasks
     let state_for_metrics = Arc::clone(&app_state);
     tokio::spawn(async move {
@@ -171,29 +449,83 @@ async fn main() {
         monitor_circuit_breaker(state_for_circuit_breaker).await;
     });
     
+    let state_for_acks = Arc::clone(&app_state);
+    tokio::spawn(async move {
+        process_risk_decisions(state_for_acks, ack_consumer).await;
+    });
+    
+    let state_for_ack_timeouts = Arc::clone(&app_state);
+    tokio::spawn(async move {
+        monitor_pending_orders(state_for_ack_timeouts).await;
+    });
+    
+    let state_for_trades = Arc::clone(&app_state);
+    tokio::spawn(async move {
+        process_trades(state_for_trades, trade_consumer).await;
+    });
+    
+    let state_for_order_events = Arc::clone(&app_state);
+    tokio::spawn(async move {
+        process_order_events(state_for_order_events, order_event_consumer).await;
+    });
+    
+    let state_for_sessions = Arc::clone(&app_state);
+    tokio::spawn(async move {
+        process_session_events(state_for_sessions, session_consumer).await;
+    });
+    
+    let state_for_session_timeouts = Arc::clone(&app_state);
+    tokio::spawn(async move {
+        monitor_sessions(state_for_session_timeouts).await;
+    });
+    
+    let state_for_kill_switch = Arc::clone(&app_state);
+    tokio::spawn(async move {
+        process_kill_switch(state_for_kill_switch, kill_switch_consumer).await;
+    });
+    
     // Main loop for processing orders
     loop {
         let start = Instant::now();
         
-        // Process order messages
-        {
-            let mut state = app_state.lock().await;
+        // Wait for the next message without holding the state lock so the
+        // acknowledgement tasks can make progress while the topic is idle
+        if let Some(message) = consume_messages(&order_consumer, &config.input_topic, Duration::from_millis(100)).await {
+            KAFKA_MESSAGES_CONSUMED.with_label_values(&[&config.input_topic]).inc();
             
-            if let Some(message) = consume_messages(&state.order_consumer, &state.config.input_topic, Duration::from_millis(100)).await {
-                KAFKA_MESSAGES_CONSUMED.with_label_values(&[&state.config.input_topic]).inc();
-                
-                if let Ok(order_request) = serde_json::from_str::<OrderRequest>(&message) {
-                    let result = handle_order(&mut state, order_request).await;
+            match serde_json::from_str::<ClientMessage>(&message) {
+                Ok(ClientMessage::NewOrder(order_request)) => {
+                    let mut state = app_state.lock().await;
+                    let session_id = order_request.session_id.clone();
+                    let (result, order) = handle_order(&mut state, order_request).await;
                     
-                    // Send result to appropriate topic
-                    let output_topic = &state.config.output_topic;
+                    // Forward accepted orders to the risk manager and track them
+                    // until a risk decision comes back
+                    if let Some(order) = order {
+                        let output_topic = &state.config.output_topic;
+                        
+                        let order_json = serde_json::to_string(&order).unwrap();
+                        produce_message(&state.kafka_producer, output_topic, &order.order_id, &order_json)
+                            .await
+                            .expect("Failed to forward order");
+                        
+                        KAFKA_MESSAGES_PRODUCED.with_label_values(&[output_topic]).inc();
+                        
+                        track_open_order(&mut state, &order, session_id);
+                        state.pending_orders.insert(order.order_id.clone(), PendingOrder {
+                            order,
+                            forwarded_at: Instant::now(),
+                        });
+                    }
                     
-                    let result_json = serde_json::to_string(&result).unwrap();
-                    produce_message(&state.kafka_producer, output_topic, &result.order_id, &result_json)
-                        .await
-                        .expect("Failed to produce order response");
-                    
-                    KAFKA_MESSAGES_PRODUCED.with_label_values(&[output_topic]).inc();
+                    publish_response(&state, &result).await;
+                }
+                Ok(ClientMessage::MassCancel(request)) => {
+                    let mut state = app_state.lock().await;
+                    handle_mass_cancel(&mut state, request).await;
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to parse client message: {}", e);
                 }
             }
         }
@@ -213,6 +545,10 @@ async fn report_metrics(state: Arc<Mutex<AppState>>) {
         // Report rate limiting stats
         info!("Rate limit - Current QPS: {}", state.rate_limiter.get_current_qps());
         
+        info!("Tracked client order ids: {}", state.client_order_ids.len());
+        info!("Orders awaiting risk decision: {}", state.pending_orders.len());
+        info!("Open orders: {} across {} sessions", state.open_orders.len(), state.sessions.len());
+        
         // Report error rate
         if state.error_count > 0 {
             info!("Total errors: {}", state.error_count);
@@ -256,21 +592,443 @@ async fn monitor_circuit_breaker(state: Arc<Mutex<AppState>>) {
     }
 }
 
-async fn handle_order(state: &mut AppState, order_request: OrderRequest) -> OrderResponse {
+async fn process_risk_decisions(state: Arc<Mutex<AppState>>, consumer: StreamConsumer) {
+    let decision_topics = {
+        let state = state.lock().await;
+        format!("{},{}", state.config.approved_topic, state.config.rejected_topic)
+    };
+    
+    loop {
+        if let Some(message) = consume_messages(&consumer, &decision_topics, Duration::from_millis(100)).await {
+            let decision = match serde_json::from_str::<RiskDecision>(&message) {
+                Ok(decision) => decision,
+                Err(e) => {
+                    tracing::warn!("Failed to parse risk decision: {}", e);
+                    continue;
+                }
+            };
+            
+            let mut state = state.lock().await;
+            
+            // Decisions for orders submitted through another gateway instance
+            // or already timed out are ignored
+            let pending = match state.pending_orders.remove(&decision.order_id) {
+                Some(pending) => pending,
+                None => continue,
+            };
+            
+            let topic = if decision.status == "approved" {
+                state.config.approved_topic.clone()
+            } else {
+                state.config.rejected_topic.clone()
+            };
+            KAFKA_MESSAGES_CONSUMED.with_label_values(&[&topic]).inc();
+            
+            if decision.status != "approved" {
+                untrack_open_order(&mut state, &decision.order_id);
+            }
+            
+            let response = if decision.status == "approved" {
+                create_order_response(
+                    &pending.order.order_id,
+                    &pending.order.client_order_id,
+                    "new",
+                    "risk_approved",
+                    Utc::now()
+                )
+            } else {
+                create_order_response(
+                    &pending.order.order_id,
+                    &pending.order.client_order_id,
+                    "rejected",
+                    &format!("risk_rejected: {}", decision.violations.join("; ")),
+                    Utc::now()
+                )
+            };
+            
+            // A retry of this client_order_id now gets the decision, not the
+            // original acceptance
+            state.client_order_ids.update(&pending.order.user_id, response.clone());
+            publish_response(&state, &response).await;
+        }
+    }
+}
+
+async fn monitor_pending_orders(state: Arc<Mutex<AppState>>) {
+    loop {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        
+        let mut state = state.lock().await;
+        let ack_timeout = Duration::from_millis(state.config.ack_timeout);
+        
+        let expired: Vec<String> = state.pending_orders.iter()
+            .filter(|(_, pending)| pending.forwarded_at.elapsed() > ack_timeout)
+            .map(|(order_id, _)| order_id.clone())
+            .collect();
+        
+        // Tell the client the order's fate is unknown rather than leaving it
+        // waiting forever; a late decision for it is dropped
+        for order_id in expired {
+            if let Some(pending) = state.pending_orders.remove(&order_id) {
+                tracing::warn!("No risk decision for order {} within {:?}", order_id, ack_timeout);
+                state.error_count += 1;
+                
+                let response = create_order_response(
+                    &pending.order.order_id,
+                    &pending.order.client_order_id,
+                    "unacknowledged",
+                    "downstream_ack_timeout",
+                    Utc::now()
+                );
+                publish_response(&state, &response).await;
+            }
+        }
+    }
+}
+
+async fn process_trades(state: Arc<Mutex<AppState>>, consumer: StreamConsumer) {
+    let trades_topic = state.lock().await.config.trades_topic.clone();
+    
+    loop {
+        if let Some(message) = consume_messages(&consumer, &trades_topic, Duration::from_millis(100)).await {
+            KAFKA_MESSAGES_CONSUMED.with_label_values(&[&trades_topic]).inc();
+            
+            let trade = match serde_json::from_str::<TradeNotice>(&message) {
+                Ok(trade) => trade,
+                Err(e) => {
+                    tracing::warn!("Failed to parse trade: {}", e);
+                    continue;
+                }
+            };
+            
+            // The matching engine identifies both sides by user and client_order_id
+            let mut state = state.lock().await;
+            for (user_id, client_order_id) in [(&trade.buyer_user_id, &trade.buyer_id), (&trade.seller_user_id, &trade.seller_id)] {
+                let filled = state.open_orders.iter_mut()
+                    .find(|(_, open)| &open.order.user_id == user_id && &open.order.client_order_id == client_order_id)
+                    .and_then(|(order_id, open)| {
+                        open.remaining_quantity -= trade.quantity;
+                        if open.remaining_quantity <= 0.0 {
+                            Some(order_id.clone())
+                        } else {
+                            None
+                        }
+                    });
+                
+                if let Some(order_id) = filled {
+                    untrack_open_order(&mut state, &order_id);
+                }
+            }
+        }
+    }
+}
+
+async fn process_order_events(state: Arc<Mutex<AppState>>, consumer: StreamConsumer) {
+    loop {
+        if let Some(message) = consume_message_with_metadata(&consumer).await {
+            KAFKA_MESSAGES_CONSUMED.with_label_values(&[&message.topic]).inc();
+            
+            let notice = match serde_json::from_str::<OrderStatusNotice>(&message.payload) {
+                Ok(notice) => notice,
+                Err(e) => {
+                    tracing::warn!("Failed to parse order status from {}: {}", message.topic, e);
+                    continue;
+                }
+            };
+            
+            // A cancel that found nothing to cancel is left to the other reports
+            if matches!(notice.status.as_str(), "filled" | "cancelled" | "rejected" | "expired") {
+                untrack_open_order(&mut *state.lock().await, &notice.order_id);
+            }
+        }
+    }
+}
+
+async fn process_session_events(state: Arc<Mutex<AppState>>, consumer: StreamConsumer) {
+    let session_topic = state.lock().await.config.session_topic.clone();
+    
+    loop {
+        if let Some(message) = consume_messages(&consumer, &session_topic, Duration::from_millis(100)).await {
+            KAFKA_MESSAGES_CONSUMED.with_label_values(&[&session_topic]).inc();
+            
+            let event = match serde_json::from_str::<SessionEvent>(&message) {
+                Ok(event) => event,
+                Err(e) => {
+                    tracing::warn!("Failed to parse session event: {}", e);
+                    continue;
+                }
+            };
+            
+            let mut state = state.lock().await;
+            match event.event.as_str() {
+                "connected" | "heartbeat" => {
+                    let default_cancel_on_disconnect = state.config.cancel_on_disconnect;
+                    let session = state.sessions.entry(event.session_id.clone()).or_insert_with(|| Session {
+                        user_id: event.user_id.clone(),
+                        last_seen: Instant::now(),
+                        cancel_on_disconnect: default_cancel_on_disconnect,
+                        order_ids: HashSet::new(),
+                    });
+                    session.last_seen = Instant::now();
+                    if let Some(cancel_on_disconnect) = event.cancel_on_disconnect {
+                        session.cancel_on_disconnect = cancel_on_disconnect;
+                    }
+                }
+                "disconnected" => {
+                    info!("Session {} for user {} disconnected", event.session_id, event.user_id);
+                    handle_session_disconnect(&mut state, &event.session_id, "session_disconnected").await;
+                }
+                other => {
+                    tracing::warn!("Unknown session event {} for session {}", other, event.session_id);
+                }
+            }
+        }
+    }
+}
+
+async fn process_kill_switch(state: Arc<Mutex<AppState>>, consumer: StreamConsumer) {
+    let kill_switch_topic = state.lock().await.config.kill_switch_topic.clone();
+    
+    loop {
+        if let Some(message) = consume_messages(&consumer, &kill_switch_topic, Duration::from_millis(100)).await {
+            KAFKA_MESSAGES_CONSUMED.with_label_values(&[&kill_switch_topic]).inc();
+            
+            apply_kill_switch_message(&mut *state.lock().await, &message);
+        }
+    }
+}
+
+// Reads the kill switch topic to its end; the consumer is left there for live commands
+async fn replay_kill_switch(state: &Arc<Mutex<AppState>>, consumer: &StreamConsumer) -> Result<(), String> {
+    let kill_switch_topic = state.lock().await.config.kill_switch_topic.clone();
+    let messages = read_to_end(consumer, &[&kill_switch_topic], &TopicOffsets::new())
+        .await
+        .map_err(|e| format!("Failed to read kill switch commands: {}", e))?;
+    
+    let mut state = state.lock().await;
+    for message in &messages {
+        apply_kill_switch_message(&mut state, &message.payload);
+    }
+    info!("Replayed {} kill switch commands, {} active", messages.len(), state.kill_switch.active().len());
+    Ok(())
+}
+
+fn apply_kill_switch_message(state: &mut AppState, message: &str) {
+    let command = match serde_json::from_str::<KillSwitchCommand>(message) {
+        Ok(command) => command,
+        Err(e) => {
+            tracing::warn!("Failed to parse kill switch command: {}", e);
+            return;
+        }
+    };
+    
+    match state.kill_switch.apply(&command) {
+        KillSwitchOutcome::Ignored(reason) => {
+            tracing::warn!("Ignoring kill switch command {}: {}", command.command_id, reason);
+        }
+        outcome => {
+            tracing::warn!("Kill switch {:?} for {} by {}: {}", outcome, command.scope, command.issued_by, command.reason);
+        }
+    }
+}
+
+async fn monitor_sessions(state: Arc<Mutex<AppState>>) {
+    loop {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        
+        let mut state = state.lock().await;
+        let session_timeout = Duration::from_millis(state.config.session_timeout);
+        
+        let lapsed: Vec<String> = state.sessions.iter()
+            .filter(|(_, session)| session.last_seen.elapsed() > session_timeout)
+            .map(|(session_id, _)| session_id.clone())
+            .collect();
+        
+        for session_id in lapsed {
+            tracing::warn!("Session {} missed heartbeats for {:?}", session_id, session_timeout);
+            handle_session_disconnect(&mut state, &session_id, "heartbeat_timeout").await;
+        }
+    }
+}
+
+async fn handle_session_disconnect(state: &mut AppState, session_id: &str, reason: &str) {
+    let session = match state.sessions.remove(session_id) {
+        Some(session) => session,
+        None => return,
+    };
+    
+    if !session.cancel_on_disconnect {
+        return;
+    }
+    
+    let order_ids: Vec<String> = session.order_ids.into_iter().collect();
+    if !order_ids.is_empty() {
+        info!("Cancelling {} open orders of user {} on {}", order_ids.len(), session.user_id, reason);
+    }
+    cancel_orders(state, &order_ids, reason).await;
+}
+
+async fn handle_mass_cancel(state: &mut AppState, request: MassCancelRequest) {
+    let requesting_user = request.session_id.as_ref()
+        .and_then(|session_id| state.sessions.get(session_id))
+        .map(|session| session.user_id.clone());
+    let user_id = request.user_id.clone().or_else(|| requesting_user.clone()).unwrap_or_default();
+    
+    let order_ids: Vec<String> = if requesting_user.is_none() {
+        tracing::warn!("Mass cancel {} from unknown session {:?}", request.mass_cancel_id, request.session_id);
+        Vec::new()
+    } else if requesting_user.as_ref() != Some(&user_id) {
+        tracing::warn!("User {:?} attempted mass cancel for user {}", requesting_user, user_id);
+        Vec::new()
+    } else {
+        state.open_orders.values()
+            .filter(|open| open.order.user_id == user_id)
+            .filter(|open| request.symbol.as_ref().is_none_or(|symbol| &open.order.symbol == symbol))
+            .filter(|open| request.side.as_ref().is_none_or(|side| &open.order.side == side))
+            .map(|open| open.order.order_id.clone())
+            .collect()
+    };
+    
+    info!("Mass cancel {} matched {} open orders", request.mass_cancel_id, order_ids.len());
+    cancel_orders(state, &order_ids, "mass_cancel").await;
+    
+    let response = MassCancelResponse {
+        mass_cancel_id: request.mass_cancel_id.clone(),
+        cancelled_order_ids: order_ids,
+        timestamp: Utc::now(),
+    };
+    
+    let response_topic = &state.config.response_topic;
+    let response_json = serde_json::to_string(&response).unwrap();
+    produce_message(&state.kafka_producer, response_topic, &request.mass_cancel_id, &response_json)
+        .await
+        .expect("Failed to produce mass cancel response");
+    
+    KAFKA_MESSAGES_PRODUCED.with_label_values(&[response_topic]).inc();
+}
+
+async fn cancel_orders(state: &mut AppState, order_ids: &[String], reason: &str) {
+    for order_id in order_ids {
+        let open = match untrack_open_order(state, order_id) {
+            Some(open) => open,
+            None => continue,
+        };
+        
+        let cancel = CancelRequest {
+            cancel_id: Uuid::new_v4().to_string(),
+            order_id: open.order.order_id.clone(),
+            client_order_id: open.order.client_order_id.clone(),
+            symbol: open.order.symbol.clone(),
+            side: open.order.side.clone(),
+            user_id: open.order.user_id.clone(),
+            reason: reason.to_string(),
+            timestamp: Utc::now(),
+        };
+        
+        let cancel_topic = &state.config.cancel_topic;
+        let cancel_json = serde_json::to_string(&cancel).unwrap();
+        produce_message(&state.kafka_producer, cancel_topic, &cancel.order_id, &cancel_json)
+            .await
+            .expect("Failed to produce cancel request");
+        
+        KAFKA_MESSAGES_PRODUCED.with_label_values(&[cancel_topic]).inc();
+        
+        let response = create_order_response(
+            &open.order.order_id,
+            &open.order.client_order_id,
+            "pending_cancel",
+            reason,
+            Utc::now()
+        );
+        publish_response(state, &response).await;
+    }
+}
+
+fn track_open_order(state: &mut AppState, order: &Order, session_id: Option<String>) {
+    if let Some(session_id) = &session_id {
+        // Submitting an order counts as session activity
+        let default_cancel_on_disconnect = state.config.cancel_on_disconnect;
+        let session = state.sessions.entry(session_id.clone()).or_insert_with(|| Session {
+            user_id: order.user_id.clone(),
+            last_seen: Instant::now(),
+            cancel_on_disconnect: default_cancel_on_disconnect,
+            order_ids: HashSet::new(),
+        });
+        session.last_seen = Instant::now();
+        session.order_ids.insert(order.order_id.clone());
+    }
+    
+    state.open_orders.insert(order.order_id.clone(), OpenOrder {
+        order: order.clone(),
+        session_id,
+        remaining_quantity: order.quantity,
+    });
+}
+
+fn untrack_open_order(state: &mut AppState, order_id: &str) -> Option<OpenOrder> {
+    let open = state.open_orders.remove(order_id)?;
+    if let Some(session) = open.session_id.as_ref().and_then(|id| state.sessions.get_mut(id)) {
+        session.order_ids.remove(order_id);
+    }
+    Some(open)
+}
+
+async fn publish_response(state: &AppState, response: &OrderResponse) {
+    let response_topic = &state.config.response_topic;
+    
+    let response_json = serde_json::to_string(response).unwrap();
+    produce_message(&state.kafka_producer, response_topic, &response.client_order_id, &response_json)
+        .await
+        .expect("Failed to produce order response");
+    
+    KAFKA_MESSAGES_PRODUCED.with_label_values(&[response_topic]).inc();
+}
+
+async fn handle_order(state: &mut AppState, order_request: OrderRequest) -> (OrderResponse, Option<Order>) {
     let start = Instant::now();
+    let user_id = "user1".to_string(); // In production, this would come from authentication
+    let client_order_id = order_request.client_order_id.clone();
+    
+    if client_order_id.is_empty() {
+        return (create_order_response(
+            "",
+            &client_order_id,
+            "rejected",
+            "missing_client_order_id",
+            Utc::now()
+        ), None);
+    }
+    
+    // A retried submission gets the original acknowledgement back
+    if let Some(original) = state.client_order_ids.get(&user_id, &client_order_id) {
+        info!("Duplicate client_order_id {} for user {}, returning original response", client_order_id, user_id);
+        return (original, None);
+    }
     
     // Check circuit breaker
     if let Some(cooldown) = state.circuit_breaker_state.cooldown_until {
         if Instant::now() < cooldown {
-            return create_order_response(
+            return (create_order_response(
                 "",
+                &client_order_id,
                 "rejected",
                 "circuit_breaker_active",
                 Utc::now()
-            );
+            ), None);
         }
     }
     
+    // Check kill switch
+    if let Some(scope) = state.kill_switch.blocking_scope(&user_id, &order_request.symbol) {
+        return (create_order_response(
+            "",
+            &client_order_id,
+            "rejected",
+            &format!("kill_switch_active: {}", scope),
+            Utc::now()
+        ), None);
+    }
+    
     // Convert OrderRequest to Order for validation
     let order_for_validation = polaris_core::Order {
         order_id: Uuid::new_v4().to_string(),
@@ -288,27 +1046,28 @@ async fn handle_order(state: &mut AppState, order_request: OrderRequest) -> Orde
     // Validate request
     let validation_result = validate_order(&order_for_validation);
     if let Err(e) = validation_result {
-        return create_order_response(
+        return (create_order_response(
             "",
+            &client_order_id,
             "rejected",
             &format!("validation_error: {:?}", e),
             Utc::now()
-        );
+        ), None);
     }
     
     // Check rate limiting
     if let Err(RateLimitExceeded) = state.rate_limiter.check() {
-        return create_order_response(
+        return (create_order_response(
             "",
+            &client_order_id,
             "rejected",
             "rate_limit_exceeded",
             Utc::now()
-        );
+        ), None);
     }
     
     // Create order with unique ID
     let order_id = Uuid::new_v4().to_string();
-    let client_order_id = order_request.client_order_id.clone();
     
     let order = Order {
         order_id: order_id.clone(),
@@ -319,7 +1078,7 @@ async fn handle_order(state: &mut AppState, order_request: OrderRequest) -> Orde
         side: order_request.side,
         order_type: order_request.order_type,
         time_in_force: order_request.time_in_force,
-        user_id: "user1".to_string(), // In production, this would come from authentication
+        user_id: user_id.clone(),
         timestamp: Utc::now(),
     };
     
@@ -327,18 +1086,25 @@ async fn handle_order(state: &mut AppState, order_request: OrderRequest) -> Orde
     let latency = (Utc::now().timestamp_nanos_opt().unwrap() - order.timestamp.timestamp_nanos_opt().unwrap()) as u64;
     ORDER_LATENCY.with_label_values(&[&order.symbol]).observe(latency as f64);
     
-    create_order_response(
+    let response = create_order_response(
         &order_id,
+        &client_order_id,
         "accepted",
         "order_received",
         Utc::now()
-    )
+    );
+    
+    // Only accepted orders reserve the client_order_id, so a submission
+    // rejected for a transient reason can be retried with the same id
+    state.client_order_ids.insert(&user_id, response.clone());
+    
+    (response, Some(order))
 }
 
-fn create_order_response(order_id: &str, status: &str, reason: &str, timestamp: DateTime<Utc>) -> OrderResponse {
+fn create_order_response(order_id: &str, client_order_id: &str, status: &str, reason: &str, timestamp: DateTime<Utc>) -> OrderResponse {
     OrderResponse {
         order_id: order_id.to_string(),
-        client_order_id: Uuid::new_v4().to_string(),
+        client_order_id: client_order_id.to_string(),
         status: status.to_string(),
         reason: reason.to_string(),
         timestamp: timestamp,
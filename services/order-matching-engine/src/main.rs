@@ -1,4 +1,6 @@
-use polaris_core::kafka_utils::{create_kafka_consumer, create_kafka_producer, consume_messages, produce_message};
+use polaris_core::kafka_utils::{create_kafka_consumer, create_kafka_producer, create_replay_consumer, consume_messages, produce_message,
+    read_to_end, TopicOffsets};
+use polaris_core::kill_switch::{KillSwitch, KillSwitchCommand, KillSwitchOutcome, KillSwitchScope};
 use polaris_core::metrics::{KAFKA_MESSAGES_CONSUMED, KAFKA_MESSAGES_PRODUCED};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
@@ -44,6 +46,8 @@ struct Order {
     quantity: f64,
     side: OrderSide,
     order_type: OrderType,
+    #[serde(default)]
+    user_id: String,
     timestamp: DateTime<Utc>,
 }
 
@@ -59,6 +63,29 @@ enum OrderType {
     Limit,
 }
 
+// Published by the order gateway for cancel-on-disconnect and mass cancels
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CancelRequest {
+    cancel_id: String,
+    order_id: String,
+    client_order_id: String,
+    symbol: String,
+    reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CancelReport {
+    cancel_id: String,
+    order_id: String,
+    client_order_id: String,
+    user_id: String, // owner of the cancelled order; empty when it was not found
+    symbol: String,
+    status: String, // "cancelled" or "not_found"
+    cancelled_quantity: f64,
+    reason: String,
+    timestamp: DateTime<Utc>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct Trade {
     trade_id: String,
@@ -67,6 +94,9 @@ struct Trade {
     quantity: f64,
     buyer_id: String,
     seller_id: String,
+    // client_order_ids are only unique per user, so each side names its owner
+    buyer_user_id: String,
+    seller_user_id: String,
     timestamp: DateTime<Utc>,
 }
 
@@ -151,6 +181,16 @@ impl MatchingEngine {
                             } else {
                                 existing_order.client_order_id.clone()
                             },
+                            buyer_user_id: if incoming_order.side == OrderSide::Buy {
+                                incoming_order.user_id.clone()
+                            } else {
+                                existing_order.user_id.clone()
+                            },
+                            seller_user_id: if incoming_order.side == OrderSide::Sell {
+                                incoming_order.user_id.clone()
+                            } else {
+                                existing_order.user_id.clone()
+                            },
                             timestamp: Utc::now(),
                         };
                         
@@ -179,6 +219,55 @@ impl MatchingEngine {
         trades
     }
 
+    fn cancel_order(&mut self, symbol: &str, order_id: &str) -> Option<Order> {
+        for book in [&mut self.buy_orders, &mut self.sell_orders] {
+            let symbol_book = match book.get_mut(symbol) {
+                Some(symbol_book) => symbol_book,
+                None => continue,
+            };
+            
+            let mut found = None;
+            for (price_level, orders_at_price) in symbol_book.iter_mut() {
+                if let Some(i) = orders_at_price.iter().position(|o| o.order_id == order_id) {
+                    found = Some((*price_level, orders_at_price.remove(i)));
+                    break;
+                }
+            }
+            
+            if let Some((price_level, order)) = found {
+                // Remove empty price level
+                if symbol_book.get(&price_level).is_some_and(|orders| orders.is_empty()) {
+                    symbol_book.remove(&price_level);
+                }
+                return Some(order);
+            }
+        }
+        
+        None
+    }
+
+    // Pulls every resting order covered by the kill switch scope out of the book
+    fn cancel_matching(&mut self, scope: &KillSwitchScope) -> Vec<Order> {
+        let mut cancelled = Vec::new();
+        
+        for book in [&mut self.buy_orders, &mut self.sell_orders] {
+            for symbol_book in book.values_mut() {
+                for orders_at_price in symbol_book.values_mut() {
+                    let (removed, kept): (Vec<Order>, Vec<Order>) = orders_at_price
+                        .drain(..)
+                        .partition(|o| scope.matches(&o.user_id, &o.symbol));
+                    *orders_at_price = kept;
+                    cancelled.extend(removed);
+                }
+                
+                // Remove empty price levels
+                symbol_book.retain(|_, orders| !orders.is_empty());
+            }
+        }
+        
+        cancelled
+    }
+
     fn add_to_order_book(&mut self, order: Order) {
         let book = match order.side {
             OrderSide::Buy => &mut self.buy_orders,
@@ -198,6 +287,7 @@ impl MatchingEngine {
 struct AppState {
     kafka_producer: FutureProducer,
     matching_engine: Arc<Mutex<MatchingEngine>>,
+    kill_switch: Mutex<KillSwitch>,
 }
 
 #[tokio::main]
@@ -217,6 +307,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let state = Arc::new(AppState {
         kafka_producer: producer,
         matching_engine: matching_engine.clone(),
+        kill_switch: Mutex::new(KillSwitch::new()),
+    });
+    
+    // Cancels are consumed separately so they are not queued behind new orders
+    let cancel_consumer: StreamConsumer = create_kafka_consumer(&kafka_brokers, "order-matching-cancel-group");
+    cancel_consumer.subscribe(&["orders.cancel"]).expect("Failed to subscribe to topic");
+    
+    let state_for_cancels = Arc::clone(&state);
+    tokio::spawn(async move {
+        process_cancels(state_for_cancels, cancel_consumer).await;
+    });
+    
+    // Kill switch commands are replayed from the start so a restart keeps them,
+    // and no order is booked until they are. Live commands follow on from there.
+    let kill_switch_consumer: StreamConsumer = create_replay_consumer(&kafka_brokers, "order-matching-kill-switch-group");
+    while let Err(e) = replay_kill_switch(&state, &kill_switch_consumer).await {
+        eprintln!("Failed to replay kill switch commands, retrying: {}", e);
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+    
+    let state_for_kill_switch = Arc::clone(&state);
+    tokio::spawn(async move {
+        process_kill_switch(state_for_kill_switch, kill_switch_consumer).await;
     });
     
     println!("Order matching engine started, consuming from orders.validated");
@@ -228,6 +341,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             
             match serde_json::from_str::<Order>(&message) {
                 Ok(order) => {
+                    // Orders already in flight when the kill switch fired are not booked
+                    let blocked = state.kill_switch.lock().await.blocking_scope(&order.user_id, &order.symbol).is_some();
+                    if blocked {
+                        let report = kill_switch_cancel_report(&order, "");
+                        let report_json = serde_json::to_string(&report)?;
+                        produce_message(&state.kafka_producer, "orders.cancelled", &report.order_id, &report_json).await.ok();
+                        continue;
+                    }
+                    
                     let mut engine = matching_engine.lock().await;
                     let trades = engine.process_order(order);
                     drop(engine);
@@ -247,4 +369,100 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         
         tokio::time::sleep(Duration::from_millis(10)).await;
     }
-}
\ No newline at end of file
+}
+
+async fn process_cancels(state: Arc<AppState>, consumer: StreamConsumer) {
+    loop {
+        if let Some(message) = consume_messages(&consumer, "orders.cancel", Duration::from_millis(1000)).await {
+            let cancel = match serde_json::from_str::<CancelRequest>(&message) {
+                Ok(cancel) => cancel,
+                Err(e) => {
+                    eprintln!("Failed to parse cancel request: {}", e);
+                    continue;
+                }
+            };
+            
+            let cancelled = state.matching_engine.lock().await.cancel_order(&cancel.symbol, &cancel.order_id);
+            
+            // Orders already filled or never booked are reported as not found
+            let report = CancelReport {
+                cancel_id: cancel.cancel_id.clone(),
+                order_id: cancel.order_id.clone(),
+                client_order_id: cancel.client_order_id.clone(),
+                user_id: cancelled.as_ref().map(|order| order.user_id.clone()).unwrap_or_default(),
+                symbol: cancel.symbol.clone(),
+                status: if cancelled.is_some() { "cancelled" } else { "not_found" }.to_string(),
+                cancelled_quantity: cancelled.map(|order| order.quantity).unwrap_or(0.0),
+                reason: cancel.reason.clone(),
+                timestamp: Utc::now(),
+            };
+            
+            if let Ok(report_json) = serde_json::to_string(&report) {
+                produce_message(&state.kafka_producer, "orders.cancelled", &report.order_id, &report_json).await.ok();
+            }
+        }
+    }
+}
+
+// The book starts empty, so replayed commands only restore which scopes are halted
+async fn replay_kill_switch(state: &Arc<AppState>, consumer: &StreamConsumer) -> Result<(), String> {
+    let messages = read_to_end(consumer, &["control.kill_switch"], &TopicOffsets::new())
+        .await
+        .map_err(|e| format!("Failed to read kill switch commands: {}", e))?;
+    
+    let mut kill_switch = state.kill_switch.lock().await;
+    for message in &messages {
+        match serde_json::from_str::<KillSwitchCommand>(&message.payload) {
+            Ok(command) => {
+                kill_switch.apply(&command);
+            }
+            Err(e) => eprintln!("Failed to parse kill switch command: {}", e),
+        }
+    }
+    println!("Replayed {} kill switch commands, {} active", messages.len(), kill_switch.active().len());
+    Ok(())
+}
+
+async fn process_kill_switch(state: Arc<AppState>, consumer: StreamConsumer) {
+    loop {
+        if let Some(message) = consume_messages(&consumer, "control.kill_switch", Duration::from_millis(1000)).await {
+            let command = match serde_json::from_str::<KillSwitchCommand>(&message) {
+                Ok(command) => command,
+                Err(e) => {
+                    eprintln!("Failed to parse kill switch command: {}", e);
+                    continue;
+                }
+            };
+            
+            let outcome = state.kill_switch.lock().await.apply(&command);
+            println!("Kill switch command {} for {}: {:?}", command.command_id, command.scope, outcome);
+            if outcome != KillSwitchOutcome::Activated {
+                continue;
+            }
+            
+            let cancelled = state.matching_engine.lock().await.cancel_matching(&command.scope);
+            println!("Kill switch cancelled {} resting orders for {}", cancelled.len(), command.scope);
+            
+            for order in cancelled {
+                let report = kill_switch_cancel_report(&order, &command.command_id);
+                if let Ok(report_json) = serde_json::to_string(&report) {
+                    produce_message(&state.kafka_producer, "orders.cancelled", &report.order_id, &report_json).await.ok();
+                }
+            }
+        }
+    }
+}
+
+fn kill_switch_cancel_report(order: &Order, command_id: &str) -> CancelReport {
+    CancelReport {
+        cancel_id: command_id.to_string(),
+        order_id: order.order_id.clone(),
+        client_order_id: order.client_order_id.clone(),
+        user_id: order.user_id.clone(),
+        symbol: order.symbol.clone(),
+        status: "cancelled".to_string(),
+        cancelled_quantity: order.quantity,
+        reason: "kill_switch".to_string(),
+        timestamp: Utc::now(),
+    }
+}
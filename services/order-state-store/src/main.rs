@@ -0,0 +1,634 @@
+use polaris_core::kafka_utils::{create_kafka_consumer, create_kafka_producer, consume_message_with_metadata, produce_message,
+    read_to_end, ConsumedMessage, TopicOffsets};
+use polaris_core::metrics::KAFKA_MESSAGES_CONSUMED;
+use polaris_core::auth::ApiKeyAuth;
+use polaris_core::order_lifecycle::OrderState;
+use polaris_core::Order;
+use chrono::{Utc, DateTime};
+use tracing::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use rdkafka::producer::FutureProducer;
+use rdkafka::consumer::StreamConsumer;
+use tokio::sync::Mutex;
+use std::sync::Arc;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::Json,
+    routing::get,
+    Router,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct OrderRecord {
+    order_id: String,
+    client_order_id: String,
+    user_id: String,
+    symbol: String,
+    side: String,
+    order_type: String,
+    price: f64,
+    quantity: f64,
+    filled_quantity: f64,
+    avg_fill_price: f64,
+    state: OrderState,
+    reason: Option<String>,
+    sequence: u64,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    transitions: Vec<StateTransition>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct StateTransition {
+    from: OrderState,
+    to: OrderState,
+    source: String, // topic the triggering event came from
+    timestamp: DateTime<Utc>,
+}
+
+// Gateway acknowledgement published to the client response topic
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct OrderResponse {
+    order_id: String,
+    client_order_id: String,
+    status: String,
+    reason: String,
+}
+
+// Risk manager decision published to orders.validated / orders.rejected
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct RiskValidation {
+    order_id: String,
+    status: String,
+    #[serde(default)]
+    violations: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Trade {
+    trade_id: String,
+    symbol: String,
+    price: f64,
+    quantity: f64,
+    buyer_id: String,
+    seller_id: String,
+    #[serde(default)]
+    buyer_user_id: String,
+    #[serde(default)]
+    seller_user_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ExecutionReport {
+    order_id: String,
+    status: String,
+    filled_quantity: f64,
+    remaining_quantity: f64,
+    avg_price: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CancelReport {
+    order_id: String,
+    status: String,
+    reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct OrderStateStoreConfig {
+    kafka_brokers: String,
+    orders_topic: String,
+    response_topic: String,
+    approved_topic: String,
+    rejected_topic: String,
+    trades_topic: String,
+    fills_topic: String,
+    cancelled_topic: String,
+    http_port: u16,
+    heartbeat_interval: u64,
+    max_page_size: usize,
+    max_unattributed: usize,
+    api_keys: HashMap<String, ApiKeyAuth>, // a key reads its own orders; "orders_read_all" reads every user's
+}
+
+impl Default for OrderStateStoreConfig {
+    fn default() -> Self {
+        OrderStateStoreConfig {
+            kafka_brokers: "redpanda:9092".to_string(),
+            orders_topic: "orders.incoming".to_string(),
+            response_topic: "orders.responses".to_string(),
+            approved_topic: "orders.validated".to_string(),
+            rejected_topic: "orders.rejected".to_string(),
+            trades_topic: "trades.executed".to_string(),
+            fills_topic: "fills".to_string(),
+            cancelled_topic: "orders.cancelled".to_string(),
+            http_port: 8080,
+            heartbeat_interval: 30_000,
+            max_page_size: 500,
+            max_unattributed: 10_000,
+            api_keys: HashMap::new(),
+        }
+    }
+}
+
+// An event that arrived before the order it refers to. Topics are consumed
+// independently, so decisions and fills can overtake orders.incoming.
+enum UnattributedEvent {
+    Event { order_id: String, message: ConsumedMessage },
+    Trade { user_id: String, client_order_id: String, quantity: f64, price: f64, message: ConsumedMessage },
+}
+
+impl UnattributedEvent {
+    fn belongs_to(&self, order: &Order) -> bool {
+        match self {
+            UnattributedEvent::Event { order_id, .. } => *order_id == order.order_id,
+            UnattributedEvent::Trade { user_id, client_order_id, .. } => {
+                *user_id == order.user_id && *client_order_id == order.client_order_id
+            }
+        }
+    }
+}
+
+// All known orders, indexed for the query API
+struct OrderStore {
+    orders: HashMap<String, OrderRecord>,
+    client_order_index: HashMap<(String, String), String>, // (user_id, client_order_id) -> order_id
+    user_orders: HashMap<String, Vec<String>>, // user_id -> order_ids in arrival order
+    unattributed: VecDeque<UnattributedEvent>,
+    next_sequence: u64,
+}
+
+impl OrderStore {
+    fn new() -> Self {
+        Self {
+            orders: HashMap::new(),
+            client_order_index: HashMap::new(),
+            user_orders: HashMap::new(),
+            unattributed: VecDeque::new(),
+            next_sequence: 0,
+        }
+    }
+
+    fn insert_order(&mut self, order: Order, now: DateTime<Utc>) {
+        if self.orders.contains_key(&order.order_id) {
+            return;
+        }
+        
+        self.next_sequence += 1;
+        let record = OrderRecord {
+            order_id: order.order_id.clone(),
+            client_order_id: order.client_order_id.clone(),
+            user_id: order.user_id.clone(),
+            symbol: order.symbol,
+            side: order.side,
+            order_type: order.order_type,
+            price: order.price,
+            quantity: order.quantity,
+            filled_quantity: 0.0,
+            avg_fill_price: 0.0,
+            state: OrderState::PendingNew,
+            reason: None,
+            sequence: self.next_sequence,
+            created_at: order.timestamp,
+            updated_at: now,
+            transitions: Vec::new(),
+        };
+        
+        self.client_order_index.insert((order.user_id.clone(), order.client_order_id), order.order_id.clone());
+        self.user_orders.entry(order.user_id).or_default().push(order.order_id.clone());
+        self.orders.insert(order.order_id, record);
+    }
+
+    fn transition(&mut self, order_id: &str, next: OrderState, source: &str, reason: Option<String>, now: DateTime<Utc>) {
+        let Some(record) = self.orders.get_mut(order_id) else {
+            return;
+        };
+        
+        // Redelivered events leave the record untouched
+        if record.state == next && next != OrderState::PartiallyFilled {
+            return;
+        }
+        
+        if !record.state.can_transition_to(next) {
+            warn!("Ignoring invalid transition {} -> {} for order {} from {}", record.state, next, order_id, source);
+            return;
+        }
+        
+        if record.state != next {
+            record.transitions.push(StateTransition {
+                from: record.state,
+                to: next,
+                source: source.to_string(),
+                timestamp: now,
+            });
+        }
+        record.state = next;
+        record.updated_at = now;
+        if reason.is_some() {
+            record.reason = reason;
+        }
+    }
+
+    fn apply_fill(&mut self, order_id: &str, quantity: f64, price: f64, source: &str, now: DateTime<Utc>) {
+        let next = match self.orders.get_mut(order_id) {
+            Some(record) if record.state.is_open() => {
+                let total = record.filled_quantity + quantity;
+                record.avg_fill_price = (record.avg_fill_price * record.filled_quantity + price * quantity) / total;
+                record.filled_quantity = total;
+                
+                if record.filled_quantity >= record.quantity {
+                    OrderState::Filled
+                } else {
+                    OrderState::PartiallyFilled
+                }
+            }
+            Some(record) => {
+                warn!("Fill from {} for order {} in terminal state {}", source, order_id, record.state);
+                return;
+            }
+            None => return,
+        };
+        
+        self.transition(order_id, next, source, None, now);
+    }
+
+    fn hold_unattributed(&mut self, event: UnattributedEvent, max_unattributed: usize) {
+        if self.unattributed.len() >= max_unattributed {
+            if let Some(UnattributedEvent::Event { message, .. } | UnattributedEvent::Trade { message, .. }) = self.unattributed.pop_front() {
+                warn!("Dropping unattributed {} event at offset {}", message.topic, message.offset);
+            }
+        }
+        self.unattributed.push_back(event);
+    }
+
+    fn open_orders(&self, user_id: &str) -> Vec<OrderRecord> {
+        self.user_orders.get(user_id)
+            .map(|order_ids| {
+                order_ids.iter()
+                    .filter_map(|order_id| self.orders.get(order_id))
+                    .filter(|record| record.state.is_open())
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    // Newest first; `before` is the sequence number of the last order on the
+    // previous page
+    fn history(&self, user_id: &str, before: Option<u64>, limit: usize) -> Vec<OrderRecord> {
+        self.user_orders.get(user_id)
+            .map(|order_ids| {
+                order_ids.iter()
+                    .rev()
+                    .filter_map(|order_id| self.orders.get(order_id))
+                    .filter(|record| before.is_none_or(|before| record.sequence < before))
+                    .take(limit)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+struct AppState {
+    kafka_producer: FutureProducer,
+    event_consumer: StreamConsumer,
+    config: OrderStateStoreConfig,
+    store: Mutex<OrderStore>,
+}
+
+#[derive(Deserialize, Debug)]
+struct HistoryParams {
+    user_id: String,
+    before: Option<u64>,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize, Debug)]
+struct HistoryPage {
+    orders: Vec<OrderRecord>,
+    next_before: Option<u64>,
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+    
+    let config = OrderStateStoreConfig {
+        kafka_brokers: env::var("KAFKA_BROKERS").unwrap_or_else(|_| "redpanda:9092".to_string()),
+        orders_topic: env::var("ORDERS_TOPIC").unwrap_or_else(|_| "orders.incoming".to_string()),
+        response_topic: env::var("RESPONSE_TOPIC").unwrap_or_else(|_| "orders.responses".to_string()),
+        approved_topic: env::var("APPROVED_TOPIC").unwrap_or_else(|_| "orders.validated".to_string()),
+        rejected_topic: env::var("REJECTED_TOPIC").unwrap_or_else(|_| "orders.rejected".to_string()),
+        trades_topic: env::var("TRADES_TOPIC").unwrap_or_else(|_| "trades.executed".to_string()),
+        fills_topic: env::var("FILLS_TOPIC").unwrap_or_else(|_| "fills".to_string()),
+        cancelled_topic: env::var("CANCELLED_TOPIC").unwrap_or_else(|_| "orders.cancelled".to_string()),
+        http_port: env::var("HTTP_PORT")
+            .map(|v| v.parse().unwrap_or(8080))
+            .unwrap_or(8080),
+        heartbeat_interval: env::var("HEARTBEAT_INTERVAL")
+            .map(|v| v.parse().unwrap_or(30_000))
+            .unwrap_or(30_000),
+        api_keys: env::var("API_KEYS")
+            .map(|v| serde_json::from_str(&v).expect("Invalid API_KEYS"))
+            .unwrap_or_default(),
+        ..OrderStateStoreConfig::default()
+    };
+    
+    let producer = create_kafka_producer(&config.kafka_brokers);
+    
+    // Create a single consumer for every order lifecycle topic
+    let event_consumer = create_kafka_consumer(&config.kafka_brokers, "order-state-store-group");
+    
+    info!("Order state store started");
+    
+    let http_port = config.http_port;
+    let app_state = Arc::new(AppState {
+        kafka_producer: producer,
+        event_consumer,
+        config,
+        store: Mutex::new(OrderStore::new()),
+    });
+    
+    // The store lives in memory, so it is rebuilt from the start of every
+    // lifecycle topic before queries are served. Live processing carries on
+    // from where the replay stops.
+    while let Err(e) = replay_events(&app_state).await {
+        tracing::error!("Failed to replay order lifecycle topics, retrying: {}", e);
+        *app_state.store.lock().await = OrderStore::new();
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+    
+    // Start background tasks
+    let state_for_processing = Arc::clone(&app_state);
+    tokio::spawn(async move {
+        process_events(state_for_processing).await;
+    });
+    
+    let state_for_heartbeat = Arc::clone(&app_state);
+    tokio::spawn(async move {
+        send_heartbeat(state_for_heartbeat).await;
+    });
+    
+    // Start REST API server
+    let app = Router::new()
+        .route("/health", get(health_check))
+        .route("/orders/:order_id", get(get_order))
+        .route("/users/:user_id/orders/open", get(get_open_orders))
+        .route("/orders", get(get_order_history))
+        .with_state(Arc::clone(&app_state));
+    
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", http_port))
+        .await
+        .expect("Failed to bind HTTP port");
+    
+    info!("Order query API listening on port {}", http_port);
+    
+    axum::serve(listener, app)
+        .await
+        .expect("Failed to start server");
+}
+
+async fn replay_events(state: &AppState) -> Result<(), String> {
+    let config = &state.config;
+    let topics = [
+        config.orders_topic.as_str(),
+        config.response_topic.as_str(),
+        config.approved_topic.as_str(),
+        config.rejected_topic.as_str(),
+        config.trades_topic.as_str(),
+        config.fills_topic.as_str(),
+        config.cancelled_topic.as_str(),
+    ];
+    let messages = read_to_end(&state.event_consumer, &topics, &TopicOffsets::new())
+        .await
+        .map_err(|e| format!("Failed to read order lifecycle topics: {}", e))?;
+    
+    let mut store = state.store.lock().await;
+    for message in &messages {
+        if let Err(e) = handle_event(&mut store, config, message) {
+            warn!("Failed to replay event from {}: {}", message.topic, e);
+        }
+    }
+    
+    info!("Replayed {} order lifecycle events into {} orders", messages.len(), store.orders.len());
+    Ok(())
+}
+
+async fn process_events(state: Arc<AppState>) {
+    loop {
+        if let Some(message) = consume_message_with_metadata(&state.event_consumer).await {
+            KAFKA_MESSAGES_CONSUMED.with_label_values(&[&message.topic]).inc();
+            
+            let mut store = state.store.lock().await;
+            if let Err(e) = handle_event(&mut store, &state.config, &message) {
+                warn!("Failed to handle event from {}: {}", message.topic, e);
+            }
+        }
+    }
+}
+
+fn handle_event(store: &mut OrderStore, config: &OrderStateStoreConfig, message: &ConsumedMessage) -> Result<(), serde_json::Error> {
+    let topic = message.topic.as_str();
+    let now = message.timestamp_ms.and_then(DateTime::from_timestamp_millis).unwrap_or_else(Utc::now);
+    
+    if topic == config.orders_topic {
+        let order: Order = serde_json::from_str(&message.payload)?;
+        store.insert_order(order.clone(), now);
+        
+        // Apply anything that overtook the order, in the order it arrived
+        let (ready, waiting): (VecDeque<_>, VecDeque<_>) = store.unattributed
+            .drain(..)
+            .partition(|event| event.belongs_to(&order));
+        store.unattributed = waiting;
+        for event in ready {
+            match event {
+                UnattributedEvent::Event { message, .. } => handle_event(store, config, &message)?,
+                UnattributedEvent::Trade { quantity, price, message, .. } => {
+                    let now = message.timestamp_ms.and_then(DateTime::from_timestamp_millis).unwrap_or_else(Utc::now);
+                    store.apply_fill(&order.order_id, quantity, price, &message.topic, now);
+                }
+            }
+        }
+        return Ok(());
+    }
+    
+    if topic == config.trades_topic {
+        // The matching engine identifies both sides by user and client_order_id
+        let trade: Trade = serde_json::from_str(&message.payload)?;
+        for (user_id, client_order_id) in [(&trade.buyer_user_id, &trade.buyer_id), (&trade.seller_user_id, &trade.seller_id)] {
+            match store.client_order_index.get(&(user_id.clone(), client_order_id.clone())).cloned() {
+                Some(order_id) => store.apply_fill(&order_id, trade.quantity, trade.price, topic, now),
+                None => store.hold_unattributed(UnattributedEvent::Trade {
+                    user_id: user_id.clone(),
+                    client_order_id: client_order_id.clone(),
+                    quantity: trade.quantity,
+                    price: trade.price,
+                    message: message.clone(),
+                }, config.max_unattributed),
+            }
+        }
+        return Ok(());
+    }
+    
+    // Every other topic refers to the order by order_id
+    let order_id = serde_json::from_str::<serde_json::Value>(&message.payload)?
+        .get("order_id")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default()
+        .to_string();
+    if order_id.is_empty() {
+        // Mass cancel summaries share the response topic but carry no single order
+        return Ok(());
+    }
+    if !store.orders.contains_key(&order_id) {
+        store.hold_unattributed(UnattributedEvent::Event { order_id, message: message.clone() }, config.max_unattributed);
+        return Ok(());
+    }
+    
+    if topic == config.response_topic {
+        if let Ok(response) = serde_json::from_str::<OrderResponse>(&message.payload) {
+            if response.status == "rejected" {
+                store.transition(&response.order_id, OrderState::Rejected, topic, Some(response.reason), now);
+            }
+        }
+    } else if topic == config.approved_topic {
+        let validation: RiskValidation = serde_json::from_str(&message.payload)?;
+        store.transition(&validation.order_id, OrderState::New, topic, None, now);
+    } else if topic == config.rejected_topic {
+        let validation: RiskValidation = serde_json::from_str(&message.payload)?;
+        store.transition(&validation.order_id, OrderState::Rejected, topic, Some(validation.violations.join("; ")), now);
+    } else if topic == config.fills_topic {
+        let report: ExecutionReport = serde_json::from_str(&message.payload)?;
+        match report.status.as_str() {
+            "filled" | "partial" => {
+                // Execution reports carry cumulative quantities and average price,
+                // so the new fill is priced at what the cumulative average implies
+                let (already_filled, already_avg) = store.orders.get(&report.order_id)
+                    .map(|record| (record.filled_quantity, record.avg_fill_price))
+                    .unwrap_or((0.0, 0.0));
+                let delta = report.filled_quantity - already_filled;
+                if delta > 0.0 {
+                    let price = (report.avg_price * report.filled_quantity - already_avg * already_filled) / delta;
+                    let price = if price.is_finite() && price > 0.0 { price } else { report.avg_price };
+                    store.apply_fill(&report.order_id, delta, price, topic, now);
+                }
+            }
+            "rejected" => {
+                store.transition(&report.order_id, OrderState::Rejected, topic, Some("execution_rejected".to_string()), now);
+            }
+            _ => {}
+        }
+    } else if topic == config.cancelled_topic {
+        let report: CancelReport = serde_json::from_str(&message.payload)?;
+        if report.status == "cancelled" {
+            store.transition(&report.order_id, OrderState::Cancelled, topic, Some(report.reason), now);
+        }
+    }
+    
+    Ok(())
+}
+
+async fn send_heartbeat(state: Arc<AppState>) {
+    loop {
+        tokio::time::sleep(Duration::from_millis(state.config.heartbeat_interval)).await;
+        
+        let heartbeat = serde_json::json!({
+            "timestamp": Utc::now(),
+            "status": "alive",
+            "component": "order-state-store"
+        });
+        
+        produce_message(&state.kafka_producer, "system.heartbeats", "order-state-store", &heartbeat.to_string())
+            .await
+            .expect("Failed to send heartbeat");
+    }
+}
+
+// REST API handlers
+async fn health_check() -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "status": "healthy",
+        "timestamp": Utc::now(),
+        "service": "order-state-store"
+    }))
+}
+
+type ApiError = (StatusCode, Json<serde_json::Value>);
+
+fn api_error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(serde_json::json!({ "error": message })))
+}
+
+// Checks the X-API-Key / X-API-Secret headers and returns the caller's key
+fn authenticate_request<'a>(config: &'a OrderStateStoreConfig, headers: &HeaderMap) -> Result<&'a ApiKeyAuth, ApiError> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let (key, secret) = match (header("x-api-key"), header("x-api-secret")) {
+        (Some(key), Some(secret)) => (key, secret),
+        _ => return Err(api_error(StatusCode::UNAUTHORIZED, "Missing API credentials")),
+    };
+    
+    match config.api_keys.get(key) {
+        Some(auth) if auth.secret == secret => Ok(auth),
+        _ => Err(api_error(StatusCode::UNAUTHORIZED, "Invalid API credentials")),
+    }
+}
+
+fn can_read_user(auth: &ApiKeyAuth, user_id: &str) -> bool {
+    auth.key == user_id || auth.permissions.iter().any(|p| p == "orders_read_all")
+}
+
+async fn get_order(
+    State(state): State<Arc<AppState>>,
+    Path(order_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<OrderRecord>, ApiError> {
+    let auth = authenticate_request(&state.config, &headers)?;
+    let store = state.store.lock().await;
+    
+    // Clients usually only know their own client_order_id
+    let order_id = store.client_order_index.get(&(auth.key.clone(), order_id.clone())).cloned().unwrap_or(order_id);
+    match store.orders.get(&order_id) {
+        // Other users' orders look the same as missing ones
+        Some(record) if can_read_user(auth, &record.user_id) => Ok(Json(record.clone())),
+        _ => Err(api_error(StatusCode::NOT_FOUND, "Order not found")),
+    }
+}
+
+async fn get_open_orders(
+    State(state): State<Arc<AppState>>,
+    Path(user_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<OrderRecord>>, ApiError> {
+    let auth = authenticate_request(&state.config, &headers)?;
+    if !can_read_user(auth, &user_id) {
+        return Err(api_error(StatusCode::FORBIDDEN, "Not authorized for this user"));
+    }
+    Ok(Json(state.store.lock().await.open_orders(&user_id)))
+}
+
+async fn get_order_history(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<HistoryParams>,
+    headers: HeaderMap,
+) -> Result<Json<HistoryPage>, ApiError> {
+    let auth = authenticate_request(&state.config, &headers)?;
+    if !can_read_user(auth, &params.user_id) {
+        return Err(api_error(StatusCode::FORBIDDEN, "Not authorized for this user"));
+    }
+    
+    let limit = params.limit.unwrap_or(100).min(state.config.max_page_size);
+    let orders = state.store.lock().await.history(&params.user_id, params.before, limit);
+    
+    let next_before = if orders.len() == limit {
+        orders.last().map(|record| record.sequence)
+    } else {
+        None
+    };
+    
+    Ok(Json(HistoryPage { orders, next_before }))
+}
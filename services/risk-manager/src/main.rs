@@ -1,8 +1,17 @@
-use polaris_core::kafka_utils::{create_kafka_consumer, create_kafka_producer, consume_messages, produce_message};
-use polaris_core::metrics::{KAFKA_MESSAGES_CONSUMED, KAFKA_MESSAGES_PRODUCED, RISK_VIOLATIONS, CIRCUIT_BREAKER_TRIPPED, POSITION_EXPOSURE};
-use polaris_core::risk_engine::{RiskEngine, PositionLimit, RiskRule, ComplianceCheck};
+use polaris_core::kafka_utils::{create_kafka_consumer, create_kafka_producer, create_replay_consumer, consume_message_with_metadata, produce_message,
+    assign_from_offsets, high_watermarks, offsets_for_timestamp, read_to_end, ConsumedMessage, TopicOffsets};
+use polaris_core::metrics::{KAFKA_MESSAGES_CONSUMED, KAFKA_MESSAGES_PRODUCED, RISK_VIOLATIONS, CIRCUIT_BREAKER_TRIPPED, CIRCUIT_BREAKER_STATE, POSITION_EXPOSURE, POSITION_PNL, USER_VAR, ACCOUNT_MARGIN, SURVEILLANCE_ALERTS, RISK_SNAPSHOT_FAILURES};
+use polaris_core::risk_engine::{RiskEngine, PositionLimit, RiskRule, ComplianceCheck, PnlMethod, MarketPrice, InstrumentSpec, LossLimitBreach, FatFingerLimits,
+    TradeThroughAction, ExchangeQuote, BboSnapshot, MarginRequirement, MarginUsage, MarginEvent, RiskEngineState,
+    PortfolioLimits, PortfolioExposure, StressScenario, StressReport};
+use polaris_core::risk_engine::compile_risk_rules;
+use polaris_core::auth::ApiKeyAuth;
+use polaris_core::value_at_risk::{VarEstimate, VarModel, MIN_SCENARIOS};
+use polaris_core::circuit_breaker::{BreakerKey, BreakerSnapshot, BreakerStatus, CircuitBreaker, CircuitBreakerConfig};
+use polaris_core::surveillance::{Activity, ActivityStats, OrderSurveillance, SurveillanceAlert, SurveillanceLimits};
+use polaris_core::kill_switch::{KillSwitch, KillSwitchAction, KillSwitchCommand, KillSwitchOutcome, KillSwitchScope};
 use polaris_core::Order;
-use chrono::{Utc, DateTime};
+use chrono::{Utc, DateTime, NaiveTime};
 use tracing::info;
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
@@ -12,8 +21,15 @@ use tokio::sync::Mutex;
 use std::sync::Arc;
 use std::collections::{HashMap, HashSet};
 use uuid::Uuid;
-use tokio::time::Instant;
+use std::time::Instant;
 use std::env;
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::Json,
+    routing::{get, post},
+    Router,
+};
 
 // Using Order from polaris_core
 
@@ -22,6 +38,8 @@ struct RiskValidation {
     order_id: String,
     status: String, // "approved", "rejected", "modified"
     violations: Vec<String>,
+    flags: Vec<String>, // compliance findings that did not block the order
+    bbo_snapshot: Option<BboSnapshot>, // consolidated quote behind a trade-through finding
     position_status: PositionStatus,
     timestamp: DateTime<Utc>,
 }
@@ -30,22 +48,190 @@ struct RiskValidation {
 struct PositionStatus {
     symbol: String,
     current_position: f64,
+    avg_entry_price: f64,
     exposure: f64,
     pnl: f64,
     last_updated: DateTime<Utc>,
 }
 
+// Trade published by the matching engine; both sides are identified by
+// user and client_order_id
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Trade {
+    trade_id: String,
+    symbol: String,
+    price: f64,
+    quantity: f64,
+    buyer_id: String,
+    seller_id: String,
+    #[serde(default)]
+    buyer_user_id: String,
+    #[serde(default)]
+    seller_user_id: String,
+}
+
+// Execution report published by the execution engine with cumulative fills
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ExecutionReport {
+    order_id: String,
+    symbol: String,
+    status: String,
+    filled_quantity: f64,
+    avg_price: f64,
+}
+
+// Cancel outcome published by the matching engine
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CancelReport {
+    order_id: String,
+    client_order_id: String,
+    #[serde(default)]
+    user_id: String,
+    status: String, // "cancelled" or "not_found"
+}
+
+// Normalized quote/trade from the market data handler
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct MarketDataMessage {
+    symbol: String,
+    exchange_id: String,
+    price: f64,
+    quantity: f64,
+    bid: f64,
+    ask: f64,
+    #[serde(default)]
+    bid_size: f64,
+    #[serde(default)]
+    ask_size: f64,
+    timestamp: DateTime<Utc>,
+}
+
+// Runtime change to the risk configuration; omitted sections are left as is
+#[derive(Deserialize, Debug)]
+struct RiskConfigUpdate {
+    expected_version: Option<u64>, // reject the update if someone else changed the config first
+    reason: Option<String>,
+    position_limits: Option<HashMap<String, PositionLimit>>,
+    risk_rules: Option<Vec<RiskRule>>,
+    compliance_rules: Option<Vec<ComplianceCheck>>,
+    fat_finger_limits: Option<HashMap<String, FatFingerLimits>>,
+    user_fat_finger_limits: Option<HashMap<String, FatFingerLimits>>,
+    portfolio_limits: Option<PortfolioLimits>,
+    user_portfolio_limits: Option<HashMap<String, PortfolioLimits>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct KillSwitchRequest {
+    action: KillSwitchAction,
+    scope: KillSwitchScope,
+    reason: String,
+    confirms: Option<String>, // re-enable request id, required to confirm
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ConfigAuditEntry {
+    version: u64,
+    changed_by: String,
+    changed_at: DateTime<Utc>,
+    reason: Option<String>,
+    section: String,
+    previous: serde_json::Value,
+    current: serde_json::Value,
+}
+
+// Every version is one message under one key, so a change is recorded whole
+// and versions stay in order. Older releases published an entry per section.
+const AUDIT_KEY: &str = "risk_config";
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ConfigAuditMessage {
+    Version(Vec<ConfigAuditEntry>),
+    Section(ConfigAuditEntry),
+}
+
+impl ConfigAuditMessage {
+    fn into_entries(self) -> Vec<ConfigAuditEntry> {
+        match self {
+            ConfigAuditMessage::Version(entries) => entries,
+            ConfigAuditMessage::Section(entry) => vec![entry],
+        }
+    }
+}
+
+// An approved order whose fills will move the user's position
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct TrackedOrder {
+    user_id: String,
+    client_order_id: String,
+    symbol: String,
+    side: String,
+    quantity: f64,
+    filled_quantity: f64,
+    #[serde(default)]
+    avg_fill_price: f64,
+}
+
+// Runtime state written to disk periodically. On startup it is restored and the
+// order and fill streams are replayed from the recorded offsets.
+#[derive(Serialize, Deserialize, Debug)]
+struct RiskSnapshot {
+    taken_at: DateTime<Utc>,
+    offsets: TopicOffsets, // next offset to read on the order and fill topics
+    // End of the decision topics; decisions for orders after the snapshot come later
+    #[serde(default)]
+    decision_offsets: TopicOffsets,
+    engine: RiskEngineState,
+    circuit_breakers: Vec<BreakerSnapshot>,
+    tracked_orders: HashMap<String, TrackedOrder>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct RiskManagerConfig {
     kafka_brokers: String,
     input_topic: String,
     approved_topic: String,
     rejected_topic: String,
+    trades_topic: String,
+    fills_topic: String,
+    cancelled_topic: String,
+    market_data_topic: String,
+    breach_topic: String,
+    margin_topic: String,
+    liquidation_topic: String, // sent straight to venues by the execution engine
+    pnl_method: PnlMethod,
+    session_reset_time: String, // HH:MM UTC
+    trade_through_action: TradeThroughAction,
+    quote_max_age_ms: i64,
     heartbeat_interval: u64,
-    circuit_breaker_threshold: u64,
+    circuit_breaker: CircuitBreakerConfig,
+    surveillance: SurveillanceLimits,
+    surveillance_topic: String,
     position_limits: HashMap<String, PositionLimit>,
     risk_rules: Vec<RiskRule>,
     compliance_rules: Vec<ComplianceCheck>,
+    instruments: HashMap<String, InstrumentSpec>,
+    fat_finger_limits: HashMap<String, FatFingerLimits>, // per symbol
+    user_fat_finger_limits: HashMap<String, FatFingerLimits>, // per user
+    portfolio_limits: PortfolioLimits, // per user, across all symbols
+    user_portfolio_limits: HashMap<String, PortfolioLimits>,
+    stress_scenarios: Vec<StressScenario>,
+    max_stress_loss: Option<f64>, // default per-account limit on the worst scenario loss
+    stress_loss_limits: HashMap<String, f64>, // user_id -> limit
+    account_balances: HashMap<String, f64>, // user_id -> starting cash
+    leverage_limits: HashMap<String, f64>, // user_id -> max leverage on margined instruments
+    margin_check_interval_ms: u64,
+    snapshot_path: String,
+    snapshot_interval: u64, // seconds
+    var_sample_interval: u64, // seconds between return samples
+    var_lookback: usize, // return samples kept
+    var_refresh_interval: u64, // seconds
+    var_model: VarModel,
+    max_incremental_var: Option<f64>,
+    http_port: u16,
+    audit_topic: String,
+    kill_switch_topic: String,
+    admin_keys: HashMap<String, ApiKeyAuth>,
 }
 
 impl Default for RiskManagerConfig {
@@ -55,8 +241,21 @@ impl Default for RiskManagerConfig {
             input_topic: "orders.incoming".to_string(),
             approved_topic: "orders.validated".to_string(),
             rejected_topic: "orders.rejected".to_string(),
+            trades_topic: "trades.executed".to_string(),
+            fills_topic: "fills".to_string(),
+            cancelled_topic: "orders.cancelled".to_string(),
+            market_data_topic: "market_data.normalized".to_string(),
+            breach_topic: "risk.breaches".to_string(),
+            margin_topic: "risk.margin_events".to_string(),
+            liquidation_topic: "orders.liquidation".to_string(),
+            pnl_method: PnlMethod::AverageCost,
+            session_reset_time: "00:00".to_string(),
+            trade_through_action: TradeThroughAction::Reject,
+            quote_max_age_ms: 5000,
             heartbeat_interval: 30_000,
-            circuit_breaker_threshold: 5000,
+            circuit_breaker: CircuitBreakerConfig::default(),
+            surveillance: SurveillanceLimits::default(),
+            surveillance_topic: "risk.surveillance_alerts".to_string(),
             position_limits: {
                 let mut limits = HashMap::new();
                 limits.insert("BTC/USD".to_string(), PositionLimit {
@@ -99,25 +298,119 @@ impl Default for RiskManagerConfig {
                     validation: "wallet address must pass KYC/AML checks".to_string(),
                 }
             ],
+            instruments: {
+                let mut instruments = HashMap::new();
+                instruments.insert("BTC/USD".to_string(), InstrumentSpec {
+                    tick_size: 0.01,
+                    lot_size: 0.0001,
+                    min_notional: 10.0,
+                    margin: None,
+                    base_currency: Some("BTC".to_string()),
+                    quote_currency: Some("USD".to_string()),
+                    asset_class: None,
+                });
+                instruments.insert("ETH/USD".to_string(), InstrumentSpec {
+                    tick_size: 0.01,
+                    lot_size: 0.001,
+                    min_notional: 10.0,
+                    margin: None,
+                    base_currency: Some("ETH".to_string()),
+                    quote_currency: Some("USD".to_string()),
+                    asset_class: None,
+                });
+                instruments.insert("BTC-PERP".to_string(), InstrumentSpec {
+                    tick_size: 0.1,
+                    lot_size: 0.001,
+                    min_notional: 10.0,
+                    margin: Some(MarginRequirement {
+                        initial: 0.10,
+                        maintenance: 0.05,
+                    }),
+                    base_currency: Some("BTC".to_string()),
+                    quote_currency: Some("USD".to_string()),
+                    asset_class: None,
+                });
+                instruments
+            },
+            fat_finger_limits: {
+                let mut limits = HashMap::new();
+                limits.insert("BTC/USD".to_string(), FatFingerLimits {
+                    max_order_quantity: Some(50.0),
+                    max_order_notional: Some(2_500_000.0),
+                    max_price_deviation: Some(0.05),
+                });
+                limits.insert("ETH/USD".to_string(), FatFingerLimits {
+                    max_order_quantity: Some(500.0),
+                    max_order_notional: Some(1_000_000.0),
+                    max_price_deviation: Some(0.05),
+                });
+                limits
+            },
+            user_fat_finger_limits: HashMap::new(),
+            portfolio_limits: PortfolioLimits {
+                max_gross_notional: Some(5_000_000.0),
+                max_net_notional: Some(2_500_000.0),
+                max_asset_class_exposure: {
+                    let mut limits = HashMap::new();
+                    limits.insert("BTC".to_string(), 2_000_000.0);
+                    limits.insert("ETH".to_string(), 1_000_000.0);
+                    limits
+                },
+                max_currency_exposure: HashMap::new(),
+            },
+            user_portfolio_limits: HashMap::new(),
+            stress_scenarios: vec![
+                StressScenario {
+                    name: "crypto_crash".to_string(),
+                    shocks: HashMap::from([("BTC".to_string(), -0.20), ("ETH".to_string(), -0.30)]),
+                },
+                StressScenario {
+                    name: "crypto_rally".to_string(),
+                    shocks: HashMap::from([("BTC".to_string(), 0.15), ("ETH".to_string(), 0.20)]),
+                },
+            ],
+            max_stress_loss: None,
+            stress_loss_limits: HashMap::new(),
+            account_balances: HashMap::new(),
+            leverage_limits: HashMap::new(),
+            margin_check_interval_ms: 1000,
+            snapshot_path: "/var/lib/risk-manager/snapshot.json".to_string(),
+            snapshot_interval: 30,
+            var_sample_interval: 3600,
+            var_lookback: 250,
+            var_refresh_interval: 60,
+            var_model: VarModel::Historical,
+            max_incremental_var: None,
+            http_port: 8080,
+            audit_topic: "risk.config_changes".to_string(),
+            kill_switch_topic: "control.kill_switch".to_string(),
+            // No built-in credentials; the admin API stays off until keys are configured
+            admin_keys: HashMap::new(),
         }
     }
 }
 
 struct AppState {
     kafka_producer: FutureProducer,
-    order_consumer: StreamConsumer,
     config: RiskManagerConfig,
     risk_engine: RiskEngine,
-    circuit_breaker_state: CircuitBreakerState,
+    circuit_breaker: CircuitBreaker,
+    surveillance: OrderSurveillance,
     last_error_time: Option<Instant>,
     error_count: u64,
+    tracked_orders: HashMap<String, TrackedOrder>, // order_id -> order
+    client_order_index: HashMap<(String, String), String>, // (user_id, client_order_id) -> order_id
+    config_version: u64,
+    config_audit: Vec<ConfigAuditEntry>,
+    kill_switch: KillSwitch,
+    var_estimates: HashMap<String, VarEstimate>, // user_id -> latest estimate
+    consumed_offsets: TopicOffsets, // order and fill topics, as of the current state
+    snapshot_failures: u32, // consecutive failed snapshot writes
+    last_snapshot_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Clone, Debug)]
-struct CircuitBreakerState {
-    error_count: u64,
-    cooldown_until: Option<Instant>,
-}
+// Health reports the service degraded once this many snapshots in a row fail
+const MAX_SNAPSHOT_FAILURES: u32 = 3;
 
 #[tokio::main]
 async fn main() {
@@ -128,12 +421,71 @@ async fn main() {
         input_topic: env::var("INPUT_TOPIC").unwrap_or_else(|_| "orders.incoming".to_string()),
         approved_topic: env::var("APPROVED_TOPIC").unwrap_or_else(|_| "orders.validated".to_string()),
         rejected_topic: env::var("REJECTED_TOPIC").unwrap_or_else(|_| "orders.rejected".to_string()),
+        trades_topic: env::var("TRADES_TOPIC").unwrap_or_else(|_| "trades.executed".to_string()),
+        fills_topic: env::var("FILLS_TOPIC").unwrap_or_else(|_| "fills".to_string()),
+        cancelled_topic: env::var("CANCELLED_TOPIC").unwrap_or_else(|_| "orders.cancelled".to_string()),
+        market_data_topic: env::var("MARKET_DATA_TOPIC").unwrap_or_else(|_| "market_data.normalized".to_string()),
+        breach_topic: env::var("BREACH_TOPIC").unwrap_or_else(|_| "risk.breaches".to_string()),
+        margin_topic: env::var("MARGIN_TOPIC").unwrap_or_else(|_| "risk.margin_events".to_string()),
+        liquidation_topic: env::var("LIQUIDATION_TOPIC").unwrap_or_else(|_| "orders.liquidation".to_string()),
+        pnl_method: env::var("PNL_METHOD")
+            .map(|v| v.parse().unwrap_or(PnlMethod::AverageCost))
+            .unwrap_or(PnlMethod::AverageCost),
+        session_reset_time: env::var("SESSION_RESET_TIME").unwrap_or_else(|_| "00:00".to_string()),
+        trade_through_action: env::var("TRADE_THROUGH_ACTION")
+            .map(|v| v.parse().unwrap_or(TradeThroughAction::Reject))
+            .unwrap_or(TradeThroughAction::Reject),
+        quote_max_age_ms: env::var("QUOTE_MAX_AGE_MS")
+            .map(|v| v.parse().unwrap_or(5000))
+            .unwrap_or(5000),
         heartbeat_interval: env::var("HEARTBEAT_INTERVAL")
             .map(|v| v.parse().unwrap_or(30_000))
             .unwrap_or(30_000),
-        circuit_breaker_threshold: env::var("CIRCUIT_BREAKER_THRESHOLD")
-            .map(|v| v.parse().unwrap_or(5000))
-            .unwrap_or(5000),
+        circuit_breaker: CircuitBreakerConfig {
+            window_secs: env::var("CIRCUIT_BREAKER_WINDOW")
+                .map(|v| v.parse().unwrap_or(60))
+                .unwrap_or(60),
+            min_orders: env::var("CIRCUIT_BREAKER_MIN_ORDERS")
+                .map(|v| v.parse().unwrap_or(20))
+                .unwrap_or(20),
+            max_rejection_rate: env::var("CIRCUIT_BREAKER_MAX_REJECTION_RATE")
+                .map(|v| v.parse().unwrap_or(0.5))
+                .unwrap_or(0.5),
+            max_violations: env::var("CIRCUIT_BREAKER_MAX_VIOLATIONS")
+                .map(|v| v.parse().unwrap_or(100))
+                .unwrap_or(100),
+            cooldown_secs: env::var("CIRCUIT_BREAKER_COOLDOWN")
+                .map(|v| v.parse().unwrap_or(30))
+                .unwrap_or(30),
+            max_cooldown_secs: env::var("CIRCUIT_BREAKER_MAX_COOLDOWN")
+                .map(|v| v.parse().unwrap_or(600))
+                .unwrap_or(600),
+            probe_orders: env::var("CIRCUIT_BREAKER_PROBE_ORDERS")
+                .map(|v| v.parse().unwrap_or(3))
+                .unwrap_or(3),
+        },
+        // Ratio and rate limits are switched off with any non-numeric value, e.g. "off"
+        surveillance: SurveillanceLimits {
+            window_secs: env::var("SURVEILLANCE_WINDOW")
+                .map(|v| v.parse().unwrap_or(60))
+                .unwrap_or(60),
+            max_orders: env::var("MAX_ORDERS_PER_WINDOW")
+                .map(|v| v.parse().ok())
+                .unwrap_or(Some(600)),
+            max_order_to_trade: env::var("MAX_ORDER_TO_TRADE")
+                .map(|v| v.parse().ok())
+                .unwrap_or(Some(50.0)),
+            max_cancel_to_fill: env::var("MAX_CANCEL_TO_FILL")
+                .map(|v| v.parse().ok())
+                .unwrap_or(Some(20.0)),
+            min_orders: env::var("SURVEILLANCE_MIN_ORDERS")
+                .map(|v| v.parse().unwrap_or(50))
+                .unwrap_or(50),
+            throttle_secs: env::var("THROTTLE_SECS")
+                .map(|v| v.parse().unwrap_or(60))
+                .unwrap_or(60),
+        },
+        surveillance_topic: env::var("SURVEILLANCE_TOPIC").unwrap_or_else(|_| "risk.surveillance_alerts".to_string()),
         position_limits: env::var("POSITION_LIMITS")
             .map(|v| serde_json::from_str(&v).unwrap_or_default())
             .unwrap_or_default(),
@@ -141,32 +493,190 @@ async fn main() {
             .map(|v| serde_json::from_str(&v).unwrap_or_default())
             .unwrap_or_default(),
         compliance_rules: env::var("COMPLIANCE_RULES")
+            .map(|v| serde_json::from_str(&v).unwrap_or_default())
+            .unwrap_or_else(|_| RiskManagerConfig::default().compliance_rules),
+        instruments: env::var("INSTRUMENTS")
+            .map(|v| serde_json::from_str(&v).unwrap_or_default())
+            .unwrap_or_else(|_| RiskManagerConfig::default().instruments),
+        fat_finger_limits: env::var("FAT_FINGER_LIMITS")
+            .map(|v| serde_json::from_str(&v).unwrap_or_default())
+            .unwrap_or_else(|_| RiskManagerConfig::default().fat_finger_limits),
+        user_fat_finger_limits: env::var("USER_FAT_FINGER_LIMITS")
+            .map(|v| serde_json::from_str(&v).unwrap_or_default())
+            .unwrap_or_default(),
+        portfolio_limits: env::var("PORTFOLIO_LIMITS")
+            .map(|v| serde_json::from_str(&v).unwrap_or_default())
+            .unwrap_or_else(|_| RiskManagerConfig::default().portfolio_limits),
+        user_portfolio_limits: env::var("USER_PORTFOLIO_LIMITS")
+            .map(|v| serde_json::from_str(&v).unwrap_or_default())
+            .unwrap_or_default(),
+        stress_scenarios: env::var("STRESS_SCENARIOS")
+            .map(|v| serde_json::from_str(&v).unwrap_or_default())
+            .unwrap_or_else(|_| RiskManagerConfig::default().stress_scenarios),
+        max_stress_loss: env::var("MAX_STRESS_LOSS").ok().and_then(|v| v.parse().ok()),
+        stress_loss_limits: env::var("STRESS_LOSS_LIMITS")
             .map(|v| serde_json::from_str(&v).unwrap_or_default())
             .unwrap_or_default(),
+        account_balances: env::var("ACCOUNT_BALANCES")
+            .map(|v| serde_json::from_str(&v).unwrap_or_default())
+            .unwrap_or_default(),
+        leverage_limits: env::var("LEVERAGE_LIMITS")
+            .map(|v| serde_json::from_str(&v).unwrap_or_default())
+            .unwrap_or_default(),
+        margin_check_interval_ms: env::var("MARGIN_CHECK_INTERVAL_MS")
+            .map(|v| v.parse().unwrap_or(1000))
+            .unwrap_or(1000),
+        snapshot_path: env::var("SNAPSHOT_PATH").unwrap_or_else(|_| "/var/lib/risk-manager/snapshot.json".to_string()),
+        snapshot_interval: env::var("SNAPSHOT_INTERVAL")
+            .map(|v| v.parse().unwrap_or(30))
+            .unwrap_or(30),
+        var_sample_interval: env::var("VAR_SAMPLE_INTERVAL")
+            .map(|v| v.parse().unwrap_or(3600))
+            .unwrap_or(3600),
+        var_lookback: env::var("VAR_LOOKBACK")
+            .map(|v| v.parse().unwrap_or(250))
+            .unwrap_or(250),
+        var_refresh_interval: env::var("VAR_REFRESH_INTERVAL")
+            .map(|v| v.parse().unwrap_or(60))
+            .unwrap_or(60),
+        var_model: env::var("VAR_MODEL")
+            .map(|v| v.parse().unwrap_or(VarModel::Historical))
+            .unwrap_or(VarModel::Historical),
+        max_incremental_var: env::var("MAX_INCREMENTAL_VAR").ok().and_then(|v| v.parse().ok()),
+        http_port: env::var("HTTP_PORT")
+            .map(|v| v.parse().unwrap_or(8080))
+            .unwrap_or(8080),
+        audit_topic: env::var("AUDIT_TOPIC").unwrap_or_else(|_| "risk.config_changes".to_string()),
+        kill_switch_topic: env::var("KILL_SWITCH_TOPIC").unwrap_or_else(|_| "control.kill_switch".to_string()),
+        admin_keys: env::var("ADMIN_API_KEYS")
+            .map(|v| serde_json::from_str(&v).expect("Invalid ADMIN_API_KEYS"))
+            .unwrap_or_default(),
         ..RiskManagerConfig::default()
     };
     
     let producer = create_kafka_producer(&config.kafka_brokers);
     
-    // Create order consumer
+    // Create order consumer. After a restart it is positioned by the snapshot,
+    // right after the last order that was decided.
     let order_consumer = create_kafka_consumer(&config.kafka_brokers, "risk-manager-group");
-    order_consumer.subscribe(&[&config.input_topic]).expect("Failed to subscribe to incoming orders topic");
+    
+    // Create consumer for fills and cancels from the matching engine and execution engine.
+    // After a restart it is positioned by the snapshot instead.
+    let fill_consumer = create_kafka_consumer(&config.kafka_brokers, "risk-manager-fills-group");
+    let snapshot = load_snapshot(&config.snapshot_path);
+    
+    // Create consumer for live prices used to mark positions to market
+    let market_data_consumer = create_kafka_consumer(&config.kafka_brokers, "risk-manager-market-data-group");
+    market_data_consumer.subscribe(&[&config.market_data_topic]).expect("Failed to subscribe to market data topic");
+    
+    // Kill switch commands are replayed from the start so a restart keeps them.
+    // Every instance reads all partitions itself rather than sharing a group.
+    let kill_switch_consumer = create_replay_consumer(&config.kafka_brokers, "risk-manager-kill-switch-group");
     
     info!("Risk manager started");
     
+    let input_topic = config.input_topic.clone();
+    let fill_topics = [config.trades_topic.clone(), config.fills_topic.clone(), config.cancelled_topic.clone()];
+    let http_port = config.http_port;
+    let mut risk_engine = RiskEngine::new();
+    risk_engine.set_pnl_method(config.pnl_method);
+    risk_engine.update_position_limits(config.position_limits.clone());
+    risk_engine.add_compliance_rules(config.compliance_rules.clone());
+    risk_engine.update_instruments(config.instruments.clone());
+    risk_engine.update_fat_finger_limits(config.fat_finger_limits.clone(), config.user_fat_finger_limits.clone());
+    risk_engine.set_trade_through_action(config.trade_through_action);
+    risk_engine.update_portfolio_limits(config.portfolio_limits.clone(), config.user_portfolio_limits.clone());
+    risk_engine.set_stress_loss_limits(config.max_stress_loss, config.stress_loss_limits.clone());
+    risk_engine.update_account_balances(config.account_balances.clone());
+    risk_engine.update_leverage_limits(config.leverage_limits.clone());
+    risk_engine.configure_var(config.var_lookback, Duration::from_secs(config.var_sample_interval));
+    risk_engine.set_var_limit(config.var_model, config.max_incremental_var);
+    risk_engine.set_quote_max_age(chrono::Duration::milliseconds(config.quote_max_age_ms));
+    
+    // Reject bad rule expressions at startup rather than on the first order
+    if let Err(e) = risk_engine.add_risk_rules(config.risk_rules.clone()) {
+        panic!("Invalid risk rule configuration: {}", e);
+    }
+    if let Err(e) = risk_engine.update_stress_scenarios(config.stress_scenarios.clone()) {
+        panic!("Invalid stress scenario configuration: {}", e);
+    }
+    let circuit_breaker = CircuitBreaker::new(config.circuit_breaker.clone());
+    let surveillance = OrderSurveillance::new(config.surveillance.clone());
     let app_state = Arc::new(Mutex::new(AppState {
         kafka_producer: producer,
-        order_consumer: order_consumer,
         config: config,
-        risk_engine: RiskEngine::new(),
-        circuit_breaker_state: CircuitBreakerState {
-            error_count: 0,
-            cooldown_until: None,
-        },
+        risk_engine,
+        circuit_breaker,
+        surveillance,
         last_error_time: None,
         error_count: 0,
+        tracked_orders: HashMap::new(),
+        client_order_index: HashMap::new(),
+        config_version: 1,
+        config_audit: Vec::new(),
+        kill_switch: KillSwitch::new(),
+        var_estimates: HashMap::new(),
+        consumed_offsets: TopicOffsets::new(),
+        snapshot_failures: 0,
+        last_snapshot_at: None,
     }));
     
+    // Hot-reloaded config lives in the audit topic; replay it so a restart keeps
+    // the latest value of each section along with the version and history
+    while let Err(e) = restore_risk_config(&app_state).await {
+        tracing::error!("Failed to replay risk config audit trail, retrying: {}", e);
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+    
+    // Positions must be caught up before any order is checked against them.
+    // Kafka may still be starting, so reads are retried rather than serving
+    // orders against positions that are missing fills.
+    if let Some(snapshot) = snapshot {
+        let replay = loop {
+            match read_since_snapshot(&app_state, &snapshot, &fill_consumer).await {
+                Ok(replay) => break replay,
+                Err(e) => {
+                    tracing::error!("Failed to replay streams since risk snapshot, retrying: {}", e);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+            }
+        };
+        restore_risk_state(&app_state, snapshot, replay).await;
+        
+        let offsets = app_state.lock().await.consumed_offsets.clone();
+        while let Err(e) = assign_from_offsets(&order_consumer, &[&input_topic], &offsets) {
+            tracing::error!("Failed to position order consumer at the snapshot, retrying: {}", e);
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    } else {
+        // A fresh start begins at the end of the streams. Every partition is
+        // recorded, so a snapshot taken before it sees any traffic still says
+        // where to resume instead of leaving restore to start at the beginning.
+        let topics: Vec<&str> = fill_topics.iter().map(|topic| topic.as_str()).collect();
+        while let Err(e) = start_at_end(&app_state, &order_consumer, &[&input_topic]).await {
+            tracing::error!("Failed to position order consumer, retrying: {}", e);
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+        while let Err(e) = start_at_end(&app_state, &fill_consumer, &topics).await {
+            tracing::error!("Failed to position fill consumer, retrying: {}", e);
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    }
+    
+    // VaR scenarios come from recorded market data so the limit is usable
+    // straight after a restart instead of after the lookback has elapsed
+    let var_history_consumer = create_replay_consumer(&app_state.lock().await.config.kafka_brokers, "risk-manager-var-history-group");
+    while let Err(e) = seed_var_history(&app_state, &var_history_consumer).await {
+        tracing::error!("Failed to replay market data for VaR history, retrying: {}", e);
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+    
+    // No order is checked until every kill switch command so far is applied
+    while let Err(e) = replay_kill_switch(&app_state, &kill_switch_consumer).await {
+        tracing::error!("Failed to replay kill switch commands, retrying: {}", e);
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+    
     // Start background tasks
     let state_for_metrics = Arc::clone(&app_state);
     tokio::spawn(async move {
@@ -183,33 +693,77 @@ async fn main() {
         monitor_circuit_breaker(state_for_circuit_breaker).await;
     });
     
+    let state_for_fills = Arc::clone(&app_state);
+    tokio::spawn(async move {
+        process_fills(state_for_fills, fill_consumer).await;
+    });
+    
+    let state_for_market_data = Arc::clone(&app_state);
+    tokio::spawn(async move {
+        process_market_data(state_for_market_data, market_data_consumer).await;
+    });
+    
+    let state_for_sessions = Arc::clone(&app_state);
+    tokio::spawn(async move {
+        monitor_session_reset(state_for_sessions).await;
+    });
+    
+    let state_for_kill_switch = Arc::clone(&app_state);
+    tokio::spawn(async move {
+        process_kill_switch(state_for_kill_switch, kill_switch_consumer).await;
+    });
+    
+    let state_for_margin = Arc::clone(&app_state);
+    tokio::spawn(async move {
+        monitor_margin(state_for_margin).await;
+    });
+    
+    let state_for_var_samples = Arc::clone(&app_state);
+    tokio::spawn(async move {
+        sample_market_returns(state_for_var_samples).await;
+    });
+    
+    let state_for_var = Arc::clone(&app_state);
+    tokio::spawn(async move {
+        refresh_var(state_for_var).await;
+    });
+    
+    let state_for_snapshots = Arc::clone(&app_state);
+    tokio::spawn(async move {
+        snapshot_risk_state(state_for_snapshots).await;
+    });
+    
+    let state_for_admin = Arc::clone(&app_state);
+    tokio::spawn(async move {
+        start_admin_server(state_for_admin, http_port).await;
+    });
+    
     // Main loop for processing orders
     loop {
         let start = Instant::now();
         
-        // Process order messages
-        {
-            let mut state = app_state.lock().await;
+        // Wait for the next order without holding the state lock so fills
+        // keep updating positions while the topic is idle
+        if let Some(message) = consume_message_with_metadata(&order_consumer).await {
+            KAFKA_MESSAGES_CONSUMED.with_label_values(&[&input_topic]).inc();
             
-            if let Some(message) = consume_messages(&state.order_consumer, &state.config.input_topic, Duration::from_millis(100)).await {
-                KAFKA_MESSAGES_CONSUMED.with_label_values(&[&state.config.input_topic]).inc();
+            let mut state = app_state.lock().await;
+            record_offset(&mut state, &message);
+            if let Ok(order) = serde_json::from_str::<Order>(&message.payload) {
+                let result = handle_risk_check(&mut state, order).await;
                 
-                if let Ok(order) = serde_json::from_str::<Order>(&message) {
-                    let result = handle_risk_check(&mut state, order).await;
-                    
-                    // Send validation result to appropriate topic
-                    let output_topic = match result.status.as_str() {
-                        "approved" => &state.config.approved_topic,
-                        _ => &state.config.rejected_topic,
-                    };
-                    
-                    let result_json = serde_json::to_string(&result).unwrap();
-                    produce_message(&state.kafka_producer, output_topic, &result.order_id, &result_json)
-                        .await
-                        .expect("Failed to produce risk validation result");
-                    
-                    KAFKA_MESSAGES_PRODUCED.with_label_values(&[output_topic]).inc();
-                }
+                // Send validation result to appropriate topic
+                let output_topic = match result.status.as_str() {
+                    "approved" => &state.config.approved_topic,
+                    _ => &state.config.rejected_topic,
+                };
+                
+                let result_json = serde_json::to_string(&result).unwrap();
+                produce_message(&state.kafka_producer, output_topic, &result.order_id, &result_json)
+                    .await
+                    .expect("Failed to produce risk validation result");
+                
+                KAFKA_MESSAGES_PRODUCED.with_label_values(&[output_topic]).inc();
             }
         }
         
@@ -234,6 +788,14 @@ async fn report_metrics(state: Arc<Mutex<AppState>>) {
                 symbol, exposure, state.risk_engine.get_position_pnl(symbol));
         }
         
+        // Report marked-to-market PnL per user and symbol
+        for (user_id, symbol) in state.risk_engine.position_keys() {
+            let realized = state.risk_engine.get_user_realized_pnl(&user_id, &symbol);
+            let unrealized = state.risk_engine.get_user_unrealized_pnl(&user_id, &symbol);
+            POSITION_PNL.with_label_values(&[&user_id, &symbol, "realized"]).set(realized);
+            POSITION_PNL.with_label_values(&[&user_id, &symbol, "unrealized"]).set(unrealized);
+        }
+        
         // Report error rate
         if state.error_count > 0 {
             info!("Total risk violations: {}", state.error_count);
@@ -243,7 +805,9 @@ async fn report_metrics(state: Arc<Mutex<AppState>>) {
 
 async fn send_heartbeat(state: Arc<Mutex<AppState>>) {
     loop {
-        tokio::time::sleep(Duration::from_millis(state.lock().await.config.heartbeat_interval)).await;
+        // Read the interval first so the lock isn't held while sleeping
+        let heartbeat_interval = state.lock().await.config.heartbeat_interval;
+        tokio::time::sleep(Duration::from_millis(heartbeat_interval)).await;
         
         let state = state.lock().await;
         
@@ -260,19 +824,16 @@ async fn send_heartbeat(state: Arc<Mutex<AppState>>) {
     }
 }
 
+// Moves expired breakers to half-open and exports their state
 async fn monitor_circuit_breaker(state: Arc<Mutex<AppState>>) {
     loop {
-        tokio::time::sleep(Duration::from_secs(60)).await;
-        
-        let mut state = state.lock().await;
+        tokio::time::sleep(Duration::from_secs(5)).await;
         
-        // Check circuit breaker state
-        if let Some(cooldown) = state.circuit_breaker_state.cooldown_until {
-            if Instant::now() >= cooldown {
-                info!("Circuit breaker cooldown expired");
-                state.circuit_breaker_state.cooldown_until = None;
-                state.circuit_breaker_state.error_count = 0;
-            }
+        let statuses = state.lock().await.circuit_breaker.statuses(Instant::now());
+        for status in statuses {
+            CIRCUIT_BREAKER_STATE
+                .with_label_values(&[status.key.scope(), status.key.value()])
+                .set(status.state.as_gauge());
         }
     }
 }
@@ -280,60 +841,126 @@ async fn monitor_circuit_breaker(state: Arc<Mutex<AppState>>) {
 async fn handle_risk_check(state: &mut AppState, order: Order) -> RiskValidation {
     let start = Instant::now();
     
+    // Check kill switch
+    if let Some(scope) = state.kill_switch.blocking_scope(&order.user_id, &order.symbol).cloned() {
+        return create_risk_validation(
+            &state.risk_engine,
+            &order,
+            "rejected",
+            vec![format!("kill_switch_active: {}", scope)],
+            Utc::now()
+        );
+    }
+    
     // Check circuit breaker
-    if let Some(cooldown) = state.circuit_breaker_state.cooldown_until {
-        if Instant::now() < cooldown {
-            return create_risk_validation(
-                &order,
-                "rejected",
-                vec!["circuit_breaker_active".to_string()],
-                Utc::now()
-            );
-        }
+    if let Some(key) = state.circuit_breaker.blocking(&order.user_id, &order.symbol, Instant::now()) {
+        return create_risk_validation(
+            &state.risk_engine,
+            &order,
+            "rejected",
+            vec![format!("circuit_breaker_active: {}", key)],
+            Utc::now()
+        );
     }
     
-    // Initialize risk engine with current config
-    state.risk_engine.update_position_limits(state.config.position_limits.clone());
-    state.risk_engine.add_risk_rules(state.config.risk_rules.clone());
-    state.risk_engine.add_compliance_rules(state.config.compliance_rules.clone());
+    // Check surveillance throttle
+    if let Some(remaining) = state.surveillance.throttled(&order.user_id, Instant::now()) {
+        return create_risk_validation(
+            &state.risk_engine,
+            &order,
+            "rejected",
+            vec![format!("throttled: Order surveillance limit exceeded, {}s remaining", remaining.as_secs())],
+            Utc::now()
+        );
+    }
     
     // Validate order against all risk checks
     let mut violations = Vec::new();
     
+    // Fat-finger and price collar checks
+    if let Some(fat_finger_violations) = state.risk_engine.check_fat_finger(&order) {
+        violations.extend(fat_finger_violations);
+    }
+    
     // Position limit checks
     if let Some(limit_violations) = state.risk_engine.check_position_limits(&order) {
         violations.extend(limit_violations);
     }
     
+    // Portfolio concentration checks
+    if let Some(portfolio_violations) = state.risk_engine.check_portfolio_limits(&order) {
+        violations.extend(portfolio_violations);
+    }
+    
+    // Daily loss and drawdown checks
+    if let Some(loss_violations) = state.risk_engine.check_loss_limits(&order) {
+        violations.extend(loss_violations);
+    }
+    
     // Risk rule checks
     if let Some(rule_violations) = state.risk_engine.check_risk_rules(&order) {
         violations.extend(rule_violations);
     }
     
+    // Account buying power checks
+    if let Some(balance_violations) = state.risk_engine.check_buying_power(&order) {
+        violations.extend(balance_violations);
+    }
+    
+    // Margin and leverage checks
+    if let Some(margin_violations) = state.risk_engine.check_margin(&order) {
+        violations.extend(margin_violations);
+    }
+    
+    // Stress scenario checks
+    if let Some(stress_violations) = state.risk_engine.check_stress_limits(&order) {
+        violations.extend(stress_violations);
+    }
+    
+    // Incremental VaR checks
+    if let Some(var_violations) = state.risk_engine.check_var_limit(&order) {
+        violations.extend(var_violations);
+    }
+    
     // Compliance checks
-    if let Some(compliance_violations) = state.risk_engine.check_compliance(&order) {
-        violations.extend(compliance_violations);
+    let compliance = state.risk_engine.check_compliance(&order);
+    violations.extend(compliance.violations);
+    for flag in &compliance.flags {
+        tracing::warn!("Compliance flag on order {}: {}", order.order_id, flag);
     }
     
-    // Circuit breaker check
-    if violations.len() >= state.config.circuit_breaker_threshold as usize {
-        state.circuit_breaker_state = CircuitBreakerState {
-            error_count: violations.len() as u64,
-            cooldown_until: Some(Instant::now() + Duration::from_secs(300)),
-        };
+    // Feed the outcome to the circuit breakers
+    let trips = state.circuit_breaker.record(&order.user_id, &order.symbol, violations.len(), Instant::now());
+    for trip in trips {
+        tracing::warn!("Circuit breaker {} tripped for {:?}: {}", trip.key, trip.cooldown, trip.reason);
         CIRCUIT_BREAKER_TRIPPED.inc();
-        
-        return create_risk_validation(
-            &order,
-            "rejected",
-            vec!["circuit_breaker_tripped".to_string()],
-            Utc::now()
-        );
+        CIRCUIT_BREAKER_STATE
+            .with_label_values(&[trip.key.scope(), trip.key.value()])
+            .set(2.0);
     }
     
     // If no violations, approve order
-    if violations.is_empty() {
+    let mut validation = if violations.is_empty() {
+        // Track the order so its fills can be attributed to the user
+        state.client_order_index.insert((order.user_id.clone(), order.client_order_id.clone()), order.order_id.clone());
+        state.tracked_orders.insert(order.order_id.clone(), TrackedOrder {
+            user_id: order.user_id.clone(),
+            client_order_id: order.client_order_id.clone(),
+            symbol: order.symbol.clone(),
+            side: order.side.clone(),
+            quantity: order.quantity,
+            filled_quantity: 0.0,
+            avg_fill_price: 0.0,
+        });
+        state.risk_engine.reserve_funds(&order);
+        
+        // Only orders that go on to the venue count towards its ratios
+        if let Some(alert) = state.surveillance.record(&order.user_id, Activity::Order, Instant::now()) {
+            publish_surveillance_alert(state, alert).await;
+        }
+        
         create_risk_validation(
+            &state.risk_engine,
             &order,
             "approved",
             vec![],
@@ -344,26 +971,1106 @@ async fn handle_risk_check(state: &mut AppState, order: Order) -> RiskValidation
         RISK_VIOLATIONS.inc_by((violations.len() as u64) as f64);
         
         create_risk_validation(
+            &state.risk_engine,
             &order,
             "rejected",
             violations,
             Utc::now()
         )
-    }
+    };
+    
+    validation.flags = compliance.flags;
+    validation.bbo_snapshot = compliance.bbo;
+    validation
 }
 
-fn create_risk_validation(order: &Order, status: &str, violations: Vec<String>, timestamp: DateTime<Utc>) -> RiskValidation {
+fn create_risk_validation(risk_engine: &RiskEngine, order: &Order, status: &str, violations: Vec<String>, timestamp: DateTime<Utc>) -> RiskValidation {
+    let position = risk_engine.get_position(&order.user_id, &order.symbol);
+    
     RiskValidation {
         order_id: order.order_id.clone(),
         status: status.to_string(),
         violations: violations,
+        flags: Vec::new(),
+        bbo_snapshot: None,
         position_status: PositionStatus {
             symbol: order.symbol.clone(),
-            current_position: 0.0, // Would be populated from risk engine
-            exposure: 0.0, // Would be populated from risk engine
-            pnl: 0.0, // Would be populated from risk engine
+            current_position: position.quantity,
+            avg_entry_price: position.avg_entry_price,
+            exposure: risk_engine.get_user_exposure(&order.user_id, &order.symbol),
+            pnl: risk_engine.get_user_pnl(&order.user_id, &order.symbol),
             last_updated: timestamp,
         },
         timestamp: timestamp,
     }
 }
+
+async fn process_fills(state: Arc<Mutex<AppState>>, consumer: StreamConsumer) {
+    loop {
+        if let Some(message) = consume_message_with_metadata(&consumer).await {
+            KAFKA_MESSAGES_CONSUMED.with_label_values(&[&message.topic]).inc();
+            handle_fill_message(&mut *state.lock().await, message, false).await;
+        }
+    }
+}
+
+// Replayed messages were already acted on before the restart: they only bring
+// positions and reservations up to date, with nothing published and no
+// surveillance activity recorded
+async fn handle_fill_message(state: &mut AppState, message: ConsumedMessage, replaying: bool) {
+    record_offset(state, &message);
+    
+    if message.topic == state.config.trades_topic {
+        match serde_json::from_str::<Trade>(&message.payload) {
+            Ok(trade) => {
+                for key in [(trade.buyer_user_id.clone(), trade.buyer_id.clone()), (trade.seller_user_id.clone(), trade.seller_id.clone())] {
+                    let order_id = match state.client_order_index.get(&key) {
+                        Some(order_id) => order_id.clone(),
+                        None => continue,
+                    };
+                    let breaches = apply_order_fill(state, &order_id, trade.quantity, trade.price, replaying);
+                    if !replaying {
+                        publish_breaches(state, breaches).await;
+                    }
+                }
+            }
+            Err(e) => tracing::warn!("Failed to parse trade: {}", e),
+        }
+    } else if message.topic == state.config.cancelled_topic {
+        match serde_json::from_str::<CancelReport>(&message.payload) {
+            Ok(report) if report.status == "cancelled" => {
+                let order_id = if state.tracked_orders.contains_key(&report.order_id) {
+                    Some(report.order_id.clone())
+                } else {
+                    state.client_order_index.get(&(report.user_id.clone(), report.client_order_id.clone())).cloned()
+                };
+                if let Some(order_id) = order_id {
+                    if !replaying {
+                        record_cancel(state, &order_id).await;
+                    }
+                    close_tracked_order(state, &order_id);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!("Failed to parse cancel report: {}", e),
+        }
+    } else {
+        match serde_json::from_str::<ExecutionReport>(&message.payload) {
+            Ok(report) => {
+                // Execution reports carry cumulative quantities and average price,
+                // so the new fill is priced at what the cumulative average implies
+                let (already_filled, already_avg) = match state.tracked_orders.get(&report.order_id) {
+                    Some(tracked) => (tracked.filled_quantity, tracked.avg_fill_price),
+                    None => return,
+                };
+                let delta = report.filled_quantity - already_filled;
+                if delta > 0.0 {
+                    let price = (report.avg_price * report.filled_quantity - already_avg * already_filled) / delta;
+                    let price = if price.is_finite() && price > 0.0 { price } else { report.avg_price };
+                    let breaches = apply_order_fill(state, &report.order_id, delta, price, replaying);
+                    if !replaying {
+                        publish_breaches(state, breaches).await;
+                    }
+                }
+                
+                if report.status == "cancelled" && !replaying {
+                    record_cancel(state, &report.order_id).await;
+                }
+                
+                // The exchange is done with the order; free what it still holds
+                if matches!(report.status.as_str(), "rejected" | "cancelled" | "expired") {
+                    close_tracked_order(state, &report.order_id);
+                }
+            }
+            Err(e) => tracing::warn!("Failed to parse execution report: {}", e),
+        }
+    }
+}
+
+async fn record_cancel(state: &mut AppState, order_id: &str) {
+    let user_id = match state.tracked_orders.get(order_id) {
+        Some(tracked) => tracked.user_id.clone(),
+        None => return,
+    };
+    if let Some(alert) = state.surveillance.record(&user_id, Activity::Cancel, Instant::now()) {
+        publish_surveillance_alert(state, alert).await;
+    }
+}
+
+async fn publish_surveillance_alert(state: &AppState, alert: SurveillanceAlert) {
+    tracing::warn!("Throttling {} for {}s - {} {:.2} > {} ({} orders, {} cancels, {} trades in {}s)",
+        alert.user_id, alert.throttle_secs, alert.metric, alert.value, alert.limit,
+        alert.orders, alert.cancels, alert.trades, alert.window_secs);
+    SURVEILLANCE_ALERTS.with_label_values(&[&alert.metric]).inc();
+    
+    let alert_json = serde_json::to_string(&alert).unwrap();
+    match produce_message(&state.kafka_producer, &state.config.surveillance_topic, &alert.user_id, &alert_json).await {
+        Ok(_) => KAFKA_MESSAGES_PRODUCED.with_label_values(&[&state.config.surveillance_topic]).inc(),
+        Err(e) => tracing::error!("Failed to publish surveillance alert: {}", e),
+    }
+}
+
+fn record_offset(state: &mut AppState, message: &ConsumedMessage) {
+    state.consumed_offsets
+        .entry(message.topic.clone())
+        .or_default()
+        .insert(message.partition, message.offset + 1);
+}
+
+fn apply_order_fill(state: &mut AppState, order_id: &str, quantity: f64, price: f64, replaying: bool) -> Vec<LossLimitBreach> {
+    let tracked = match state.tracked_orders.get_mut(order_id) {
+        Some(tracked) => tracked,
+        None => return Vec::new(),
+    };
+    tracked.avg_fill_price = (tracked.avg_fill_price * tracked.filled_quantity + price * quantity) / (tracked.filled_quantity + quantity);
+    tracked.filled_quantity += quantity;
+    let tracked = tracked.clone();
+    
+    state.risk_engine.settle_fill(order_id, quantity, price);
+    if !replaying {
+        state.surveillance.record(&tracked.user_id, Activity::Trade, Instant::now());
+    }
+    
+    // Fully filled orders will not see further fills
+    if tracked.filled_quantity >= tracked.quantity {
+        close_tracked_order(state, order_id);
+    }
+    
+    state.risk_engine.apply_fill(&tracked.user_id, &tracked.symbol, &tracked.side, quantity, price);
+    
+    let position = state.risk_engine.get_position(&tracked.user_id, &tracked.symbol);
+    info!("Position - {} {} | Quantity: {}, Avg entry: {:.2}, Realized PnL: {:.2}",
+        tracked.user_id, tracked.symbol, position.quantity, position.avg_entry_price, position.realized_pnl);
+    
+    state.risk_engine.refresh_loss_limits(&tracked.user_id, &tracked.symbol)
+}
+
+async fn process_market_data(state: Arc<Mutex<AppState>>, consumer: StreamConsumer) {
+    loop {
+        if let Some(message) = consume_message_with_metadata(&consumer).await {
+            KAFKA_MESSAGES_CONSUMED.with_label_values(&[&message.topic]).inc();
+            
+            match serde_json::from_str::<MarketDataMessage>(&message.payload) {
+                Ok(market_data) => {
+                    // Handlers publish zero for missing book fields
+                    let present = |value: f64| Some(value).filter(|value| *value > 0.0);
+                    let price = MarketPrice {
+                        last: market_data.price,
+                        bid: present(market_data.bid),
+                        ask: present(market_data.ask),
+                        bid_size: present(market_data.bid_size),
+                        ask_size: present(market_data.ask_size),
+                    };
+                    let quote = ExchangeQuote {
+                        bid: price.bid,
+                        ask: price.ask,
+                        bid_size: price.bid_size,
+                        ask_size: price.ask_size,
+                        timestamp: market_data.timestamp,
+                    };
+                    let mut state = state.lock().await;
+                    state.risk_engine.update_market_price(&market_data.symbol, price);
+                    state.risk_engine.update_quote(&market_data.symbol, &market_data.exchange_id, quote);
+                    let breaches = state.risk_engine.refresh_symbol_loss_limits(&market_data.symbol);
+                    publish_breaches(&state, breaches).await;
+                }
+                Err(e) => tracing::warn!("Failed to parse market data: {}", e),
+            }
+        }
+    }
+}
+
+// Stops attributing fills to the order and releases its remaining reservation
+fn close_tracked_order(state: &mut AppState, order_id: &str) {
+    if let Some(tracked) = state.tracked_orders.remove(order_id) {
+        state.client_order_index.remove(&(tracked.user_id, tracked.client_order_id));
+    }
+    state.risk_engine.release_reservation(order_id);
+    state.risk_engine.close_liquidation(order_id);
+}
+
+async fn publish_breaches(state: &AppState, breaches: Vec<LossLimitBreach>) {
+    for breach in breaches {
+        tracing::warn!("Loss limit breached - {} {} | {}: {} (session PnL: {:.2}, drawdown: {:.2})",
+            breach.user_id, breach.symbol, breach.limit_type, breach.limit, breach.session_pnl, breach.drawdown);
+        RISK_VIOLATIONS.inc();
+        
+        let breach_json = serde_json::to_string(&breach).unwrap();
+        match produce_message(&state.kafka_producer, &state.config.breach_topic, &breach.user_id, &breach_json).await {
+            Ok(_) => KAFKA_MESSAGES_PRODUCED.with_label_values(&[&state.config.breach_topic]).inc(),
+            Err(e) => tracing::error!("Failed to publish loss limit breach: {}", e),
+        }
+    }
+}
+
+// Rebuilds the VaR return history from market data published within the lookback
+async fn seed_var_history(state: &Arc<Mutex<AppState>>, consumer: &StreamConsumer) -> Result<(), String> {
+    let (market_data_topic, sample_interval, lookback, has_limit) = {
+        let state = state.lock().await;
+        (state.config.market_data_topic.clone(), state.config.var_sample_interval, state.config.var_lookback,
+            state.config.max_incremental_var.is_some())
+    };
+    
+    let window_secs = sample_interval.saturating_mul(lookback as u64 + 1);
+    let since = Utc::now() - chrono::Duration::seconds(window_secs.min(i64::MAX as u64) as i64);
+    let offsets = offsets_for_timestamp(consumer, &[&market_data_topic], since.timestamp_millis())
+        .map_err(|e| format!("Failed to find market data offsets: {}", e))?;
+    let messages = read_to_end(consumer, &[&market_data_topic], &offsets)
+        .await
+        .map_err(|e| format!("Failed to read market data: {}", e))?;
+    
+    // Partitions are interleaved, so samples are put back in time order
+    let mut prices: Vec<(DateTime<Utc>, String, f64)> = messages
+        .iter()
+        .filter_map(|message| serde_json::from_str::<MarketDataMessage>(&message.payload).ok())
+        .filter_map(|market_data| {
+            let present = |value: f64| Some(value).filter(|value| *value > 0.0);
+            let price = MarketPrice {
+                last: market_data.price,
+                bid: present(market_data.bid),
+                ask: present(market_data.ask),
+                bid_size: None,
+                ask_size: None,
+            };
+            price.reference().map(|price| (market_data.timestamp, market_data.symbol, price))
+        })
+        .collect();
+    prices.sort_by_key(|(timestamp, _, _)| *timestamp);
+    
+    let scenarios = state.lock().await.risk_engine.seed_return_history(lookback, Duration::from_secs(sample_interval), prices);
+    if scenarios < MIN_SCENARIOS {
+        if has_limit {
+            tracing::warn!("Only {} of {} VaR scenarios available; orders are rejected by the VaR limit until enough samples are recorded", scenarios, MIN_SCENARIOS);
+        } else {
+            tracing::warn!("Only {} of {} VaR scenarios available; VaR estimates are unavailable until enough samples are recorded", scenarios, MIN_SCENARIOS);
+        }
+    } else {
+        info!("Seeded VaR history with {} scenarios from {} market data messages", scenarios, messages.len());
+    }
+    Ok(())
+}
+
+// Reads the kill switch topic to its end; the consumer is left there for live commands
+async fn replay_kill_switch(state: &Arc<Mutex<AppState>>, consumer: &StreamConsumer) -> Result<(), String> {
+    let kill_switch_topic = state.lock().await.config.kill_switch_topic.clone();
+    let messages = read_to_end(consumer, &[&kill_switch_topic], &TopicOffsets::new())
+        .await
+        .map_err(|e| format!("Failed to read kill switch commands: {}", e))?;
+    
+    let mut state = state.lock().await;
+    for message in &messages {
+        apply_kill_switch_message(&mut state, message);
+    }
+    info!("Replayed {} kill switch commands, {} active", messages.len(), state.kill_switch.active().len());
+    Ok(())
+}
+
+async fn process_kill_switch(state: Arc<Mutex<AppState>>, consumer: StreamConsumer) {
+    loop {
+        if let Some(message) = consume_message_with_metadata(&consumer).await {
+            KAFKA_MESSAGES_CONSUMED.with_label_values(&[&message.topic]).inc();
+            apply_kill_switch_message(&mut *state.lock().await, &message);
+        }
+    }
+}
+
+fn apply_kill_switch_message(state: &mut AppState, message: &ConsumedMessage) {
+    let command = match serde_json::from_str::<KillSwitchCommand>(&message.payload) {
+        Ok(command) => command,
+        Err(e) => {
+            tracing::warn!("Failed to parse kill switch command: {}", e);
+            return;
+        }
+    };
+    
+    match state.kill_switch.apply(&command) {
+        KillSwitchOutcome::Ignored(reason) => {
+            tracing::warn!("Ignoring kill switch command {}: {}", command.command_id, reason);
+        }
+        outcome => {
+            tracing::warn!("Kill switch {:?} for {} by {}: {}", outcome, command.scope, command.issued_by, command.reason);
+        }
+    }
+}
+
+// Reports margin status changes and sends out liquidation orders for accounts
+// below maintenance margin
+async fn monitor_margin(state: Arc<Mutex<AppState>>) {
+    let check_interval = state.lock().await.config.margin_check_interval_ms;
+    
+    loop {
+        tokio::time::sleep(Duration::from_millis(check_interval)).await;
+        
+        let mut events: Vec<MarginEvent> = Vec::new();
+        let mut liquidations = Vec::new();
+        let (producer, margin_topic, liquidation_topic) = {
+            let mut state = state.lock().await;
+            for user_id in state.risk_engine.margin_accounts() {
+                if let Some(usage) = state.risk_engine.get_margin_usage(&user_id) {
+                    ACCOUNT_MARGIN.with_label_values(&[&user_id, "equity"]).set(usage.equity);
+                    ACCOUNT_MARGIN.with_label_values(&[&user_id, "initial"]).set(usage.initial_margin);
+                    ACCOUNT_MARGIN.with_label_values(&[&user_id, "maintenance"]).set(usage.maintenance_margin);
+                }
+                events.extend(state.risk_engine.refresh_margin(&user_id));
+                
+                // Track liquidation orders so their fills update the position
+                for order in state.risk_engine.liquidation_orders(&user_id) {
+                    state.tracked_orders.insert(order.order_id.clone(), TrackedOrder {
+                        user_id: order.user_id.clone(),
+                        client_order_id: order.client_order_id.clone(),
+                        symbol: order.symbol.clone(),
+                        side: order.side.clone(),
+                        quantity: order.quantity,
+                        filled_quantity: 0.0,
+                        avg_fill_price: 0.0,
+                    });
+                    state.client_order_index.insert((order.user_id.clone(), order.client_order_id.clone()), order.order_id.clone());
+                    liquidations.push(order);
+                }
+            }
+            (state.kafka_producer.clone(), state.config.margin_topic.clone(), state.config.liquidation_topic.clone())
+        };
+        
+        for event in events {
+            tracing::warn!("Margin status for {} changed from {:?} to {:?} (equity: {:.2}, maintenance: {:.2})",
+                event.user_id, event.previous_status, event.status, event.equity, event.maintenance_margin);
+            let event_json = serde_json::to_string(&event).unwrap();
+            match produce_message(&producer, &margin_topic, &event.user_id, &event_json).await {
+                Ok(_) => KAFKA_MESSAGES_PRODUCED.with_label_values(&[&margin_topic]).inc(),
+                Err(e) => tracing::error!("Failed to publish margin event: {}", e),
+            }
+        }
+        
+        for order in liquidations {
+            tracing::warn!("Liquidating {} {} {} for {}", order.side, order.quantity, order.symbol, order.user_id);
+            let order_json = serde_json::to_string(&order).unwrap();
+            match produce_message(&producer, &liquidation_topic, &order.order_id, &order_json).await {
+                Ok(_) => KAFKA_MESSAGES_PRODUCED.with_label_values(&[&liquidation_topic]).inc(),
+                Err(e) => tracing::error!("Failed to publish liquidation order {}: {}", order.order_id, e),
+            }
+        }
+    }
+}
+
+// A missing snapshot means a fresh start; one that cannot be read must not be
+// silently replaced by empty positions
+fn load_snapshot(path: &str) -> Option<RiskSnapshot> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            info!("No risk snapshot at {}, starting with empty state", path);
+            return None;
+        }
+        Err(e) => panic!("Failed to read risk snapshot {}: {}", path, e),
+    };
+    match serde_json::from_str(&contents) {
+        Ok(snapshot) => Some(snapshot),
+        Err(e) => panic!("Failed to parse risk snapshot {}: {}", path, e),
+    }
+}
+
+// Assigns every partition of the topics at its current end
+async fn start_at_end(state: &Arc<Mutex<AppState>>, consumer: &StreamConsumer, topics: &[&str]) -> Result<(), String> {
+    let end = high_watermarks(consumer, topics).map_err(|e| format!("Failed to read end offsets: {}", e))?;
+    let start = assign_from_offsets(consumer, topics, &end).map_err(|e| format!("Failed to assign partitions: {}", e))?;
+    
+    let mut state = state.lock().await;
+    for (topic, partitions) in start {
+        state.consumed_offsets.entry(topic).or_default().extend(partitions);
+    }
+    Ok(())
+}
+
+// Everything published since a snapshot. It is all read before any state is
+// touched, so a failed read can simply be retried.
+struct SnapshotReplay {
+    offsets: TopicOffsets, // where the order and fill topics were read from
+    orders: Vec<ConsumedMessage>,
+    decisions: HashMap<String, String>, // order_id -> status
+    fills: Vec<ConsumedMessage>,
+}
+
+async fn read_since_snapshot(state: &Arc<Mutex<AppState>>, snapshot: &RiskSnapshot, fill_consumer: &StreamConsumer) -> Result<SnapshotReplay, String> {
+    let (brokers, input_topic, decision_topics, fill_topics) = {
+        let state = state.lock().await;
+        let config = &state.config;
+        (
+            config.kafka_brokers.clone(),
+            config.input_topic.clone(),
+            [config.approved_topic.clone(), config.rejected_topic.clone()],
+            [config.trades_topic.clone(), config.fills_topic.clone(), config.cancelled_topic.clone()],
+        )
+    };
+    let consumer = create_replay_consumer(&brokers, "risk-manager-recovery-group");
+    
+    // A partition the snapshot has no offset for (added since, or never read
+    // by an older version) resumes at the snapshot time, not its beginning
+    let mut topics: Vec<&str> = fill_topics.iter().map(|topic| topic.as_str()).collect();
+    topics.push(&input_topic);
+    let mut offsets = offsets_for_timestamp(&consumer, &topics, snapshot.taken_at.timestamp_millis())
+        .map_err(|e| format!("Failed to find offsets at the snapshot time: {}", e))?;
+    for (topic, partitions) in &snapshot.offsets {
+        offsets.entry(topic.clone()).or_default().extend(partitions);
+    }
+    
+    let topics: Vec<&str> = decision_topics.iter().map(|topic| topic.as_str()).collect();
+    let decisions = read_to_end(&consumer, &topics, &snapshot.decision_offsets)
+        .await
+        .map_err(|e| format!("Failed to read risk decisions: {}", e))?
+        .iter()
+        .filter_map(|message| serde_json::from_str::<RiskValidation>(&message.payload).ok())
+        .map(|validation| (validation.order_id, validation.status))
+        .collect();
+    let orders = read_to_end(&consumer, &[&input_topic], &offsets)
+        .await
+        .map_err(|e| format!("Failed to read orders: {}", e))?;
+    
+    // Replaying leaves the consumer at the end of the streams, where live processing picks up
+    let topics: Vec<&str> = fill_topics.iter().map(|topic| topic.as_str()).collect();
+    let fills = read_to_end(fill_consumer, &topics, &offsets)
+        .await
+        .map_err(|e| format!("Failed to read fill streams: {}", e))?;
+    
+    Ok(SnapshotReplay { offsets, orders, decisions, fills })
+}
+
+async fn restore_risk_state(state: &Arc<Mutex<AppState>>, snapshot: RiskSnapshot, replay: SnapshotReplay) {
+    let mut state = state.lock().await;
+    state.risk_engine.restore_state(snapshot.engine);
+    state.circuit_breaker.restore_state(snapshot.circuit_breakers, Instant::now());
+    state.client_order_index = snapshot.tracked_orders.iter()
+        .map(|(order_id, tracked)| ((tracked.user_id.clone(), tracked.client_order_id.clone()), order_id.clone()))
+        .collect();
+    state.tracked_orders = snapshot.tracked_orders;
+    state.consumed_offsets = replay.offsets;
+    
+    // Orders decided after the snapshot are not tracked in it. Their tracking is
+    // rebuilt from the decisions already published for them, so that they are
+    // not checked and published a second time and replayed fills are attributed
+    // to the right users. A partition's orders are decided in order, so its
+    // first undecided order is where live processing resumes.
+    let mut undecided = HashSet::new();
+    let mut recovered = 0;
+    for message in replay.orders {
+        if undecided.contains(&message.partition) {
+            continue;
+        }
+        let order = match serde_json::from_str::<Order>(&message.payload) {
+            Ok(order) => order,
+            Err(_) => {
+                record_offset(&mut state, &message);
+                continue;
+            }
+        };
+        let approved = match replay.decisions.get(&order.order_id) {
+            Some(status) => status == "approved",
+            None => {
+                undecided.insert(message.partition);
+                continue;
+            }
+        };
+        record_offset(&mut state, &message);
+        if !approved {
+            continue;
+        }
+        state.client_order_index.insert((order.user_id.clone(), order.client_order_id.clone()), order.order_id.clone());
+        state.tracked_orders.insert(order.order_id.clone(), TrackedOrder {
+            user_id: order.user_id.clone(),
+            client_order_id: order.client_order_id.clone(),
+            symbol: order.symbol.clone(),
+            side: order.side.clone(),
+            quantity: order.quantity,
+            filled_quantity: 0.0,
+            avg_fill_price: 0.0,
+        });
+        state.risk_engine.reserve_funds(&order);
+        recovered += 1;
+    }
+    
+    let replayed = replay.fills.len();
+    for message in replay.fills {
+        handle_fill_message(&mut state, message, true).await;
+    }
+    
+    info!("Restored risk state from snapshot taken at {}, recovered {} approved orders and replayed {} fill messages",
+        snapshot.taken_at, recovered, replayed);
+}
+
+// Taken under the state lock so positions and offsets always agree
+async fn snapshot_risk_state(state: Arc<Mutex<AppState>>) {
+    let (snapshot_interval, brokers, decision_topics) = {
+        let state = state.lock().await;
+        (state.config.snapshot_interval, state.config.kafka_brokers.clone(), [state.config.approved_topic.clone(), state.config.rejected_topic.clone()])
+    };
+    let topics: Vec<&str> = decision_topics.iter().map(|topic| topic.as_str()).collect();
+    let consumer = create_replay_consumer(&brokers, "risk-manager-snapshot-group");
+    
+    loop {
+        tokio::time::sleep(Duration::from_secs(snapshot_interval)).await;
+        
+        // Read before locking: every decision for an order in the snapshot is
+        // already published, and later ones can only land after this point
+        let result = match high_watermarks(&consumer, &topics) {
+            Ok(decision_offsets) => {
+                let (path, snapshot_json) = {
+                    let state = state.lock().await;
+                    let snapshot = RiskSnapshot {
+                        taken_at: Utc::now(),
+                        offsets: state.consumed_offsets.clone(),
+                        decision_offsets,
+                        engine: state.risk_engine.export_state(),
+                        circuit_breakers: state.circuit_breaker.export_state(Instant::now()),
+                        tracked_orders: state.tracked_orders.clone(),
+                    };
+                    (state.config.snapshot_path.clone(), serde_json::to_string(&snapshot).unwrap())
+                };
+                write_snapshot(&path, &snapshot_json).await.map_err(|e| format!("{}: {}", path, e))
+            }
+            Err(e) => Err(format!("failed to read decision offsets: {}", e)),
+        };
+        
+        let mut state = state.lock().await;
+        match result {
+            Ok(()) => {
+                state.snapshot_failures = 0;
+                state.last_snapshot_at = Some(Utc::now());
+            }
+            Err(e) => {
+                state.snapshot_failures += 1;
+                RISK_SNAPSHOT_FAILURES.inc();
+                tracing::error!("Failed to write risk snapshot ({} in a row): {}", state.snapshot_failures, e);
+            }
+        }
+    }
+}
+
+// Written next to the target and renamed so a crash never leaves half a snapshot
+async fn write_snapshot(path: &str, contents: &str) -> std::io::Result<()> {
+    let path = std::path::Path::new(path);
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let temp_path = path.with_extension("tmp");
+    tokio::fs::write(&temp_path, contents).await?;
+    tokio::fs::rename(&temp_path, path).await
+}
+
+// Builds the return history VaR is computed from
+async fn sample_market_returns(state: Arc<Mutex<AppState>>) {
+    let sample_interval = state.lock().await.config.var_sample_interval;
+    
+    loop {
+        tokio::time::sleep(Duration::from_secs(sample_interval)).await;
+        state.lock().await.risk_engine.record_return_sample();
+    }
+}
+
+async fn refresh_var(state: Arc<Mutex<AppState>>) {
+    let refresh_interval = state.lock().await.config.var_refresh_interval;
+    
+    loop {
+        tokio::time::sleep(Duration::from_secs(refresh_interval)).await;
+        
+        let mut state = state.lock().await;
+        let estimates: HashMap<String, VarEstimate> = state.risk_engine
+            .users()
+            .into_iter()
+            .map(|user_id| (user_id.clone(), state.risk_engine.compute_var(&user_id)))
+            .collect();
+        
+        for estimate in estimates.values() {
+            if let Some(historical) = estimate.historical {
+                USER_VAR.with_label_values(&[&estimate.user_id, "historical"]).set(historical);
+            }
+            if let Some(parametric) = estimate.parametric {
+                USER_VAR.with_label_values(&[&estimate.user_id, "parametric"]).set(parametric);
+            }
+        }
+        state.var_estimates = estimates;
+    }
+}
+
+// Starts a new PnL session at the configured time each day
+async fn monitor_session_reset(state: Arc<Mutex<AppState>>) {
+    let reset_time = {
+        let state = state.lock().await;
+        NaiveTime::parse_from_str(&state.config.session_reset_time, "%H:%M").unwrap_or_else(|e| {
+            tracing::warn!("Invalid session reset time {}: {}, using 00:00", state.config.session_reset_time, e);
+            NaiveTime::MIN
+        })
+    };
+    
+    loop {
+        let now = Utc::now();
+        let mut next_reset = now.date_naive().and_time(reset_time).and_utc();
+        if next_reset <= now {
+            next_reset += chrono::Duration::days(1);
+        }
+        tokio::time::sleep((next_reset - now).to_std().unwrap_or_default()).await;
+        
+        state.lock().await.risk_engine.reset_sessions();
+        info!("Trading session reset, daily PnL and drawdown limits cleared");
+    }
+}
+
+// Admin API
+async fn restore_risk_config(state: &Arc<Mutex<AppState>>) -> Result<(), String> {
+    let (brokers, audit_topic) = {
+        let state = state.lock().await;
+        (state.config.kafka_brokers.clone(), state.config.audit_topic.clone())
+    };
+    let consumer = create_replay_consumer(&brokers, "risk-manager-config-audit-group");
+    let mut entries: Vec<ConfigAuditEntry> = read_to_end(&consumer, &[&audit_topic], &TopicOffsets::new())
+        .await
+        .map_err(|e| format!("Failed to read config audit trail: {}", e))?
+        .iter()
+        .filter_map(|message| serde_json::from_str::<ConfigAuditMessage>(&message.payload).ok())
+        .flat_map(ConfigAuditMessage::into_entries)
+        .collect();
+    // Older entries were keyed by section, so only the order within a partition is kept
+    entries.sort_by_key(|entry| (entry.version, entry.changed_at));
+    let Some(version) = entries.last().map(|entry| entry.version) else {
+        return Ok(());
+    };
+    
+    // Later entries overwrite earlier ones, leaving the latest value per section
+    let mut sections = serde_json::Map::new();
+    for entry in &entries {
+        sections.insert(entry.section.clone(), entry.current.clone());
+    }
+    let update: RiskConfigUpdate = serde_json::from_value(serde_json::Value::Object(sections))
+        .map_err(|e| format!("Invalid config in audit trail: {}", e))?;
+    validate_risk_config_update(&update).map_err(|e| format!("Invalid config in audit trail: {}", e))?;
+    
+    let mut state = state.lock().await;
+    apply_risk_config_update(&mut state, &update)?;
+    state.config_version = version;
+    state.config_audit = entries;
+    info!("Restored risk config at version {}", version);
+    Ok(())
+}
+
+async fn start_admin_server(state: Arc<Mutex<AppState>>, http_port: u16) {
+    let admin_enabled = !state.lock().await.config.admin_keys.is_empty();
+    let mut app = Router::new().route("/health", get(health_check));
+    if admin_enabled {
+        app = app
+            .route("/admin/risk-config", get(get_risk_config).put(update_risk_config))
+            .route("/admin/risk-config/history", get(get_risk_config_history))
+            .route("/admin/var", get(get_var))
+            .route("/admin/margin", get(get_margin))
+            .route("/admin/exposure", get(get_portfolio_exposure))
+            .route("/admin/stress", get(get_stress_report))
+            .route("/admin/surveillance", get(get_surveillance))
+            .route("/admin/circuit-breakers", get(get_circuit_breakers))
+            .route("/admin/circuit-breakers/reset", post(reset_circuit_breaker))
+            .route("/admin/kill-switch", get(get_kill_switch).post(issue_kill_switch_command));
+    } else {
+        tracing::warn!("ADMIN_API_KEYS is not set; risk admin API is disabled");
+    }
+    let app = app.with_state(state);
+    
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", http_port))
+        .await
+        .expect("Failed to bind HTTP port");
+    
+    info!("Risk admin API listening on port {}", http_port);
+    
+    axum::serve(listener, app)
+        .await
+        .expect("Failed to start server");
+}
+
+type ApiError = (StatusCode, Json<serde_json::Value>);
+
+fn api_error(status: StatusCode, message: &str) -> ApiError {
+    (status, Json(serde_json::json!({ "error": message })))
+}
+
+// Checks the X-API-Key / X-API-Secret headers and returns the caller's key
+fn authenticate_admin(config: &RiskManagerConfig, headers: &HeaderMap, permission: &str) -> Result<String, ApiError> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let (key, secret) = match (header("x-api-key"), header("x-api-secret")) {
+        (Some(key), Some(secret)) => (key, secret),
+        _ => return Err(api_error(StatusCode::UNAUTHORIZED, "Missing API credentials")),
+    };
+    
+    let auth = match config.admin_keys.get(key) {
+        Some(auth) if auth.secret == secret => auth,
+        _ => return Err(api_error(StatusCode::UNAUTHORIZED, "Invalid API credentials")),
+    };
+    
+    if !auth.permissions.iter().any(|p| p == permission) {
+        return Err(api_error(StatusCode::FORBIDDEN, "Insufficient permissions"));
+    }
+    
+    Ok(auth.key.clone())
+}
+
+fn validate_risk_config_update(update: &RiskConfigUpdate) -> Result<(), String> {
+    if let Some(limits) = &update.position_limits {
+        for (symbol, limit) in limits {
+            let values = [limit.max_long, limit.max_short, limit.max_exposure, limit.max_daily_pnl, limit.max_drawdown];
+            if values.iter().any(|value| !value.is_finite() || *value < 0.0) {
+                return Err(format!("Position limits for {} must be finite and non-negative", symbol));
+            }
+        }
+    }
+    
+    if let Some(rules) = &update.risk_rules {
+        compile_risk_rules(rules)?;
+    }
+    
+    if let Some(rules) = &update.compliance_rules {
+        if rules.iter().any(|rule| rule.name.trim().is_empty()) {
+            return Err("Compliance checks must have a name".to_string());
+        }
+    }
+    
+    for limits in [&update.fat_finger_limits, &update.user_fat_finger_limits].into_iter().flatten() {
+        for (key, limit) in limits {
+            let values = [limit.max_order_quantity, limit.max_order_notional, limit.max_price_deviation];
+            if values.iter().flatten().any(|value| !value.is_finite() || *value <= 0.0) {
+                return Err(format!("Fat-finger limits for {} must be positive", key));
+            }
+        }
+    }
+    
+    let user_portfolio_limits = update.user_portfolio_limits.iter().flatten().map(|(user_id, limits)| (user_id.as_str(), limits));
+    for (key, limits) in update.portfolio_limits.iter().map(|limits| ("default", limits)).chain(user_portfolio_limits) {
+        let mut values = [limits.max_gross_notional, limits.max_net_notional]
+            .into_iter()
+            .flatten()
+            .chain(limits.max_asset_class_exposure.values().copied())
+            .chain(limits.max_currency_exposure.values().copied());
+        if values.any(|value| !value.is_finite() || value <= 0.0) {
+            return Err(format!("Portfolio limits for {} must be positive", key));
+        }
+    }
+    
+    Ok(())
+}
+
+fn risk_config_view(state: &AppState) -> serde_json::Value {
+    serde_json::json!({
+        "version": state.config_version,
+        "position_limits": state.config.position_limits,
+        "risk_rules": state.config.risk_rules,
+        "compliance_rules": state.config.compliance_rules,
+        "fat_finger_limits": state.config.fat_finger_limits,
+        "user_fat_finger_limits": state.config.user_fat_finger_limits,
+        "portfolio_limits": state.config.portfolio_limits,
+        "user_portfolio_limits": state.config.user_portfolio_limits,
+    })
+}
+
+// Unhealthy while snapshots keep failing, since a restart would then lose
+// every position change since the last good one
+async fn health_check(State(state): State<Arc<Mutex<AppState>>>) -> (StatusCode, Json<serde_json::Value>) {
+    let state = state.lock().await;
+    let (status, code) = if state.snapshot_failures >= MAX_SNAPSHOT_FAILURES {
+        ("degraded", StatusCode::SERVICE_UNAVAILABLE)
+    } else {
+        ("healthy", StatusCode::OK)
+    };
+    (code, Json(serde_json::json!({
+        "status": status,
+        "timestamp": Utc::now(),
+        "service": "risk-manager",
+        "snapshot_failures": state.snapshot_failures,
+        "last_snapshot_at": state.last_snapshot_at,
+    })))
+}
+
+async fn get_risk_config(
+    State(state): State<Arc<Mutex<AppState>>>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let state = state.lock().await;
+    authenticate_admin(&state.config, &headers, "risk_read")?;
+    Ok(Json(risk_config_view(&state)))
+}
+
+async fn get_risk_config_history(
+    State(state): State<Arc<Mutex<AppState>>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<ConfigAuditEntry>>, ApiError> {
+    let state = state.lock().await;
+    authenticate_admin(&state.config, &headers, "risk_read")?;
+    Ok(Json(state.config_audit.clone()))
+}
+
+// Swaps in every section present in the update and returns (section, previous, current) for each
+// Section, previous value and new value for everything the update changes
+fn risk_config_changes(config: &RiskManagerConfig, update: &RiskConfigUpdate) -> Vec<(String, serde_json::Value, serde_json::Value)> {
+    let mut changes = Vec::new();
+    let mut record = |section: &str, previous: serde_json::Value, current: serde_json::Value| {
+        changes.push((section.to_string(), previous, current));
+    };
+    
+    if let Some(limits) = &update.position_limits {
+        record("position_limits", serde_json::json!(config.position_limits), serde_json::json!(limits));
+    }
+    if let Some(rules) = &update.risk_rules {
+        record("risk_rules", serde_json::json!(config.risk_rules), serde_json::json!(rules));
+    }
+    if let Some(rules) = &update.compliance_rules {
+        record("compliance_rules", serde_json::json!(config.compliance_rules), serde_json::json!(rules));
+    }
+    if let Some(limits) = &update.fat_finger_limits {
+        record("fat_finger_limits", serde_json::json!(config.fat_finger_limits), serde_json::json!(limits));
+    }
+    if let Some(limits) = &update.user_fat_finger_limits {
+        record("user_fat_finger_limits", serde_json::json!(config.user_fat_finger_limits), serde_json::json!(limits));
+    }
+    if let Some(limits) = &update.portfolio_limits {
+        record("portfolio_limits", serde_json::json!(config.portfolio_limits), serde_json::json!(limits));
+    }
+    if let Some(limits) = &update.user_portfolio_limits {
+        record("user_portfolio_limits", serde_json::json!(config.user_portfolio_limits), serde_json::json!(limits));
+    }
+    
+    changes
+}
+
+fn apply_risk_config_update(state: &mut AppState, update: &RiskConfigUpdate) -> Result<(), String> {
+    if let Some(limits) = update.position_limits.clone() {
+        state.risk_engine.update_position_limits(limits.clone());
+        state.config.position_limits = limits;
+    }
+    if let Some(rules) = update.risk_rules.clone() {
+        state.risk_engine.add_risk_rules(rules.clone())?;
+        state.config.risk_rules = rules;
+    }
+    if let Some(rules) = update.compliance_rules.clone() {
+        state.risk_engine.add_compliance_rules(rules.clone());
+        state.config.compliance_rules = rules;
+    }
+    if update.fat_finger_limits.is_some() || update.user_fat_finger_limits.is_some() {
+        if let Some(limits) = update.fat_finger_limits.clone() {
+            state.config.fat_finger_limits = limits;
+        }
+        if let Some(limits) = update.user_fat_finger_limits.clone() {
+            state.config.user_fat_finger_limits = limits;
+        }
+        let (instrument, user) = (state.config.fat_finger_limits.clone(), state.config.user_fat_finger_limits.clone());
+        state.risk_engine.update_fat_finger_limits(instrument, user);
+    }
+    if update.portfolio_limits.is_some() || update.user_portfolio_limits.is_some() {
+        if let Some(limits) = update.portfolio_limits.clone() {
+            state.config.portfolio_limits = limits;
+        }
+        if let Some(limits) = update.user_portfolio_limits.clone() {
+            state.config.user_portfolio_limits = limits;
+        }
+        let (default, user) = (state.config.portfolio_limits.clone(), state.config.user_portfolio_limits.clone());
+        state.risk_engine.update_portfolio_limits(default, user);
+    }
+    
+    Ok(())
+}
+
+async fn update_risk_config(
+    State(state): State<Arc<Mutex<AppState>>>,
+    headers: HeaderMap,
+    Json(update): Json<RiskConfigUpdate>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let mut state = state.lock().await;
+    let changed_by = authenticate_admin(&state.config, &headers, "risk_admin")?;
+    
+    if update.expected_version.is_some_and(|version| version != state.config_version) {
+        return Err(api_error(StatusCode::CONFLICT, &format!("Config is at version {}", state.config_version)));
+    }
+    validate_risk_config_update(&update).map_err(|e| api_error(StatusCode::BAD_REQUEST, &e))?;
+    
+    let version = state.config_version + 1;
+    let changed_at = Utc::now();
+    let entries: Vec<ConfigAuditEntry> = risk_config_changes(&state.config, &update)
+        .into_iter()
+        .map(|(section, previous, current)| ConfigAuditEntry {
+            version,
+            changed_by: changed_by.clone(),
+            changed_at,
+            reason: update.reason.clone(),
+            section,
+            previous,
+            current,
+        })
+        .collect();
+    
+    if entries.is_empty() {
+        return Err(api_error(StatusCode::BAD_REQUEST, "Update contains no changes"));
+    }
+    
+    // The audit topic is what a restart restores from, so the change only takes
+    // effect once it is published there. The state lock is held throughout, which
+    // keeps versions in order on the topic and orders from seeing a partial update.
+    let audit_topic = state.config.audit_topic.clone();
+    let entries_json = serde_json::to_string(&entries).unwrap();
+    if let Err(e) = produce_message(&state.kafka_producer, &audit_topic, AUDIT_KEY, &entries_json).await {
+        tracing::error!("Failed to publish risk config v{}, leaving config unchanged: {}", version, e);
+        return Err(api_error(StatusCode::SERVICE_UNAVAILABLE, "Failed to record config change, nothing was applied"));
+    }
+    KAFKA_MESSAGES_PRODUCED.with_label_values(&[&audit_topic]).inc();
+    
+    // Everything is validated, so the swap cannot fail halfway
+    apply_risk_config_update(&mut state, &update).map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, &e))?;
+    for entry in &entries {
+        info!("Risk config v{} - {} updated by {}", entry.version, entry.section, entry.changed_by);
+    }
+    state.config_version = version;
+    state.config_audit.extend(entries);
+    
+    Ok(Json(risk_config_view(&state)))
+}
+
+async fn get_kill_switch(
+    State(state): State<Arc<Mutex<AppState>>>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let state = state.lock().await;
+    authenticate_admin(&state.config, &headers, "risk_read")?;
+    Ok(Json(serde_json::json!({
+        "active": state.kill_switch.active(),
+        "pending_reenables": state.kill_switch.pending_reenables(),
+    })))
+}
+
+// Publishes a command to the control topic; every service applies it from there.
+// Re-enabling takes two calls: request_reenable, then confirm_reenable with the
+// request's command_id.
+async fn issue_kill_switch_command(
+    State(state): State<Arc<Mutex<AppState>>>,
+    headers: HeaderMap,
+    Json(request): Json<KillSwitchRequest>,
+) -> Result<(StatusCode, Json<KillSwitchCommand>), ApiError> {
+    let state = state.lock().await;
+    let issued_by = authenticate_admin(&state.config, &headers, "risk_admin")?;
+    
+    if request.reason.trim().is_empty() {
+        return Err(api_error(StatusCode::BAD_REQUEST, "A reason is required"));
+    }
+    if request.action == KillSwitchAction::ConfirmReenable {
+        let pending = state.kill_switch.pending_reenables();
+        let confirms = request.confirms.as_ref();
+        let Some(pending) = pending.iter().find(|p| Some(&p.command_id) == confirms && p.scope == request.scope) else {
+            return Err(api_error(StatusCode::BAD_REQUEST, "No matching re-enable request to confirm"));
+        };
+        if pending.issued_by == issued_by {
+            return Err(api_error(StatusCode::FORBIDDEN, "A re-enable must be confirmed by someone other than the requester"));
+        }
+    }
+    
+    let command = KillSwitchCommand {
+        command_id: Uuid::new_v4().to_string(),
+        action: request.action,
+        scope: request.scope,
+        issued_by,
+        reason: request.reason,
+        confirms: request.confirms,
+        timestamp: Utc::now(),
+    };
+    let producer = state.kafka_producer.clone();
+    let kill_switch_topic = state.config.kill_switch_topic.clone();
+    drop(state);
+    
+    let command_json = serde_json::to_string(&command).unwrap();
+    produce_message(&producer, &kill_switch_topic, &command.scope.to_string(), &command_json)
+        .await
+        .map_err(|e| api_error(StatusCode::SERVICE_UNAVAILABLE, &format!("Failed to publish kill switch command: {}", e)))?;
+    KAFKA_MESSAGES_PRODUCED.with_label_values(&[&kill_switch_topic]).inc();
+    
+    tracing::warn!("Kill switch {:?} for {} issued by {}", command.action, command.scope, command.issued_by);
+    Ok((StatusCode::ACCEPTED, Json(command)))
+}
+
+async fn get_var(
+    State(state): State<Arc<Mutex<AppState>>>,
+    headers: HeaderMap,
+) -> Result<Json<HashMap<String, VarEstimate>>, ApiError> {
+    let state = state.lock().await;
+    authenticate_admin(&state.config, &headers, "risk_read")?;
+    Ok(Json(state.var_estimates.clone()))
+}
+
+async fn get_circuit_breakers(
+    State(state): State<Arc<Mutex<AppState>>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<BreakerStatus>>, ApiError> {
+    let mut state = state.lock().await;
+    authenticate_admin(&state.config, &headers, "risk_read")?;
+    Ok(Json(state.circuit_breaker.statuses(Instant::now())))
+}
+
+async fn reset_circuit_breaker(
+    State(state): State<Arc<Mutex<AppState>>>,
+    headers: HeaderMap,
+    Json(key): Json<BreakerKey>,
+) -> Result<Json<Vec<BreakerStatus>>, ApiError> {
+    let mut state = state.lock().await;
+    let reset_by = authenticate_admin(&state.config, &headers, "risk_admin")?;
+    
+    if !state.circuit_breaker.reset(&key) {
+        return Err(api_error(StatusCode::NOT_FOUND, &format!("No circuit breaker for {}", key)));
+    }
+    CIRCUIT_BREAKER_STATE
+        .with_label_values(&[key.scope(), key.value()])
+        .set(0.0);
+    info!("Circuit breaker {} reset by {}", key, reset_by);
+    
+    Ok(Json(state.circuit_breaker.statuses(Instant::now())))
+}
+
+async fn get_margin(
+    State(state): State<Arc<Mutex<AppState>>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<MarginUsage>>, ApiError> {
+    let state = state.lock().await;
+    authenticate_admin(&state.config, &headers, "risk_read")?;
+    let usages = state.risk_engine
+        .margin_accounts()
+        .iter()
+        .filter_map(|user_id| state.risk_engine.get_margin_usage(user_id))
+        .collect();
+    Ok(Json(usages))
+}
+
+async fn get_surveillance(
+    State(state): State<Arc<Mutex<AppState>>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<ActivityStats>>, ApiError> {
+    let mut state = state.lock().await;
+    authenticate_admin(&state.config, &headers, "risk_read")?;
+    Ok(Json(state.surveillance.stats(Instant::now())))
+}
+
+async fn get_portfolio_exposure(
+    State(state): State<Arc<Mutex<AppState>>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<PortfolioExposure>>, ApiError> {
+    let state = state.lock().await;
+    authenticate_admin(&state.config, &headers, "risk_read")?;
+    let exposures = state.risk_engine
+        .users()
+        .iter()
+        .map(|user_id| state.risk_engine.get_portfolio_exposure(user_id))
+        .collect();
+    Ok(Json(exposures))
+}
+
+async fn get_stress_report(
+    State(state): State<Arc<Mutex<AppState>>>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let state = state.lock().await;
+    authenticate_admin(&state.config, &headers, "risk_read")?;
+    let reports: Vec<StressReport> = state.risk_engine
+        .users()
+        .iter()
+        .map(|user_id| state.risk_engine.stress_report(user_id))
+        .collect();
+    Ok(Json(serde_json::json!({
+        "scenarios": state.risk_engine.stress_scenarios(),
+        "accounts": reports,
+    })))
+}